@@ -0,0 +1,20 @@
+﻿use std::process::Command;
+
+/// Embed the git commit the binary was built from as GIT_HASH, for /health.
+/// Builds outside a git checkout can pass GIT_HASH in the environment instead.
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=GIT_HASH");
+
+    let hash = std::env::var("GIT_HASH").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|hash| hash.trim().to_string())
+    });
+    println!("cargo:rustc-env=GIT_HASH={}", hash.as_deref().unwrap_or("unknown"));
+}
@@ -0,0 +1,138 @@
+﻿use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::{
+    config::{Config, DEVICE_KINDS},
+    error::{ApiError, Result},
+};
+
+/// Alternate names for one device in one room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceAliases {
+    pub room: String,
+    /// The configured device or group name
+    pub device: String,
+    /// Display name for dashboards and voice assistants
+    #[serde(default)]
+    pub nickname: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+impl DeviceAliases {
+    fn names(&self) -> impl Iterator<Item = &String> {
+        self.nickname.iter().chain(self.aliases.iter())
+    }
+}
+
+/// Device nicknames and aliases, persisted as JSON so they survive restarts and config edits
+pub struct AliasStore {
+    path: PathBuf,
+    entries: Vec<DeviceAliases>,
+}
+
+impl AliasStore {
+    /// Load the persisted aliases; a missing or unreadable file starts empty
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("aliases.json");
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        Self { path, entries }
+    }
+
+    pub fn list(&self) -> Vec<DeviceAliases> {
+        self.entries.clone()
+    }
+
+    /// A device's nickname and aliases, if it has any
+    pub fn get(&self, room: &str, device: &str) -> Option<&DeviceAliases> {
+        self.entries
+            .iter()
+            .find(|e| e.room == room && e.device.eq_ignore_ascii_case(device))
+    }
+
+    /// The configured device a nickname or alias refers to in a room
+    pub fn resolve(&self, room: &str, name: &str) -> Option<String> {
+        self.entries
+            .iter()
+            .filter(|e| e.room == room)
+            .find(|e| e.names().any(|n| n.eq_ignore_ascii_case(name)))
+            .map(|e| e.device.clone())
+    }
+
+    /// Replace a device's nickname and aliases. Names must not shadow a configured device
+    /// or another device's alias in the same room.
+    pub fn set(&mut self, config: &Config, mut entry: DeviceAliases) -> Result<DeviceAliases> {
+        entry.device = entry.device.to_lowercase();
+        if config.device_pins(&entry.room, &entry.device).is_none() {
+            return Err(ApiError::UnknownDevice(entry.device));
+        }
+        // Key devices by their configured name, so a kind and the name it resolves to share aliases
+        if let Some(device) = config.zone(Some(&entry.room))?.device(&entry.device) {
+            entry.device = device.name.to_lowercase();
+        }
+
+        entry.aliases.retain(|a| !a.trim().is_empty());
+        entry.aliases.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        for name in entry.names() {
+            if name.trim().is_empty() {
+                return Err(ApiError::InvalidAlias("Nickname must not be empty".to_string()));
+            }
+            if config.device_pins(&entry.room, name).is_some()
+                || DEVICE_KINDS.contains(&name.to_lowercase().as_str())
+            {
+                return Err(ApiError::InvalidAlias(format!(
+                    "'{}' is already a device in '{}'",
+                    name, entry.room
+                )));
+            }
+            if let Some(owner) = self.resolve(&entry.room, name).filter(|d| *d != entry.device) {
+                return Err(ApiError::InvalidAlias(format!(
+                    "'{}' already refers to '{}' in '{}'",
+                    name, owner, entry.room
+                )));
+            }
+        }
+
+        self.entries.retain(|e| !(e.room == entry.room && e.device == entry.device));
+        self.entries.push(entry.clone());
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Drop every nickname and alias of a device
+    pub fn remove(&mut self, room: &str, device: &str) -> Result<DeviceAliases> {
+        let index = self
+            .entries
+            .iter()
+            .position(|e| e.room == room && e.device.eq_ignore_ascii_case(device))
+            .ok_or(ApiError::AliasNotFound)?;
+        let removed = self.entries.remove(index);
+        self.save()?;
+        Ok(removed)
+    }
+
+    fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.entries)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode aliases: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
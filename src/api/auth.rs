@@ -0,0 +1,111 @@
+﻿use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Local;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{
+    audit::AuditEntry,
+    auth::{AuthConfig, AuthFailure, Identity, Role},
+    error::ApiError,
+    state::AppState,
+};
+
+/// Routes that never need an identity; the failover peer authenticates with its own token
+const PUBLIC_ROUTES: &[&str] = &["/health", "/ui", "/api/v1/setup", "/api/v1/failover/heartbeat"];
+
+/// State-changing routes too frequent and routine to audit
+const UNAUDITED_ROUTES: &[&str] = &["/api/v1/failover/heartbeat"];
+
+/// Middleware restricting control endpoints to `security.allowed_networks`, identifying the
+/// caller from a bearer token or trusted proxy headers, enforcing its role and recording
+/// state-changing requests in the audit log
+pub async fn authorize(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let source = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip());
+    let (method, path) = (request.method().clone(), request.uri().path().to_string());
+
+    let control = Role::required(&method, &path) > Role::Viewer;
+    if control && !PUBLIC_ROUTES.contains(&path.as_str()) {
+        let Some(source) = source else {
+            return ApiError::InternalError.into_response();
+        };
+        if !state.config.load().security.allows(source) {
+            tracing::warn!("Refused {} {} from {}, outside security.allowed_networks", method, path, source);
+            return ApiError::Forbidden(format!("Control is not allowed from {}", source)).into_response();
+        }
+    }
+
+    let identity = if PUBLIC_ROUTES.contains(&path.as_str()) {
+        None
+    } else {
+        match identify(&state.config.load().auth, source, request.headers()) {
+            Ok(identity) => identity,
+            Err(failure) => return refuse(failure),
+        }
+    };
+
+    if let Some(identity) = &identity {
+        let required = Role::required(&method, &path);
+        if identity.role < required {
+            tracing::warn!("{} ({:?}) may not {} {}", identity.user, identity.role, method, path);
+            return ApiError::Forbidden(format!("{:?} role required", required).to_lowercase()).into_response();
+        }
+        request.extensions_mut().insert(identity.clone());
+    }
+
+    let response = next.run(request).await;
+
+    // The legacy endpoint switches relays on a GET, so it is audited too
+    let changes_state = path == "/" || (method != Method::GET && method != Method::HEAD);
+    if changes_state && !UNAUDITED_ROUTES.contains(&path.as_str()) {
+        state.audit.lock().await.record(AuditEntry {
+            user: identity.as_ref().map(|i: &Identity| i.user.clone()),
+            role: identity.as_ref().map(|i| i.role),
+            source: source.map(|s| s.to_string()).unwrap_or_default(),
+            method: method.to_string(),
+            path,
+            status: response.status().as_u16(),
+            timestamp: Local::now().to_rfc3339(),
+        });
+    }
+    response
+}
+
+/// A bearer token wins when one is sent; otherwise the trusted proxy must vouch for the
+/// caller. With neither configured every request is anonymous.
+fn identify(auth: &AuthConfig, source: Option<IpAddr>, headers: &HeaderMap) -> Result<Option<Identity>, AuthFailure> {
+    if let Some(jwt) = &auth.jwt {
+        if let Some(identity) = jwt.identify(headers)? {
+            return Ok(Some(identity));
+        }
+    }
+    match &auth.trusted_proxy {
+        Some(proxy) => {
+            let source = source.ok_or(AuthFailure::MissingUser)?;
+            proxy.identify(source, headers).map(Some)
+        }
+        None if auth.jwt.is_some() => Err(AuthFailure::MissingUser),
+        None => Ok(None),
+    }
+}
+
+fn refuse(failure: AuthFailure) -> Response {
+    match failure {
+        AuthFailure::UntrustedSource(source) => {
+            tracing::warn!("Refused request from {}, which is not a trusted proxy", source);
+            ApiError::Forbidden("Requests must come through the authenticating proxy".to_string()).into_response()
+        }
+        AuthFailure::MissingUser => ApiError::Unauthenticated.into_response(),
+        AuthFailure::NoRole(user) => {
+            tracing::warn!("{} has no role", user);
+            ApiError::Forbidden("No role is granted to you".to_string()).into_response()
+        }
+        AuthFailure::InvalidToken(reason) => {
+            tracing::warn!("Rejected bearer token: {}", reason);
+            ApiError::Unauthenticated.into_response()
+        }
+    }
+}
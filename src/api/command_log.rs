@@ -0,0 +1,124 @@
+﻿use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, MatchedPath, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Local;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use crate::{auth::Identity, command_log::CommandRecord, error::ApiError, state::AppState};
+
+/// Control actions, by method and matched path, recorded in the command log
+const CONTROL_ROUTES: &[(&str, &str)] = &[
+    ("GET", "/"),
+    ("POST", "/api/v1/fireplace/control"),
+    ("POST", "/api/v1/fireplace/pulse"),
+    ("POST", "/api/v1/scenes/:name/activate"),
+    ("POST", "/api/v1/emergency_stop"),
+    ("POST", "/api/v1/unlock"),
+    ("POST", "/api/v1/faults/reset"),
+    ("POST", "/api/v1/selftest"),
+];
+
+/// Control requests and their responses are small; anything bigger isn't one
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// What a control request or its response says about the device and action
+#[derive(Default)]
+struct Target {
+    room: Option<String>,
+    device: Option<String>,
+    pin: Option<u32>,
+    action: Option<String>,
+}
+
+impl Target {
+    /// Read the fields from a JSON request or response body
+    fn from_json(value: &Value) -> Self {
+        let text = |key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+        Self {
+            room: text("room"),
+            device: text("device"),
+            pin: value.get("pin").and_then(Value::as_u64).map(|pin| pin as u32),
+            action: text("action"),
+        }
+    }
+
+    /// Read the fields from a legacy query string
+    fn from_legacy_query(query: &HashMap<String, String>) -> Self {
+        Self {
+            pin: query.get("m_PIN").and_then(|pin| pin.parse().ok()),
+            action: query.get("cmdAction").map(|action| action.to_uppercase()),
+            ..Self::default()
+        }
+    }
+
+    /// Fill gaps from another source; the response knows resolved names, the request what was asked
+    fn or(self, other: Self) -> Self {
+        Self {
+            room: self.room.or(other.room),
+            device: self.device.or(other.device),
+            pin: self.pin.or(other.pin),
+            action: self.action.or(other.action),
+        }
+    }
+}
+
+/// Middleware recording every control request, with who sent it and how it ended, in the
+/// persistent command log
+pub async fn record_commands(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let route = request.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string());
+    let method = request.method().as_str();
+    if !CONTROL_ROUTES.iter().any(|(m, p)| *m == method && Some(*p) == route.as_deref()) {
+        return next.run(request).await;
+    }
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|c| c.0.ip().to_string())
+        .unwrap_or_default();
+    let user = request.extensions().get::<Identity>().map(|i| i.user.clone());
+
+    // Buffer the body so the target can be read and the handler still gets it
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_BODY_BYTES).await else {
+        return ApiError::InvalidQuery("Request body too large".to_string()).into_response();
+    };
+    let asked = match Query::<HashMap<String, String>>::try_from_uri(&parts.uri) {
+        Ok(Query(query)) if path == "/" => Target::from_legacy_query(&query),
+        _ => serde_json::from_slice(&bytes).map(|v| Target::from_json(&v)).unwrap_or_default(),
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+
+    let (parts, body) = response.into_parts();
+    let bytes = to_bytes(body, MAX_BODY_BYTES).await.unwrap_or_default();
+    let body: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    let (target, result) = if parts.status == StatusCode::ACCEPTED {
+        (Target::from_json(&body).or(asked), "accepted".to_string())
+    } else if parts.status.is_success() {
+        (Target::from_json(&body).or(asked), "ok".to_string())
+    } else {
+        let message = body.get("message").and_then(Value::as_str).unwrap_or("failed");
+        (asked, message.to_string())
+    };
+
+    state.command_log.lock().await.append(CommandRecord {
+        timestamp: Local::now(),
+        client,
+        user,
+        endpoint: path,
+        room: target.room,
+        device: target.device,
+        pin: target.pin,
+        action: target.action,
+        status: parts.status.as_u16(),
+        result,
+    });
+    Response::from_parts(parts, Body::from(bytes))
+}
@@ -0,0 +1,11 @@
+﻿use axum::response::Html;
+
+/// The dashboard page, compiled into the binary so the Pi needs no static files
+const DASHBOARD: &str = include_str!("../../assets/dashboard.html");
+
+/// Serve the built-in web dashboard
+///
+/// The page is public; the API calls it makes are authenticated as usual.
+pub async fn handle_dashboard() -> Html<&'static str> {
+    Html(DASHBOARD)
+}
@@ -0,0 +1,71 @@
+﻿use axum::{
+    extract::{MatchedPath, Request},
+    http::{HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+
+/// Metadata for a route clients should migrate off
+#[derive(Debug, Serialize)]
+pub struct DeprecatedRoute {
+    pub method: &'static str,
+    pub path: &'static str,
+    /// Date (YYYY-MM-DD) the route was deprecated
+    pub deprecated_since: &'static str,
+    /// Date (YYYY-MM-DD) after which the route may be removed
+    pub sunset: Option<&'static str>,
+    /// Route to use instead
+    pub replacement: Option<&'static str>,
+    pub note: &'static str,
+}
+
+/// Routes that are deprecated. Add an entry here when an endpoint is renamed or superseded;
+/// responses from it then carry Deprecation/Sunset/Link headers.
+pub const DEPRECATED_ROUTES: &[DeprecatedRoute] = &[];
+
+/// Look up the deprecation metadata for a method and matched route path
+pub fn find(method: &Method, path: &str) -> Option<&'static DeprecatedRoute> {
+    DEPRECATED_ROUTES
+        .iter()
+        .find(|r| r.method.eq_ignore_ascii_case(method.as_str()) && r.path == path)
+}
+
+/// Middleware attaching RFC 9745 Deprecation, RFC 8594 Sunset and successor Link headers
+pub async fn add_deprecation_headers(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| find(request.method(), path.as_str()));
+
+    let mut response = next.run(request).await;
+
+    if let Some(route) = route {
+        let headers = response.headers_mut();
+
+        if let Some(since) = parse_date(route.deprecated_since) {
+            if let Ok(value) = HeaderValue::from_str(&format!("@{}", since.timestamp())) {
+                headers.insert("deprecation", value);
+            }
+        }
+        if let Some(sunset) = route.sunset.and_then(parse_date) {
+            let http_date = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            if let Ok(value) = HeaderValue::from_str(&http_date) {
+                headers.insert("sunset", value);
+            }
+        }
+        if let Some(replacement) = route.replacement {
+            if let Ok(value) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", replacement)) {
+                headers.insert("link", value);
+            }
+        }
+    }
+
+    response
+}
+
+fn parse_date(date: &str) -> Option<chrono::DateTime<Utc>> {
+    let midnight = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0)?;
+    Some(Utc.from_utc_datetime(&midnight))
+}
@@ -1,20 +1,170 @@
 ﻿use axum::{
-    extract::{Query, State, Json},
-    http::StatusCode,
+    extract::{Extension, Path, Query, State, Json},
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
 };
-use chrono::Local;
+use futures::Stream;
+use std::convert::Infallible;
+use chrono::{DateTime, Local};
+use std::time::Duration;
+use tokio::sync::broadcast;
 use crate::{
+    aliases::DeviceAliases,
+    auth::Identity,
     api::models::*,
+    canary::{self, CanaryStatus},
+    commands::{self, Command, Progress},
+    config::{Capability, Config, DeviceConfig, DeviceGroup, DeviceKind, GroupPolicy, OutputMode, Zone},
     error::{ApiError, Result},
-    state::AppState,
+    failover::{self, FailoverStatus, Heartbeat},
+    fireplace,
+    gpio::{GpioController, PinState},
+    graph::DeviceGraph,
+    lockout::{self, LockReason},
+    rules::{Rule, RuleStatus},
+    scenes::{Scene, SceneStatus},
+    sequence::Step,
+    scheduler::{self, Schedule, ScheduleState, ScheduleStatus},
+    sensor_history,
+    simulation::{self, SimulationReport},
+    state::{AppState, ChangeSource},
+    thermostat::{self, ThermostatStatus},
+    timers,
+    usage,
 };
+use uuid::Uuid;
+
+/// Note control actions taken while the controller is running on battery
+async fn annotate_on_battery(state: &AppState, pin: u32) {
+    if state.power.read().await.on_battery() {
+        tracing::warn!("Pin {} changed while running on battery power", pin);
+    }
+}
+
+/// Resolve a requested toggle count, treating a missing or zero count as one toggle
+fn validate_cycles(config: &Config, cycles: Option<u32>) -> Result<u32> {
+    let max = config.safety.max_cycles;
+    let cycles = cycles.filter(|c| *c != 0).unwrap_or(1);
+    if cycles > max {
+        return Err(ApiError::InvalidCycles(max));
+    }
+    Ok(cycles)
+}
+
+/// Refuse control of a device that is latched in fault
+async fn check_fault(state: &AppState, room: &str, device: &str) -> Result<()> {
+    if state.faults.read().await.get(room, device).is_some() {
+        return Err(ApiError::DeviceFaulted {
+            room: room.to_string(),
+            device: device.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Whether a device, or any member of a group, is a fireplace
+pub fn involves_fireplace(config: &Config, zone: Zone, device: &str) -> bool {
+    let is_fireplace = |pin: u32| config.find_pin(pin).is_some_and(|(_, d)| d.kind == DeviceKind::Fireplace);
+    match config.group(zone.name, device) {
+        Some(group) => group.pins.iter().any(|pin| is_fireplace(*pin)),
+        None => zone.device(device).is_some_and(|d| d.kind == DeviceKind::Fireplace),
+    }
+}
+
+/// Under `safety.require_confirmation` an ON for a fireplace only goes ahead when it carries
+/// the token an earlier request for the same device was given. Returns the confirmation to
+/// send back when the request has to wait for one; `token_field` names the token parameter.
+pub async fn check_confirmation(
+    state: &AppState,
+    config: &Config,
+    zone: Zone<'_>,
+    device: &str,
+    action: &str,
+    token: Option<&str>,
+    token_field: &str,
+) -> Result<Option<ConfirmationResponse>> {
+    if !config.safety.require_confirmation
+        || !action.eq_ignore_ascii_case("ON")
+        || !involves_fireplace(config, zone, device)
+    {
+        return Ok(None);
+    }
+
+    let mut confirmations = state.confirmations.lock().await;
+    if let Some(token) = token {
+        if !confirmations.confirm(token, zone.name, device) {
+            return Err(ApiError::InvalidConfirmation);
+        }
+        tracing::info!("ON for {}/{} confirmed", zone.name, device);
+        return Ok(None);
+    }
+
+    let timeout = config.safety.confirmation_timeout;
+    let (token, expires) = confirmations.issue(zone.name, device, timeout);
+    tracing::info!("Holding ON for {}/{} until confirmed", zone.name, device);
+    Ok(Some(ConfirmationResponse {
+        confirmation_required: true,
+        confirmation_token: token,
+        expires_at: expires.to_rfc3339(),
+        room: zone.name.to_string(),
+        device: device.to_string(),
+        action: "ON".to_string(),
+        message: format!(
+            "Repeat the request with {} set to this token within {}",
+            token_field,
+            humantime::format_duration(timeout)
+        ),
+    }))
+}
+
+/// Refuse to switch a device again within its `min_dwell` of the last change. Takes the
+/// locked controller so two requests can't both pass the check.
+fn check_dwell(gpio: &GpioController, device: &DeviceConfig) -> Result<()> {
+    if device.min_dwell.is_zero() {
+        return Ok(());
+    }
+    let Some(changed) = gpio.last_changed(device.pin) else {
+        return Ok(());
+    };
+    let elapsed = (Local::now() - changed).to_std().unwrap_or_default();
+    if elapsed < device.min_dwell {
+        return Err(ApiError::DwellTime {
+            device: device.name.clone(),
+            retry_after: device.min_dwell - elapsed,
+        });
+    }
+    Ok(())
+}
+
+/// Refuse to turn a fireplace back on while it is cooling after being turned off; gas
+/// valves wear from rapid cycling
+async fn check_short_cycle(state: &AppState, room: &str, device: &DeviceConfig, on: bool) -> Result<()> {
+    if !on || device.kind != DeviceKind::Fireplace {
+        return Ok(());
+    }
+    state.fireplaces.read().await.check_ignite(room, &device.name)
+}
+
+/// Whether a request left a device on. Toggled relays flip whatever was asked, so their
+/// pin is the truth; a momentary contact's pin is back low by now.
+fn switched_on(gpio: &GpioController, device: &DeviceConfig, action: &str) -> bool {
+    match device.mode {
+        OutputMode::Pulse => action == "ON",
+        OutputMode::Toggle | OutputMode::Latch => gpio.get_pin_state(device.pin) == PinState::High,
+    }
+}
+
 
 /// Handle legacy GPIO endpoint (backward compatible)
 pub async fn handle_legacy_gpio(
     Query(req): Query<LegacyGpioRequest>,
     State(state): State<AppState>,
-) -> Result<Json<ApiResponse>> {
+) -> Result<Response> {
     tracing::debug!("Legacy GPIO request: {:?}", req);
+    let config = state.config.load_full();
 
     // Validate command type
     if req.cmd_type.to_lowercase() != "toggle" {
@@ -27,35 +177,180 @@ pub async fn handle_legacy_gpio(
         return Err(ApiError::InvalidAction);
     }
 
-    // Get the GPIO pin and execute the toggle
+    // n_CYCLE repeats the toggle for stubborn RF igniters; 0 means a single toggle
+    let cycles = validate_cycles(&config, req.n_cycle)?;
+
+    state.lock.read().await.check(ChangeSource::Legacy)?;
+
+    // The Python API fired a momentary output alongside the main pin; 0 means none. The
+    // pulse switches its device on, so it gets the same checks, and a fireplace, which has
+    // to go through ignition, can't be pulsed this way.
+    let pulse_pin = req.m_pulse_pin.filter(|p| *p != 0);
+    let pulse_owner = pulse_pin.and_then(|p| config.find_pin(p));
+    if let Some((zone, device)) = pulse_owner {
+        if device.kind == DeviceKind::Fireplace {
+            return Err(ApiError::InvalidQuery(format!(
+                "m_pulsePIN {} is a fireplace; switch it with m_PIN",
+                device.pin
+            )));
+        }
+        check_fault(&state, zone.name, &device.name).await?;
+        state.cooldowns.read().await.check(zone.name, &device.name, "ON")?;
+    }
+
+    // Refuse to drive a device that is latched in fault
     let pin = req.m_pin;
-    let mut gpio = state.gpio_controller.lock().await;
-    gpio.toggle_pin(pin).await?;
+    let owner = config.find_pin(pin);
+    if let Some((zone, device)) = owner {
+        check_fault(&state, zone.name, &device.name).await?;
+        state.cooldowns.read().await.check(zone.name, &device.name, &action_upper)?;
 
-    let device_name = state.config.get_pin_name(pin);
+        let token = req.confirm_token.as_deref();
+        if let Some(pending) =
+            check_confirmation(&state, &config, zone, &device.name, &action_upper, token, "confirmToken").await?
+        {
+            return Ok((StatusCode::ACCEPTED, Json(pending)).into_response());
+        }
+    }
+
+    // Get the GPIO pin and execute the toggle
+    let pins: Vec<u32> = std::iter::once(pin).chain(pulse_pin).collect();
+    let mut gpio = state.gpio_controller.lock(ChangeSource::Legacy, &pins).await;
+    if let Some((zone, device)) = owner {
+        check_dwell(&gpio, device)?;
+        check_short_cycle(&state, zone.name, device, action_upper == "ON").await?;
+        state.cutoffs.read().await.check(zone.name, device, action_upper == "ON")?;
+    }
+    if let Some((zone, device)) = pulse_owner {
+        check_dwell(&gpio, device)?;
+        check_short_cycle(&state, zone.name, device, true).await?;
+        state.cutoffs.read().await.check(zone.name, device, true)?;
+    }
+    gpio.cycle_pin(pin, cycles, config.safety.cycle_delay).await?;
+    annotate_on_battery(&state, pin).await;
+
+    let mut duration_ms = None;
+    if let Some(pulse_pin) = pulse_pin {
+        let duration = config.safety.pulse_duration;
+        gpio.pulse_pin(pulse_pin, duration).await?;
+        duration_ms = Some(duration.as_millis() as u32);
+    }
+
+    // Confirm via m_monPIN, or the configured monitor pin for a room's fireplace
+    let monitor_pin = match req.m_mon_pin.filter(|p| *p != 0) {
+        Some(monitor_pin) => Some(monitor_pin),
+        None => owner.and_then(|(_, device)| device.monitor),
+    };
+    let verified = match (monitor_pin, owner) {
+        (_, Some((zone, device))) if device.kind == DeviceKind::Fireplace => {
+            let on = switched_on(&gpio, device, &action_upper);
+            fireplace::settle(&state, &config, &mut gpio, zone.name, device, on, monitor_pin, &Progress::none())
+                .await?
+        }
+        (Some(monitor_pin), _) => Some(gpio.verify_pin(pin, monitor_pin).await?),
+        (None, _) => None,
+    };
+    // A write that reads back as written is verified when no monitor pin says otherwise
+    let attempts = gpio.attempts(pin);
+    let verified = verified.or(attempts.map(|_| true));
 
     Ok(Json(ApiResponse {
         success: true,
         action: action_upper,
         pin,
-        device: device_name,
+        room: owner.map(|(zone, _)| zone.name.to_string()),
+        device: owner.map(|(_, device)| device.name.clone()),
+        pins: None,
+        pulse_pin,
+        duration_ms,
+        cycles: (cycles > 1).then_some(cycles),
+        verified,
+        attempts,
+        timer: None,
+        config_generation: config.generation,
         timestamp: Local::now().to_rfc3339(),
-    }))
+    })
+    .into_response())
 }
 
 /// Handle modern fireplace control endpoint
 pub async fn handle_fireplace_control(
     State(state): State<AppState>,
     Json(req): Json<FireplaceControlRequest>,
-) -> Result<Json<ApiResponse>> {
+) -> Result<Response> {
     tracing::debug!("Fireplace control request: {:?}", req);
+    let req = resolve_alias(&state, req).await;
+    state.lock.read().await.check(req.source)?;
+    let config = state.config.load_full();
 
-    // Determine which PIN to control
-    let pin = match req.device.to_lowercase().as_str() {
-        "fireplace" => state.config.pins.fireplace,
-        "fan" => state.config.pins.fireplace_fan,
-        _ => return Err(ApiError::InvalidPin),
-    };
+    // An ON for a fireplace may have to wait for a confirming request
+    if let Ok(zone) = config.zone(req.room.as_deref()) {
+        let token = req.confirmation_token.as_deref();
+        if let Some(pending) =
+            check_confirmation(&state, &config, zone, &req.device, &req.action, token, "confirmation_token").await?
+        {
+            return Ok((StatusCode::ACCEPTED, Json(pending)).into_response());
+        }
+    }
+
+    if req.run_async {
+        // Reject requests for unknown targets now rather than as a failed command
+        let zone = config.zone(req.room.as_deref())?;
+        if config.device_pins(zone.name, &req.device).is_none() {
+            return Err(ApiError::UnknownDevice(req.device));
+        }
+
+        let command = commands::submit(&state, req).await;
+        let location = format!("/api/v1/commands/{}", command.id);
+        return Ok((StatusCode::ACCEPTED, [(header::LOCATION, location)], Json(command)).into_response());
+    }
+
+    Ok(Json(run_control(&state, req, &Progress::none()).await?).into_response())
+}
+
+/// Swap a device nickname or alias for the configured device name
+async fn resolve_alias(state: &AppState, mut req: FireplaceControlRequest) -> FireplaceControlRequest {
+    let config = state.config.load_full();
+    if let Ok(zone) = config.zone(req.room.as_deref()) {
+        if let Some(device) = state.aliases.read().await.resolve(zone.name, &req.device) {
+            tracing::debug!("Resolved alias '{}' to '{}'", req.device, device);
+            req.device = device;
+        }
+    }
+    req
+}
+
+/// Execute a control request, reporting each step to `progress`
+pub async fn run_control(
+    state: &AppState,
+    req: FireplaceControlRequest,
+    progress: &Progress,
+) -> Result<ApiResponse> {
+    canary::observe(state, &req).await;
+    state.lock.read().await.check(req.source)?;
+    let config = state.config.load_full();
+
+    // An automatic OFF only makes sense when turning something on
+    if let Some(minutes) = req.duration_minutes {
+        if minutes == 0 || !req.action.eq_ignore_ascii_case("ON") {
+            return Err(ApiError::InvalidTimerDuration);
+        }
+    }
+
+    // Determine which room and PIN to control
+    let zone = config.zone(req.room.as_deref())?;
+    if let Some(group) = config.group(zone.name, &req.device) {
+        return control_group(state, zone.name, group, &req, &config, progress).await;
+    }
+    let device = zone
+        .device(&req.device)
+        .ok_or_else(|| ApiError::UnknownDevice(req.device.clone()))?;
+    let pin = device.pin;
+
+    // A momentary contact has no OFF a timer could drive
+    if req.duration_minutes.is_some() && !device.capabilities().contains(&Capability::Timer) {
+        return Err(ApiError::InvalidTimerDuration);
+    }
 
     // Validate action
     let action_upper = req.action.to_uppercase();
@@ -63,15 +358,327 @@ pub async fn handle_fireplace_control(
         return Err(ApiError::InvalidAction);
     }
 
-    // Execute the toggle
-    let mut gpio = state.gpio_controller.lock().await;
-    gpio.toggle_pin(pin).await?;
+    check_fault(state, zone.name, &device.name).await?;
+    state.cooldowns.read().await.check(zone.name, &device.name, &action_upper)?;
 
-    Ok(Json(ApiResponse {
+    // Only toggled relays repeat; latched and momentary ones act once
+    let cycles = match device.mode {
+        OutputMode::Toggle => validate_cycles(&config, req.cycles)?,
+        OutputMode::Latch | OutputMode::Pulse => 1,
+    };
+    let cycle_delay = req
+        .cycle_delay_ms
+        .map(|ms| Duration::from_millis(ms as u64))
+        .unwrap_or(config.safety.cycle_delay);
+    if cycle_delay > config.safety.max_cycle_delay {
+        return Err(ApiError::InvalidCycleDelay(config.safety.max_cycle_delay.as_millis() as u32));
+    }
+
+    // Drive the relay the way the device is wired
+    let mut gpio = state.gpio_controller.lock(req.source, &[pin]).await;
+    check_dwell(&gpio, device)?;
+    check_short_cycle(state, zone.name, device, action_upper == "ON").await?;
+    state.cutoffs.read().await.check(zone.name, device, action_upper == "ON")?;
+
+    // A toggle, or a press of a fireplace's contact, flips whatever the device is doing, so
+    // one already as asked is left alone rather than switched the other way
+    let current = match device.mode {
+        OutputMode::Toggle => match gpio.get_pin_state(pin) {
+            PinState::High => Some(true),
+            PinState::Low => Some(false),
+            PinState::Unknown => None,
+        },
+        OutputMode::Pulse if device.kind == DeviceKind::Fireplace => Some(fireplace::is_on(state, &gpio, pin).await),
+        OutputMode::Latch | OutputMode::Pulse => None,
+    };
+    let unchanged = current == Some(action_upper == "ON");
+    match device.mode {
+        _ if unchanged => {
+            progress.step(format!("{} is already {}, leaving it", device.name, action_upper)).await;
+        }
+        OutputMode::Toggle => {
+            progress
+                .step(format!(
+                    "Toggling pin {} ({} cycle(s), {} apart)",
+                    pin,
+                    cycles,
+                    humantime::format_duration(cycle_delay)
+                ))
+                .await;
+            gpio.cycle_pin(pin, cycles, cycle_delay).await?;
+        }
+        OutputMode::Latch => {
+            progress.step(format!("Switching pin {} {}", pin, action_upper)).await;
+            gpio.set_pin(pin, action_upper == "ON").await?;
+        }
+        OutputMode::Pulse => {
+            let duration = config.safety.pulse_duration;
+            progress.step(format!("Pulsing pin {} for {}", pin, humantime::format_duration(duration))).await;
+            gpio.pulse_pin(pin, duration).await?;
+        }
+    }
+    annotate_on_battery(state, pin).await;
+
+    // Step a fireplace through ignition, confirming it through its monitor pin if one is wired
+    let verified = if device.kind == DeviceKind::Fireplace && !unchanged {
+        let on = switched_on(&gpio, device, &action_upper);
+        fireplace::settle(state, &config, &mut gpio, zone.name, device, on, device.monitor, progress).await?
+    } else {
+        None
+    };
+    let attempts = gpio.attempts(pin);
+    let verified = verified.or(attempts.map(|_| true));
+    drop(gpio);
+
+    // A new command supersedes any pending auto-off for this device
+    let timer = match req.duration_minutes {
+        Some(minutes) => Some(timers::schedule_off(state, zone.name, &req.device, vec![pin], minutes).await),
+        None => {
+            state.timers.lock().await.cancel_device(zone.name, &req.device);
+            None
+        }
+    };
+    if let Some(timer) = &timer {
+        progress.step(format!("Scheduled automatic OFF at {}", timer.fires_at)).await;
+    }
+
+    Ok(ApiResponse {
         success: true,
         action: action_upper,
         pin,
+        room: Some(zone.name.to_string()),
         device: Some(req.device),
+        pins: None,
+        pulse_pin: None,
+        duration_ms: None,
+        cycles: (cycles > 1).then_some(cycles),
+        verified,
+        attempts,
+        timer,
+        config_generation: config.generation,
+        timestamp: Local::now().to_rfc3339(),
+    })
+}
+
+/// Switch every pin of a device group according to its coordination policy
+async fn control_group(
+    state: &AppState,
+    room: &str,
+    group: &DeviceGroup,
+    req: &FireplaceControlRequest,
+    config: &Config,
+    progress: &Progress,
+) -> Result<ApiResponse> {
+    let action_upper = req.action.to_uppercase();
+    let on = match action_upper.as_str() {
+        "ON" => true,
+        "OFF" => false,
+        _ => return Err(ApiError::InvalidAction),
+    };
+    check_fault(state, room, &group.name).await?;
+    for pin in &group.pins {
+        if let Some((zone, device)) = config.find_pin(*pin) {
+            if req.duration_minutes.is_some() && !device.capabilities().contains(&Capability::Timer) {
+                return Err(ApiError::InvalidTimerDuration);
+            }
+            check_fault(state, zone.name, &device.name).await?;
+            state.cooldowns.read().await.check(zone.name, &device.name, &action_upper)?;
+        }
+    }
+
+    let stage_delay = match group.policy {
+        GroupPolicy::AllOn => Duration::ZERO,
+        GroupPolicy::Staged => group.stage_delay,
+    };
+
+    let mut gpio = state.gpio_controller.lock(req.source, &group.pins).await;
+    for pin in &group.pins {
+        if let Some((zone, device)) = config.find_pin(*pin) {
+            check_dwell(&gpio, device)?;
+            check_short_cycle(state, zone.name, device, on).await?;
+            state.cutoffs.read().await.check(zone.name, device, on)?;
+        }
+    }
+    progress
+        .step(format!(
+            "Switching pins {:?} {} ({} stage delay)",
+            group.pins,
+            action_upper,
+            humantime::format_duration(stage_delay)
+        ))
+        .await;
+    gpio.set_pins_staged(&group.pins, on, stage_delay).await?;
+    for pin in &group.pins {
+        annotate_on_battery(state, *pin).await;
+        if let Some((zone, device)) = config.find_pin(*pin).filter(|(_, d)| d.kind == DeviceKind::Fireplace) {
+            fireplace::settle(state, config, &mut gpio, zone.name, device, on, None, progress).await?;
+        }
+    }
+    let attempts = group.pins.iter().filter_map(|pin| gpio.attempts(*pin)).max();
+    drop(gpio);
+
+    let timer = match req.duration_minutes {
+        Some(minutes) => Some(timers::schedule_off(state, room, &group.name, group.pins.clone(), minutes).await),
+        None => {
+            state.timers.lock().await.cancel_device(room, &group.name);
+            None
+        }
+    };
+    if let Some(timer) = &timer {
+        progress.step(format!("Scheduled automatic OFF at {}", timer.fires_at)).await;
+    }
+
+    Ok(ApiResponse {
+        success: true,
+        action: action_upper,
+        pin: group.pins[0],
+        room: Some(room.to_string()),
+        device: Some(group.name.clone()),
+        pins: Some(group.pins.clone()),
+        pulse_pin: None,
+        duration_ms: None,
+        cycles: None,
+        verified: attempts.map(|_| true),
+        attempts,
+        timer,
+        config_generation: config.generation,
+        timestamp: Local::now().to_rfc3339(),
+    })
+}
+
+/// Handle momentary contact (pulse) endpoint
+pub async fn handle_fireplace_pulse(
+    State(state): State<AppState>,
+    Json(req): Json<FireplacePulseRequest>,
+) -> Result<Response> {
+    tracing::debug!("Fireplace pulse request: {:?}", req);
+    state.lock.read().await.check(ChangeSource::Api)?;
+    let config = state.config.load_full();
+
+    // Determine which room and PIN to pulse
+    let zone = config.zone(req.room.as_deref())?;
+    let device = zone
+        .device(&req.device)
+        .ok_or_else(|| ApiError::UnknownDevice(req.device.clone()))?;
+    let pin = device.pin;
+
+    check_fault(&state, zone.name, &device.name).await?;
+
+    // Validate duration against the safety limit
+    let max = config.safety.max_pulse_duration;
+    let duration = req
+        .duration_ms
+        .map(|ms| Duration::from_millis(ms as u64))
+        .unwrap_or(config.safety.pulse_duration);
+    if duration.is_zero() || duration > max {
+        return Err(ApiError::InvalidPulseDuration(max.as_millis() as u32));
+    }
+
+    // Pulsing a fireplace lights it, so it may have to wait for a confirming request
+    let token = req.confirmation_token.as_deref();
+    if let Some(pending) =
+        check_confirmation(&state, &config, zone, &device.name, "ON", token, "confirmation_token").await?
+    {
+        return Ok((StatusCode::ACCEPTED, Json(pending)).into_response());
+    }
+
+    // Execute the pulse
+    let mut gpio = state.gpio_controller.lock(ChangeSource::Api, &[pin]).await;
+    check_dwell(&gpio, device)?;
+    // A pulse drives the pin high, so it counts as switching on
+    check_short_cycle(&state, zone.name, device, true).await?;
+    state.cutoffs.read().await.check(zone.name, device, true)?;
+    gpio.pulse_pin(pin, duration).await?;
+    annotate_on_battery(&state, pin).await;
+
+    // A pulse is an ignition press whatever the relay's mode. The pin is back low by now,
+    // so it says nothing about the flame and must not start a cooldown.
+    let verified = if device.kind == DeviceKind::Fireplace {
+        fireplace::settle(&state, &config, &mut gpio, zone.name, device, true, device.monitor, &Progress::none()).await?
+    } else {
+        None
+    };
+    let attempts = gpio.attempts(pin);
+
+    Ok(Json(ApiResponse {
+        success: true,
+        action: "PULSE".to_string(),
+        pin,
+        room: Some(zone.name.to_string()),
+        device: Some(req.device),
+        pins: None,
+        pulse_pin: None,
+        duration_ms: Some(duration.as_millis() as u32),
+        cycles: None,
+        verified: verified.or(attempts.map(|_| true)),
+        attempts,
+        timer: None,
+        config_generation: config.generation,
+        timestamp: Local::now().to_rfc3339(),
+    })
+    .into_response())
+}
+
+/// Drive every output off, cancel timers and schedules, and lock control until unlocked.
+/// Anything left undone, such as a device not confirmed off, makes it a 500.
+pub async fn handle_emergency_stop(
+    State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Response> {
+    let stop = lockout::emergency_stop(&state, identity.map(|Extension(i)| i.user)).await?;
+
+    let status = if stop.errors.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let response = EmergencyStopResponse {
+        success: stop.errors.is_empty(),
+        stop,
+        timestamp: Local::now().to_rfc3339(),
+    };
+    Ok((status, Json(response)).into_response())
+}
+
+/// Whether control is locked, and why
+pub async fn handle_get_lock(
+    State(state): State<AppState>,
+) -> Result<Json<LockResponse>> {
+    let lock = state.lock.read().await.current().cloned();
+
+    Ok(Json(LockResponse {
+        locked: lock.is_some(),
+        lock,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+/// Lock control requests, e.g. while guests are staying; status endpoints keep working
+pub async fn handle_lock(
+    State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<LockResponse>> {
+    let by = identity.map(|Extension(i)| i.user);
+    let lock = state.lock.write().await.engage(LockReason::ChildLock, by)?;
+    tracing::warn!("Control locked ({:?})", lock.reason);
+
+    Ok(Json(LockResponse {
+        locked: true,
+        lock: Some(lock),
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+/// Release the control lock, whether set by POST /api/v1/lock or an emergency stop
+pub async fn handle_unlock(
+    State(state): State<AppState>,
+) -> Result<Json<UnlockResponse>> {
+    let released = state.lock.write().await.release()?;
+    tracing::warn!("Control unlocked");
+
+    Ok(Json(UnlockResponse {
+        success: true,
+        released,
         timestamp: Local::now().to_rfc3339(),
     }))
 }
@@ -80,12 +687,663 @@ pub async fn handle_fireplace_control(
 pub async fn handle_gpio_status(
     State(state): State<AppState>,
 ) -> Result<Json<StatusResponse>> {
-    let gpio = state.gpio_controller.lock().await;
+    let config = state.config.load_full();
+    let gpio = &state.gpio_controller;
     let pins = gpio.get_all_pin_states();
 
+    // Remaining auto-off time for every fireplace that is on
+    let mut safety_timers = Vec::new();
+    if let Some(max_runtime) = config.safety.max_runtime {
+        let mut timer = state.safety_timer.lock().await;
+        for zone in config.zones() {
+            for fireplace in zone.of_kind(DeviceKind::Fireplace) {
+                let pin = fireplace.pin;
+                timer.observe(pin, gpio.get_pin_state(pin) == PinState::High);
+                safety_timers.extend(timer.status(zone.name, pin, max_runtime));
+            }
+        }
+    }
+
+    // Report each group as one device; pins that disagree show as Unknown
+    let groups = config
+        .groups
+        .iter()
+        .map(|group| {
+            let state = gpio.combined_state(&group.pins);
+            GroupStatus {
+                name: group.name.clone(),
+                room: config
+                    .zone(group.room.as_deref())
+                    .map_or_else(|_| config.room.name.clone(), |z| z.name.to_string()),
+                pins: group.pins.clone(),
+                state,
+            }
+        })
+        .collect();
+
     Ok(Json(StatusResponse {
-        room: state.config.room.name.clone(),
+        room: config.room.name.clone(),
         pins,
+        groups,
+        safety_timers,
+        cooldowns: state.cooldowns.read().await.list(),
+        idle_off: state.idle_timers.read().await.list(&config),
+        fireplaces: state.fireplaces.read().await.list(),
+        over_temperature: state.cutoffs.read().await.list(),
+        config_generation: config.generation,
+    }))
+}
+
+/// Report the progress of an asynchronous control command
+pub async fn handle_get_command(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<Json<Command>> {
+    state
+        .commands
+        .lock()
+        .await
+        .get(id)
+        .map(Json)
+        .ok_or(ApiError::CommandNotFound)
+}
+
+/// List device nicknames and aliases
+pub async fn handle_list_aliases(
+    State(state): State<AppState>,
+) -> Result<Json<AliasesResponse>> {
+    Ok(Json(AliasesResponse {
+        aliases: state.aliases.read().await.list(),
+    }))
+}
+
+/// Set a device's nickname and aliases, replacing any it had
+pub async fn handle_set_aliases(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Json(req): Json<AliasRequest>,
+) -> Result<Json<DeviceAliases>> {
+    let config = state.config.load_full();
+    let zone = config.zone(req.room.as_deref())?;
+
+    let entry = DeviceAliases {
+        room: zone.name.to_string(),
+        device: name,
+        nickname: req.nickname,
+        aliases: req.aliases,
+    };
+    Ok(Json(state.aliases.write().await.set(&config, entry)?))
+}
+
+/// Remove a device's nickname and aliases
+pub async fn handle_delete_aliases(
+    Path(name): Path<String>,
+    Query(query): Query<AliasQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<DeviceAliases>> {
+    let config = state.config.load_full();
+    let zone = config.zone(query.room.as_deref())?;
+    Ok(Json(state.aliases.write().await.remove(zone.name, &name)?))
+}
+
+/// Dependency graph of devices, groups and inputs, for visualization
+pub async fn handle_device_graph(
+    State(state): State<AppState>,
+) -> Result<Json<DeviceGraph>> {
+    let config = state.config.load_full();
+    Ok(Json(DeviceGraph::build(&config)))
+}
+
+/// Every configured device with its capabilities and current state, for generated UIs
+pub async fn handle_list_devices(
+    State(state): State<AppState>,
+) -> Result<Json<DevicesResponse>> {
+    let config = state.config.load_full();
+    Ok(Json(DevicesResponse {
+        devices: device_infos(&state, &config).await,
+        config_generation: config.generation,
+    }))
+}
+
+/// Every configured device in every room, with its nickname, aliases and pin state
+async fn device_infos(state: &AppState, config: &Config) -> Vec<DeviceInfo> {
+    let gpio = &state.gpio_controller;
+    let aliases = state.aliases.read().await;
+
+    config
+        .zones()
+        .flat_map(|zone| zone.devices.iter().map(move |device| (zone, device)))
+        .map(|(zone, device)| {
+            let entry = aliases.get(zone.name, &device.name);
+            DeviceInfo {
+                room: zone.name.to_string(),
+                name: device.name.clone(),
+                kind: device.kind,
+                pin: device.pin,
+                mode: device.mode,
+                capabilities: device.capabilities(),
+                nickname: entry.and_then(|e| e.nickname.clone()),
+                aliases: entry.map(|e| e.aliases.clone()).unwrap_or_default(),
+                state: gpio.get_pin_state(device.pin),
+            }
+        })
+        .collect()
+}
+
+/// State, last change and on-time of a single device
+pub async fn handle_device_status(
+    Path(name): Path<String>,
+    Query(query): Query<DeviceStatusQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<DeviceStatusResponse>> {
+    let config = state.config.load_full();
+    let zone = config.zone(query.room.as_deref())?;
+    let name = state.aliases.read().await.resolve(zone.name, &name).unwrap_or(name);
+    let device = zone.device(&name).ok_or(ApiError::UnknownDevice(name))?;
+
+    let now = Local::now();
+    let pin_state = state.gpio_controller.get_pin_state(device.pin);
+    let usage = state.usage.read().await;
+    let last_change = usage.last_change(device.pin);
+
+    Ok(Json(DeviceStatusResponse {
+        room: zone.name.to_string(),
+        device: device.name.clone(),
+        kind: device.kind,
+        pin: device.pin,
+        state: pin_state,
+        last_change: last_change.map(|(at, _)| at.to_rfc3339()),
+        source: last_change.map(|(_, source)| source),
+        on_seconds: usage.on_seconds(device.pin, now),
+        on_seconds_today: usage.on_seconds_today(device.pin, now),
+        cooldown_until: state
+            .cooldowns
+            .read()
+            .await
+            .get(zone.name, &device.name)
+            .map(|cooldown| cooldown.until.to_rfc3339()),
+        fireplace_state: match device.kind {
+            DeviceKind::Fireplace => Some(state.fireplaces.read().await.status(zone.name, &device.name).state),
+            _ => None,
+        },
+        config_generation: config.generation,
+        timestamp: now.to_rfc3339(),
+    }))
+}
+
+/// The most recent on/off sessions of a single device, newest first
+pub async fn handle_device_sessions(
+    Path(name): Path<String>,
+    Query(query): Query<SessionsQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<SessionsResponse>> {
+    let config = state.config.load_full();
+    let zone = config.zone(query.room.as_deref())?;
+    let name = state.aliases.read().await.resolve(zone.name, &name).unwrap_or(name);
+    let device = zone.device(&name).ok_or(ApiError::UnknownDevice(name))?;
+
+    let now = Local::now();
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    let sessions = state
+        .history
+        .read()
+        .await
+        .recent(device.pin, limit)
+        .into_iter()
+        .map(|session| SessionSummary {
+            start: session.start.to_rfc3339(),
+            end: session.end.map(|end| end.to_rfc3339()),
+            duration_seconds: session.duration_seconds(now),
+            started_by: session.started_by,
+            stopped_by: session.stopped_by,
+            verified: session.verified,
+            interrupted: session.interrupted,
+        })
+        .collect();
+
+    Ok(Json(SessionsResponse {
+        room: zone.name.to_string(),
+        device: device.name.clone(),
+        pin: device.pin,
+        sessions,
+        config_generation: config.generation,
+    }))
+}
+
+/// On-time of every device today, this week and this heating season, across restarts
+pub async fn handle_stats(
+    Query(query): Query<StatsQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<StatsResponse>> {
+    let config = state.config.load_full();
+    let zones = match query.room.as_deref() {
+        Some(room) => vec![config.zone(Some(room))?],
+        None => config.zones().collect(),
+    };
+
+    let now = Local::now();
+    let today = now.date_naive();
+    let week_start = usage::start_of_week(today);
+    let season_start = usage::start_of_season(today, config.stats.season_start().unwrap_or((9, 1)));
+    let hours = |seconds: i64| (seconds as f64 / 36.0).round() / 100.0;
+
+    let usage = state.usage.read().await;
+    let devices = zones
+        .iter()
+        .flat_map(|zone| zone.devices.iter().map(move |device| (zone.name, device)))
+        .map(|(room, device)| DeviceStats {
+            room: room.to_string(),
+            device: device.name.clone(),
+            kind: device.kind,
+            pin: device.pin,
+            hours_today: hours(usage.on_seconds_since(device.pin, today, now)),
+            hours_this_week: hours(usage.on_seconds_since(device.pin, week_start, now)),
+            hours_this_season: hours(usage.on_seconds_since(device.pin, season_start, now)),
+        })
+        .collect();
+
+    Ok(Json(StatsResponse {
+        devices,
+        week_start: week_start.to_string(),
+        season_start: season_start.to_string(),
+        timestamp: now.to_rfc3339(),
+    }))
+}
+
+/// Stream one device's state as Server-Sent Events: the current state, then every change
+pub async fn handle_watch_device(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<WatchQuery>,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>> {
+    let config = state.config.load_full();
+    let room = config.zone(query.room.as_deref())?.name.to_string();
+    if config.device_pins(&room, &name).is_none() {
+        return Err(ApiError::UnknownDevice(name));
+    }
+    tracing::debug!("Watching {}/{}", room, name);
+
+    // Re-check the device on every bus event and emit only when its state changes.
+    // Subscribe before the first read so no change slips in between.
+    let events = state.events.subscribe();
+    let stream = futures::stream::unfold((events, None), move |(mut events, last): (_, Option<DeviceState>)| {
+        let state = state.clone();
+        let room = room.clone();
+        let name = name.clone();
+        async move {
+            loop {
+                if last.is_some() {
+                    match events.recv().await {
+                        // A lagging watcher just re-reads the current state
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+                // Follow config reloads; a device that disappears reads as Unknown
+                let config = state.config.load();
+                let pins = config.device_pins(&room, &name).unwrap_or_default();
+                let current = state.gpio_controller.combined_state(&pins);
+                let unchanged = |l: &DeviceState| {
+                    l.state == current && l.pins == pins && l.config_generation == config.generation
+                };
+                if last.as_ref().is_some_and(unchanged) {
+                    continue;
+                }
+
+                let update = DeviceState {
+                    room: room.clone(),
+                    device: name.clone(),
+                    pins,
+                    state: current,
+                    config_generation: config.generation,
+                    timestamp: Local::now().to_rfc3339(),
+                };
+                let event = Event::default()
+                    .event("state")
+                    .json_data(&update)
+                    .unwrap_or_else(|_| Event::default().comment("encoding error"));
+                return Some((Ok(event), (events, Some(update))));
+            }
+        }
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// List pending automatic OFF timers
+pub async fn handle_list_timers(
+    State(state): State<AppState>,
+) -> Result<Json<TimersResponse>> {
+    Ok(Json(TimersResponse {
+        timers: state.timers.lock().await.list(),
+    }))
+}
+
+/// Cancel a pending automatic OFF timer
+pub async fn handle_cancel_timer(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<Json<TimerCancelResponse>> {
+    let timer = state
+        .timers
+        .lock()
+        .await
+        .cancel(id)
+        .ok_or(ApiError::TimerNotFound)?;
+
+    Ok(Json(TimerCancelResponse {
+        success: true,
+        timer,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+/// List recurring schedules
+pub async fn handle_list_schedules(
+    State(state): State<AppState>,
+) -> Result<Json<SchedulesResponse>> {
+    Ok(Json(SchedulesResponse {
+        schedules: state.scheduler.lock().await.list(),
+    }))
+}
+
+/// Create a recurring schedule
+pub async fn handle_create_schedule(
+    State(state): State<AppState>,
+    Json(req): Json<ScheduleRequest>,
+) -> Result<Json<ScheduleStatus>> {
+    let status = scheduler::upsert(&state, schedule_from_request(Uuid::new_v4(), req)).await?;
+    tracing::info!("Schedule {} created", status.schedule.id);
+    Ok(Json(status))
+}
+
+/// Get one recurring schedule
+pub async fn handle_get_schedule(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<Json<ScheduleStatus>> {
+    state
+        .scheduler
+        .lock()
+        .await
+        .get(id)
+        .map(Json)
+        .ok_or(ApiError::ScheduleNotFound)
+}
+
+/// Replace a recurring schedule
+pub async fn handle_update_schedule(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Json(req): Json<ScheduleRequest>,
+) -> Result<Json<ScheduleStatus>> {
+    if state.scheduler.lock().await.get(id).is_none() {
+        return Err(ApiError::ScheduleNotFound);
+    }
+    let status = scheduler::upsert(&state, schedule_from_request(id, req)).await?;
+    tracing::info!("Schedule {} updated", id);
+    Ok(Json(status))
+}
+
+/// Delete a recurring schedule
+pub async fn handle_delete_schedule(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<Json<ScheduleDeleteResponse>> {
+    let schedule = scheduler::remove(&state, id).await?;
+    tracing::info!("Schedule {} deleted", id);
+
+    Ok(Json(ScheduleDeleteResponse {
+        success: true,
+        schedule,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+fn schedule_from_request(id: Uuid, req: ScheduleRequest) -> Schedule {
+    Schedule {
+        id,
+        name: req.name,
+        cron: req.cron,
+        action: req.action.to_uppercase(),
+        device: req.device,
+        room: req.room,
+        duration_minutes: req.duration_minutes,
+        enabled: req.enabled.unwrap_or(true),
+        missed_run: req.missed_run,
+        state: ScheduleState::default(),
+    }
+}
+
+/// List scenes, from the config and created through the API
+pub async fn handle_list_scenes(State(state): State<AppState>) -> Result<Json<ScenesResponse>> {
+    let config = state.config.load_full();
+    Ok(Json(ScenesResponse {
+        scenes: state.scenes.read().await.list(&config),
+    }))
+}
+
+/// Create a scene
+pub async fn handle_create_scene(
+    State(state): State<AppState>,
+    Json(req): Json<SceneRequest>,
+) -> Result<Json<SceneStatus>> {
+    let Some(name) = req.name.clone() else {
+        return Err(ApiError::InvalidScene("Scene name is required".to_string()));
+    };
+    let config = state.config.load_full();
+    let status = state.scenes.write().await.save_scene(&config, scene_from_request(name, req), false)?;
+    tracing::info!("Scene {} created", status.scene.name);
+    Ok(Json(status))
+}
+
+/// Get one scene
+pub async fn handle_get_scene(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<SceneStatus>> {
+    let config = state.config.load_full();
+    state
+        .scenes
+        .read()
+        .await
+        .get(&config, &name)
+        .map(Json)
+        .ok_or(ApiError::SceneNotFound(name))
+}
+
+/// Replace a scene created through the API
+pub async fn handle_update_scene(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Json(req): Json<SceneRequest>,
+) -> Result<Json<SceneStatus>> {
+    let config = state.config.load_full();
+    let status = state.scenes.write().await.save_scene(&config, scene_from_request(name, req), true)?;
+    tracing::info!("Scene {} updated", status.scene.name);
+    Ok(Json(status))
+}
+
+/// Delete a scene created through the API
+pub async fn handle_delete_scene(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<SceneDeleteResponse>> {
+    let config = state.config.load_full();
+    let scene = state.scenes.write().await.remove(&config, &name)?;
+    tracing::info!("Scene {} deleted", scene.name);
+
+    Ok(Json(SceneDeleteResponse {
+        success: true,
+        scene,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+/// Run a scene's steps in the background, returning a command receipt to follow it by
+pub async fn handle_activate_scene(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    req: Option<Json<SceneActivateRequest>>,
+) -> Result<Response> {
+    let req = req.map(|Json(req)| req).unwrap_or_default();
+    state.lock.read().await.check(ChangeSource::Scene)?;
+    let config = state.config.load_full();
+    let scene = state
+        .scenes
+        .read()
+        .await
+        .get(&config, &name)
+        .ok_or(ApiError::SceneNotFound(name))?
+        .scene;
+    // An API scene may refer to a device a config reload has since removed
+    scene.validate(&config)?;
+
+    // The first fireplace the scene lights stands in for the whole scene
+    for (device, action, room) in scene.controls() {
+        let zone = config.zone(room)?;
+        if action.eq_ignore_ascii_case("ON") && involves_fireplace(&config, zone, device) {
+            let token = req.confirmation_token.as_deref();
+            if let Some(pending) =
+                check_confirmation(&state, &config, zone, device, action, token, "confirmation_token").await?
+            {
+                return Ok((StatusCode::ACCEPTED, Json(pending)).into_response());
+            }
+            break;
+        }
+    }
+
+    let command = commands::submit_scene(&state, scene).await;
+    let location = format!("/api/v1/commands/{}", command.id);
+    Ok((StatusCode::ACCEPTED, [(header::LOCATION, location)], Json(command)).into_response())
+}
+
+fn scene_from_request(name: String, req: SceneRequest) -> Scene {
+    let normalize = |steps: Vec<Step>| -> Vec<Step> {
+        steps
+            .into_iter()
+            .map(|step| match step {
+                Step::Control { device, action, room } => Step::Control {
+                    device,
+                    action: action.to_uppercase(),
+                    room,
+                },
+                step => step,
+            })
+            .collect()
+    };
+    Scene {
+        name,
+        room: req.room,
+        steps: normalize(req.steps),
+        on_failure: normalize(req.on_failure),
+    }
+}
+
+/// List automation rules, from the config and created through the API
+pub async fn handle_list_rules(State(state): State<AppState>) -> Result<Json<RulesResponse>> {
+    let config = state.config.load_full();
+    Ok(Json(RulesResponse {
+        rules: state.rules.read().await.list(&config),
+    }))
+}
+
+/// Create an automation rule
+pub async fn handle_create_rule(
+    State(state): State<AppState>,
+    Json(req): Json<RuleRequest>,
+) -> Result<Json<RuleStatus>> {
+    let Some(name) = req.name.clone() else {
+        return Err(ApiError::InvalidRule("Rule name is required".to_string()));
+    };
+    let config = state.config.load_full();
+    let status = state.rules.write().await.save_rule(&config, rule_from_request(name, req), false)?;
+    tracing::info!("Rule {} created", status.rule.name);
+    Ok(Json(status))
+}
+
+/// Get one automation rule and its last run
+pub async fn handle_get_rule(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<RuleStatus>> {
+    let config = state.config.load_full();
+    state
+        .rules
+        .read()
+        .await
+        .get(&config, &name)
+        .map(Json)
+        .ok_or(ApiError::RuleNotFound(name))
+}
+
+/// Replace an automation rule created through the API
+pub async fn handle_update_rule(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Json(req): Json<RuleRequest>,
+) -> Result<Json<RuleStatus>> {
+    let config = state.config.load_full();
+    let status = state.rules.write().await.save_rule(&config, rule_from_request(name, req), true)?;
+    tracing::info!("Rule {} updated", status.rule.name);
+    Ok(Json(status))
+}
+
+/// Delete an automation rule created through the API
+pub async fn handle_delete_rule(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<RuleDeleteResponse>> {
+    let config = state.config.load_full();
+    let rule = state.rules.write().await.remove(&config, &name)?;
+    tracing::info!("Rule {} deleted", rule.name);
+
+    Ok(Json(RuleDeleteResponse {
+        success: true,
+        rule,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+fn rule_from_request(name: String, req: RuleRequest) -> Rule {
+    Rule {
+        name,
+        enabled: req.enabled.unwrap_or(true),
+        room: req.room,
+        trigger: req.trigger,
+        conditions: req.conditions,
+        actions: req.actions,
+    }
+}
+
+/// Restart the auto-off safety timer for a room's fireplace
+pub async fn handle_reset_safety_timer(
+    State(state): State<AppState>,
+    Json(req): Json<SafetyTimerResetRequest>,
+) -> Result<Json<SafetyTimerResetResponse>> {
+    let config = state.config.load_full();
+    let zone = config.zone(req.room.as_deref())?;
+    let max_runtime = config.safety.max_runtime.ok_or(ApiError::NoSafetyTimer)?;
+
+    // Restart the clock of the room's fireplace that is on
+    let gpio = &state.gpio_controller;
+    let mut timer = state.safety_timer.lock().await;
+    let pin = zone
+        .of_kind(DeviceKind::Fireplace)
+        .map(|fireplace| fireplace.pin)
+        .find(|pin| {
+            timer.observe(*pin, gpio.get_pin_state(*pin) == PinState::High);
+            timer.reset(*pin)
+        })
+        .ok_or(ApiError::NoSafetyTimer)?;
+    tracing::info!("Safety timer for {} reset", zone.name);
+
+    Ok(Json(SafetyTimerResetResponse {
+        success: true,
+        timer: timer
+            .status(zone.name, pin, max_runtime)
+            .ok_or(ApiError::NoSafetyTimer)?,
+        timestamp: Local::now().to_rfc3339(),
     }))
 }
 
@@ -93,40 +1351,402 @@ pub async fn handle_gpio_status(
 pub async fn handle_get_config(
     State(state): State<AppState>,
 ) -> Result<Json<ConfigResponse>> {
-    let config = &state.config;
+    let config = state.config.load_full();
 
     Ok(Json(ConfigResponse {
         room: config.room.name.clone(),
-        pins: serde_json::to_value(&config.pins)
-            .map_err(|_| ApiError::InternalError)?,
-        safety: serde_json::to_value(&config.safety)
-            .map_err(|_| ApiError::InternalError)?,
+        devices: config.redacted(&config.devices)?,
+        rooms: config.redacted(&config.rooms)?,
+        groups: config.redacted(&config.groups)?,
+        safety: config.redacted(&config.safety)?,
+        config_generation: config.generation,
     }))
 }
 
 /// Reload configuration from file
 pub async fn handle_reload_config(
-    State(_state): State<AppState>,
-) -> Result<(StatusCode, Json<serde_json::Value>)> {
-    // In a real implementation, this would reload from the config file
-    // For now, just acknowledge the request
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<ReloadResponse>)> {
     tracing::info!("Configuration reload requested");
 
+    let changed = state.reload_config().await?;
+
     Ok((
         StatusCode::OK,
-        Json(serde_json::json!({
-            "success": true,
-            "message": "Configuration reload requested",
-            "timestamp": Local::now().to_rfc3339(),
-        })),
+        Json(ReloadResponse {
+            success: true,
+            changed,
+            config_generation: state.config.load().generation,
+            timestamp: Local::now().to_rfc3339(),
+        }),
     ))
 }
 
+/// Push a config to run in shadow; the body is the config file as TOML
+pub async fn handle_start_canary(
+    State(state): State<AppState>,
+    body: String,
+) -> Result<(StatusCode, Json<CanaryStatus>)> {
+    tracing::info!("Canary config pushed");
+
+    let status = canary::start(&state, body).await?;
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+/// Shadow results of the running canary config
+pub async fn handle_get_canary(
+    State(state): State<AppState>,
+) -> Result<Json<CanaryStatus>> {
+    let canary = state.canary.lock().await;
+    let canary = canary.as_ref().ok_or(ApiError::NoCanary)?;
+    Ok(Json(canary.status()))
+}
+
+/// Make the canary config live once its shadow period is over
+pub async fn handle_promote_canary(
+    State(state): State<AppState>,
+) -> Result<Json<ReloadResponse>> {
+    let changed = canary::promote(&state).await?;
+
+    Ok(Json(ReloadResponse {
+        success: true,
+        changed,
+        config_generation: state.config.load().generation,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+/// Discard the canary config
+pub async fn handle_abort_canary(
+    State(state): State<AppState>,
+) -> Result<StatusCode> {
+    canary::abort(&state).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// This node's role in its failover pair and the health of its peer
+pub async fn handle_failover_status(
+    State(state): State<AppState>,
+) -> Result<Json<FailoverStatus>> {
+    let config = state.config.load_full();
+    Ok(Json(state.failover.read().await.status(config.failover.as_ref())))
+}
+
+/// Accept a heartbeat from the failover peer and answer with this node's own
+pub async fn handle_failover_heartbeat(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(peer): Json<Heartbeat>,
+) -> Result<Json<Heartbeat>> {
+    let config = state.config.load_full();
+    let failover = config.failover.as_ref().ok_or(ApiError::FailoverNotConfigured)?;
+    failover::authorize(failover, &headers)?;
+
+    failover::receive(&state, failover, peer).await;
+    Ok(Json(failover::heartbeat(&state, failover).await))
+}
+
+/// Latest reading of every temperature sensor
+pub async fn handle_list_sensors(
+    State(state): State<AppState>,
+) -> Result<Json<SensorsResponse>> {
+    let config = state.config.load_full();
+    Ok(Json(SensorsResponse {
+        sensors: state.sensors.read().await.list(&config),
+    }))
+}
+
+/// The debounced state of every `[[inputs]]` pin
+pub async fn handle_list_inputs(
+    State(state): State<AppState>,
+) -> Result<Json<InputsResponse>> {
+    let config = state.config.load_full();
+    Ok(Json(InputsResponse {
+        inputs: state.inputs.read().await.list(&config),
+    }))
+}
+
+/// Stop a room's idle countdown, leaving its devices on until it sees motion again
+pub async fn handle_cancel_idle_off(
+    Path(input): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<IdleCancelResponse>> {
+    let config = state.config.load_full();
+    let countdown = state
+        .idle_timers
+        .write()
+        .await
+        .cancel(&config, &input)
+        .ok_or(ApiError::IdleCountdownNotFound(input))?;
+
+    Ok(Json(IdleCancelResponse {
+        success: true,
+        countdown,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+/// A sensor's readings over a time range, averaged for charting
+pub async fn handle_sensor_history(
+    Path(id): Path<String>,
+    Query(query): Query<SensorHistoryQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<SensorHistoryResponse>> {
+    let config = state.config.load_full();
+    let parse = |name: &str, value: Option<&str>, default: &str| {
+        let value = value.unwrap_or(default);
+        humantime::parse_duration(value)
+            .map_err(|e| ApiError::InvalidQuery(format!("Invalid {} '{}': {}", name, value, e)))
+    };
+    let range = parse("range", query.range.as_deref(), "24h")?;
+    let resolution = parse("resolution", query.resolution.as_deref(), "5m")?;
+    if resolution < Duration::from_secs(60) {
+        return Err(ApiError::InvalidQuery("Invalid resolution. Expected at least 1m".to_string()));
+    }
+    if range.as_secs() / resolution.as_secs() > sensor_history::MAX_POINTS {
+        return Err(ApiError::InvalidQuery(format!(
+            "Range '{}' at resolution '{}' is more than {} points. Use a coarser resolution",
+            humantime::format_duration(range),
+            humantime::format_duration(resolution),
+            sensor_history::MAX_POINTS
+        )));
+    }
+
+    let points = sensor_history::history(&state, &config, &id, range, resolution).await?;
+    let sensor = config.sensor(&id).ok_or_else(|| ApiError::UnknownSensor(id.clone()))?;
+    Ok(Json(SensorHistoryResponse {
+        sensor: sensor.id.clone(),
+        room: sensor.room(&config).to_string(),
+        range: humantime::format_duration(range).to_string(),
+        resolution: humantime::format_duration(resolution).to_string(),
+        points,
+    }))
+}
+
+/// Thermostat mode, setpoint and the room temperature it is working from
+pub async fn handle_get_thermostat(
+    State(state): State<AppState>,
+) -> Result<Json<ThermostatStatus>> {
+    Ok(Json(thermostat::status(&state).await?))
+}
+
+/// Change the thermostat mode and/or setpoint
+pub async fn handle_set_thermostat(
+    State(state): State<AppState>,
+    Json(req): Json<ThermostatRequest>,
+) -> Result<Json<ThermostatStatus>> {
+    Ok(Json(thermostat::update(&state, req.mode, req.setpoint_c).await?))
+}
+
+/// List devices latched in fault
+pub async fn handle_list_faults(
+    State(state): State<AppState>,
+) -> Result<Json<FaultsResponse>> {
+    Ok(Json(FaultsResponse {
+        faults: state.faults.read().await.all(),
+    }))
+}
+
+/// Manually reset a latched fault
+pub async fn handle_reset_fault(
+    State(state): State<AppState>,
+    Json(req): Json<FaultResetRequest>,
+) -> Result<Json<FaultResetResponse>> {
+    let config = state.config.load_full();
+    let zone = config.zone(req.room.as_deref())?;
+    let device = &zone
+        .device(&req.device)
+        .ok_or_else(|| ApiError::UnknownDevice(req.device.clone()))?
+        .name;
+
+    let was_faulted = state.faults.write().await.reset(zone.name, device).is_some();
+
+    Ok(Json(FaultResetResponse {
+        success: true,
+        room: zone.name.to_string(),
+        device: req.device,
+        was_faulted,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
+
+/// Get system status (power source)
+pub async fn handle_system_status(
+    State(state): State<AppState>,
+) -> Result<Json<SystemResponse>> {
+    let config = state.config.load_full();
+    let power = state.power.read().await.clone();
+
+    Ok(Json(SystemResponse {
+        room: config.room.name.clone(),
+        power_monitored: config.power.is_some(),
+        power,
+        lock: state.lock.read().await.current().cloned(),
+    }))
+}
+
+/// Serve recent log lines from the in-memory ring buffer
+pub async fn handle_admin_logs(
+    Query(query): Query<LogQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<LogsResponse>> {
+    let level = match query.level.as_deref() {
+        Some(level) => level.parse().map_err(|_| ApiError::InvalidLogLevel)?,
+        None => tracing::Level::TRACE,
+    };
+    let lines = query.lines.unwrap_or(100);
+
+    Ok(Json(LogsResponse {
+        capacity: state.logs.capacity(),
+        entries: state.logs.recent(lines, level),
+    }))
+}
+
+/// Recent state-changing requests and who made them
+pub async fn handle_admin_audit(
+    Query(query): Query<AuditQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<AuditResponse>> {
+    Ok(Json(AuditResponse {
+        entries: state.audit.lock().await.recent(query.limit.unwrap_or(100)),
+    }))
+}
+
+/// Control requests from the persistent command log, newest first, a page at a time
+pub async fn handle_command_history(
+    Query(query): Query<HistoryQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<HistoryResponse>> {
+    let since = query
+        .since
+        .as_deref()
+        .map(|since| {
+            DateTime::parse_from_rfc3339(since)
+                .map(|t| t.with_timezone(&Local))
+                .map_err(|e| ApiError::InvalidQuery(format!("Invalid since '{}': {}", since, e)))
+        })
+        .transpose()?;
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    // Requests name a device by name, kind or alias, so match on the pins it drives too
+    let config = state.config.load_full();
+    let pins: Vec<u32> = match &query.device {
+        Some(device) => config
+            .zones()
+            .filter_map(|zone| config.device_pins(zone.name, device))
+            .flatten()
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let (total, entries) = state.command_log.lock().await.query(
+        query.device.as_deref().map(|device| (device, pins.as_slice())),
+        since,
+        offset,
+        limit,
+    );
+    let next_offset = Some(offset + entries.len()).filter(|next| *next < total);
+    Ok(Json(HistoryResponse { entries, total, next_offset }))
+}
+
+/// Check the GPIO backend, the configured pins and monitor inputs, and optionally
+/// switch a spare test pin
+pub async fn handle_selftest(
+    State(state): State<AppState>,
+    req: Option<Json<SelfTestRequest>>,
+) -> Result<Json<crate::selftest::SelfTestReport>> {
+    let req = req.map(|Json(req)| req).unwrap_or_default();
+    Ok(Json(crate::selftest::run(&state, req.test_pin).await?))
+}
+
+/// Replay schedules, timers, the safety watchdog and load shedding in simulated time
+pub async fn handle_simulate(
+    State(state): State<AppState>,
+    Json(req): Json<SimulationRequest>,
+) -> Result<Json<SimulationReport>> {
+    let config = state.config.load_full();
+    let start = match req.start.as_deref() {
+        Some(start) => simulation::parse_time(start)?,
+        None => Local::now(),
+    };
+
+    let schedules: Vec<Schedule> = match req.schedules {
+        Some(requests) => {
+            let schedules: Vec<Schedule> = requests
+                .into_iter()
+                .map(|r| schedule_from_request(Uuid::new_v4(), r))
+                .collect();
+            for schedule in &schedules {
+                schedule.validate(&config)?;
+            }
+            schedules
+        }
+        None => state.scheduler.lock().await.list().into_iter().map(|s| s.schedule).collect(),
+    };
+
+    let report = simulation::run(
+        &config,
+        &schedules,
+        start,
+        req.hours.unwrap_or(12),
+        &req.initial_high,
+        &req.power_trace,
+    )?;
+    Ok(Json(report))
+}
+
+/// Describe the API: its routes, the configured devices and which integrations are enabled
+pub async fn handle_api_index(State(state): State<AppState>) -> Json<ApiIndexResponse> {
+    let config = state.config.load_full();
+    Json(ApiIndexResponse {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        room: config.room.name.clone(),
+        config_generation: config.generation,
+        endpoints: crate::api::index::ENDPOINTS,
+        devices: device_infos(&state, &config).await,
+        integrations: Integrations {
+            mqtt: config.mqtt.is_some(),
+            telegram: config.telegram.is_some(),
+            notifications: config.notifications.is_some(),
+            webhooks: !config.webhooks.is_empty(),
+            syslog: config.logging.syslog.is_some(),
+            failover: config.failover.is_some(),
+            power_monitor: config.power.is_some(),
+            sensors: !config.sensors.is_empty(),
+            // There is no HomeKit accessory server in this build
+            homekit: false,
+        },
+    })
+}
+
+/// List deprecated routes clients should migrate off
+pub async fn handle_deprecations() -> Json<DeprecationsResponse> {
+    Json(DeprecationsResponse {
+        deprecations: crate::api::deprecation::DEPRECATED_ROUTES,
+    })
+}
+
 /// Health check endpoint
-pub async fn handle_health() -> Json<HealthResponse> {
+pub async fn handle_health(
+    State(state): State<AppState>,
+) -> Json<HealthResponse> {
+    let over_temperature = state.cutoffs.read().await.list();
+    let gpio = state.gpio_controller.backend_health();
+    let status = if !over_temperature.is_empty() {
+        "over_temperature"
+    } else if !gpio.healthy {
+        "degraded"
+    } else {
+        "healthy"
+    };
     Json(HealthResponse {
-        status: "healthy".to_string(),
-        version: "1.0.0".to_string(),
-        uptime_ms: 0, // Could track actual uptime
+        status: status.to_string(),
+        over_temperature,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: env!("GIT_HASH").to_string(),
+        uptime_ms: state.started_at.elapsed().as_millis() as u64,
+        gpio,
     })
 }
@@ -0,0 +1,80 @@
+﻿use serde::Serialize;
+
+/// A route listed by the API index
+#[derive(Debug, Serialize)]
+pub struct Endpoint {
+    pub method: &'static str,
+    /// Route path, with `:name` placeholders for path parameters
+    pub path: &'static str,
+    pub description: &'static str,
+}
+
+/// Every route the server exposes. Add an entry here when a route is added to the router.
+pub const ENDPOINTS: &[Endpoint] = &[
+    Endpoint { method: "GET", path: "/", description: "Legacy GPIO control (Python API compatible)" },
+    Endpoint { method: "GET", path: "/health", description: "Health check" },
+    Endpoint { method: "GET", path: "/ui", description: "Built-in web dashboard" },
+    Endpoint { method: "GET", path: "/api/v1", description: "This index" },
+    Endpoint { method: "GET", path: "/api/v1/setup", description: "First-boot setup status" },
+    Endpoint { method: "POST", path: "/api/v1/setup", description: "Submit the initial config" },
+    Endpoint { method: "POST", path: "/api/v1/fireplace/control", description: "Turn a device or group on or off" },
+    Endpoint { method: "POST", path: "/api/v1/fireplace/pulse", description: "Pulse a momentary-contact device" },
+    Endpoint { method: "GET", path: "/api/v1/commands/:id", description: "Progress of an asynchronous command" },
+    Endpoint { method: "GET", path: "/api/v1/gpio/status", description: "State of every pin" },
+    Endpoint { method: "GET", path: "/api/v1/config", description: "Current configuration" },
+    Endpoint { method: "POST", path: "/api/v1/config/reload", description: "Reload the config file" },
+    Endpoint { method: "GET", path: "/api/v1/config/canary", description: "Canary rollout status" },
+    Endpoint { method: "POST", path: "/api/v1/config/canary", description: "Start a canary rollout" },
+    Endpoint { method: "DELETE", path: "/api/v1/config/canary", description: "Abort a canary rollout" },
+    Endpoint { method: "POST", path: "/api/v1/config/canary/promote", description: "Promote the canary config" },
+    Endpoint { method: "GET", path: "/api/v1/system", description: "Room, power and lock status" },
+    Endpoint { method: "GET", path: "/api/v1/ws", description: "WebSocket stream of state changes" },
+    Endpoint { method: "GET", path: "/api/v1/devices", description: "Configured devices and their states" },
+    Endpoint { method: "GET", path: "/api/v1/devices/graph", description: "Device dependency graph" },
+    Endpoint { method: "GET", path: "/api/v1/devices/aliases", description: "Device nicknames and aliases" },
+    Endpoint { method: "PUT", path: "/api/v1/devices/:name/aliases", description: "Set a device's nickname and aliases" },
+    Endpoint { method: "DELETE", path: "/api/v1/devices/:name/aliases", description: "Clear a device's nickname and aliases" },
+    Endpoint { method: "GET", path: "/api/v1/devices/:name/status", description: "One device's state" },
+    Endpoint { method: "GET", path: "/api/v1/devices/:name/sessions", description: "One device's on/off sessions" },
+    Endpoint { method: "GET", path: "/api/v1/devices/:name/watch", description: "Server-sent events for one device" },
+    Endpoint { method: "GET", path: "/api/v1/stats", description: "Burn-hour statistics" },
+    Endpoint { method: "GET", path: "/api/v1/timers", description: "Pending auto-off timers" },
+    Endpoint { method: "DELETE", path: "/api/v1/timers/:id", description: "Cancel a timer" },
+    Endpoint { method: "GET", path: "/api/v1/schedules", description: "List schedules" },
+    Endpoint { method: "POST", path: "/api/v1/schedules", description: "Create a schedule" },
+    Endpoint { method: "GET", path: "/api/v1/schedules/:id", description: "Get a schedule" },
+    Endpoint { method: "PUT", path: "/api/v1/schedules/:id", description: "Update a schedule" },
+    Endpoint { method: "DELETE", path: "/api/v1/schedules/:id", description: "Delete a schedule" },
+    Endpoint { method: "GET", path: "/api/v1/scenes", description: "List scenes" },
+    Endpoint { method: "POST", path: "/api/v1/scenes", description: "Create a scene" },
+    Endpoint { method: "GET", path: "/api/v1/scenes/:name", description: "Get a scene" },
+    Endpoint { method: "PUT", path: "/api/v1/scenes/:name", description: "Update a scene" },
+    Endpoint { method: "DELETE", path: "/api/v1/scenes/:name", description: "Delete a scene" },
+    Endpoint { method: "POST", path: "/api/v1/scenes/:name/activate", description: "Run a scene" },
+    Endpoint { method: "GET", path: "/api/v1/rules", description: "List automation rules" },
+    Endpoint { method: "POST", path: "/api/v1/rules", description: "Create a rule" },
+    Endpoint { method: "GET", path: "/api/v1/rules/:name", description: "Get a rule" },
+    Endpoint { method: "PUT", path: "/api/v1/rules/:name", description: "Update a rule" },
+    Endpoint { method: "DELETE", path: "/api/v1/rules/:name", description: "Delete a rule" },
+    Endpoint { method: "POST", path: "/api/v1/emergency_stop", description: "Turn everything off and lock control" },
+    Endpoint { method: "GET", path: "/api/v1/lock", description: "Current control lock" },
+    Endpoint { method: "POST", path: "/api/v1/lock", description: "Engage the child lock" },
+    Endpoint { method: "POST", path: "/api/v1/unlock", description: "Release the control lock" },
+    Endpoint { method: "POST", path: "/api/v1/safety/timer/reset", description: "Restart the safety auto-off timer" },
+    Endpoint { method: "GET", path: "/api/v1/failover", description: "Failover pair status" },
+    Endpoint { method: "POST", path: "/api/v1/failover/heartbeat", description: "Heartbeat from the failover peer" },
+    Endpoint { method: "GET", path: "/api/v1/sensors", description: "Latest temperature sensor readings" },
+    Endpoint { method: "GET", path: "/api/v1/sensors/:id/history", description: "Downsampled sensor history" },
+    Endpoint { method: "GET", path: "/api/v1/inputs", description: "Debounced state of the input pins" },
+    Endpoint { method: "DELETE", path: "/api/v1/idle_off/:input", description: "Cancel a room's idle countdown" },
+    Endpoint { method: "GET", path: "/api/v1/thermostat", description: "Thermostat status" },
+    Endpoint { method: "POST", path: "/api/v1/thermostat", description: "Set the thermostat" },
+    Endpoint { method: "GET", path: "/api/v1/faults", description: "Latched ignition faults" },
+    Endpoint { method: "POST", path: "/api/v1/faults/reset", description: "Clear an ignition fault" },
+    Endpoint { method: "GET", path: "/api/v1/admin/logs", description: "Recent log lines" },
+    Endpoint { method: "GET", path: "/api/v1/admin/audit", description: "Audit log" },
+    Endpoint { method: "GET", path: "/api/v1/history", description: "Command history" },
+    Endpoint { method: "POST", path: "/api/v1/admin/simulate", description: "Simulate schedules" },
+    Endpoint { method: "GET", path: "/api/v1/deprecations", description: "Deprecated routes" },
+    Endpoint { method: "POST", path: "/api/v1/selftest", description: "Run the GPIO self-test" },
+];
@@ -1,2 +1,11 @@
-﻿pub mod handlers;
+﻿pub mod auth;
+pub mod command_log;
+pub mod dashboard;
+pub mod deprecation;
+pub mod handlers;
+pub mod index;
 pub mod models;
+pub mod problem;
+pub mod rate_limit;
+pub mod setup;
+pub mod ws;
@@ -10,6 +10,7 @@ pub struct LegacyGpioRequest {
     pub cmd_action: String,
     
     #[serde(rename = "v_ACTION")]
+    #[allow(dead_code)] // sent by the Python clients, superseded by cmdAction
     pub v_action: String,
     
     #[serde(rename = "m_PIN")]
@@ -23,43 +24,563 @@ pub struct LegacyGpioRequest {
     
     #[serde(rename = "n_CYCLE")]
     pub n_cycle: Option<u32>,
+
+    #[serde(rename = "confirmToken")]
+    pub confirm_token: Option<String>,
 }
 
 // Modern request model
 #[derive(Debug, Deserialize)]
 pub struct FireplaceControlRequest {
     pub action: String,      // ON or OFF
-    pub device: String,      // fireplace or fan
+    pub device: String,      // fireplace, fan, lights, secondary_device or a group
+    pub room: Option<String>, // optional room identifier
+    pub cycles: Option<u32>,         // repeat the toggle, defaults to 1
+    pub cycle_delay_ms: Option<u32>, // defaults to safety.cycle_delay
+    pub duration_minutes: Option<u32>, // turn back OFF automatically after this long
+    #[serde(default, rename = "async")]
+    pub run_async: bool, // return 202 with a command receipt instead of waiting
+    pub confirmation_token: Option<String>, // from the first request, under safety.require_confirmation
+    #[serde(skip)]
+    pub source: crate::state::ChangeSource, // set by internal callers such as the scheduler
+}
+
+// Recurring schedule request model
+#[derive(Debug, Deserialize)]
+pub struct ScheduleRequest {
+    pub name: Option<String>,
+    pub cron: String,          // e.g. "30 6 * * Mon-Fri"
+    pub action: String,        // ON or OFF
+    pub device: String,        // fireplace, fan, lights, secondary_device or a group
+    pub room: Option<String>,
+    pub duration_minutes: Option<u32>,
+    pub enabled: Option<bool>, // defaults to true
+    pub missed_run: Option<crate::config::MissedRunPolicy>, // defaults to scheduler.missed_run_policy
+}
+
+// Simulation request model
+#[derive(Debug, Deserialize)]
+pub struct SimulationRequest {
+    pub start: Option<String>,     // RFC 3339, defaults to now
+    pub hours: Option<u32>,        // defaults to 12
+    #[serde(default)]
+    pub initial_high: Vec<u32>,    // pins on at the start; the rest start off
+    #[serde(default)]
+    pub power_trace: Vec<crate::simulation::PowerSample>,
+    pub schedules: Option<Vec<ScheduleRequest>>, // defaults to the persisted schedules
+}
+
+// Device nickname/alias request model
+#[derive(Debug, Deserialize)]
+pub struct AliasRequest {
+    pub room: Option<String>,
+    pub nickname: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,      // e.g. ["blower"] for the fan
+}
+
+// Device alias query
+#[derive(Debug, Deserialize)]
+pub struct AliasQuery {
+    pub room: Option<String>,
+}
+
+// Audit log query
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub limit: Option<usize>,      // defaults to 100
+}
+
+// Command history query, e.g. ?device=fireplace&since=2026-01-24T00:00:00-05:00&limit=20
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub device: Option<String>,
+    pub since: Option<String>,     // RFC 3339
+    pub limit: Option<usize>,      // defaults to 50, at most 500
+    pub offset: Option<usize>,     // entries to skip, newest first
+}
+
+// Device status query
+#[derive(Debug, Deserialize)]
+pub struct DeviceStatusQuery {
+    pub room: Option<String>,
+}
+
+// Device sessions query
+#[derive(Debug, Deserialize)]
+pub struct SessionsQuery {
+    pub room: Option<String>,
+    pub limit: Option<usize>,      // defaults to 10, at most 100
+}
+
+// Burn-hour statistics query
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    pub room: Option<String>,      // defaults to every room
+}
+
+// Device watch query
+#[derive(Debug, Deserialize)]
+pub struct WatchQuery {
+    pub room: Option<String>,
+}
+
+// Momentary contact request model
+#[derive(Debug, Deserialize)]
+pub struct FireplacePulseRequest {
+    pub device: String,           // fireplace, fan, lights or secondary_device
+    pub room: Option<String>,     // optional room identifier
+    pub duration_ms: Option<u32>, // defaults to safety.pulse_duration
+    /// Token from a 202 response, confirming a pulse that lights a fireplace
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+}
+
+// Fault reset request model
+#[derive(Debug, Deserialize)]
+pub struct FaultResetRequest {
+    pub device: String,       // fireplace, fan, lights or secondary_device
     pub room: Option<String>, // optional room identifier
 }
 
+// Safety timer reset request model
+#[derive(Debug, Deserialize)]
+pub struct SafetyTimerResetRequest {
+    pub room: Option<String>, // optional room identifier
+}
+
+// Sensor history query, e.g. ?range=24h&resolution=5m
+#[derive(Debug, Deserialize)]
+pub struct SensorHistoryQuery {
+    pub range: Option<String>,      // defaults to 24h
+    pub resolution: Option<String>, // defaults to 5m, at least 1m
+}
+
+// Thermostat request model; omitted fields are left as they are
+#[derive(Debug, Deserialize)]
+pub struct ThermostatRequest {
+    pub mode: Option<crate::thermostat::ThermostatMode>, // "heat" or "off"
+    #[serde(alias = "setpoint")]
+    pub setpoint_c: Option<f64>,
+}
+
+// Admin log query, e.g. ?lines=500&level=debug
+#[derive(Debug, Deserialize)]
+pub struct LogQuery {
+    pub lines: Option<usize>,
+    pub level: Option<String>,
+}
+
 // Unified response model
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ApiResponse {
     pub success: bool,
     pub action: String,
     pub pin: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pins: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pulse_pin: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cycles: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+    /// Writes the pin took to latch, with `gpio.verify`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timer: Option<crate::timers::Timer>,
+    pub config_generation: u64,
     pub timestamp: String,
 }
 
+// ON held back until it is repeated with the token, under safety.require_confirmation
+#[derive(Debug, Serialize)]
+pub struct ConfirmationResponse {
+    pub confirmation_required: bool,
+    pub confirmation_token: String,
+    pub expires_at: String,
+    pub room: String,
+    pub device: String,
+    pub action: String,
+    pub message: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct HealthResponse {
     pub status: String,
     pub version: String,
+    /// Commit the binary was built from
+    pub git_hash: String,
     pub uptime_ms: u64,
+    pub gpio: crate::gpio::BackendHealth,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub over_temperature: Vec<crate::overheat::Cutoff>, // rooms held off by the high-temperature cutoff
 }
 
 #[derive(Debug, Serialize)]
 pub struct StatusResponse {
     pub room: String,
     pub pins: Vec<crate::gpio::PinStatus>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupStatus>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub safety_timers: Vec<crate::safety::SafetyTimerStatus>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cooldowns: Vec<crate::interlock::Cooldown>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub idle_off: Vec<crate::motion::IdleCountdown>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fireplaces: Vec<crate::fireplace::FireplaceStatus>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub over_temperature: Vec<crate::overheat::Cutoff>,
+    pub config_generation: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DevicesResponse {
+    pub devices: Vec<DeviceInfo>,
+    pub config_generation: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceInfo {
+    pub room: String,
+    pub name: String,
+    pub kind: crate::config::DeviceKind,
+    pub pin: u32,
+    pub mode: crate::config::OutputMode,
+    pub capabilities: Vec<crate::config::Capability>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    pub state: crate::gpio::PinState,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceStatusResponse {
+    pub room: String,
+    pub device: String,
+    pub kind: crate::config::DeviceKind,
+    pub pin: u32,
+    pub state: crate::gpio::PinState,
+    pub last_change: Option<String>, // null until it changes after startup
+    pub source: Option<crate::state::ChangeSource>,
+    pub on_seconds: i64,             // since startup
+    pub on_seconds_today: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooldown_until: Option<String>, // kept on by an interlock until then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fireplace_state: Option<crate::fireplace::FireplaceState>, // fireplaces only
+    pub config_generation: u64,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionsResponse {
+    pub room: String,
+    pub device: String,
+    pub pin: u32,
+    pub sessions: Vec<SessionSummary>, // newest first
+    pub config_generation: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    pub start: String,
+    pub end: Option<String>,         // null while still on, or if interrupted
+    pub duration_seconds: Option<i64>, // up to now while still on, null if interrupted
+    pub started_by: crate::state::ChangeSource,
+    pub stopped_by: Option<crate::state::ChangeSource>,
+    pub verified: Option<bool>,      // null unless a monitor pin checked ignition
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,           // the server stopped while it was on
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+    pub devices: Vec<DeviceStats>,
+    pub week_start: String,         // Monday of the current week
+    pub season_start: String,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceStats {
+    pub room: String,
+    pub device: String,
+    pub kind: crate::config::DeviceKind,
+    pub pin: u32,
+    pub hours_today: f64,
+    pub hours_this_week: f64,
+    pub hours_this_season: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupStatus {
+    pub name: String,
+    pub room: String,
+    pub pins: Vec<u32>,
+    pub state: crate::gpio::PinState, // Unknown when the pins disagree
 }
 
 #[derive(Debug, Serialize)]
 pub struct ConfigResponse {
     pub room: String,
-    pub pins: serde_json::Value,
+    pub devices: serde_json::Value,
+    pub rooms: serde_json::Value,
+    pub groups: serde_json::Value,
     pub safety: serde_json::Value,
+    pub config_generation: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReloadResponse {
+    pub success: bool,
+    pub changed: Vec<String>,
+    pub config_generation: u64,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SystemResponse {
+    pub room: String,
+    pub power_monitored: bool,
+    pub power: crate::power::PowerStatus,
+    pub lock: Option<crate::lockout::Lock>, // set while control is locked
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmergencyStopResponse {
+    pub success: bool,
+    #[serde(flatten)]
+    pub stop: crate::lockout::EmergencyStop,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LockResponse {
+    pub locked: bool,
+    pub lock: Option<crate::lockout::Lock>,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnlockResponse {
+    pub success: bool,
+    pub released: crate::lockout::Lock,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogsResponse {
+    pub capacity: usize,
+    pub entries: Vec<crate::logging::LogEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiIndexResponse {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub room: String,
+    pub config_generation: u64,
+    pub endpoints: &'static [crate::api::index::Endpoint],
+    pub devices: Vec<DeviceInfo>,
+    pub integrations: Integrations,
+}
+
+/// Which optional integrations are configured
+#[derive(Debug, Serialize)]
+pub struct Integrations {
+    pub mqtt: bool,
+    pub telegram: bool,
+    pub notifications: bool,
+    pub webhooks: bool,
+    pub syslog: bool,
+    pub failover: bool,
+    pub power_monitor: bool,
+    pub sensors: bool,
+    pub homekit: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeprecationsResponse {
+    pub deprecations: &'static [crate::api::deprecation::DeprecatedRoute],
+}
+
+#[derive(Debug, Serialize)]
+pub struct SensorsResponse {
+    pub sensors: Vec<crate::sensors::SensorStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IdleCancelResponse {
+    pub success: bool,
+    pub countdown: crate::motion::IdleCountdown,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InputsResponse {
+    pub inputs: Vec<crate::inputs::InputStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SensorHistoryResponse {
+    pub sensor: String,
+    pub room: String,
+    pub range: String,
+    pub resolution: String,
+    pub points: Vec<crate::sensor_history::HistoryPoint>, // oldest first
+}
+
+#[derive(Debug, Serialize)]
+pub struct FaultsResponse {
+    pub faults: Vec<crate::fault::Fault>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FaultResetResponse {
+    pub success: bool,
+    pub room: String,
+    pub device: String,
+    pub was_faulted: bool,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SafetyTimerResetResponse {
+    pub success: bool,
+    pub timer: crate::safety::SafetyTimerStatus,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditResponse {
+    pub entries: Vec<crate::audit::AuditEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryResponse {
+    pub entries: Vec<crate::command_log::CommandRecord>, // newest first
+    pub total: usize,
+    pub next_offset: Option<usize>,  // null on the last page
+}
+
+#[derive(Debug, Serialize)]
+pub struct AliasesResponse {
+    pub aliases: Vec<crate::aliases::DeviceAliases>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimersResponse {
+    pub timers: Vec<crate::timers::Timer>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimerCancelResponse {
+    pub success: bool,
+    pub timer: crate::timers::Timer,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchedulesResponse {
+    pub schedules: Vec<crate::scheduler::ScheduleStatus>,
+}
+
+// Scene request model; the name comes from the path on PUT
+#[derive(Debug, Deserialize)]
+pub struct SceneRequest {
+    pub name: Option<String>,
+    pub room: Option<String>,
+    pub steps: Vec<crate::sequence::Step>,
+    #[serde(default)]
+    pub on_failure: Vec<crate::sequence::Step>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SceneActivateRequest {
+    pub confirmation_token: Option<String>, // under safety.require_confirmation, for a scene that lights a fireplace
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SelfTestRequest {
+    pub test_pin: Option<u32>, // overrides selftest.test_pin
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScenesResponse {
+    pub scenes: Vec<crate::scenes::SceneStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SceneDeleteResponse {
+    pub success: bool,
+    pub scene: crate::scenes::Scene,
+    pub timestamp: String,
+}
+
+// Rule request model; the name comes from the path on PUT
+#[derive(Debug, Deserialize)]
+pub struct RuleRequest {
+    pub name: Option<String>,
+    pub enabled: Option<bool>, // defaults to true
+    pub room: Option<String>,
+    pub trigger: crate::rules::Trigger,
+    #[serde(default)]
+    pub conditions: Vec<crate::rules::Condition>,
+    pub actions: Vec<crate::rules::Action>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RulesResponse {
+    pub rules: Vec<crate::rules::RuleStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuleDeleteResponse {
+    pub success: bool,
+    pub rule: crate::rules::Rule,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduleDeleteResponse {
+    pub success: bool,
+    pub schedule: crate::scheduler::Schedule,
+    pub timestamp: String,
+}
+
+/// One device's state, as sent on watch streams
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceState {
+    pub room: String,
+    pub device: String,
+    pub pins: Vec<u32>,
+    pub state: crate::gpio::PinState,
+    pub config_generation: u64,
+    pub timestamp: String,
+}
+
+/// A pin state change, as sent on /api/v1/ws
+#[derive(Debug, Clone, Serialize)]
+pub struct PinEvent {
+    pub pin: u32,
+    pub state: crate::gpio::PinState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<crate::state::ChangeSource>, // absent in snapshots
+    pub config_generation: u64,
+    pub timestamp: String,
 }
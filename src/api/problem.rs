@@ -0,0 +1,75 @@
+﻿use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::json;
+
+use crate::error::ErrorInfo;
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Whether the client lists application/problem+json in Accept with a non-zero quality
+fn accepts_problem_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            media_type.eq_ignore_ascii_case(PROBLEM_JSON) && !refused
+        })
+}
+
+/// "DEVICE_LOCKED" -> "Device locked"
+fn title(code: &str) -> String {
+    let words = code.to_lowercase().replace('_', " ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => words,
+    }
+}
+
+/// Middleware re-rendering error responses as RFC 7807 problem details for clients that
+/// ask for application/problem+json. `code` and `details` are kept as extension members.
+pub async fn negotiate_problem_json(request: Request, next: Next) -> Response {
+    let wanted = accepts_problem_json(request.headers());
+    let instance = request.uri().path().to_string();
+
+    let mut response = next.run(request).await;
+    let Some(error) = response.extensions().get::<ErrorInfo>().cloned() else {
+        return response;
+    };
+    // Error bodies depend on Accept, so caches must key on it
+    response.headers_mut().append(header::VARY, HeaderValue::from_static("accept"));
+    if !wanted {
+        return response;
+    }
+
+    let (mut parts, _) = response.into_parts();
+    let mut problem = json!({
+        "type": format!("urn:fireplace-api:error:{}", error.code.to_lowercase().replace('_', "-")),
+        "title": title(error.code),
+        "status": parts.status.as_u16(),
+        "detail": error.message,
+        "instance": instance,
+        "code": error.code,
+    });
+    if let Some(details) = error.details {
+        problem["details"] = details;
+    }
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(problem.to_string()))
+}
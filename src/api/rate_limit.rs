@@ -0,0 +1,58 @@
+﻿use axum::{
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use crate::{auth::Identity, error::ApiError, state::AppState};
+
+/// Routes, by method and matched path, that drive outputs directly or set up something that
+/// will. Every one counts against the same per-client limit.
+const ACTUATING_ROUTES: &[(&str, &str)] = &[
+    ("GET", "/"),
+    ("POST", "/api/v1/fireplace/control"),
+    ("POST", "/api/v1/fireplace/pulse"),
+    ("POST", "/api/v1/scenes/:name/activate"),
+    ("POST", "/api/v1/emergency_stop"),
+    ("POST", "/api/v1/selftest"),
+    ("POST", "/api/v1/thermostat"),
+    ("POST", "/api/v1/schedules"),
+    ("PUT", "/api/v1/schedules/:id"),
+    ("DELETE", "/api/v1/schedules/:id"),
+];
+
+fn actuates(request: &Request) -> bool {
+    let Some(path) = request.extensions().get::<MatchedPath>() else {
+        return false;
+    };
+    let method = request.method().as_str();
+    ACTUATING_ROUTES.iter().any(|(m, p)| *m == method && *p == path.as_str())
+}
+
+/// Middleware limiting how many control requests each client may send, per `[rate_limit]`.
+/// Clients are told apart by their authenticated user, or else their address.
+pub async fn limit_control_requests(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let config = state.config.load_full();
+    let Some(limit) = config.rate_limit.as_ref() else {
+        return next.run(request).await;
+    };
+    if !actuates(&request) {
+        return next.run(request).await;
+    }
+
+    let client = match request.extensions().get::<Identity>() {
+        Some(identity) => format!("user:{}", identity.user),
+        None => request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|c| c.0.ip().to_string())
+            .unwrap_or_default(),
+    };
+    if let Err(retry_after) = state.rate_limiter.lock().await.check(&client, limit, Instant::now()) {
+        tracing::warn!("Rate limited control request from {}", client);
+        return ApiError::RateLimited { retry_after }.into_response();
+    }
+    next.run(request).await
+}
@@ -0,0 +1,111 @@
+﻿use axum::{
+    extract::{Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Local;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    api::models::ReloadResponse,
+    config::Config,
+    error::{ApiError, Result},
+    state::AppState,
+};
+
+/// Header carrying the one-time setup token
+pub const SETUP_TOKEN_HEADER: &str = "x-setup-token";
+
+/// Routes reachable while the server is waiting for its first config
+const SETUP_ROUTES: &[&str] = &["/health", "/api/v1/setup"];
+
+/// First-boot setup mode: active until an initial config is submitted
+pub struct SetupSession {
+    token: String,
+    started_at: String,
+}
+
+impl SetupSession {
+    pub fn new() -> Self {
+        Self {
+            token: Uuid::new_v4().simple().to_string(),
+            started_at: Local::now().to_rfc3339(),
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetupStatus {
+    pub setup_mode: bool,
+    pub since: Option<String>,
+    pub config_path: String,
+}
+
+/// Middleware refusing everything but setup and health checks until the server is configured
+pub async fn require_configured(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if state.setup.read().await.is_some() && !SETUP_ROUTES.contains(&request.uri().path()) {
+        return ApiError::SetupRequired.into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether the server is waiting for its first config
+pub async fn handle_setup_status(
+    State(state): State<AppState>,
+) -> Result<Json<SetupStatus>> {
+    let setup = state.setup.read().await;
+    Ok(Json(SetupStatus {
+        setup_mode: setup.is_some(),
+        since: setup.as_ref().map(|s| s.started_at.clone()),
+        config_path: state.config_path.to_string(),
+    }))
+}
+
+/// Submit the initial config as TOML, authorized by the setup token from the server log
+pub async fn handle_submit_setup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<ReloadResponse>> {
+    let mut setup = state.setup.write().await;
+    let session = setup.as_ref().ok_or(ApiError::AlreadyConfigured)?;
+    let token = headers.get(SETUP_TOKEN_HEADER).and_then(|v| v.to_str().ok());
+    if token != Some(session.token()) {
+        tracing::warn!("Setup submission with a missing or wrong setup token");
+        return Err(ApiError::InvalidSetupToken);
+    }
+
+    // Refuse anything that wouldn't load, before it reaches disk
+    Config::parse(&body).map_err(|e| match e {
+        ApiError::ConfigError(msg) => ApiError::InvalidConfig(msg),
+        other => other,
+    })?;
+
+    let path = std::path::Path::new(state.config_path.as_str());
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+    }
+    let tmp = path.with_extension("toml.tmp");
+    std::fs::write(&tmp, &body)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", path.display(), e)))?;
+
+    let changed = state.reload_config().await?;
+    setup.take();
+    tracing::info!("Initial configuration written to {}, leaving setup mode", path.display());
+
+    Ok(Json(ReloadResponse {
+        success: true,
+        changed,
+        config_generation: state.config.load().generation,
+        timestamp: Local::now().to_rfc3339(),
+    }))
+}
@@ -0,0 +1,100 @@
+﻿use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use chrono::Local;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    api::models::PinEvent,
+    gpio::PinState,
+    state::{AppState, ChangeSource, StateEvent},
+};
+
+/// Upgrade to a WebSocket streaming a JSON event for every pin state change and config reload
+pub async fn handle_ws(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.on_upgrade(move |socket| stream_pin_events(socket, state))
+}
+
+/// Send the current state of every pin, then each change until the client disconnects
+async fn stream_pin_events(mut socket: WebSocket, state: AppState) {
+    tracing::debug!("WebSocket client connected");
+    let mut events = state.events.subscribe();
+    if send_snapshot(&mut socket, &state).await.is_err() {
+        return;
+    }
+
+    loop {
+        let sent = tokio::select! {
+            event = events.recv() => match event {
+                Ok(StateEvent::PinChanged { pin, state: pin_state, source, timestamp }) => {
+                    send_pin(&mut socket, &state, pin, pin_state, Some(source), timestamp).await
+                }
+                Ok(
+                    event @ (StateEvent::IgnitionChecked { .. }
+                    | StateEvent::FireplaceChanged { .. }
+                    | StateEvent::GpioFailed { .. }
+                    | StateEvent::TemperatureCutoff { .. }
+                    | StateEvent::EmergencyStopped { .. }
+                    | StateEvent::InputChanged { .. }),
+                ) => send_json(&mut socket, &event).await,
+                // Tell clients their cached device mappings may be stale
+                Ok(event @ StateEvent::ConfigReloaded { .. }) => send_json(&mut socket, &event).await,
+                // Missed events: resynchronise the client with a full snapshot
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("WebSocket client lagged by {} events", skipped);
+                    send_snapshot(&mut socket, &state).await
+                }
+                Err(RecvError::Closed) => return,
+            },
+            received = socket.recv() => match received {
+                // Clients only listen; anything but a close is ignored
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => Err(()),
+                Some(Ok(_)) => Ok(()),
+            }
+        };
+        if sent.is_err() {
+            tracing::debug!("WebSocket client disconnected");
+            return;
+        }
+    }
+}
+
+async fn send_snapshot(socket: &mut WebSocket, state: &AppState) -> Result<(), ()> {
+    let pins = state.gpio_controller.get_all_pin_states();
+    let timestamp = Local::now().to_rfc3339();
+    for status in pins {
+        send_pin(socket, state, status.pin, status.state, None, timestamp.clone()).await?;
+    }
+    Ok(())
+}
+
+async fn send_pin(
+    socket: &mut WebSocket,
+    state: &AppState,
+    pin: u32,
+    pin_state: PinState,
+    source: Option<ChangeSource>,
+    timestamp: String,
+) -> Result<(), ()> {
+    let config = state.config.load();
+    let owner = config.find_pin(pin);
+    let event = PinEvent {
+        pin,
+        state: pin_state,
+        room: owner.as_ref().map(|(zone, _)| zone.name.to_string()),
+        device: owner.as_ref().map(|(_, device)| device.name.clone()),
+        source,
+        config_generation: config.generation,
+        timestamp,
+    };
+    send_json(socket, &event).await
+}
+
+async fn send_json(socket: &mut WebSocket, message: &impl serde::Serialize) -> Result<(), ()> {
+    let json = serde_json::to_string(message).map_err(|_| ())?;
+    socket.send(Message::Text(json)).await.map_err(|_| ())
+}
@@ -0,0 +1,50 @@
+﻿use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Audit entries kept in memory
+const MAX_ENTRIES: usize = 500;
+
+/// A state-changing request and who made it
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub user: Option<String>,
+    pub role: Option<crate::auth::Role>,
+    pub source: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub timestamp: String,
+}
+
+/// Recent state-changing requests, newest last
+#[derive(Default)]
+pub struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, entry: AuditEntry) {
+        tracing::info!(
+            event = "audit",
+            user = entry.user.as_deref().unwrap_or("anonymous"),
+            "{} {} -> {}",
+            entry.method,
+            entry.path,
+            entry.status
+        );
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// The newest `limit` entries, oldest first
+    pub fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let skip = self.entries.len().saturating_sub(limit);
+        self.entries.iter().skip(skip).cloned().collect()
+    }
+}
@@ -0,0 +1,283 @@
+﻿use axum::http::{header::AUTHORIZATION, HeaderMap, Method};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::secrets::Secret;
+
+/// How callers are identified. Without `trusted_proxy` or `jwt` every request is anonymous
+/// and allowed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub trusted_proxy: Option<TrustedProxyConfig>,
+    #[serde(default)]
+    pub jwt: Option<JwtConfig>,
+}
+
+/// Bearer tokens signed with a shared HS256 key, e.g. issued by a hosted dashboard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtConfig {
+    pub secret: Secret,
+    /// Required `iss` claim, if set
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Required `aud` claim, if set
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// Claim holding the caller's role
+    #[serde(default = "default_role_claim")]
+    pub role_claim: String,
+}
+
+fn default_role_claim() -> String {
+    "role".to_string()
+}
+
+/// Shortest signing key accepted; HS256 keys should be at least as long as the hash
+const MIN_JWT_SECRET_BYTES: usize = 32;
+
+/// Identity asserted by an authenticating reverse proxy (Authelia, Traefik forward auth, ...)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedProxyConfig {
+    /// Addresses or CIDR ranges the proxy connects from; anyone else is refused
+    pub proxies: Vec<String>,
+    #[serde(default = "default_user_header")]
+    pub user_header: String,
+    /// Comma-separated group list
+    #[serde(default = "default_groups_header")]
+    pub groups_header: String,
+    /// Groups granting each role, e.g. `admin = ["admins"]`
+    #[serde(default)]
+    pub roles: HashMap<Role, Vec<String>>,
+    /// Role of an authenticated user in none of the mapped groups; unset refuses them
+    #[serde(default)]
+    pub default_role: Option<Role>,
+}
+
+fn default_user_header() -> String {
+    "Remote-User".to_string()
+}
+
+fn default_groups_header() -> String {
+    "Remote-Groups".to_string()
+}
+
+/// Network-level restrictions, applied before any identity check
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    /// Addresses or CIDR ranges that may reach control endpoints; empty allows any
+    #[serde(default)]
+    pub allowed_networks: Vec<String>,
+}
+
+impl SecurityConfig {
+    /// Whether a peer may use control endpoints
+    pub fn allows(&self, source: IpAddr) -> bool {
+        self.allowed_networks.is_empty() || self.allowed_networks.iter().any(|range| in_range(source, range))
+    }
+
+    /// Check every network parses
+    pub fn validate(&self) -> Result<(), String> {
+        for range in &self.allowed_networks {
+            parse_range(range)
+                .ok_or_else(|| format!("security.allowed_networks: invalid address or range '{}'", range))?;
+        }
+        Ok(())
+    }
+}
+
+/// What a caller may do; each role includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Read-only access
+    Viewer,
+    /// Control devices, timers and schedules
+    Operator,
+    /// Configuration and admin endpoints
+    Admin,
+}
+
+impl Role {
+    /// The role a request needs. The legacy endpoint switches relays on a GET, so it
+    /// needs an operator like the other control endpoints.
+    pub fn required(method: &Method, path: &str) -> Self {
+        if path.starts_with("/api/v1/config") || path.starts_with("/api/v1/admin") {
+            Role::Admin
+        } else if path == "/" {
+            Role::Operator
+        } else if method == Method::GET || method == Method::HEAD {
+            Role::Viewer
+        } else {
+            Role::Operator
+        }
+    }
+}
+
+/// A caller as identified by the proxy or a bearer token
+#[derive(Debug, Clone, Serialize)]
+pub struct Identity {
+    pub user: String,
+    pub groups: Vec<String>,
+    pub role: Role,
+}
+
+/// Why a request could not be attributed to a user
+#[derive(Debug)]
+pub enum AuthFailure {
+    UntrustedSource(IpAddr),
+    MissingUser,
+    NoRole(String),
+    InvalidToken(String),
+}
+
+impl TrustedProxyConfig {
+    /// Check the connection came through the proxy and read the user it vouches for
+    pub fn identify(&self, source: IpAddr, headers: &HeaderMap) -> Result<Identity, AuthFailure> {
+        if !self.proxies.iter().any(|range| in_range(source, range)) {
+            return Err(AuthFailure::UntrustedSource(source));
+        }
+
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+        let user = header(&self.user_header)
+            .filter(|u| !u.is_empty())
+            .ok_or(AuthFailure::MissingUser)?
+            .to_string();
+        let groups: Vec<String> = header(&self.groups_header)
+            .map(|g| g.split(',').map(str::trim).filter(|g| !g.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default();
+
+        let role = self
+            .roles
+            .iter()
+            .filter(|(_, mapped)| mapped.iter().any(|m| groups.iter().any(|g| g.eq_ignore_ascii_case(m))))
+            .map(|(role, _)| *role)
+            .max()
+            .or(self.default_role)
+            .ok_or_else(|| AuthFailure::NoRole(user.clone()))?;
+
+        Ok(Identity { user, groups, role })
+    }
+
+    /// Check every proxy entry parses as an address or CIDR range
+    pub fn validate(&self) -> Result<(), String> {
+        for range in &self.proxies {
+            parse_range(range).ok_or_else(|| format!("auth.trusted_proxy.proxies: invalid address or range '{}'", range))?;
+        }
+        Ok(())
+    }
+}
+
+impl JwtConfig {
+    /// Read the caller from an `Authorization: Bearer` token. Returns None when the request
+    /// carries no bearer token at all.
+    pub fn identify(&self, headers: &HeaderMap) -> Result<Option<Identity>, AuthFailure> {
+        let Some(token) = headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+        else {
+            return Ok(None);
+        };
+
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let key = DecodingKey::from_secret(self.secret.expose().as_bytes());
+        let claims = jsonwebtoken::decode::<HashMap<String, serde_json::Value>>(token, &key, &validation)
+            .map_err(|e| AuthFailure::InvalidToken(e.to_string()))?
+            .claims;
+
+        let user = claims
+            .get("sub")
+            .and_then(|sub| sub.as_str())
+            .filter(|sub| !sub.is_empty())
+            .ok_or(AuthFailure::MissingUser)?
+            .to_string();
+        let role = claims
+            .get(&self.role_claim)
+            .and_then(|role| serde_json::from_value::<Role>(role.clone()).ok())
+            .ok_or_else(|| AuthFailure::NoRole(user.clone()))?;
+
+        Ok(Some(Identity { user, groups: Vec::new(), role }))
+    }
+
+    /// Check the signing key is long enough to be worth having
+    pub fn validate(&self) -> Result<(), String> {
+        if self.secret.expose().len() < MIN_JWT_SECRET_BYTES {
+            return Err(format!("auth.jwt.secret must be at least {} bytes", MIN_JWT_SECRET_BYTES));
+        }
+        Ok(())
+    }
+}
+
+fn parse_range(range: &str) -> Option<(IpAddr, u32)> {
+    let (addr, prefix) = match range.split_once('/') {
+        Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+        None => (range.parse::<IpAddr>().ok()?, None),
+    };
+    let bits = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(bits);
+    (prefix <= bits).then_some((addr, prefix))
+}
+
+/// Whether `addr` falls within an address or CIDR range
+fn in_range(addr: IpAddr, range: &str) -> bool {
+    let Some((network, prefix)) = parse_range(range) else {
+        return false;
+    };
+    // Compare IPv4-mapped IPv6 sources against IPv4 ranges
+    let addr = match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        v4 => v4,
+    };
+    match (addr, network) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(a) & mask == u32::from(n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(a) & mask == u128::from(n) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_need_a_viewer() {
+        assert_eq!(Role::required(&Method::GET, "/api/v1/gpio/status"), Role::Viewer);
+        assert_eq!(Role::required(&Method::HEAD, "/health"), Role::Viewer);
+    }
+
+    #[test]
+    fn writes_need_an_operator() {
+        assert_eq!(Role::required(&Method::POST, "/api/v1/fireplace/control"), Role::Operator);
+        assert_eq!(Role::required(&Method::DELETE, "/api/v1/timers/1"), Role::Operator);
+    }
+
+    #[test]
+    fn legacy_endpoint_needs_an_operator_on_a_get() {
+        assert_eq!(Role::required(&Method::GET, "/"), Role::Operator);
+    }
+
+    #[test]
+    fn config_and_admin_need_an_admin_even_to_read() {
+        assert_eq!(Role::required(&Method::GET, "/api/v1/config"), Role::Admin);
+        assert_eq!(Role::required(&Method::POST, "/api/v1/config/reload"), Role::Admin);
+        assert_eq!(Role::required(&Method::GET, "/api/v1/admin/audit"), Role::Admin);
+    }
+}
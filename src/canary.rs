@@ -0,0 +1,222 @@
+﻿use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::{
+    api::models::FireplaceControlRequest,
+    config::{Config, DeviceKind},
+    error::{ApiError, Result},
+    state::AppState,
+};
+
+/// Shadow observations kept per canary; older ones are dropped
+const MAX_OBSERVATIONS: usize = 200;
+
+/// What a config would do with a control command
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    Actuate {
+        pins: Vec<u32>,
+        /// Monitor pin that would confirm ignition
+        verify_with: Option<u32>,
+        /// Safety watchdog limit that would apply
+        max_runtime_minutes: Option<u32>,
+    },
+    Reject {
+        reason: String,
+    },
+}
+
+impl Outcome {
+    /// Evaluate a command against a config without touching hardware
+    pub fn plan(config: &Config, req: &FireplaceControlRequest) -> Self {
+        match Self::try_plan(config, req) {
+            Ok(outcome) => outcome,
+            Err(e) => Outcome::Reject { reason: e.to_string() },
+        }
+    }
+
+    fn try_plan(config: &Config, req: &FireplaceControlRequest) -> Result<Self> {
+        if !matches!(req.action.to_uppercase().as_str(), "ON" | "OFF") {
+            return Err(ApiError::InvalidAction);
+        }
+        let zone = config.zone(req.room.as_deref())?;
+        let pins = config
+            .device_pins(zone.name, &req.device)
+            .ok_or_else(|| ApiError::UnknownDevice(req.device.clone()))?;
+
+        let max = config.safety.max_cycles;
+        if req.cycles.filter(|c| *c != 0).unwrap_or(1) > max {
+            return Err(ApiError::InvalidCycles(max));
+        }
+
+        let fireplace = zone.device(&req.device).filter(|d| d.kind == DeviceKind::Fireplace);
+        Ok(Outcome::Actuate {
+            pins,
+            verify_with: fireplace.and_then(|d| d.monitor),
+            max_runtime_minutes: config
+                .safety
+                .max_runtime
+                .filter(|_| fireplace.is_some())
+                .map(|limit| (limit.as_secs() / 60) as u32),
+        })
+    }
+}
+
+/// A command as the live config handled it and as the canary would have
+#[derive(Debug, Clone, Serialize)]
+pub struct Observation {
+    pub action: String,
+    pub device: String,
+    pub room: Option<String>,
+    pub live: Outcome,
+    pub canary: Outcome,
+    pub diverges: bool,
+    pub timestamp: String,
+}
+
+/// A pushed config running in shadow alongside the live one
+pub struct Canary {
+    pub config: Arc<Config>,
+    /// The TOML as pushed, written to the config file on promotion
+    pub source: String,
+    pub changed: Vec<String>,
+    /// Persisted schedules the canary config would reject
+    pub schedule_issues: Vec<String>,
+    pub started_at: DateTime<Local>,
+    pub promotable_at: DateTime<Local>,
+    pub observations: VecDeque<Observation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CanaryStatus {
+    pub changed: Vec<String>,
+    pub schedule_issues: Vec<String>,
+    pub started_at: String,
+    pub promotable_at: String,
+    pub promotable: bool,
+    pub observed: usize,
+    pub divergences: usize,
+    pub observations: Vec<Observation>,
+}
+
+impl Canary {
+    pub fn status(&self) -> CanaryStatus {
+        CanaryStatus {
+            changed: self.changed.clone(),
+            schedule_issues: self.schedule_issues.clone(),
+            started_at: self.started_at.to_rfc3339(),
+            promotable_at: self.promotable_at.to_rfc3339(),
+            promotable: Local::now() >= self.promotable_at,
+            observed: self.observations.len(),
+            divergences: self.observations.iter().filter(|o| o.diverges).count(),
+            observations: self.observations.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Start shadowing a pushed config, replacing any canary already running
+pub async fn start(state: &AppState, source: String) -> Result<CanaryStatus> {
+    let config = Config::parse(&source).map_err(|e| match e {
+        ApiError::ConfigError(msg) => ApiError::InvalidConfig(msg),
+        other => other,
+    })?;
+    let live = state.config.load_full();
+
+    let schedule_issues = state
+        .scheduler
+        .lock()
+        .await
+        .list()
+        .into_iter()
+        .filter_map(|s| {
+            s.schedule
+                .validate(&config)
+                .err()
+                .map(|e| format!("schedule {}: {}", s.schedule.id, e))
+        })
+        .collect();
+
+    let started_at = Local::now();
+    let canary = Canary {
+        changed: live.diff(&config),
+        config: Arc::new(config),
+        source,
+        schedule_issues,
+        started_at,
+        promotable_at: started_at + chrono::Duration::from_std(live.canary.shadow_period).unwrap_or(chrono::Duration::MAX),
+        observations: VecDeque::new(),
+    };
+    let status = canary.status();
+    tracing::info!(
+        "Canary config started ({} changes), promotable at {}",
+        status.changed.len(),
+        status.promotable_at
+    );
+    *state.canary.lock().await = Some(canary);
+    Ok(status)
+}
+
+/// Record what the canary config would do with a command the live config is about to run
+pub async fn observe(state: &AppState, req: &FireplaceControlRequest) {
+    let mut canary = state.canary.lock().await;
+    let Some(canary) = canary.as_mut() else {
+        return;
+    };
+
+    let live = Outcome::plan(&state.config.load(), req);
+    let shadow = Outcome::plan(&canary.config, req);
+    let diverges = live != shadow;
+    if diverges {
+        tracing::info!(
+            "Canary diverges on {} {}: live {:?}, canary {:?}",
+            req.action,
+            req.device,
+            live,
+            shadow
+        );
+    }
+
+    if canary.observations.len() == MAX_OBSERVATIONS {
+        canary.observations.pop_front();
+    }
+    canary.observations.push_back(Observation {
+        action: req.action.clone(),
+        device: req.device.clone(),
+        room: req.room.clone(),
+        live,
+        canary: shadow,
+        diverges,
+        timestamp: Local::now().to_rfc3339(),
+    });
+}
+
+/// Make the canary the live config: write it to the config file and reload
+pub async fn promote(state: &AppState) -> Result<Vec<String>> {
+    let mut slot = state.canary.lock().await;
+    let canary = slot.as_ref().ok_or(ApiError::NoCanary)?;
+    if Local::now() < canary.promotable_at {
+        return Err(ApiError::CanaryNotReady(canary.promotable_at.to_rfc3339()));
+    }
+
+    // Write then rename so the file watcher never sees a partial file
+    let path = std::path::Path::new(state.config_path.as_str());
+    let tmp = path.with_extension("toml.tmp");
+    std::fs::write(&tmp, &canary.source)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", path.display(), e)))?;
+    slot.take();
+    drop(slot);
+
+    tracing::info!("Canary config promoted");
+    state.reload_config().await
+}
+
+/// Discard the running canary
+pub async fn abort(state: &AppState) -> Result<()> {
+    state.canary.lock().await.take().ok_or(ApiError::NoCanary)?;
+    tracing::info!("Canary config discarded");
+    Ok(())
+}
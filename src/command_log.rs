@@ -0,0 +1,110 @@
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::error::{ApiError, Result};
+
+/// A control request, who sent it and how it ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandRecord {
+    pub timestamp: DateTime<Local>,
+    /// Address the request came from
+    pub client: String,
+    /// Caller named by the trusted proxy, if one is configured
+    #[serde(default)]
+    pub user: Option<String>,
+    pub endpoint: String,
+    #[serde(default)]
+    pub room: Option<String>,
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub pin: Option<u32>,
+    #[serde(default)]
+    pub action: Option<String>,
+    pub status: u16,
+    /// `ok`, `accepted` for asynchronous commands, or the error message
+    pub result: String,
+}
+
+/// Every control request, appended to a JSON Lines file that is never rewritten
+pub struct CommandLog {
+    path: PathBuf,
+    entries: Vec<CommandRecord>,
+}
+
+impl CommandLog {
+    /// Load the log; a missing file starts empty and unreadable lines are skipped
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("commands.jsonl");
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(content) => content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| match serde_json::from_str(line) {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        tracing::warn!("Skipping unreadable line in {}: {}", path.display(), e);
+                        None
+                    }
+                })
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        Self { path, entries }
+    }
+
+    pub fn append(&mut self, entry: CommandRecord) {
+        if let Err(e) = self.write(&entry) {
+            tracing::warn!("{}", e);
+        }
+        self.entries.push(entry);
+    }
+
+    /// Entries at or after `since`, newest first, optionally only those for a device named
+    /// `device` or driving one of `pins`. Returns the number of matching entries and the
+    /// requested page of them.
+    pub fn query(
+        &self,
+        device: Option<(&str, &[u32])>,
+        since: Option<DateTime<Local>>,
+        offset: usize,
+        limit: usize,
+    ) -> (usize, Vec<CommandRecord>) {
+        let for_device = |e: &CommandRecord, (name, pins): (&str, &[u32])| {
+            e.device.as_deref().is_some_and(|d| d.eq_ignore_ascii_case(name))
+                || e.pin.is_some_and(|pin| pins.contains(&pin))
+        };
+        let matching: Vec<&CommandRecord> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|e| device.is_none_or(|device| for_device(e, device)))
+            .filter(|e| since.is_none_or(|since| e.timestamp >= since))
+            .collect();
+        let page = matching.iter().skip(offset).take(limit).map(|e| (*e).clone()).collect();
+        (matching.len(), page)
+    }
+
+    fn write(&self, entry: &CommandRecord) -> Result<()> {
+        let mut line = serde_json::to_string(entry)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode command record: {}", e)))?;
+        line.push('\n');
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
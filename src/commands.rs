@@ -0,0 +1,181 @@
+﻿use chrono::Local;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    api::models::{ApiResponse, FireplaceControlRequest},
+    error::Result,
+    scenes::Scene,
+    state::AppState,
+};
+
+/// Finished commands kept for GET /api/v1/commands/{id}
+const MAX_COMMANDS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandStep {
+    pub description: String,
+    pub at: String,
+}
+
+/// A control request or scene running in the background
+#[derive(Debug, Clone, Serialize)]
+pub struct Command {
+    pub id: Uuid,
+    pub action: String,
+    pub device: String,
+    pub room: Option<String>,
+    pub status: CommandStatus,
+    pub steps: Vec<CommandStep>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<ApiResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Stable code of the error, as in error responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<&'static str>,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+}
+
+/// Recent asynchronous commands, oldest evicted first
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: HashMap<Uuid, Command>,
+    order: VecDeque<Uuid>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Command> {
+        self.commands.get(&id).cloned()
+    }
+
+    fn insert(&mut self, command: Command) {
+        while self.order.len() >= MAX_COMMANDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.commands.remove(&oldest);
+            }
+        }
+        self.order.push_back(command.id);
+        self.commands.insert(command.id, command);
+    }
+
+    fn update(&mut self, id: Uuid, f: impl FnOnce(&mut Command)) {
+        if let Some(command) = self.commands.get_mut(&id) {
+            f(command);
+        }
+    }
+}
+
+/// Where a control sequence reports its steps; a no-op for synchronous requests
+#[derive(Clone, Default)]
+pub struct Progress {
+    command: Option<(Uuid, Arc<Mutex<CommandRegistry>>)>,
+}
+
+impl Progress {
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub async fn step(&self, description: impl Into<String>) {
+        let Some((id, registry)) = &self.command else {
+            return;
+        };
+        let step = CommandStep {
+            description: description.into(),
+            at: Local::now().to_rfc3339(),
+        };
+        registry.lock().await.update(*id, |command| command.steps.push(step));
+    }
+}
+
+/// Run a control request in the background and return its receipt immediately
+pub async fn submit(state: &AppState, req: FireplaceControlRequest) -> Command {
+    let command = receipt(req.action.to_uppercase(), req.device.clone(), req.room.clone());
+    start(state, command, |state, progress| async move {
+        crate::api::handlers::run_control(&state, req, &progress).await.map(Some)
+    })
+    .await
+}
+
+/// Activate a scene in the background and return its receipt immediately
+pub async fn submit_scene(state: &AppState, scene: Scene) -> Command {
+    let command = receipt("ACTIVATE".to_string(), scene.name.clone(), scene.room.clone());
+    start(state, command, |state, progress| async move {
+        crate::scenes::run(&state, &scene, &progress).await.map(|_| None)
+    })
+    .await
+}
+
+fn receipt(action: String, device: String, room: Option<String>) -> Command {
+    Command {
+        id: Uuid::new_v4(),
+        action,
+        device,
+        room,
+        status: CommandStatus::Pending,
+        steps: Vec::new(),
+        result: None,
+        error: None,
+        error_code: None,
+        created_at: Local::now().to_rfc3339(),
+        finished_at: None,
+    }
+}
+
+/// Register a command and drive it to completion on its own task
+async fn start<F, Fut>(state: &AppState, command: Command, run: F) -> Command
+where
+    F: FnOnce(AppState, Progress) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Option<ApiResponse>>> + Send + 'static,
+{
+    state.commands.lock().await.insert(command.clone());
+
+    let id = command.id;
+    let state = state.clone();
+    tokio::spawn(async move {
+        let registry = state.commands.clone();
+        registry.lock().await.update(id, |c| c.status = CommandStatus::Running);
+
+        let progress = Progress {
+            command: Some((id, registry.clone())),
+        };
+        let outcome = run(state, progress).await;
+
+        registry.lock().await.update(id, |c| {
+            match outcome {
+                Ok(response) => {
+                    c.status = CommandStatus::Succeeded;
+                    c.result = response;
+                }
+                Err(e) => {
+                    tracing::warn!("Command {} failed: {}", id, e);
+                    c.status = CommandStatus::Failed;
+                    c.error = Some(e.to_string());
+                    c.error_code = Some(e.code());
+                }
+            }
+            c.finished_at = Some(Local::now().to_rfc3339());
+        });
+    });
+
+    command
+}
@@ -1,10 +1,96 @@
 ﻿use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub room: RoomConfig,
-    pub pins: PinConfig,
+    /// Legacy fixed-field layout of the primary room, moved into `devices` on load
+    #[serde(default, skip_serializing)]
+    pub pins: Option<PinConfig>,
+    /// Outputs in the primary room
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+    /// Additional rooms controlled by this server, beyond the primary `[room]`
+    #[serde(default)]
+    pub rooms: Vec<RoomZone>,
+    /// Several pins controlled together as one logical device
+    #[serde(default)]
+    pub groups: Vec<DeviceGroup>,
+    /// Devices kept running after another turns off
+    #[serde(default)]
+    pub interlocks: Vec<crate::interlock::InterlockRule>,
+    /// URLs notified of state changes and safety events
+    #[serde(default)]
+    pub webhooks: Vec<crate::webhooks::WebhookConfig>,
+    /// Temperature sensors, polled in the background
+    #[serde(default)]
+    pub sensors: Vec<crate::sensors::SensorConfig>,
+    /// Contact, flame and motion sensors on input pins, polled in the background
+    #[serde(default)]
+    pub inputs: Vec<crate::inputs::InputConfig>,
+    /// Rooms turned off when their motion sensor sees no one for a while
+    #[serde(default)]
+    pub idle_off: Vec<crate::motion::IdleOffConfig>,
+    #[serde(default)]
+    pub sensor_history: crate::sensor_history::SensorHistoryConfig,
+    #[serde(default)]
+    pub selftest: crate::selftest::SelfTestConfig,
+    #[serde(default)]
+    pub thermostat: Option<crate::thermostat::ThermostatConfig>,
+    /// Fans switched by a firebox sensor, like the fireplace's own snap switch
+    #[serde(default)]
+    pub fan_control: Vec<crate::fan_control::FanControlConfig>,
+    /// Named multi-device actions, activated through the API
+    #[serde(default)]
+    pub scenes: Vec<crate::scenes::Scene>,
+    /// Automations: a trigger, optional conditions and actions
+    #[serde(default)]
+    pub rules: Vec<crate::rules::Rule>,
     pub safety: SafetyConfig,
+    #[serde(default)]
+    pub gpio: GpioConfig,
+    #[serde(default)]
+    pub power: Option<PowerConfig>,
+    /// An LED kept in step with a fireplace's state
+    #[serde(default)]
+    pub status_led: Option<crate::status_led::StatusLedConfig>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub secrets: crate::secrets::SecretsConfig,
+    #[serde(default)]
+    pub canary: CanaryConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    #[serde(default)]
+    pub auth: crate::auth::AuthConfig,
+    #[serde(default)]
+    pub security: crate::auth::SecurityConfig,
+    #[serde(default)]
+    pub mqtt: Option<crate::mqtt::MqttConfig>,
+    #[serde(default)]
+    pub notifications: Option<crate::notifications::NotificationsConfig>,
+    #[serde(default)]
+    pub telegram: Option<crate::telegram::TelegramConfig>,
+    #[serde(default)]
+    pub failover: Option<crate::failover::FailoverConfig>,
+    #[serde(default)]
+    pub rate_limit: Option<crate::rate_limit::RateLimitConfig>,
+    /// Values substituted from `${env:..}`/`${secret:..}` references, masked on output
+    #[serde(skip)]
+    pub secret_values: crate::secrets::SecretValues,
+    /// Bumped on every reload so clients can tell their cached pin mappings are stale
+    #[serde(skip)]
+    pub generation: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,6 +99,190 @@ pub struct RoomConfig {
     pub device_ip: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomZone {
+    pub name: String,
+    #[serde(default)]
+    pub device_ip: Option<String>,
+    /// Legacy fixed-field layout, moved into `devices` on load
+    #[serde(default, skip_serializing)]
+    pub pins: Option<PinConfig>,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+}
+
+/// A room and its devices, whether the primary room or a `[[rooms]]` entry
+#[derive(Debug, Clone, Copy)]
+pub struct Zone<'a> {
+    pub name: &'a str,
+    pub devices: &'a [DeviceConfig],
+}
+
+/// One output wired to a relay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    pub name: String,
+    /// Filled in from `expander`, `port` and `bit` for a relay on an I2C expander
+    #[serde(default = "unset_pin")]
+    pub pin: u32,
+    /// The `[[gpio.expanders]]` entry the relay hangs off, instead of a header pin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expander: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<ExpanderPort>,
+    /// Bit of `port`, 0-7
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bit: Option<u32>,
+    /// Codes sent by a 433MHz transmitter instead of driving a relay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rf: Option<crate::rf::RfCodes>,
+    /// Keys sent by an IR blaster through LIRC instead of driving a relay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ir: Option<crate::lirc::IrKeys>,
+    #[serde(default)]
+    pub kind: DeviceKind,
+    /// Logical ON drives the pin low
+    #[serde(default)]
+    pub active_low: bool,
+    #[serde(default)]
+    pub mode: OutputMode,
+    /// Input confirming ignition; fireplaces only
+    #[serde(default)]
+    pub monitor: Option<u32>,
+    /// State the relay is driven to when the server starts or stops
+    #[serde(default)]
+    pub safe_state: SafeState,
+    /// Shortest time between two switches of the relay; control requests inside it are refused
+    #[serde(default, with = "crate::duration::seconds")]
+    pub min_dwell: Duration,
+}
+
+/// Stands in for a device's pin until `normalize` works it out from the expander
+const UNSET_PIN: u32 = u32::MAX;
+
+fn unset_pin() -> u32 {
+    UNSET_PIN
+}
+
+/// Where a device is left when the server starts, and on shutdown with
+/// `shutdown.apply_safe_states`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SafeState {
+    /// Keep whatever state the relay is found in
+    #[default]
+    Unchanged,
+    /// Drive the relay off
+    Off,
+}
+
+/// What a device is, which decides the safety behaviour that applies to it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    /// Ignition is verified by `monitor` and runtime is capped by `safety.max_runtime`
+    Fireplace,
+    /// Shed while on battery when `power.shed_fan` is set
+    Fan,
+    Light,
+    #[default]
+    Switch,
+}
+
+/// Every kind, as accepted in place of a device name
+pub const DEVICE_KINDS: [&str; 4] = ["fireplace", "fan", "light", "switch"];
+
+impl DeviceKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "fireplace" => Some(Self::Fireplace),
+            "fan" => Some(Self::Fan),
+            "light" => Some(Self::Light),
+            "switch" => Some(Self::Switch),
+            _ => None,
+        }
+    }
+}
+
+/// How ON/OFF commands drive a device's relay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    /// Each command flips the relay, as the legacy Python API did
+    #[default]
+    Toggle,
+    /// ON drives the relay on and OFF drives it off
+    Latch,
+    /// Each command presses a momentary contact for `safety.pulse_duration`
+    Pulse,
+}
+
+/// Something a client can ask of a device, as listed by GET /api/v1/devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// ON and OFF flip the relay
+    Toggle,
+    /// ON and OFF set the relay directly
+    Latch,
+    /// Momentary contact via POST /api/v1/fireplace/pulse
+    Pulse,
+    /// Repeated toggles via `cycles`
+    Cycle,
+    /// Automatic OFF via `duration_minutes`
+    Timer,
+    /// Ignition confirmed through a monitor input
+    Verify,
+}
+
+impl DeviceConfig {
+    pub fn capabilities(&self) -> Vec<Capability> {
+        let mut capabilities = match self.mode {
+            OutputMode::Toggle => vec![Capability::Toggle, Capability::Cycle, Capability::Pulse, Capability::Timer],
+            OutputMode::Latch => vec![Capability::Latch, Capability::Pulse, Capability::Timer],
+            OutputMode::Pulse => vec![Capability::Pulse],
+        };
+        if self.kind == DeviceKind::Fireplace && self.monitor.is_some() {
+            capabilities.push(Capability::Verify);
+        }
+        capabilities
+    }
+
+    /// Whether the device is worked over the air (RF or IR) rather than through a pin
+    pub fn is_remote(&self) -> bool {
+        self.rf.is_some() || self.ir.is_some()
+    }
+}
+
+impl<'a> Zone<'a> {
+    /// Find a device by name. A kind (`fireplace`, `fan`, ...) names the room's first
+    /// device of that kind, so clients written against the `[pins]` layout keep working.
+    pub fn device(&self, name: &str) -> Option<&'a DeviceConfig> {
+        self.devices
+            .iter()
+            .find(|d| d.name.eq_ignore_ascii_case(name))
+            .or_else(|| {
+                let kind = DeviceKind::from_name(name)?;
+                self.of_kind(kind).next()
+            })
+    }
+
+    pub fn of_kind(&self, kind: DeviceKind) -> impl Iterator<Item = &'a DeviceConfig> {
+        self.devices.iter().filter(move |d| d.kind == kind)
+    }
+
+    /// Every output pin with its device name
+    pub fn named_pins(&self) -> Vec<(&'a str, u32)> {
+        self.devices.iter().map(|d| (d.name.as_str(), d.pin)).collect()
+    }
+
+    /// Monitor inputs of the room's fireplaces
+    pub fn monitor_pins(&self) -> impl Iterator<Item = u32> + 'a {
+        self.devices.iter().filter_map(|d| d.monitor)
+    }
+}
+
+/// The fixed pin layout used before `[[devices]]`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PinConfig {
     pub fireplace: u32,
@@ -21,21 +291,961 @@ pub struct PinConfig {
     pub lights: Option<u32>,
     #[serde(default)]
     pub secondary_device: Option<u32>,
+    #[serde(default)]
+    pub monitor: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceGroup {
+    pub name: String,
+    /// Room the group belongs to; defaults to the primary room
+    #[serde(default)]
+    pub room: Option<String>,
+    pub pins: Vec<u32>,
+    #[serde(default)]
+    pub policy: GroupPolicy,
+    /// Delay between pins when the policy is `staged`
+    #[serde(default = "default_stage_delay", alias = "stage_delay_ms", with = "crate::duration::millis")]
+    pub stage_delay: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupPolicy {
+    /// Switch every pin at once
+    #[default]
+    AllOn,
+    /// Switch pins on one at a time to limit inrush current; off is always simultaneous
+    Staged,
+}
+
+fn default_stage_delay() -> Duration {
+    Duration::from_secs(2)
+}
+
+impl PinConfig {
+    /// The equivalent `[[devices]]` list
+    fn into_devices(self) -> Vec<DeviceConfig> {
+        let device = |name: &str, pin: u32, kind: DeviceKind| DeviceConfig {
+            name: name.to_string(),
+            pin,
+            expander: None,
+            port: None,
+            bit: None,
+            rf: None,
+            ir: None,
+            kind,
+            active_low: false,
+            mode: OutputMode::Toggle,
+            monitor: None,
+            safe_state: SafeState::Unchanged,
+            min_dwell: Duration::ZERO,
+        };
+        let mut devices = vec![
+            DeviceConfig {
+                monitor: self.monitor,
+                ..device("fireplace", self.fireplace, DeviceKind::Fireplace)
+            },
+            device("fireplace_fan", self.fireplace_fan, DeviceKind::Fan),
+        ];
+        devices.extend(self.lights.map(|pin| device("lights", pin, DeviceKind::Light)));
+        devices.extend(self.secondary_device.map(|pin| device("secondary_device", pin, DeviceKind::Switch)));
+        devices
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SafetyConfig {
-    pub max_pulse_duration_ms: u32,
+    #[serde(alias = "max_pulse_duration_ms", with = "crate::duration::millis")]
+    pub max_pulse_duration: Duration,
+    /// Turning a fireplace on takes a second request carrying the token the first one returned
     pub require_confirmation: bool,
+    /// How long a confirmation token stays valid
+    #[serde(default = "default_confirmation_timeout", with = "crate::duration::seconds")]
+    pub confirmation_timeout: Duration,
+    #[serde(default = "default_pulse_duration", alias = "pulse_duration_ms", with = "crate::duration::millis")]
+    pub pulse_duration: Duration,
+    #[serde(default = "default_cycle_delay", alias = "cycle_delay_ms", with = "crate::duration::millis")]
+    pub cycle_delay: Duration,
+    /// Longest `cycle_delay_ms` a control request may ask for
+    #[serde(default = "default_max_cycle_delay", alias = "max_cycle_delay_ms", with = "crate::duration::millis")]
+    pub max_cycle_delay: Duration,
+    #[serde(default = "default_max_cycles")]
+    pub max_cycles: u32,
+    /// Automatic re-ignition attempts when the monitor pin doesn't confirm ON
+    #[serde(default)]
+    pub ignition_retries: u32,
+    #[serde(
+        default = "default_ignition_retry_delay",
+        alias = "ignition_retry_delay_ms",
+        with = "crate::duration::millis"
+    )]
+    pub ignition_retry_delay: Duration,
+    /// Turn the fireplace off after it has been on this long
+    #[serde(default, alias = "max_runtime_minutes", with = "crate::duration::minutes::option")]
+    pub max_runtime: Option<Duration>,
+    /// Refuse to re-ignite a fireplace this soon after it turned off; zero allows it at once
+    #[serde(default, alias = "short_cycle_seconds", with = "crate::duration::seconds")]
+    pub short_cycle_delay: Duration,
+    /// Turn a room's fireplaces off and fans on when one of its sensors reads above this
+    #[serde(default)]
+    pub max_temperature_c: Option<f64>,
+    /// Fireplaces can be lit again once the room reads below this; 5°C under the limit by default
+    #[serde(default)]
+    pub temperature_reset_c: Option<f64>,
+}
+
+impl SafetyConfig {
+    pub fn temperature_reset_c(&self) -> f64 {
+        let limit = self.max_temperature_c.unwrap_or(f64::INFINITY);
+        self.temperature_reset_c.unwrap_or(limit - 5.0)
+    }
+}
+
+fn default_confirmation_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_pulse_duration() -> Duration {
+    Duration::from_millis(500)
+}
+
+fn default_cycle_delay() -> Duration {
+    Duration::from_millis(500)
+}
+
+fn default_max_cycle_delay() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_max_cycles() -> u32 {
+    10
+}
+
+fn default_ignition_retry_delay() -> Duration {
+    Duration::from_secs(2)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GpioConfig {
+    /// What drives the pins
+    #[serde(default)]
+    pub backend: GpioBackendKind,
+    /// Path of wiringPi's `gpio` utility, for the wiringpi backend (default "gpio")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Host running pigpiod, for the pigpiod backend (default "localhost")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// pigpiod's port (default 8888)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Numbering scheme used for every pin in the config and in requests
+    #[serde(default)]
+    pub numbering: PinNumbering,
+    /// Treat every pin as active-low (logical ON drives the pin low)
+    #[serde(default)]
+    pub active_low: bool,
+    /// Pins that are active-low when `active_low` is not set globally
+    #[serde(default)]
+    pub active_low_pins: Vec<u32>,
+    /// Read every write back, rewriting the pin until it latches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<WriteVerifyConfig>,
+    /// Retry backend calls that fail for a transient reason
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// I2C GPIO expanders, whose pins are numbered after the header's
+    #[serde(default)]
+    pub expanders: Vec<ExpanderConfig>,
+    /// 433MHz transmitters for devices with `rf` codes
+    #[serde(default)]
+    pub rf_transmitters: Vec<crate::rf::RfTransmitterConfig>,
+    /// How keys of devices with `ir` are sent
+    #[serde(default)]
+    pub lirc: crate::lirc::LircConfig,
+}
+
+/// `[[gpio.expanders]]`: an MCP23017 I2C GPIO expander. Its 16 pins are numbered from
+/// `base`: A0-A7 are `base` to `base + 7` and B0-B7 `base + 8` to `base + 15`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpanderConfig {
+    pub name: String,
+    /// The N of /dev/i2c-N
+    #[serde(default = "default_i2c_bus")]
+    pub bus: u32,
+    /// 0x20-0x27, set by the A0-A2 address pins
+    #[serde(default = "default_expander_address")]
+    pub address: u8,
+    /// Pin number of A0
+    pub base: u32,
+}
+
+fn default_i2c_bus() -> u32 {
+    1
+}
+
+fn default_expander_address() -> u8 {
+    0x20
+}
+
+/// Pins on one MCP23017
+pub const EXPANDER_PINS: u32 = 16;
+
+/// One of an MCP23017's two 8-bit ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExpanderPort {
+    #[serde(alias = "a")]
+    A,
+    #[serde(alias = "b")]
+    B,
+}
+
+impl ExpanderConfig {
+    /// The expander's pin number of `bit` on `port`
+    pub fn pin(&self, port: ExpanderPort, bit: u32) -> u32 {
+        match port {
+            ExpanderPort::A => self.base + bit,
+            ExpanderPort::B => self.base + 8 + bit,
+        }
+    }
+
+    /// Whether `pin` is one of this expander's
+    pub fn contains(&self, pin: u32) -> bool {
+        (self.base..self.base.saturating_add(EXPANDER_PINS)).contains(&pin)
+    }
+}
+
+impl GpioConfig {
+    /// The pin number of a device on an expander, if `expander`, `port` and `bit` name one
+    fn expander_pin(&self, device: &DeviceConfig) -> Option<u32> {
+        let expander = self.expanders.iter().find(|e| Some(&e.name) == device.expander.as_ref())?;
+        match (device.port, device.bit) {
+            (Some(port), Some(bit)) if bit < 8 => Some(expander.pin(port, bit)),
+            _ => None,
+        }
+    }
+
+    /// The expander a pin number belongs to
+    pub fn expander_for(&self, pin: u32) -> Option<&ExpanderConfig> {
+        self.expanders.iter().find(|e| e.contains(pin))
+    }
+
+    fn validate_expanders(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for (index, expander) in self.expanders.iter().enumerate() {
+            let field = format!("gpio.expanders[{}]", index);
+            if !names.insert(expander.name.to_lowercase()) {
+                return Err(format!("expander '{}' is defined more than once", expander.name));
+            }
+            if !(0x20..=0x27).contains(&expander.address) {
+                return Err(format!("{}.address must be between 0x20 and 0x27", field));
+            }
+            if let Some(pin) = (0..EXPANDER_PINS)
+                .map(|offset| expander.base.saturating_add(offset))
+                .find(|pin| crate::pinout::to_bcm(self.numbering, *pin).is_some())
+            {
+                return Err(format!("{}: pin {} is a header pin in {:?} numbering", field, pin, self.numbering));
+            }
+            let clash = self.expanders[..index]
+                .iter()
+                .find(|other| other.contains(expander.base) || expander.contains(other.base));
+            if let Some(other) = clash {
+                return Err(format!("expanders '{}' and '{}' overlap", other.name, expander.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ways a GPIO backend call can fail, for choosing which to retry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GpioFailure {
+    /// The backend's command isn't installed
+    Unavailable,
+    /// The command couldn't be started
+    Spawn,
+    /// The command exited with an error, or the daemon answered with one
+    Exit,
+    /// The command printed something unexpected
+    Output,
+    /// The daemon couldn't be reached, or the connection to it dropped
+    Connection,
+}
+
+/// `[gpio.retry]`: retries of failed backend calls
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Calls tried before giving up, the first one included; 1 turns retries off
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: u32,
+    /// Wait before the first retry, doubled before each one after
+    #[serde(default = "default_retry_backoff", alias = "backoff_ms", with = "crate::duration::millis")]
+    pub backoff: Duration,
+    /// Longest wait between two tries
+    #[serde(default = "default_retry_max_backoff", alias = "max_backoff_ms", with = "crate::duration::millis")]
+    pub max_backoff: Duration,
+    /// Failures worth retrying
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<GpioFailure>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: default_retry_attempts(),
+            backoff: default_retry_backoff(),
+            max_backoff: default_retry_max_backoff(),
+            retry_on: default_retry_on(),
+        }
+    }
+}
+
+fn default_retry_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff() -> Duration {
+    Duration::from_millis(25)
+}
+
+fn default_retry_max_backoff() -> Duration {
+    Duration::from_millis(500)
+}
+
+fn default_retry_on() -> Vec<GpioFailure> {
+    vec![GpioFailure::Spawn, GpioFailure::Exit, GpioFailure::Output, GpioFailure::Connection]
+}
+
+impl RetryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=10).contains(&self.attempts) {
+            return Err("gpio.retry.attempts must be between 1 and 10".to_string());
+        }
+        if self.max_backoff > Duration::from_secs(5) {
+            return Err("gpio.retry.max_backoff may be at most 5s".to_string());
+        }
+        if self.backoff > self.max_backoff {
+            return Err("gpio.retry.backoff may not exceed max_backoff".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether a failed call should be tried again
+    pub fn retries(&self, error: &crate::error::ApiError) -> bool {
+        error.gpio_failure().is_some_and(|failure| self.retry_on.contains(&failure))
+    }
+}
+
+/// `[gpio.verify]`: read-back of every pin write
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteVerifyConfig {
+    /// Writes tried before giving up, the first one included
+    #[serde(default = "default_verify_attempts")]
+    pub attempts: u32,
+    /// Wait before the first rewrite, doubled before each one after
+    #[serde(default = "default_verify_backoff", alias = "backoff_ms", with = "crate::duration::millis")]
+    pub backoff: Duration,
+    /// Longest wait between two writes
+    #[serde(default = "default_verify_max_backoff", alias = "max_backoff_ms", with = "crate::duration::millis")]
+    pub max_backoff: Duration,
+}
+
+fn default_verify_attempts() -> u32 {
+    3
+}
+
+fn default_verify_backoff() -> Duration {
+    Duration::from_millis(50)
+}
+
+fn default_verify_max_backoff() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl WriteVerifyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=10).contains(&self.attempts) {
+            return Err("gpio.verify.attempts must be between 1 and 10".to_string());
+        }
+        if self.max_backoff > Duration::from_secs(5) {
+            return Err("gpio.verify.max_backoff may be at most 5s".to_string());
+        }
+        if self.backoff > self.max_backoff {
+            return Err("gpio.verify.backoff may not exceed max_backoff".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GpioBackendKind {
+    /// No hardware: reads return the last written level
+    #[default]
+    Simulated,
+    /// wiringPi's `gpio` command-line utility
+    Wiringpi,
+    /// A pigpiod daemon over TCP, possibly on another machine
+    Pigpiod,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinNumbering {
+    #[default]
+    Bcm,
+    Physical,
+    Wiringpi,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerConfig {
+    pub on_battery_pin: u32,
+    #[serde(default)]
+    pub active_low: bool,
+    #[serde(default = "default_power_poll_interval", alias = "poll_interval_ms", with = "crate::duration::millis")]
+    pub poll_interval: Duration,
+    #[serde(default)]
+    pub shed_fan: bool,
+}
+
+fn default_power_poll_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub syslog: Option<SyslogConfig>,
+    #[serde(default)]
+    pub journald: bool,
+    /// Recent log events kept in memory for GET /api/v1/admin/logs
+    #[serde(default = "default_buffer_lines")]
+    pub buffer_lines: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            syslog: None,
+            journald: false,
+            buffer_lines: default_buffer_lines(),
+        }
+    }
+}
+
+pub fn default_buffer_lines() -> usize {
+    1000
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyslogConfig {
+    pub address: String,
+    #[serde(default)]
+    pub protocol: SyslogProtocol,
+    #[serde(default = "default_syslog_app_name")]
+    pub app_name: String,
+    #[serde(default = "default_syslog_facility")]
+    pub facility: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogProtocol {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+fn default_syslog_app_name() -> String {
+    "fireplace_api".to_string()
+}
+
+fn default_syslog_facility() -> u8 {
+    16 // local0
+}
+
+/// How the REST API is served. Read once at startup; changes need a restart.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// PEM certificate chain; with `tls_key` the API is served over HTTPS instead of HTTP
+    #[serde(default)]
+    pub tls_cert: Option<String>,
+    /// PEM private key for `tls_cert`
+    #[serde(default)]
+    pub tls_key: Option<String>,
+}
+
+impl ServerConfig {
+    /// Certificate and key paths, if HTTPS is configured
+    pub fn tls(&self) -> Option<(&str, &str)> {
+        Some((self.tls_cert.as_deref()?, self.tls_key.as_deref()?))
+    }
+}
+
+/// Where runtime state (schedules, ...) is persisted between restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    #[serde(default = "default_storage_dir")]
+    pub dir: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_storage_dir(),
+        }
+    }
+}
+
+fn default_storage_dir() -> String {
+    "data".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerConfig {
+    #[serde(default)]
+    pub missed_run_policy: MissedRunPolicy,
+    /// How late a missed run may still start under `run_if_within_grace`
+    #[serde(default = "default_grace_period", alias = "grace_minutes", with = "crate::duration::minutes")]
+    pub grace_period: Duration,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            missed_run_policy: MissedRunPolicy::default(),
+            grace_period: default_grace_period(),
+        }
+    }
+}
+
+/// Shadow period for configs pushed through the canary endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanaryConfig {
+    /// How long a canary config must shadow the live one before it can be promoted
+    #[serde(default = "default_shadow_period", alias = "shadow_minutes", with = "crate::duration::minutes")]
+    pub shadow_period: Duration,
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        Self {
+            shadow_period: default_shadow_period(),
+        }
+    }
+}
+
+fn default_shadow_period() -> Duration {
+    Duration::from_secs(30 * 60)
+}
+
+/// Burn-hour statistics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsConfig {
+    /// First day of the heating season as `MM-DD`
+    #[serde(default = "default_season_start")]
+    pub season_start: String,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            season_start: default_season_start(),
+        }
+    }
+}
+
+fn default_season_start() -> String {
+    "09-01".to_string()
+}
+
+impl StatsConfig {
+    /// The season start as (month, day); None if it isn't a valid `MM-DD`
+    pub fn season_start(&self) -> Option<(u32, u32)> {
+        let (month, day) = self.season_start.split_once('-')?;
+        let (month, day) = (month.parse().ok()?, day.parse().ok()?);
+        // Any leap year will do to accept 02-29
+        chrono::NaiveDate::from_ymd_opt(2024, month, day).map(|_| (month, day))
+    }
+}
+
+/// What happens on SIGTERM/SIGINT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownConfig {
+    /// Drive devices with `safe_state = "off"` off on the way down; otherwise relays are left as they are
+    #[serde(default)]
+    pub apply_safe_states: bool,
+    /// How long open requests, WebSockets and event streams get to finish
+    #[serde(default = "default_drain_timeout", with = "crate::duration::seconds")]
+    pub drain_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            apply_safe_states: false,
+            drain_timeout: default_drain_timeout(),
+        }
+    }
+}
+
+fn default_drain_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// What to do at startup about schedule runs missed while the server was down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissedRunPolicy {
+    #[default]
+    Skip,
+    RunIfWithinGrace,
+    AlwaysRunOnce,
+}
+
+fn default_grace_period() -> Duration {
+    Duration::from_secs(15 * 60)
 }
 
 impl Config {
     pub fn load(path: &str) -> crate::error::Result<Self> {
         let content = std::fs::read_to_string(path)
             .map_err(|e| crate::error::ApiError::ConfigError(format!("Failed to read config: {}", e)))?;
-        
-        toml::from_str(&content)
-            .map_err(|e| crate::error::ApiError::ConfigError(format!("Failed to parse config: {}", e)))
+        Self::parse(&content)
+    }
+
+    /// Parse and validate config file contents
+    pub fn parse(content: &str) -> crate::error::Result<Self> {
+        let mut raw: toml::Table = toml::from_str(content)
+            .map_err(|e| crate::error::ApiError::ConfigError(format!("Failed to parse config: {}", e)))?;
+        let secret_values = crate::secrets::resolve(&mut raw)?;
+
+        // Track the key path so errors name the offending field, e.g. `safety.max_pulse_duration`
+        let mut config: Self = serde_path_to_error::deserialize(toml::Value::Table(raw)).map_err(|e| {
+            crate::error::ApiError::ConfigError(format!("Failed to parse config: {}: {}", e.path(), e.inner()))
+        })?;
+        config.secret_values = secret_values;
+        config.normalize();
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Move legacy `[pins]` tables into the `devices` lists they stand for, and number the
+    /// pins of devices on an expander or sent over RF or IR
+    fn normalize(&mut self) {
+        if let Some(pins) = self.pins.take() {
+            let mut devices = pins.into_devices();
+            devices.append(&mut self.devices);
+            self.devices = devices;
+        }
+        for room in &mut self.rooms {
+            if let Some(pins) = room.pins.take() {
+                let mut devices = pins.into_devices();
+                devices.append(&mut room.devices);
+                room.devices = devices;
+            }
+        }
+        let devices = self.devices.iter_mut().chain(self.rooms.iter_mut().flat_map(|r| r.devices.iter_mut()));
+        let mut used: HashSet<u32> = devices.map(|d| d.pin).collect();
+        let mut next_rf_pin = crate::rf::FIRST_RF_PIN;
+        let devices = self.devices.iter_mut().chain(self.rooms.iter_mut().flat_map(|r| r.devices.iter_mut()));
+        for device in devices.filter(|d| d.pin == UNSET_PIN) {
+            if let Some(pin) = self.gpio.expander_pin(device) {
+                device.pin = pin;
+            } else if device.is_remote() && device.expander.is_none() {
+                while used.contains(&next_rf_pin) || self.gpio.expander_for(next_rf_pin).is_some() {
+                    next_rf_pin += 1;
+                }
+                device.pin = next_rf_pin;
+                used.insert(next_rf_pin);
+            }
+        }
+    }
+
+    /// Check that every configured pin exists under the configured numbering scheme,
+    /// that room names are unique, that no pin is claimed twice, and that the device
+    /// dependency graph has no conflicts
+    pub fn validate(&self) -> crate::error::Result<()> {
+        let numbering = self.gpio.numbering;
+        let invalid = |msg: String| crate::error::ApiError::ConfigError(format!("Invalid config: {}", msg));
+
+        let mut room_names = HashSet::new();
+        let mut claimed: HashMap<u32, String> = HashMap::new();
+        for (index, zone) in self.zones().enumerate() {
+            if !room_names.insert(zone.name) {
+                return Err(invalid(format!("room '{}' is defined more than once", zone.name)));
+            }
+
+            let prefix = if index == 0 { "devices".to_string() } else { format!("rooms[{}].devices", index - 1) };
+            let mut device_names = HashSet::new();
+            for (position, device) in zone.devices.iter().enumerate() {
+                let field = format!("{}[{}]", prefix, position);
+                if device.name.trim().is_empty() {
+                    return Err(invalid(format!("{} has no name", field)));
+                }
+                if !device_names.insert(device.name.to_lowercase()) {
+                    return Err(invalid(format!("device '{}' is defined more than once in {}", device.name, zone.name)));
+                }
+                if device.monitor.is_some() && device.kind != DeviceKind::Fireplace {
+                    return Err(invalid(format!("{}: only fireplaces take a monitor pin", field)));
+                }
+                if device.is_remote() {
+                    let remote = if device.rf.is_some() { "RF" } else { "IR" };
+                    if device.expander.is_some() || (device.rf.is_some() && device.ir.is_some()) {
+                        return Err(invalid(format!("{}: a device is on an expander, sent over RF or sent over IR, only one", field)));
+                    }
+                    if let Some(rf) = &device.rf {
+                        if !self.gpio.rf_transmitters.iter().any(|t| t.name == rf.transmitter) {
+                            return Err(invalid(format!("{}: no rf transmitter named '{}'", field, rf.transmitter)));
+                        }
+                    }
+                    if let Some(ir) = &device.ir {
+                        ir.validate().map_err(|e| invalid(format!("{}: {}", field, e)))?;
+                    }
+                    if crate::pinout::to_bcm(numbering, device.pin).is_some() || self.gpio.expander_for(device.pin).is_some() {
+                        return Err(invalid(format!("{}: pin {} of an {} device may not be a GPIO pin", field, device.pin, remote)));
+                    }
+                    if device.active_low {
+                        return Err(invalid(format!("{}: an {} device can't be active-low", field, remote)));
+                    }
+                } else if let Some(expander) = &device.expander {
+                    if !self.gpio.expanders.iter().any(|e| &e.name == expander) {
+                        return Err(invalid(format!("{}: no expander named '{}'", field, expander)));
+                    }
+                    match self.gpio.expander_pin(device) {
+                        None => return Err(invalid(format!("{}: an expander needs a port (A or B) and a bit (0-7)", field))),
+                        Some(pin) if pin != device.pin => {
+                            return Err(invalid(format!("{}: pin {} isn't {} on '{}'", field, device.pin, pin, expander)));
+                        }
+                        Some(_) => {}
+                    }
+                } else if device.pin == UNSET_PIN {
+                    return Err(invalid(format!("{} needs a pin, or an expander with a port and bit", field)));
+                }
+
+                let mut pins = vec![(format!("{}.pin", field), device.pin)];
+                pins.extend(device.monitor.map(|pin| (format!("{}.monitor", field), pin)));
+                for (field, pin) in pins {
+                    if let Some(other) = claimed.insert(pin, field.clone()) {
+                        return Err(invalid(format!("{} and {} both use pin {}", other, field, pin)));
+                    }
+                }
+            }
+        }
+
+        let mut group_names = HashSet::new();
+        let mut group_pins: HashMap<u32, &str> = HashMap::new();
+        for (index, group) in self.groups.iter().enumerate() {
+            let room = self.zone(group.room.as_deref())?.name;
+            let zone = self.zone(Some(room))?;
+            if DEVICE_KINDS.contains(&group.name.to_lowercase().as_str()) || zone.device(&group.name).is_some() {
+                return Err(invalid(format!("groups[{}] may not be named '{}'", index, group.name)));
+            }
+            if !group_names.insert((room, group.name.to_lowercase())) {
+                return Err(invalid(format!("group '{}' is defined more than once in {}", group.name, room)));
+            }
+            if group.pins.is_empty() {
+                return Err(invalid(format!("group '{}' has no pins", group.name)));
+            }
+            for pin in &group.pins {
+                if let Some(other) = group_pins.insert(*pin, &group.name) {
+                    return Err(invalid(format!("groups '{}' and '{}' both use pin {}", other, group.name, pin)));
+                }
+                if !self.is_gpio_pin(*pin) {
+                    return Err(invalid(format!(
+                        "groups[{}].pins: {} is not a GPIO pin in {:?} numbering",
+                        index, pin, numbering
+                    )));
+                }
+            }
+        }
+
+        for sensor in &self.sensors {
+            if let crate::sensors::SensorKind::Dht22 { pin, .. } = sensor.kind {
+                let field = format!("sensors.{}.pin", sensor.id);
+                if let Some(other) = claimed.insert(pin, field.clone()) {
+                    return Err(invalid(format!("{} and {} both use pin {}", other, field, pin)));
+                }
+            }
+        }
+        if let Some(led) = &self.status_led {
+            if let Some(other) = claimed.insert(led.pin, "status_led.pin".to_string()) {
+                return Err(invalid(format!("{} and status_led.pin both use pin {}", other, led.pin)));
+            }
+        }
+        for input in &self.inputs {
+            let field = format!("inputs.{}.pin", input.name);
+            if let Some(other) = claimed.insert(input.pin, field.clone()) {
+                return Err(invalid(format!("{} and {} both use pin {}", other, field, input.pin)));
+            }
+        }
+
+        let mut pins: Vec<(String, u32)> = claimed.into_iter().map(|(pin, field)| (field, pin)).collect();
+        pins.extend(self.power.as_ref().map(|p| ("power.on_battery_pin".to_string(), p.on_battery_pin)));
+        pins.extend(self.gpio.active_low_pins.iter().map(|p| ("gpio.active_low_pins".to_string(), *p)));
+
+        for (field, pin) in pins {
+            if !self.is_gpio_pin(pin) {
+                return Err(invalid(format!(
+                    "{} = {} is not a GPIO pin in {:?} numbering",
+                    field, pin, numbering
+                )));
+            }
+        }
+
+        if let Some(proxy) = &self.auth.trusted_proxy {
+            proxy.validate().map_err(invalid)?;
+        }
+        if let Some(jwt) = &self.auth.jwt {
+            jwt.validate().map_err(invalid)?;
+        }
+        self.security.validate().map_err(invalid)?;
+        for rule in &self.interlocks {
+            rule.validate(self).map_err(invalid)?;
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.validate().map_err(invalid)?;
+        }
+        if let Some(telegram) = &self.telegram {
+            telegram.validate().map_err(invalid)?;
+        }
+        if let Some(notifications) = &self.notifications {
+            notifications.validate().map_err(invalid)?;
+            let telegram_alerts = self.telegram.as_ref().is_some_and(|t| t.alerts);
+            if notifications.ntfy.is_none() && notifications.pushover.is_none() && !telegram_alerts {
+                return Err(invalid(
+                    "notifications: configure [notifications.ntfy], [notifications.pushover] or a [telegram] bot"
+                        .to_string(),
+                ));
+            }
+        }
+        for webhook in &self.webhooks {
+            webhook.validate().map_err(invalid)?;
+        }
+        let mut sensor_ids = HashSet::new();
+        for sensor in &self.sensors {
+            sensor.validate(self).map_err(invalid)?;
+            if !sensor_ids.insert(sensor.id.as_str()) {
+                return Err(invalid(format!("sensor '{}' is defined more than once", sensor.id)));
+            }
+        }
+        let mut input_names = HashSet::new();
+        for input in &self.inputs {
+            input.validate(self).map_err(invalid)?;
+            if !input_names.insert(input.name.as_str()) {
+                return Err(invalid(format!("input '{}' is defined more than once", input.name)));
+            }
+        }
+        if let Some(led) = &self.status_led {
+            led.validate(self).map_err(invalid)?;
+        }
+        let mut idle_inputs = HashSet::new();
+        for entry in &self.idle_off {
+            entry.validate(self).map_err(invalid)?;
+            if !idle_inputs.insert(entry.input.as_str()) {
+                return Err(invalid(format!("idle_off: input '{}' is used more than once", entry.input)));
+            }
+        }
+        self.sensor_history.validate().map_err(invalid)?;
+        self.selftest.validate(self).map_err(invalid)?;
+        if let Some(verify) = &self.gpio.verify {
+            verify.validate().map_err(invalid)?;
+        }
+        self.gpio.retry.validate().map_err(invalid)?;
+        self.gpio.validate_expanders().map_err(invalid)?;
+        let mut transmitter_names = HashSet::new();
+        for transmitter in &self.gpio.rf_transmitters {
+            transmitter.validate().map_err(invalid)?;
+            if !transmitter_names.insert(transmitter.name.as_str()) {
+                return Err(invalid(format!("rf transmitter '{}' is defined more than once", transmitter.name)));
+            }
+        }
+        self.gpio.lirc.validate().map_err(invalid)?;
+        if self.safety.pulse_duration.is_zero() || self.safety.pulse_duration > self.safety.max_pulse_duration {
+            return Err(invalid("safety.pulse_duration must be between 1ms and max_pulse_duration".to_string()));
+        }
+        if self.safety.cycle_delay > self.safety.max_cycle_delay {
+            return Err(invalid("safety.cycle_delay must not exceed max_cycle_delay".to_string()));
+        }
+        if let Some(limit) = self.safety.max_temperature_c {
+            if !limit.is_finite() || !self.safety.temperature_reset_c().is_finite() {
+                return Err(invalid("safety.max_temperature_c and temperature_reset_c must be numbers".to_string()));
+            }
+            if self.safety.temperature_reset_c() >= limit {
+                return Err(invalid("safety.temperature_reset_c must be below max_temperature_c".to_string()));
+            }
+        }
+        if let Some(thermostat) = &self.thermostat {
+            thermostat.validate(self).map_err(invalid)?;
+        }
+        let mut controlled_fans = HashSet::new();
+        for control in &self.fan_control {
+            control.validate(self).map_err(invalid)?;
+            if let Some((room, fan)) = control.target(self) {
+                if !controlled_fans.insert((room, fan.name.as_str())) {
+                    return Err(invalid(format!("fan '{}' in '{}' has more than one [[fan_control]] entry", fan.name, room)));
+                }
+            }
+        }
+        let mut scene_names = HashSet::new();
+        for scene in &self.scenes {
+            scene.validate(self).map_err(|e| invalid(e.to_string()))?;
+            if !scene_names.insert(scene.name.to_lowercase()) {
+                return Err(invalid(format!("scene '{}' is defined more than once", scene.name)));
+            }
+        }
+        let mut rule_names = HashSet::new();
+        for rule in &self.rules {
+            rule.validate(self).map_err(|e| invalid(e.to_string()))?;
+            if !rule_names.insert(rule.name.to_lowercase()) {
+                return Err(invalid(format!("rule '{}' is defined more than once", rule.name)));
+            }
+        }
+        if let Some(failover) = &self.failover {
+            failover.validate().map_err(invalid)?;
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.validate().map_err(invalid)?;
+        }
+        if self.server.tls_cert.is_some() != self.server.tls_key.is_some() {
+            return Err(invalid("server.tls_cert and server.tls_key must be set together".to_string()));
+        }
+        if self.stats.season_start().is_none() {
+            return Err(invalid(format!(
+                "stats.season_start = '{}' is not a MM-DD date",
+                self.stats.season_start
+            )));
+        }
+
+        crate::graph::DeviceGraph::build(self).validate().map_err(invalid)?;
+        Ok(())
+    }
+
+    /// The primary room followed by every `[[rooms]]` entry
+    pub fn zones(&self) -> impl Iterator<Item = Zone<'_>> {
+        std::iter::once(Zone {
+            name: &self.room.name,
+            devices: &self.devices,
+        })
+        .chain(self.rooms.iter().map(|r| Zone {
+            name: &r.name,
+            devices: &r.devices,
+        }))
+    }
+
+    /// Find a room by name; `None` selects the primary room
+    pub fn zone(&self, name: Option<&str>) -> crate::error::Result<Zone<'_>> {
+        match name {
+            None => Ok(self.zones().next().expect("primary room always exists")),
+            Some(name) => self
+                .zones()
+                .find(|z| z.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| crate::error::ApiError::UnknownRoom(name.to_string())),
+        }
     }
 
     pub fn default() -> Self {
@@ -44,36 +1254,242 @@ impl Config {
                 name: "family_room".to_string(),
                 device_ip: Some("127.0.0.1".to_string()),
             },
-            pins: PinConfig {
+            pins: None,
+            devices: PinConfig {
                 fireplace: 17,
                 fireplace_fan: 27,
                 lights: Some(22),
                 secondary_device: Some(23),
-            },
+                monitor: None,
+            }
+            .into_devices(),
+            rooms: Vec::new(),
+            groups: Vec::new(),
+            interlocks: Vec::new(),
+            webhooks: Vec::new(),
+            sensors: Vec::new(),
+            inputs: Vec::new(),
+            idle_off: Vec::new(),
+            sensor_history: crate::sensor_history::SensorHistoryConfig::default(),
+            selftest: crate::selftest::SelfTestConfig::default(),
+            thermostat: None,
+            fan_control: Vec::new(),
+            scenes: Vec::new(),
+            rules: Vec::new(),
             safety: SafetyConfig {
-                max_pulse_duration_ms: 5000,
+                max_pulse_duration: Duration::from_secs(5),
                 require_confirmation: false,
+                confirmation_timeout: default_confirmation_timeout(),
+                pulse_duration: default_pulse_duration(),
+                cycle_delay: default_cycle_delay(),
+                max_cycle_delay: default_max_cycle_delay(),
+                max_cycles: default_max_cycles(),
+                ignition_retries: 0,
+                ignition_retry_delay: default_ignition_retry_delay(),
+                max_runtime: None,
+                short_cycle_delay: Duration::ZERO,
+                max_temperature_c: None,
+                temperature_reset_c: None,
             },
+            gpio: GpioConfig::default(),
+            power: None,
+            status_led: None,
+            logging: LoggingConfig::default(),
+            server: ServerConfig::default(),
+            storage: StorageConfig::default(),
+            scheduler: SchedulerConfig::default(),
+            secrets: crate::secrets::SecretsConfig::default(),
+            canary: CanaryConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            stats: StatsConfig::default(),
+            auth: crate::auth::AuthConfig::default(),
+            security: crate::auth::SecurityConfig::default(),
+            mqtt: None,
+            notifications: None,
+            telegram: None,
+            failover: None,
+            rate_limit: None,
+            secret_values: crate::secrets::SecretValues::default(),
+            generation: 0,
         }
     }
 
-    pub fn get_pin_name(&self, pin: u32) -> Option<String> {
-        if pin == self.pins.fireplace {
-            Some("fireplace".to_string())
-        } else if pin == self.pins.fireplace_fan {
-            Some("fireplace_fan".to_string())
-        } else if let Some(lights_pin) = self.pins.lights {
-            if pin == lights_pin {
-                return Some("lights".to_string());
+    /// Serialize part of the config for output. `Secret` fields are masked by their type;
+    /// values substituted from secret references are masked here.
+    pub fn redacted<T: Serialize>(&self, section: &T) -> crate::error::Result<serde_json::Value> {
+        let mut value = serde_json::to_value(section).map_err(|_| crate::error::ApiError::InternalError)?;
+        self.secret_values.redact(&mut value);
+        Ok(value)
+    }
+
+    /// Dotted paths of every setting that differs between `self` and `other`.
+    /// `Secret` fields serialize masked, so a changed secret is not listed.
+    pub fn diff(&self, other: &Config) -> Vec<String> {
+        let old = serde_json::to_value(self).unwrap_or_default();
+        let new = serde_json::to_value(other).unwrap_or_default();
+        let mut changed = Vec::new();
+        diff_values("", &old, &new, &mut changed);
+        changed
+    }
+
+    /// Find a device group by name within a room
+    pub fn group(&self, room: &str, name: &str) -> Option<&DeviceGroup> {
+        self.groups.iter().find(|g| {
+            g.name.eq_ignore_ascii_case(name)
+                && self.zone(g.room.as_deref()).is_ok_and(|z| z.name == room)
+        })
+    }
+
+    /// Output pins behind a device in a room: a device name or kind, or a group name
+    pub fn device_pins(&self, room: &str, device: &str) -> Option<Vec<u32>> {
+        let zone = self.zone(Some(room)).ok()?;
+        match zone.device(device) {
+            Some(device) => Some(vec![device.pin]),
+            None => self.group(zone.name, device).map(|g| g.pins.clone()),
+        }
+    }
+
+    /// Find a `[[rules]]` entry by name
+    pub fn rule(&self, name: &str) -> Option<&crate::rules::Rule> {
+        self.rules.iter().find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Find a `[[scenes]]` entry by name
+    pub fn scene(&self, name: &str) -> Option<&crate::scenes::Scene> {
+        self.scenes.iter().find(|s| s.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Find a `[[sensors]]` entry by ID
+    pub fn sensor(&self, id: &str) -> Option<&crate::sensors::SensorConfig> {
+        self.sensors.iter().find(|s| s.id == id)
+    }
+
+    /// Find an `[[inputs]]` entry by name
+    pub fn input(&self, name: &str) -> Option<&crate::inputs::InputConfig> {
+        self.inputs.iter().find(|i| i.name == name)
+    }
+
+    /// Find the room and device an output pin belongs to
+    pub fn find_pin(&self, pin: u32) -> Option<(Zone<'_>, &DeviceConfig)> {
+        self.zones()
+            .find_map(|zone| zone.devices.iter().find(|d| d.pin == pin).map(|device| (zone, device)))
+    }
+
+    /// Whether a pin exists: a header pin in the configured numbering, an expander pin, or
+    /// the pin number of an RF or IR device
+    pub fn is_gpio_pin(&self, pin: u32) -> bool {
+        crate::pinout::to_bcm(self.gpio.numbering, pin).is_some()
+            || self.gpio.expander_for(pin).is_some()
+            || self.find_pin(pin).is_some_and(|(_, device)| device.is_remote())
+    }
+
+    /// Every device output pin across all rooms
+    pub fn output_pins(&self) -> Vec<u32> {
+        self.zones().flat_map(|zone| zone.devices.iter().map(|d| d.pin)).collect()
+    }
+
+    /// Pins wired active-low, globally listed or flagged per device
+    pub fn active_low_pins(&self) -> HashSet<u32> {
+        self.zones()
+            .flat_map(|zone| zone.devices.iter())
+            .filter(|d| d.active_low)
+            .map(|d| d.pin)
+            .chain(self.gpio.active_low_pins.iter().copied())
+            .collect()
+    }
+}
+
+fn diff_values(path: &str, old: &serde_json::Value, new: &serde_json::Value, changed: &mut Vec<String>) {
+    match (old, new) {
+        (serde_json::Value::Object(old), serde_json::Value::Object(new)) => {
+            let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                let null = serde_json::Value::Null;
+                diff_values(&child, old.get(key).unwrap_or(&null), new.get(key).unwrap_or(&null), changed);
             }
-            None
-        } else if let Some(secondary_pin) = self.pins.secondary_device {
-            if pin == secondary_pin {
-                return Some("secondary_device".to_string());
+        }
+        // Same-length lists, like `devices`, are compared entry by entry
+        (serde_json::Value::Array(old), serde_json::Value::Array(new)) if old.len() == new.len() => {
+            for (index, (old, new)) in old.iter().zip(new).enumerate() {
+                diff_values(&format!("{}[{}]", path, index), old, new, changed);
             }
-            None
-        } else {
-            None
         }
+        _ if old != new => changed.push(path.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"
+[room]
+name = "den"
+
+[[devices]]
+name = "fireplace"
+pin = 17
+kind = "fireplace"
+
+[[devices]]
+name = "fan"
+pin = 27
+kind = "fan"
+
+[safety]
+max_pulse_duration = "5s"
+require_confirmation = false
+"#;
+
+    fn parse(extra: &str) -> crate::error::Result<Config> {
+        Config::parse(&format!("{}{}", BASE, extra))
+    }
+
+    fn rejected(extra: &str, expected: &str) {
+        match parse(extra) {
+            Err(e) => assert!(e.to_string().contains(expected), "{}", e),
+            Ok(_) => panic!("accepted {}", extra),
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+        parse("").unwrap();
+    }
+
+    #[test]
+    fn shipped_configs_are_valid() {
+        for path in ["config/family_room.toml", "config/master_bedroom.toml"] {
+            Config::load(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        }
+    }
+
+    #[test]
+    fn pulse_duration_must_fit_the_maximum() {
+        rejected("pulse_duration = \"6s\"\n", "safety.pulse_duration");
+        rejected("pulse_duration = \"0ms\"\n", "safety.pulse_duration");
+        parse("pulse_duration = \"5s\"\n").unwrap();
+    }
+
+    #[test]
+    fn cycle_delay_must_fit_the_maximum() {
+        rejected("cycle_delay = \"2s\"\nmax_cycle_delay = \"1s\"\n", "safety.cycle_delay");
+    }
+
+    #[test]
+    fn temperature_reset_must_be_below_the_limit() {
+        rejected("max_temperature_c = 40.0\ntemperature_reset_c = 45.0\n", "temperature_reset_c");
+    }
+
+    #[test]
+    fn a_pin_cannot_be_claimed_twice() {
+        let config = BASE.replace("pin = 27", "pin = 17");
+        let error = Config::parse(&config).unwrap_err().to_string();
+        assert!(error.contains("both use pin 17"), "{}", error);
     }
 }
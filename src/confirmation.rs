@@ -0,0 +1,56 @@
+﻿use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// An ON request held back until it is confirmed
+struct Pending {
+    room: String,
+    device: String,
+    expires: DateTime<Local>,
+}
+
+/// ON requests waiting for their confirming follow-up under `safety.require_confirmation`
+#[derive(Default)]
+pub struct ConfirmationStore {
+    pending: HashMap<String, Pending>,
+}
+
+impl ConfirmationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold back an ON for `device`; returns the token that confirms it and when it expires
+    pub fn issue(&mut self, room: &str, device: &str, timeout: Duration) -> (String, DateTime<Local>) {
+        let now = Local::now();
+        self.pending.retain(|_, pending| pending.expires > now);
+
+        let token = Uuid::new_v4().simple().to_string();
+        let expires = now + chrono::Duration::from_std(timeout).unwrap_or_default();
+        self.pending.insert(
+            token.clone(),
+            Pending {
+                room: room.to_string(),
+                device: device.to_string(),
+                expires,
+            },
+        );
+        (token, expires)
+    }
+
+    /// Use up a token. Only one issued for this device that hasn't expired confirms it.
+    pub fn confirm(&mut self, token: &str, room: &str, device: &str) -> bool {
+        let now = Local::now();
+        self.pending.retain(|_, pending| pending.expires > now);
+
+        let matches = self
+            .pending
+            .get(token)
+            .is_some_and(|pending| pending.room == room && pending.device.eq_ignore_ascii_case(device));
+        if matches {
+            self.pending.remove(token);
+        }
+        matches
+    }
+}
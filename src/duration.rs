@@ -0,0 +1,96 @@
+﻿use serde::{de, Deserializer, Serializer};
+use std::fmt;
+use std::time::Duration;
+
+/// Serde support for time-valued config fields. A field takes a humantime string such as
+/// `"500ms"`, `"90s"` or `"3h30m"`, or a bare number in the unit its old `_ms`, `_seconds`
+/// or `_minutes` name used, so existing configs keep their meaning.
+struct DurationVisitor {
+    unit: &'static str,
+    from_number: fn(u64) -> Duration,
+}
+
+impl de::Visitor<'_> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a duration such as \"500ms\", \"90s\" or \"3h30m\", or a number of {}", self.unit)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Duration, E> {
+        Ok((self.from_number)(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Duration, E> {
+        u64::try_from(value)
+            .map(self.from_number)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Duration, E> {
+        humantime::parse_duration(value).map_err(|e| E::custom(format!("invalid duration '{}': {}", value, e)))
+    }
+}
+
+fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_duration(*value))
+}
+
+/// Bare numbers are milliseconds
+pub mod millis {
+    use super::*;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        deserializer.deserialize_any(DurationVisitor { unit: "milliseconds", from_number: Duration::from_millis })
+    }
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize(value, serializer)
+    }
+}
+
+/// Bare numbers are seconds
+pub mod seconds {
+    use super::*;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        deserializer.deserialize_any(DurationVisitor { unit: "seconds", from_number: Duration::from_secs })
+    }
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize(value, serializer)
+    }
+}
+
+/// Bare numbers are minutes
+pub mod minutes {
+    use super::*;
+
+    fn from_minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes.saturating_mul(60))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        deserializer.deserialize_any(DurationVisitor { unit: "minutes", from_number: from_minutes })
+    }
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize(value, serializer)
+    }
+
+    /// For optional fields; pair with `#[serde(default)]`
+    pub mod option {
+        use super::*;
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+            super::deserialize(deserializer).map(Some)
+        }
+
+        pub fn serialize<S: Serializer>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+    }
+}
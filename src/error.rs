@@ -1,9 +1,10 @@
 ﻿use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde_json::json;
+use std::time::Duration;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ApiError {
@@ -16,52 +17,629 @@ pub enum ApiError {
     #[error("Invalid PIN")]
     InvalidPin,
 
+    #[error("Unknown room: {0}")]
+    UnknownRoom(String),
+
+    #[error("Unknown device: {0}")]
+    UnknownDevice(String),
+
+    #[error("Unknown sensor: {0}")]
+    UnknownSensor(String),
+
+    #[error("Invalid pulse duration (max {0}ms)")]
+    InvalidPulseDuration(u32),
+
+    #[error("Invalid cycle count (max {0})")]
+    InvalidCycles(u32),
+
+    #[error("Invalid cycle delay (max {0}ms)")]
+    InvalidCycleDelay(u32),
+
+    #[error("Device {room}/{device} is latched in fault")]
+    DeviceFaulted { room: String, device: String },
+
+    #[error("Device {device} switched too recently")]
+    DwellTime { device: String, retry_after: Duration },
+
+    #[error("Device {device} was turned off too recently to turn back on")]
+    ShortCycle { device: String, retry_after: Duration },
+
+    #[error("Too many control requests")]
+    RateLimited { retry_after: Duration },
+
+    #[error("Invalid confirmation token")]
+    InvalidConfirmation,
+
+    #[error("Control is locked ({reason:?})")]
+    Locked { reason: crate::lockout::LockReason, since: String },
+
+    #[error("Control is not locked")]
+    NotLocked,
+
+    #[error("Room {room} is over temperature")]
+    OverTemperature { room: String, temperature_c: f64, reset_below_c: f64 },
+
+    #[error("Device {device} is running an interlock cooldown")]
+    InterlockActive { device: String, after: String, until: String },
+
+    #[error("Invalid timer duration")]
+    InvalidTimerDuration,
+
+    #[error("Timer not found")]
+    TimerNotFound,
+
+    #[error("No idle countdown for input {0}")]
+    IdleCountdownNotFound(String),
+
+    #[error("Command not found")]
+    CommandNotFound,
+
+    #[error("Invalid schedule: {0}")]
+    InvalidSchedule(String),
+
+    #[error("Schedule not found")]
+    ScheduleNotFound,
+
+    #[error("Invalid sequence: {0}")]
+    InvalidSequence(String),
+
+    #[error("Pin {pin} reads {actual:?}, expected {expected:?}")]
+    PinAssertionFailed { pin: u32, expected: crate::gpio::PinState, actual: crate::gpio::PinState },
+
+    #[error("Invalid rule: {0}")]
+    InvalidRule(String),
+
+    #[error("Rule not found: {0}")]
+    RuleNotFound(String),
+
+    #[error("Rule already exists: {0}")]
+    RuleExists(String),
+
+    #[error("Rule is defined in the config file: {0}")]
+    RuleReadOnly(String),
+
+    #[error("Invalid scene: {0}")]
+    InvalidScene(String),
+
+    #[error("Scene not found: {0}")]
+    SceneNotFound(String),
+
+    #[error("Scene already exists: {0}")]
+    SceneExists(String),
+
+    #[error("Scene is defined in the config file: {0}")]
+    SceneReadOnly(String),
+
+    #[error("Not authenticated")]
+    Unauthenticated,
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Server is in setup mode")]
+    SetupRequired,
+
+    #[error("Invalid setup token")]
+    InvalidSetupToken,
+
+    #[error("Server is already configured")]
+    AlreadyConfigured,
+
+    #[error("Invalid alias: {0}")]
+    InvalidAlias(String),
+
+    #[error("No aliases set")]
+    AliasNotFound,
+
+    #[error("Invalid simulation: {0}")]
+    InvalidSimulation(String),
+
+    #[error("Invalid self-test: {0}")]
+    InvalidSelfTest(String),
+
+    #[error("No canary config running")]
+    NoCanary,
+
+    #[error("Canary config not promotable until {0}")]
+    CanaryNotReady(String),
+
+    #[error("No safety timer running")]
+    NoSafetyTimer,
+
+    #[error("This node is the failover standby")]
+    StandbyNode,
+
+    #[error("Failover is not configured")]
+    FailoverNotConfigured,
+
+    #[error("Thermostat is not configured")]
+    ThermostatNotConfigured,
+
+    #[error("Setpoint out of range ({min}-{max}°C)")]
+    InvalidSetpoint { min: f64, max: f64 },
+
+    #[error("Invalid log level")]
+    InvalidLogLevel,
+
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+
+    #[error("Verification failed for pin {pin} (monitor pin {monitor_pin})")]
+    VerificationFailed { pin: u32, monitor_pin: u32 },
+
+    #[error("Pin {pin} did not latch after {attempts} write(s)")]
+    WriteNotLatched { pin: u32, attempts: u32, expected: crate::gpio::PinState, actual: crate::gpio::PinState },
+
+    #[error("Pin {0} was written without being locked")]
+    PinNotHeld(u32),
+
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
-    #[error("GPIO error: {0}")]
-    GpioError(String),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Storage error: {0}")]
+    StorageError(String),
+
+    #[error("GPIO backend {backend} unavailable: {missing} not found")]
+    BackendUnavailable { backend: &'static str, missing: String },
+
+    #[error("GPIO error: {message}")]
+    GpioError { failure: crate::config::GpioFailure, message: String },
 
     #[error("Internal server error")]
     InternalError,
 }
 
+impl ApiError {
+    /// Stable machine-readable code for the error, sent as `code`. Clients branch on
+    /// this rather than the message, so a code never changes once released.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidCommand => "INVALID_COMMAND",
+            ApiError::InvalidAction => "INVALID_ACTION",
+            ApiError::InvalidPin => "INVALID_PIN",
+            ApiError::UnknownRoom(_) => "UNKNOWN_ROOM",
+            ApiError::UnknownDevice(_) => "UNKNOWN_DEVICE",
+            ApiError::UnknownSensor(_) => "UNKNOWN_SENSOR",
+            ApiError::InvalidPulseDuration(_) => "INVALID_PULSE_DURATION",
+            ApiError::InvalidCycles(_) => "INVALID_CYCLES",
+            ApiError::InvalidCycleDelay(_) => "INVALID_CYCLE_DELAY",
+            ApiError::DeviceFaulted { .. } => "DEVICE_FAULTED",
+            ApiError::DwellTime { .. } => "DWELL_TIME",
+            ApiError::ShortCycle { .. } => "SHORT_CYCLE",
+            ApiError::RateLimited { .. } => "RATE_LIMITED",
+            ApiError::InvalidConfirmation => "INVALID_CONFIRMATION",
+            ApiError::Locked { .. } => "DEVICE_LOCKED",
+            ApiError::NotLocked => "NOT_LOCKED",
+            ApiError::OverTemperature { .. } => "OVER_TEMPERATURE",
+            ApiError::InterlockActive { .. } => "COOLDOWN_ACTIVE",
+            ApiError::InvalidTimerDuration => "INVALID_TIMER_DURATION",
+            ApiError::TimerNotFound => "TIMER_NOT_FOUND",
+            ApiError::IdleCountdownNotFound(_) => "IDLE_COUNTDOWN_NOT_FOUND",
+            ApiError::CommandNotFound => "COMMAND_NOT_FOUND",
+            ApiError::InvalidSchedule(_) => "INVALID_SCHEDULE",
+            ApiError::ScheduleNotFound => "SCHEDULE_NOT_FOUND",
+            ApiError::InvalidSequence(_) => "INVALID_SEQUENCE",
+            ApiError::PinAssertionFailed { .. } => "PIN_ASSERTION_FAILED",
+            ApiError::InvalidRule(_) => "INVALID_RULE",
+            ApiError::RuleNotFound(_) => "RULE_NOT_FOUND",
+            ApiError::RuleExists(_) => "RULE_EXISTS",
+            ApiError::RuleReadOnly(_) => "RULE_READ_ONLY",
+            ApiError::InvalidScene(_) => "INVALID_SCENE",
+            ApiError::SceneNotFound(_) => "SCENE_NOT_FOUND",
+            ApiError::SceneExists(_) => "SCENE_EXISTS",
+            ApiError::SceneReadOnly(_) => "SCENE_READ_ONLY",
+            ApiError::Unauthenticated => "UNAUTHENTICATED",
+            ApiError::Forbidden(_) => "FORBIDDEN",
+            ApiError::SetupRequired => "SETUP_REQUIRED",
+            ApiError::InvalidSetupToken => "INVALID_SETUP_TOKEN",
+            ApiError::AlreadyConfigured => "ALREADY_CONFIGURED",
+            ApiError::InvalidAlias(_) => "INVALID_ALIAS",
+            ApiError::AliasNotFound => "ALIAS_NOT_FOUND",
+            ApiError::InvalidSimulation(_) => "INVALID_SIMULATION",
+            ApiError::InvalidSelfTest(_) => "INVALID_SELF_TEST",
+            ApiError::NoCanary => "NO_CANARY",
+            ApiError::CanaryNotReady(_) => "CANARY_NOT_READY",
+            ApiError::NoSafetyTimer => "NO_SAFETY_TIMER",
+            ApiError::StandbyNode => "STANDBY_NODE",
+            ApiError::FailoverNotConfigured => "FAILOVER_NOT_CONFIGURED",
+            ApiError::ThermostatNotConfigured => "THERMOSTAT_NOT_CONFIGURED",
+            ApiError::InvalidSetpoint { .. } => "INVALID_SETPOINT",
+            ApiError::InvalidLogLevel => "INVALID_LOG_LEVEL",
+            ApiError::InvalidQuery(_) => "INVALID_QUERY",
+            ApiError::VerificationFailed { .. } => "VERIFICATION_FAILED",
+            ApiError::WriteNotLatched { .. } => "WRITE_NOT_LATCHED",
+            ApiError::PinNotHeld(_) => "PIN_NOT_HELD",
+            ApiError::ConfigError(_) => "CONFIG_ERROR",
+            ApiError::InvalidConfig(_) => "INVALID_CONFIG",
+            ApiError::StorageError(_) => "STORAGE_ERROR",
+            ApiError::BackendUnavailable { .. } => "GPIO_BACKEND_UNAVAILABLE",
+            ApiError::GpioError { .. } => "GPIO_ERROR",
+            ApiError::InternalError => "INTERNAL_ERROR",
+        }
+    }
+
+    /// Structured fields of the error, sent as `details`
+    pub fn details(&self) -> Option<serde_json::Value> {
+        let details = match self {
+            ApiError::UnknownRoom(room) => json!({ "room": room }),
+            ApiError::UnknownDevice(device) => json!({ "device": device }),
+            ApiError::UnknownSensor(sensor) => json!({ "sensor": sensor }),
+            ApiError::InvalidPulseDuration(max_ms) => json!({ "max_ms": max_ms }),
+            ApiError::InvalidCycles(max) => json!({ "max": max }),
+            ApiError::InvalidCycleDelay(max_ms) => json!({ "max_ms": max_ms }),
+            ApiError::DeviceFaulted { room, device } => json!({ "room": room, "device": device }),
+            ApiError::DwellTime { device, retry_after } | ApiError::ShortCycle { device, retry_after } => {
+                json!({ "device": device, "retry_after_ms": retry_after.as_millis() as u64 })
+            }
+            ApiError::RateLimited { retry_after } => json!({ "retry_after_ms": retry_after.as_millis() as u64 }),
+            ApiError::Locked { reason, since } => json!({ "reason": reason, "since": since }),
+            ApiError::OverTemperature { room, temperature_c, reset_below_c } => {
+                json!({ "room": room, "temperature_c": temperature_c, "reset_below_c": reset_below_c })
+            }
+            ApiError::InterlockActive { device, after, until } => {
+                json!({ "device": device, "after": after, "until": until })
+            }
+            ApiError::PinAssertionFailed { pin, expected, actual } => {
+                json!({ "pin": pin, "expected": expected, "actual": actual })
+            }
+            ApiError::IdleCountdownNotFound(input) => json!({ "input": input }),
+            ApiError::RuleNotFound(rule) | ApiError::RuleExists(rule) | ApiError::RuleReadOnly(rule) => {
+                json!({ "rule": rule })
+            }
+            ApiError::SceneNotFound(scene) | ApiError::SceneExists(scene) | ApiError::SceneReadOnly(scene) => {
+                json!({ "scene": scene })
+            }
+            ApiError::CanaryNotReady(at) => json!({ "promotable_at": at }),
+            ApiError::InvalidSetpoint { min, max } => json!({ "min": min, "max": max }),
+            ApiError::VerificationFailed { pin, monitor_pin } => json!({ "pin": pin, "monitor_pin": monitor_pin }),
+            ApiError::WriteNotLatched { pin, attempts, expected, actual } => {
+                json!({ "pin": pin, "attempts": attempts, "expected": expected, "actual": actual })
+            }
+            ApiError::PinNotHeld(pin) => json!({ "pin": pin }),
+            ApiError::BackendUnavailable { backend, missing } => json!({ "backend": backend, "missing": missing }),
+            ApiError::GpioError { failure, .. } => json!({ "failure": failure }),
+            _ => return None,
+        };
+        Some(details)
+    }
+
+    /// How a GPIO backend call failed, if this is a backend failure
+    pub fn gpio_failure(&self) -> Option<crate::config::GpioFailure> {
+        match self {
+            ApiError::BackendUnavailable { .. } => Some(crate::config::GpioFailure::Unavailable),
+            ApiError::GpioError { failure, .. } => Some(*failure),
+            _ => None,
+        }
+    }
+}
+
+/// What a response's error was, kept in its extensions so middleware can re-render it
+#[derive(Debug, Clone)]
+pub struct ErrorInfo {
+    pub code: &'static str,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
+        let code = self.code();
+        let details = self.details();
+        let retry_after = match &self {
+            ApiError::DwellTime { retry_after, .. }
+            | ApiError::ShortCycle { retry_after, .. }
+            | ApiError::RateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        };
         let (status, message) = match self {
             ApiError::InvalidCommand => (
                 StatusCode::BAD_REQUEST,
-                "Invalid command type. Expected ''toggle''".to_string(),
+                "Invalid command type. Expected 'toggle'".to_string(),
             ),
             ApiError::InvalidAction => (
                 StatusCode::BAD_REQUEST,
-                "Invalid action. Expected ''ON'' or ''OFF''".to_string(),
+                "Invalid action. Expected 'ON' or 'OFF'".to_string(),
             ),
             ApiError::InvalidPin => (
                 StatusCode::BAD_REQUEST,
                 "Invalid GPIO pin".to_string(),
             ),
+            ApiError::UnknownRoom(room) => (
+                StatusCode::NOT_FOUND,
+                format!("Unknown room '{}'", room),
+            ),
+            ApiError::UnknownDevice(device) => (
+                StatusCode::NOT_FOUND,
+                format!("Unknown device '{}'", device),
+            ),
+            ApiError::UnknownSensor(sensor) => (
+                StatusCode::NOT_FOUND,
+                format!("Unknown sensor '{}'", sensor),
+            ),
+            ApiError::InvalidPulseDuration(max) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid pulse duration. Expected 1-{}ms", max),
+            ),
+            ApiError::InvalidCycles(max) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid cycle count. Expected 1-{}", max),
+            ),
+            ApiError::InvalidCycleDelay(max) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid cycle delay. Expected 0-{}ms", max),
+            ),
+            ApiError::DeviceFaulted { room, device } => (
+                StatusCode::CONFLICT,
+                format!(
+                    "Device '{}' in '{}' is latched in fault after failed ignition. Reset it via POST /api/v1/faults/reset",
+                    device, room
+                ),
+            ),
+            ApiError::DwellTime { device, retry_after } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "Device '{}' was switched too recently. It can be switched again in {}",
+                    device,
+                    humantime::format_duration(Duration::from_millis(retry_after.as_millis() as u64))
+                ),
+            ),
+            ApiError::ShortCycle { device, retry_after } => (
+                StatusCode::CONFLICT,
+                format!(
+                    "Device '{}' was turned off too recently to re-ignite. It can be turned on again in {}",
+                    device,
+                    humantime::format_duration(Duration::from_millis(retry_after.as_millis() as u64))
+                ),
+            ),
+            ApiError::RateLimited { retry_after } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "Too many control requests. Retry in {}",
+                    humantime::format_duration(Duration::from_millis(retry_after.as_millis() as u64))
+                ),
+            ),
+            ApiError::InvalidConfirmation => (
+                StatusCode::CONFLICT,
+                "Confirmation token is unknown, expired or for another device. Send the request without a token for a new one".to_string(),
+            ),
+            ApiError::Locked { reason, since } => (
+                StatusCode::LOCKED,
+                match reason {
+                    crate::lockout::LockReason::EmergencyStop => format!(
+                        "Control is locked by an emergency stop at {}. Release it via POST /api/v1/unlock",
+                        since
+                    ),
+                    crate::lockout::LockReason::ChildLock => format!(
+                        "Control is locked since {}. Release it via POST /api/v1/unlock",
+                        since
+                    ),
+                },
+            ),
+            ApiError::NotLocked => (
+                StatusCode::CONFLICT,
+                "Control is not locked".to_string(),
+            ),
+            ApiError::InterlockActive { device, after, until } => (
+                StatusCode::CONFLICT,
+                format!(
+                    "Device '{}' is kept on after '{}' turned off until {}",
+                    device, after, until
+                ),
+            ),
+            ApiError::OverTemperature { room, temperature_c, reset_below_c } => (
+                StatusCode::CONFLICT,
+                format!(
+                    "Room '{}' read {:.1}°C, over safety.max_temperature_c. Fireplaces stay off and fans on until it cools below {:.1}°C",
+                    room, temperature_c, reset_below_c
+                ),
+            ),
+            ApiError::InvalidTimerDuration => (
+                StatusCode::BAD_REQUEST,
+                "Invalid duration_minutes. Expected a positive number with action 'ON', for a device with the timer capability".to_string(),
+            ),
+            ApiError::TimerNotFound => (
+                StatusCode::NOT_FOUND,
+                "Timer not found".to_string(),
+            ),
+            ApiError::IdleCountdownNotFound(input) => (
+                StatusCode::NOT_FOUND,
+                format!("Input '{}' has no idle countdown running", input),
+            ),
+            ApiError::InvalidSchedule(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::ScheduleNotFound => (
+                StatusCode::NOT_FOUND,
+                "Schedule not found".to_string(),
+            ),
+            ApiError::InvalidSequence(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::PinAssertionFailed { pin, expected, actual } => (
+                StatusCode::CONFLICT,
+                format!("Pin {} reads {:?}, expected {:?}", pin, actual, expected),
+            ),
+            ApiError::InvalidRule(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::RuleNotFound(name) => (
+                StatusCode::NOT_FOUND,
+                format!("Rule '{}' not found", name),
+            ),
+            ApiError::RuleExists(name) => (
+                StatusCode::CONFLICT,
+                format!("Rule '{}' already exists", name),
+            ),
+            ApiError::RuleReadOnly(name) => (
+                StatusCode::CONFLICT,
+                format!("Rule '{}' is defined in the config file and can only be changed there", name),
+            ),
+            ApiError::InvalidScene(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::SceneNotFound(name) => (
+                StatusCode::NOT_FOUND,
+                format!("Scene '{}' not found", name),
+            ),
+            ApiError::SceneExists(name) => (
+                StatusCode::CONFLICT,
+                format!("Scene '{}' already exists", name),
+            ),
+            ApiError::SceneReadOnly(name) => (
+                StatusCode::CONFLICT,
+                format!("Scene '{}' is defined in the config file and can only be changed there", name),
+            ),
+            ApiError::CommandNotFound => (
+                StatusCode::NOT_FOUND,
+                "Command not found".to_string(),
+            ),
+            ApiError::Unauthenticated => (
+                StatusCode::UNAUTHORIZED,
+                "Not authenticated. Send a valid bearer token or sign in through the proxy".to_string(),
+            ),
+            ApiError::Forbidden(msg) => (
+                StatusCode::FORBIDDEN,
+                msg,
+            ),
+            ApiError::SetupRequired => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Server is in setup mode. Submit a config via POST /api/v1/setup".to_string(),
+            ),
+            ApiError::InvalidSetupToken => (
+                StatusCode::UNAUTHORIZED,
+                "Missing or invalid X-Setup-Token. The token is printed in the server log".to_string(),
+            ),
+            ApiError::AlreadyConfigured => (
+                StatusCode::CONFLICT,
+                "Server is already configured. Use the config reload or canary endpoints".to_string(),
+            ),
+            ApiError::InvalidAlias(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::AliasNotFound => (
+                StatusCode::NOT_FOUND,
+                "No nickname or aliases are set for this device".to_string(),
+            ),
+            ApiError::InvalidSimulation(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::InvalidSelfTest(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::NoCanary => (
+                StatusCode::NOT_FOUND,
+                "No canary config is running".to_string(),
+            ),
+            ApiError::CanaryNotReady(at) => (
+                StatusCode::CONFLICT,
+                format!("Canary config is still in its shadow period. It can be promoted at {}", at),
+            ),
+            ApiError::NoSafetyTimer => (
+                StatusCode::CONFLICT,
+                "No safety timer is running. The fireplace is off or safety.max_runtime is not set".to_string(),
+            ),
+            ApiError::StandbyNode => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "This node is the failover standby. Send commands to the active node".to_string(),
+            ),
+            ApiError::FailoverNotConfigured => (
+                StatusCode::NOT_FOUND,
+                "Failover is not configured on this node".to_string(),
+            ),
+            ApiError::ThermostatNotConfigured => (
+                StatusCode::NOT_FOUND,
+                "No thermostat is configured. Add a [thermostat] section with a temperature sensor".to_string(),
+            ),
+            ApiError::InvalidSetpoint { min, max } => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid setpoint. Expected {}-{}°C", min, max),
+            ),
+            ApiError::InvalidLogLevel => (
+                StatusCode::BAD_REQUEST,
+                "Invalid log level. Expected 'error', 'warn', 'info', 'debug' or 'trace'".to_string(),
+            ),
+            ApiError::InvalidQuery(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::VerificationFailed { pin, monitor_pin } => (
+                StatusCode::BAD_GATEWAY,
+                format!("Monitor pin {} did not confirm the change on pin {}", monitor_pin, pin),
+            ),
+            ApiError::WriteNotLatched { pin, attempts, expected, actual } => (
+                StatusCode::BAD_GATEWAY,
+                format!("Pin {} reads {:?} after {} write(s) of {:?}", pin, actual, attempts, expected),
+            ),
+            ApiError::PinNotHeld(pin) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Pin {} was written without being locked", pin),
+            ),
             ApiError::ConfigError(msg) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 msg,
             ),
-            ApiError::GpioError(msg) => (
+            ApiError::InvalidConfig(msg) => (
+                StatusCode::BAD_REQUEST,
+                msg,
+            ),
+            ApiError::StorageError(msg) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 msg,
             ),
+            ApiError::BackendUnavailable { backend, missing } => {
+                let hint = match backend {
+                    "mcp23017" => "Install i2c-tools",
+                    "lirc" => "Install LIRC, or set gpio.lirc.command to the path of irsend",
+                    _ => "Install wiringPi, or set gpio.command to the path of its gpio utility",
+                };
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    format!("GPIO backend '{}' is unavailable: '{}' was not found. {}", backend, missing, hint),
+                )
+            }
+            ApiError::GpioError { failure: crate::config::GpioFailure::Connection, message } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                message,
+            ),
+            ApiError::GpioError { message, .. } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                message,
+            ),
             ApiError::InternalError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal server error".to_string(),
             ),
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": true,
+            "code": code,
             "message": message,
             "status": status.as_u16(),
-        }));
+        });
+        if let Some(details) = &details {
+            body["details"] = details.clone();
+        }
+        let body = Json(body);
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        response.extensions_mut().insert(ErrorInfo { code, message, details });
+        if let Some(retry_after) = retry_after {
+            // Whole seconds, rounded up so a client retrying on time isn't refused again
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
 
@@ -0,0 +1,294 @@
+﻿use axum::http::HeaderMap;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{
+    error::{ApiError, Result},
+    gpio::{PinDirection, PinState},
+    secrets::Secret,
+    state::{AppState, ChangeSource},
+};
+
+/// Header carrying `failover.token` between the pair
+pub const TOKEN_HEADER: &str = "X-Failover-Token";
+
+/// `[failover]`: run as one half of an active/standby pair driving the same relays
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailoverConfig {
+    /// This node's name, reported to the peer
+    pub node: String,
+    /// Base URL of the other node, e.g. `http://10.0.0.6:8090`
+    pub peer: String,
+    /// The higher priority node takes over when both could be active
+    #[serde(default)]
+    pub priority: u32,
+    /// Shared secret both nodes send with heartbeats
+    #[serde(default)]
+    pub token: Option<Secret>,
+    #[serde(
+        default = "default_heartbeat_interval",
+        alias = "heartbeat_interval_ms",
+        with = "crate::duration::millis"
+    )]
+    pub heartbeat_interval: Duration,
+    /// Silence from an active peer after which the standby takes over
+    #[serde(default = "default_failover_after", alias = "failover_after_ms", with = "crate::duration::millis")]
+    pub failover_after: Duration,
+}
+
+fn default_heartbeat_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_failover_after() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl FailoverConfig {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.node.trim().is_empty() {
+            return Err("failover.node must not be empty".to_string());
+        }
+        if !self.peer.starts_with("http://") && !self.peer.starts_with("https://") {
+            return Err(format!("failover.peer: '{}' is not an http:// URL", self.peer));
+        }
+        if self.heartbeat_interval.is_zero() || self.failover_after <= self.heartbeat_interval {
+            return Err("failover.failover_after must be longer than failover.heartbeat_interval".to_string());
+        }
+        Ok(())
+    }
+
+    fn rank(&self) -> (u32, &str) {
+        (self.priority, &self.node)
+    }
+}
+
+/// Which half of the pair drives the relays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Active,
+    Standby,
+}
+
+/// Exchanged both ways on every heartbeat interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub node: String,
+    pub role: Role,
+    pub priority: u32,
+    /// Logical output states of the active node, applied by the standby when it takes over
+    #[serde(default)]
+    pub pins: BTreeMap<u32, PinState>,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailoverStatus {
+    pub enabled: bool,
+    pub node: Option<String>,
+    pub role: Role,
+    pub role_since: String,
+    pub peer: Option<String>,
+    pub peer_node: Option<String>,
+    pub peer_role: Option<Role>,
+    pub peer_reachable: bool,
+    pub last_peer_heartbeat: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Times this node has taken over from its peer
+    pub failovers: u32,
+}
+
+/// This node's view of the pair
+pub struct FailoverNode {
+    role: Role,
+    role_since: DateTime<Local>,
+    peer: Option<Heartbeat>,
+    last_peer_heartbeat: Option<DateTime<Local>>,
+    last_active_peer: Option<DateTime<Local>>,
+    peer_reachable: bool,
+    last_error: Option<String>,
+    failovers: u32,
+}
+
+impl FailoverNode {
+    /// Nodes in a pair start as standby and take over only if no active peer answers;
+    /// a lone node is always active
+    pub fn new(paired: bool) -> Self {
+        Self {
+            role: if paired { Role::Standby } else { Role::Active },
+            role_since: Local::now(),
+            peer: None,
+            last_peer_heartbeat: None,
+            last_active_peer: None,
+            peer_reachable: false,
+            last_error: None,
+            failovers: 0,
+        }
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.role == Role::Standby
+    }
+
+    pub fn status(&self, config: Option<&FailoverConfig>) -> FailoverStatus {
+        FailoverStatus {
+            enabled: config.is_some(),
+            node: config.map(|c| c.node.clone()),
+            role: self.role,
+            role_since: self.role_since.to_rfc3339(),
+            peer: config.map(|c| c.peer.clone()),
+            peer_node: self.peer.as_ref().map(|p| p.node.clone()),
+            peer_role: self.peer.as_ref().map(|p| p.role),
+            peer_reachable: self.peer_reachable,
+            last_peer_heartbeat: self.last_peer_heartbeat.map(|at| at.to_rfc3339()),
+            last_error: self.last_error.clone(),
+            failovers: self.failovers,
+        }
+    }
+
+    fn set_role(&mut self, role: Role) {
+        self.role = role;
+        self.role_since = Local::now();
+    }
+}
+
+/// Check the shared token on a heartbeat from the peer
+pub fn authorize(config: &FailoverConfig, headers: &HeaderMap) -> Result<()> {
+    let Some(token) = &config.token else {
+        return Ok(());
+    };
+    match headers.get(TOKEN_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(sent) if sent == token.expose() => Ok(()),
+        _ => Err(ApiError::Forbidden("Invalid failover token".to_string())),
+    }
+}
+
+/// Build this node's heartbeat; only the active node hands over its pin states
+pub async fn heartbeat(state: &AppState, config: &FailoverConfig) -> Heartbeat {
+    let role = state.failover.read().await.role;
+    let pins = match role {
+        Role::Active => state
+            .gpio_controller
+            .get_all_pin_states()
+            .into_iter()
+            .filter(|status| status.direction == PinDirection::Output)
+            .map(|status| (status.pin, status.state))
+            .collect(),
+        Role::Standby => BTreeMap::new(),
+    };
+    Heartbeat {
+        node: config.node.clone(),
+        role,
+        priority: config.priority,
+        pins,
+        timestamp: Local::now().to_rfc3339(),
+    }
+}
+
+/// Record a heartbeat from the peer. When both nodes are active the lower ranked one
+/// steps down; the other sees the demotion on the next heartbeat.
+pub async fn receive(state: &AppState, config: &FailoverConfig, peer: Heartbeat) {
+    let now = Local::now();
+    let mut node = state.failover.write().await;
+    node.last_peer_heartbeat = Some(now);
+    node.peer_reachable = true;
+
+    if peer.role == Role::Active {
+        node.last_active_peer = Some(now);
+        if node.role == Role::Active && (peer.priority, peer.node.as_str()) > config.rank() {
+            tracing::warn!("Both nodes are active; stepping down in favour of {}", peer.node);
+            node.set_role(Role::Standby);
+            state.gpio_controller.set_standby(true);
+        }
+    }
+    node.peer = Some(peer);
+}
+
+/// Take over the relays, restoring the outputs the active node last reported
+async fn promote(state: &AppState, reason: &str) {
+    let mut node = state.failover.write().await;
+    if node.role == Role::Active {
+        return;
+    }
+    tracing::warn!("Taking over as the active node: {}", reason);
+    node.set_role(Role::Active);
+    node.failovers += 1;
+
+    let handoff = node.peer.as_ref().map(|p| p.pins.clone()).unwrap_or_default();
+    let pins: Vec<u32> = handoff.keys().copied().collect();
+    state.gpio_controller.set_standby(false);
+    let mut gpio = state.gpio_controller.lock(ChangeSource::Failover, &pins).await;
+    for (pin, pin_state) in handoff {
+        let high = match pin_state {
+            PinState::High => true,
+            PinState::Low => false,
+            PinState::Unknown => continue,
+        };
+        if let Err(e) = gpio.set_pin(pin, high).await {
+            tracing::error!("Failed to restore pin {} on takeover: {}", pin, e);
+        }
+    }
+}
+
+/// Exchange heartbeats with the peer and take over when the active one falls silent
+pub fn spawn_heartbeat(state: AppState) {
+    let Some(config) = state.config.load().failover.clone() else {
+        return;
+    };
+    tracing::info!("Failover node {} paired with {}, starting as standby", config.node, config.peer);
+
+    let interval = config.heartbeat_interval;
+    let failover_after = chrono::Duration::from_std(config.failover_after).unwrap_or(chrono::Duration::MAX);
+    let url = format!("{}/api/v1/failover/heartbeat", config.peer.trim_end_matches('/'));
+    let client = reqwest::Client::builder().timeout(interval).build().unwrap_or_default();
+    let started_at = Local::now();
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let mut request = client.post(&url).json(&heartbeat(&state, &config).await);
+            if let Some(token) = &config.token {
+                request = request.header(TOKEN_HEADER, token.expose());
+            }
+            let reply = match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(response) => response.json::<Heartbeat>().await,
+                Err(e) => Err(e),
+            };
+            match reply {
+                Ok(peer) => {
+                    receive(&state, &config, peer).await;
+                    state.failover.write().await.last_error = None;
+                }
+                Err(e) => {
+                    let mut node = state.failover.write().await;
+                    if node.peer_reachable {
+                        tracing::warn!("Failover peer {} unreachable: {}", config.peer, e);
+                    }
+                    node.peer_reachable = false;
+                    node.last_error = Some(e.to_string());
+                }
+            }
+
+            // A standby takes over once no active peer has been heard from for failover_after.
+            // While both are healthy standbys, only the higher ranked one does.
+            let now = Local::now();
+            let node = state.failover.read().await;
+            if !node.is_standby() || now - started_at < failover_after {
+                continue;
+            }
+            let active_silent = node.last_active_peer.is_none_or(|at| now - at >= failover_after);
+            let outranked = node.peer_reachable
+                && node.peer.as_ref().is_some_and(|p| (p.priority, p.node.as_str()) > config.rank());
+            drop(node);
+            if active_silent && !outranked {
+                promote(&state, "no heartbeat from an active peer").await;
+            }
+        }
+    });
+}
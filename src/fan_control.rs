@@ -0,0 +1,204 @@
+﻿use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::{
+    config::{Config, DeviceConfig, DeviceKind},
+    gpio::PinState,
+    lockout::LockReason,
+    state::{AppState, ChangeSource},
+};
+
+/// How often firebox sensors are compared to their thresholds
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// `[[fan_control]]`: run a room's blower off a firebox or plenum sensor, the way the
+/// fireplace's own snap-disc switch does
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanControlConfig {
+    /// The `[[sensors]]` entry on the firebox; the fan is in the sensor's room
+    pub sensor: String,
+    #[serde(default = "default_device")]
+    pub device: String,
+    /// The fan starts when the sensor reaches this
+    #[serde(default = "default_on_above")]
+    pub on_above_c: f64,
+    /// And stops again once it has cooled to this
+    #[serde(default = "default_off_below")]
+    pub off_below_c: f64,
+}
+
+fn default_device() -> String {
+    "fireplace_fan".to_string()
+}
+
+/// 110°F, the usual closing point of an OEM fan snap switch
+fn default_on_above() -> f64 {
+    43.0
+}
+
+/// 90°F, where the same switch opens again
+fn default_off_below() -> f64 {
+    32.0
+}
+
+impl FanControlConfig {
+    pub fn validate(&self, config: &Config) -> std::result::Result<(), String> {
+        let sensor = config
+            .sensor(&self.sensor)
+            .ok_or_else(|| format!("fan_control.sensor: unknown sensor '{}'", self.sensor))?;
+        let room = sensor.room(config);
+        let device = config
+            .zone(Some(room))
+            .ok()
+            .and_then(|zone| zone.device(&self.device))
+            .ok_or_else(|| format!("fan_control.device: unknown device '{}' in '{}'", self.device, room))?;
+        if device.kind != DeviceKind::Fan {
+            return Err(format!("fan_control.device: '{}' is not a fan", device.name));
+        }
+        if !self.on_above_c.is_finite() || !self.off_below_c.is_finite() {
+            return Err("fan_control: temperatures must be finite numbers".to_string());
+        }
+        if self.off_below_c >= self.on_above_c {
+            return Err(format!(
+                "fan_control for '{}': off_below_c must be below on_above_c",
+                self.sensor
+            ));
+        }
+        Ok(())
+    }
+
+    /// The room and fan this entry switches
+    pub fn target<'a>(&self, config: &'a Config) -> Option<(&'a str, &'a DeviceConfig)> {
+        let room = config.sensor(&self.sensor)?.room(config);
+        let device = config.zone(Some(room)).ok()?.device(&self.device)?;
+        Some((room, device))
+    }
+}
+
+/// Start each `[[fan_control]]` fan when its sensor warms past `on_above_c` and stop it
+/// once the sensor cools to `off_below_c`
+///
+/// Only crossings act on the fan, so a fan switched by hand stays as it was put until the
+/// next one. A fan is only stopped if this started it, and not while an interlock
+/// cooldown or the over-temperature cutoff is holding it on.
+pub fn spawn_controller(state: AppState) {
+    tokio::spawn(async move {
+        // Whether each fan's sensor last read hot, keyed by room and device name
+        let mut hot: HashMap<(String, String), bool> = HashMap::new();
+        let mut started: HashSet<(String, String)> = HashSet::new();
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let config = state.config.load_full();
+            // The active node drives the relays; a standby only watches
+            if state.gpio_controller.is_standby() {
+                continue;
+            }
+
+            let controls: Vec<_> = config
+                .fan_control
+                .iter()
+                .filter_map(|control| {
+                    control
+                        .target(&config)
+                        .map(|(room, fan)| (control, (room.to_string(), fan.name.clone()), fan))
+                })
+                .collect();
+            // Forget fans whose entry was removed by a reload
+            hot.retain(|key, _| controls.iter().any(|(_, k, _)| k == key));
+            started.retain(|key| controls.iter().any(|(_, k, _)| k == key));
+
+            for (control, key, fan) in controls {
+                // Without a recent reading the fan is left as it is
+                let Some(temperature) = state.sensors.read().await.temperature(&config, &control.sensor) else {
+                    continue;
+                };
+                let was_hot = hot.get(&key).copied();
+                let is_hot = if temperature >= control.on_above_c {
+                    true
+                } else if temperature <= control.off_below_c {
+                    false
+                } else {
+                    match was_hot {
+                        Some(is_hot) => is_hot,
+                        None => continue,
+                    }
+                };
+                if was_hot == Some(is_hot) {
+                    continue;
+                }
+                let done = if is_hot {
+                    start(&state, &config, &key, fan, temperature).await
+                } else {
+                    stop(&state, &key, fan, temperature, started.contains(&key)).await
+                };
+                // Left unrecorded, the crossing is tried again on the next check
+                let Some(switched) = done else {
+                    continue;
+                };
+                hot.insert(key.clone(), is_hot);
+                if !is_hot {
+                    started.remove(&key);
+                } else if switched {
+                    started.insert(key);
+                }
+            }
+        }
+    });
+}
+
+/// Turn a fan on for a hot firebox. `Some(true)` when this switched it on, `Some(false)`
+/// when it was already running, `None` to retry later.
+async fn start(state: &AppState, config: &Config, key: &(String, String), fan: &DeviceConfig, temperature: f64) -> Option<bool> {
+    let (room, name) = key;
+    if state.lock.read().await.current().is_some_and(|l| l.reason == LockReason::EmergencyStop) {
+        return None;
+    }
+    let shed = config.power.as_ref().is_some_and(|p| p.shed_fan) && state.power.read().await.on_battery();
+    if shed {
+        return None;
+    }
+    let mut gpio = state.gpio_controller.lock(ChangeSource::FanControl, &[fan.pin]).await;
+    if gpio.get_pin_state(fan.pin) == PinState::High {
+        return Some(false);
+    }
+    match gpio.set_pin(fan.pin, true).await {
+        Ok(()) => {
+            tracing::info!("Firebox in {} reads {:.1}°C, starting {}", room, temperature, name);
+            Some(true)
+        }
+        Err(e) => {
+            tracing::error!("Fan control failed to start {} in {}: {}", name, room, e);
+            None
+        }
+    }
+}
+
+/// Turn a fan this started off again once the firebox has cooled. `None` to retry later.
+async fn stop(state: &AppState, key: &(String, String), fan: &DeviceConfig, temperature: f64, started: bool) -> Option<bool> {
+    let (room, name) = key;
+    if !started {
+        return Some(false);
+    }
+    // An interlock cooldown or the over-temperature cutoff stops the fan itself
+    if state.cooldowns.read().await.check(room, name, "OFF").is_err()
+        || state.cutoffs.read().await.check(room, fan, false).is_err()
+    {
+        return Some(false);
+    }
+    let mut gpio = state.gpio_controller.lock(ChangeSource::FanControl, &[fan.pin]).await;
+    if gpio.get_pin_state(fan.pin) == PinState::Low {
+        return Some(false);
+    }
+    match gpio.set_pin(fan.pin, false).await {
+        Ok(()) => {
+            tracing::info!("Firebox in {} has cooled to {:.1}°C, stopping {}", room, temperature, name);
+            Some(true)
+        }
+        Err(e) => {
+            tracing::error!("Fan control failed to stop {} in {}: {}", name, room, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,50 @@
+﻿use serde::Serialize;
+use std::collections::HashMap;
+
+/// A device latched off after failing ignition, awaiting a manual reset
+#[derive(Debug, Clone, Serialize)]
+pub struct Fault {
+    pub room: String,
+    pub device: String,
+    pub pin: u32,
+    pub attempts: u32,
+    pub reason: String,
+    pub since: String,
+}
+
+/// Latched faults keyed by room and device name
+#[derive(Default)]
+pub struct FaultRegistry {
+    faults: HashMap<(String, String), Fault>,
+}
+
+impl FaultRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn latch(&mut self, fault: Fault) {
+        tracing::error!(
+            "Device {}/{} latched in fault after {} attempts: {}",
+            fault.room, fault.device, fault.attempts, fault.reason
+        );
+        self.faults.insert((fault.room.clone(), fault.device.clone()), fault);
+    }
+
+    pub fn get(&self, room: &str, device: &str) -> Option<&Fault> {
+        self.faults.get(&(room.to_string(), device.to_string()))
+    }
+
+    /// Clear a latched fault, returning it if one was set
+    pub fn reset(&mut self, room: &str, device: &str) -> Option<Fault> {
+        let fault = self.faults.remove(&(room.to_string(), device.to_string()));
+        if fault.is_some() {
+            tracing::info!("Fault on {}/{} reset", room, device);
+        }
+        fault
+    }
+
+    pub fn all(&self) -> Vec<Fault> {
+        self.faults.values().cloned().collect()
+    }
+}
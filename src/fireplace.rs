@@ -0,0 +1,451 @@
+﻿use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    commands::Progress,
+    config::{Config, DeviceConfig, DeviceKind, OutputMode},
+    error::{ApiError, Result},
+    fault::Fault,
+    gpio::{GpioController, PinGuard, PinState},
+    sequence::{self, Step},
+    state::{AppState, ChangeSource, StateEvent},
+};
+
+/// Where a fireplace is in its ignition cycle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FireplaceState {
+    #[default]
+    Off,
+    /// Switched on and waiting for the monitor pin to confirm the flame
+    Igniting,
+    On,
+    /// Recently turned off; it can't be re-ignited until `safety.short_cycle_delay` passes
+    Cooling,
+}
+
+/// What moves a fireplace from one state to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Ignite,
+    /// Ignition confirmed, or assumed without a monitor pin
+    Lit,
+    Extinguish,
+    Cooled,
+}
+
+impl FireplaceState {
+    /// The state `transition` leads to, or None when it can't happen from here
+    pub fn next(self, transition: Transition) -> Option<Self> {
+        use FireplaceState::*;
+        match (self, transition) {
+            (Off | Igniting | On, Transition::Ignite) => Some(Igniting),
+            (Igniting | On, Transition::Lit) => Some(On),
+            (Igniting | On, Transition::Extinguish) => Some(Cooling),
+            (Off | Cooling, Transition::Extinguish) => Some(self),
+            (Cooling, Transition::Cooled) => Some(Off),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FireplaceStatus {
+    pub room: String,
+    pub device: String,
+    pub state: FireplaceState,
+    pub since: DateTime<Local>,
+    /// When a cooling fireplace may be ignited again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cooling_until: Option<DateTime<Local>>,
+}
+
+/// The state of every fireplace, keyed by room and configured device name
+#[derive(Default)]
+pub struct Fireplaces {
+    devices: HashMap<(String, String), FireplaceStatus>,
+}
+
+impl Fireplaces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A fireplace not seen since startup is Off
+    pub fn status(&self, room: &str, device: &str) -> FireplaceStatus {
+        self.devices
+            .get(&(room.to_string(), device.to_string()))
+            .cloned()
+            .unwrap_or_else(|| FireplaceStatus {
+                room: room.to_string(),
+                device: device.to_string(),
+                state: FireplaceState::Off,
+                since: Local::now(),
+                cooling_until: None,
+            })
+    }
+
+    pub fn list(&self) -> Vec<FireplaceStatus> {
+        let mut fireplaces: Vec<FireplaceStatus> = self.devices.values().cloned().collect();
+        fireplaces.sort_by(|a, b| (&a.room, &a.device).cmp(&(&b.room, &b.device)));
+        fireplaces
+    }
+
+    /// Apply `transition`, returning the new status if the state changed. Cooling lasts
+    /// `cooling`; without it a fireplace goes straight to Off.
+    fn advance(
+        &mut self,
+        room: &str,
+        device: &str,
+        transition: Transition,
+        cooling: Duration,
+    ) -> Option<FireplaceStatus> {
+        let current = self.status(room, device).state;
+        let Some(mut next) = current.next(transition) else {
+            tracing::warn!("Ignoring {:?} for {}/{} while {:?}", transition, room, device, current);
+            return None;
+        };
+        if next == FireplaceState::Cooling && current != FireplaceState::Cooling && cooling.is_zero() {
+            next = FireplaceState::Off;
+        }
+        if next == current {
+            return None;
+        }
+
+        let now = Local::now();
+        let status = FireplaceStatus {
+            room: room.to_string(),
+            device: device.to_string(),
+            state: next,
+            since: now,
+            cooling_until: (next == FireplaceState::Cooling)
+                .then(|| now + chrono::Duration::from_std(cooling).unwrap_or_default()),
+        };
+        self.devices.insert((room.to_string(), device.to_string()), status.clone());
+        Some(status)
+    }
+
+    /// Refuse to ignite a fireplace that is still cooling
+    pub fn check_ignite(&self, room: &str, device: &str) -> Result<()> {
+        let status = self.status(room, device);
+        match status.cooling_until {
+            Some(until) if status.state == FireplaceState::Cooling => Err(ApiError::ShortCycle {
+                device: device.to_string(),
+                retry_after: (until - Local::now()).to_std().unwrap_or_default(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Move a fireplace through `transition` and publish the change. A transition that can't
+/// happen from the current state leaves it unchanged.
+pub async fn transition(state: &AppState, room: &str, device: &str, transition: Transition) -> FireplaceState {
+    let cooling = state.config.load().safety.short_cycle_delay;
+    let changed = state.fireplaces.write().await.advance(room, device, transition, cooling);
+    let Some(status) = changed else {
+        return state.fireplaces.read().await.status(room, device).state;
+    };
+    publish(state, &status);
+
+    // Finish cooling once the delay has passed, unless something else happened first
+    if let Some(until) = status.cooling_until {
+        let state = state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(cooling).await;
+            let mut fireplaces = state.fireplaces.write().await;
+            if fireplaces.status(&status.room, &status.device).cooling_until != Some(until) {
+                return;
+            }
+            let cooled = fireplaces.advance(&status.room, &status.device, Transition::Cooled, cooling);
+            drop(fireplaces);
+            if let Some(cooled) = cooled {
+                publish(&state, &cooled);
+            }
+        });
+    }
+    status.state
+}
+
+fn publish(state: &AppState, status: &FireplaceStatus) {
+    tracing::info!("Fireplace {}/{} is {:?}", status.room, status.device, status.state);
+    state.publish(StateEvent::FireplaceChanged {
+        room: status.room.clone(),
+        device: status.device.clone(),
+        state: status.state,
+        timestamp: status.since.to_rfc3339(),
+    });
+}
+
+/// Whether the device on `pin` is on. A momentary contact's pin is low between presses,
+/// so a pulse-mode fireplace is on while the state machine has it igniting or on.
+pub async fn is_on(state: &AppState, gpio: &GpioController, pin: u32) -> bool {
+    let config = state.config.load_full();
+    match config.find_pin(pin) {
+        Some((zone, device)) if device.mode == OutputMode::Pulse => {
+            device.kind == DeviceKind::Fireplace
+                && matches!(
+                    state.fireplaces.read().await.status(zone.name, &device.name).state,
+                    FireplaceState::Igniting | FireplaceState::On
+                )
+        }
+        _ => gpio.get_pin_state(pin) == PinState::High,
+    }
+}
+
+/// Turn the device on `pin` off the way it is wired, for the automatic OFF paths. A relay
+/// is driven low; a pulse-mode fireplace that is on gets a press and starts cooling.
+/// Returns whether anything was driven.
+pub async fn drive_off(state: &AppState, gpio: &mut PinGuard<'_>, pin: u32) -> Result<bool> {
+    let config = state.config.load_full();
+    match config.find_pin(pin) {
+        Some((zone, device)) if device.mode == OutputMode::Pulse => {
+            if !is_on(state, gpio, pin).await {
+                return Ok(false);
+            }
+            gpio.pulse_pin(pin, config.safety.pulse_duration).await?;
+            transition(state, zone.name, &device.name, Transition::Extinguish).await;
+            Ok(true)
+        }
+        _ => {
+            if gpio.get_pin_state(pin) == PinState::Low {
+                return Ok(false);
+            }
+            gpio.set_pin(pin, false).await?;
+            Ok(true)
+        }
+    }
+}
+
+/// Settle a fireplace after its relay was driven. An ignition is verified through the
+/// monitor pin when one is wired; a failed ignition is retried `safety.ignition_retries`
+/// times, and once exhausted the fireplace is driven off and latched in fault until reset
+/// through the API. Returns whether the monitor pin confirmed the change.
+#[allow(clippy::too_many_arguments)]
+pub async fn settle(
+    state: &AppState,
+    config: &Config,
+    gpio: &mut PinGuard<'_>,
+    room: &str,
+    device: &DeviceConfig,
+    on: bool,
+    monitor_pin: Option<u32>,
+    progress: &Progress,
+) -> Result<Option<bool>> {
+    if !on {
+        let verified = match monitor_pin {
+            Some(monitor_pin) => Some(gpio.verify_pin(device.pin, monitor_pin).await?),
+            None => None,
+        };
+        transition(state, room, &device.name, Transition::Extinguish).await;
+        return Ok(verified);
+    }
+
+    transition(state, room, &device.name, Transition::Ignite).await;
+    let Some(monitor_pin) = monitor_pin else {
+        transition(state, room, &device.name, Transition::Lit).await;
+        return Ok(None);
+    };
+    match verify_ignition(state, config, gpio, room, device, monitor_pin, progress).await {
+        Ok(verified) => {
+            transition(state, room, &device.name, Transition::Lit).await;
+            Ok(Some(verified))
+        }
+        Err(e) => {
+            transition(state, room, &device.name, Transition::Extinguish).await;
+            Err(e)
+        }
+    }
+}
+
+async fn verify_ignition(
+    state: &AppState,
+    config: &Config,
+    gpio: &mut PinGuard<'_>,
+    room: &str,
+    device: &DeviceConfig,
+    monitor_pin: u32,
+    progress: &Progress,
+) -> Result<bool> {
+    let pin = device.pin;
+    let igniting = gpio.get_pin_state(pin) == PinState::High;
+    let retries = config.safety.ignition_retries;
+    let mut attempts = 1;
+
+    loop {
+        progress.step(format!("Checking monitor pin {} (attempt {})", monitor_pin, attempts)).await;
+        match gpio.verify_pin(pin, monitor_pin).await {
+            Err(ApiError::VerificationFailed { .. }) if igniting && attempts <= retries => {
+                publish_ignition_check(state, pin, false);
+                tracing::warn!("Ignition not confirmed on pin {}, retry {}/{}", pin, attempts, retries);
+                progress.step(format!("Ignition not confirmed, retry {}/{}", attempts, retries)).await;
+                let retry = [
+                    Step::SetPin { set_pin: pin, high: false },
+                    Step::Wait { wait: config.safety.ignition_retry_delay },
+                    Step::SetPin { set_pin: pin, high: true },
+                ];
+                sequence::run_on(gpio, &retry).await?;
+                attempts += 1;
+            }
+            Err(ApiError::VerificationFailed { .. }) if igniting => {
+                publish_ignition_check(state, pin, false);
+                progress.step("Ignition failed, turning the fireplace off and latching the fault").await;
+                gpio.set_pin(pin, false).await?;
+                state.faults.write().await.latch(Fault {
+                    room: room.to_string(),
+                    device: device.name.clone(),
+                    pin,
+                    attempts,
+                    reason: format!("Monitor pin {} did not confirm ignition", monitor_pin),
+                    since: Local::now().to_rfc3339(),
+                });
+                return Err(ApiError::DeviceFaulted {
+                    room: room.to_string(),
+                    device: device.name.clone(),
+                });
+            }
+            Ok(verified) if igniting => {
+                publish_ignition_check(state, pin, verified);
+                return Ok(verified);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn publish_ignition_check(state: &AppState, pin: u32, verified: bool) {
+    state.publish(StateEvent::IgnitionChecked {
+        pin,
+        verified,
+        timestamp: Local::now().to_rfc3339(),
+    });
+}
+
+/// Start every fireplace in the state its relay was found in
+pub async fn init(state: &AppState) {
+    let config = state.config.load_full();
+    let gpio = &state.gpio_controller;
+    let mut fireplaces = state.fireplaces.write().await;
+    let now = Local::now();
+    for zone in config.zones() {
+        for device in zone.devices.iter().filter(|d| d.kind == DeviceKind::Fireplace) {
+            let lit = gpio.get_pin_state(device.pin) == PinState::High && device.mode != OutputMode::Pulse;
+            fireplaces.devices.insert(
+                (zone.name.to_string(), device.name.clone()),
+                FireplaceStatus {
+                    room: zone.name.to_string(),
+                    device: device.name.clone(),
+                    state: if lit { FireplaceState::On } else { FireplaceState::Off },
+                    since: now,
+                    cooling_until: None,
+                },
+            );
+        }
+    }
+}
+
+/// Follow fireplace relays switched outside a control request: timers, the safety
+/// watchdog, failover, interlocks and the like
+pub fn spawn_tracker(state: AppState) {
+    // Subscribe before returning so no change made after startup is missed
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(StateEvent::PinChanged { pin, state: pin_state, source, .. }) => {
+                    // Control requests move their fireplaces through the machine themselves
+                    if matches!(
+                        source,
+                        ChangeSource::Api
+                            | ChangeSource::Legacy
+                            | ChangeSource::Schedule
+                            | ChangeSource::Mqtt
+                            | ChangeSource::Telegram
+                            | ChangeSource::Thermostat
+                            | ChangeSource::Scene
+                            | ChangeSource::Rule
+                    ) {
+                        continue;
+                    }
+                    let config = state.config.load_full();
+                    let Some((zone, device)) = config.find_pin(pin) else {
+                        continue;
+                    };
+                    // A momentary contact's relay says nothing about the flame
+                    if device.kind != DeviceKind::Fireplace || device.mode == OutputMode::Pulse {
+                        continue;
+                    }
+                    match pin_state {
+                        PinState::High => {
+                            transition(&state, zone.name, &device.name, Transition::Ignite).await;
+                            transition(&state, zone.name, &device.name, Transition::Lit).await;
+                        }
+                        PinState::Low => {
+                            transition(&state, zone.name, &device.name, Transition::Extinguish).await;
+                        }
+                        PinState::Unknown => {}
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Fireplace tracker missed {} pin changes", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use FireplaceState::*;
+
+    #[test]
+    fn ignition_leads_to_on() {
+        assert_eq!(Off.next(Transition::Ignite), Some(Igniting));
+        assert_eq!(Igniting.next(Transition::Lit), Some(On));
+        assert_eq!(On.next(Transition::Ignite), Some(Igniting));
+    }
+
+    #[test]
+    fn turning_off_starts_cooling() {
+        assert_eq!(Igniting.next(Transition::Extinguish), Some(Cooling));
+        assert_eq!(On.next(Transition::Extinguish), Some(Cooling));
+        assert_eq!(Cooling.next(Transition::Cooled), Some(Off));
+    }
+
+    #[test]
+    fn turning_off_what_is_off_changes_nothing() {
+        assert_eq!(Off.next(Transition::Extinguish), Some(Off));
+        assert_eq!(Cooling.next(Transition::Extinguish), Some(Cooling));
+    }
+
+    #[test]
+    fn a_cooling_fireplace_cannot_be_ignited() {
+        assert_eq!(Cooling.next(Transition::Ignite), None);
+        assert_eq!(Off.next(Transition::Lit), None);
+        assert_eq!(On.next(Transition::Cooled), None);
+    }
+
+    #[test]
+    fn cooling_is_refused_until_it_ends() {
+        let mut fireplaces = Fireplaces::new();
+        fireplaces.advance("den", "fireplace", Transition::Ignite, Duration::from_secs(60));
+        fireplaces.advance("den", "fireplace", Transition::Extinguish, Duration::from_secs(60));
+        assert_eq!(fireplaces.status("den", "fireplace").state, Cooling);
+        assert!(matches!(fireplaces.check_ignite("den", "fireplace"), Err(ApiError::ShortCycle { .. })));
+    }
+
+    #[test]
+    fn without_a_cooling_delay_off_is_immediate() {
+        let mut fireplaces = Fireplaces::new();
+        fireplaces.advance("den", "fireplace", Transition::Ignite, Duration::ZERO);
+        fireplaces.advance("den", "fireplace", Transition::Extinguish, Duration::ZERO);
+        assert_eq!(fireplaces.status("den", "fireplace").state, Off);
+        assert!(fireplaces.check_ignite("den", "fireplace").is_ok());
+    }
+}
@@ -1,77 +1,1039 @@
-﻿use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use tokio::sync::broadcast;
+
+use crate::config::{Config, ExpanderConfig, GpioBackendKind, GpioFailure, PinNumbering, RetryConfig, WriteVerifyConfig};
+use crate::error::ApiError;
+use crate::state::{ChangeSource, StateEvent};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PinState {
     High,
     Low,
     Unknown,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinDirection {
+    Input,
+    Output,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PinStatus {
     pub pin: u32,
+    /// Logical state (ON = High), after active-low inversion
     pub state: PinState,
+    /// Electrical level on the header
+    pub level: PinState,
+    pub numbering: PinNumbering,
+    pub bcm: Option<u32>,
+    /// Expander name, port and bit of an expander pin
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expander: Option<String>,
+    pub active_low: bool,
+    pub direction: PinDirection,
+    pub backend: String,
+    /// Last logical change since startup
     pub last_toggled: Option<String>,
 }
 
+/// How the GPIO backend is doing, as reported by /health
+#[derive(Debug, Clone, Serialize)]
+pub struct BackendHealth {
+    pub backend: &'static str,
+    /// False while the most recent write failed
+    pub healthy: bool,
+    /// The standby of a failover pair doesn't drive outputs
+    pub standby: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<String>,
+    pub reads: CallTimings,
+    pub writes: CallTimings,
+    /// Failed calls tried again under `gpio.retry`
+    pub retries: u64,
+}
+
+/// Backend calls slower than this are logged as warnings
+const SLOW_CALL: Duration = Duration::from_millis(500);
+
+/// Call count and latency of one kind of backend call since startup
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CallTimings {
+    pub calls: u64,
+    pub failures: u64,
+    #[serde(rename = "last_ms", serialize_with = "as_millis")]
+    pub last: Duration,
+    #[serde(rename = "max_ms", serialize_with = "as_millis")]
+    pub max: Duration,
+    #[serde(rename = "avg_ms", serialize_with = "as_millis")]
+    pub avg: Duration,
+    #[serde(skip)]
+    total: Duration,
+}
+
+impl CallTimings {
+    fn record(&mut self, elapsed: Duration, failed: bool) {
+        self.calls += 1;
+        self.failures += u64::from(failed);
+        self.last = elapsed;
+        self.max = self.max.max(elapsed);
+        self.total += elapsed;
+        self.avg = Duration::from_secs_f64(self.total.as_secs_f64() / self.calls as f64);
+    }
+}
+
+fn as_millis<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+/// Read and write timings, behind a lock because reads happen through `&self`
+#[derive(Debug, Default)]
+struct Timings {
+    reads: CallTimings,
+    writes: CallTimings,
+    retries: u64,
+}
+
+/// Where pin levels are actually written and read. Pins are addressed by BCM number
+/// and levels are electrical, before active-low inversion. Calls for different pins may
+/// run concurrently.
+pub trait GpioBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn write(&self, bcm: u32, level: &PinState) -> crate::error::Result<()>;
+    fn read(&self, bcm: u32) -> crate::error::Result<PinState>;
+
+    /// Switch a pin's direction. The controller only calls this when the direction
+    /// changes; backends without a mode setting ignore it.
+    fn set_mode(&self, _bcm: u32, _direction: PinDirection) -> crate::error::Result<()> {
+        Ok(())
+    }
+}
+
+/// Backend for machines without GPIO: reads return the last written level
+#[derive(Default)]
+pub struct SimulatedBackend {
+    levels: Mutex<HashMap<u32, PinState>>,
+}
+
+impl GpioBackend for SimulatedBackend {
+    fn name(&self) -> &'static str {
+        "simulated"
+    }
+
+    fn write(&self, bcm: u32, level: &PinState) -> crate::error::Result<()> {
+        // On a real Raspberry Pi, this would use rppal:
+        // use rppal::gpio::Gpio;
+        // let gpio = Gpio::new()?;
+        // let mut pin = gpio.get(bcm)?.into_output();
+        // pin.write(level);
+        self.levels.lock().unwrap_or_else(|e| e.into_inner()).insert(bcm, level.clone());
+        Ok(())
+    }
+
+    fn read(&self, bcm: u32) -> crate::error::Result<PinState> {
+        // On a real Raspberry Pi: gpio.get(bcm)?.into_input().read()
+        let levels = self.levels.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(levels.get(&bcm).cloned().unwrap_or(PinState::Unknown))
+    }
+}
+
+/// Backend shelling out to wiringPi's `gpio` utility, as the Python API did
+pub struct WiringPiBackend {
+    command: String,
+}
+
+impl WiringPiBackend {
+    pub fn new(command: Option<&str>) -> Self {
+        Self {
+            command: command.unwrap_or("gpio").to_string(),
+        }
+    }
+
+    /// Run `gpio -g <args>` (BCM numbering) and return its output
+    fn run(&self, args: &[&str]) -> crate::error::Result<String> {
+        let args: Vec<&str> = std::iter::once("-g").chain(args.iter().copied()).collect();
+        run_command(self.name(), &self.command, &args)
+    }
+}
+
+/// Run a backend's command-line utility and return its output. The backend interface is
+/// synchronous, so the wait is handed to `block_in_place`: other tasks move off this
+/// worker thread rather than stalling behind the subprocess.
+pub fn run_command(backend: &'static str, command: &str, args: &[&str]) -> crate::error::Result<String> {
+    let output = tokio::task::block_in_place(|| std::process::Command::new(command).args(args).output())
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ApiError::BackendUnavailable {
+                backend,
+                missing: command.to_string(),
+            },
+            _ => ApiError::GpioError {
+                failure: GpioFailure::Spawn,
+                message: format!("Failed to run {}: {}", command, e),
+            },
+        })?;
+    if !output.status.success() {
+        return Err(ApiError::GpioError {
+            failure: GpioFailure::Exit,
+            message: format!(
+                "{} {} failed: {}",
+                command,
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl GpioBackend for WiringPiBackend {
+    fn name(&self) -> &'static str {
+        "wiringpi"
+    }
+
+    fn write(&self, bcm: u32, level: &PinState) -> crate::error::Result<()> {
+        let bcm = bcm.to_string();
+        let value = if *level == PinState::High { "1" } else { "0" };
+        self.run(&["write", &bcm, value])?;
+        Ok(())
+    }
+
+    fn read(&self, bcm: u32) -> crate::error::Result<PinState> {
+        match self.run(&["read", &bcm.to_string()])?.as_str() {
+            "1" => Ok(PinState::High),
+            "0" => Ok(PinState::Low),
+            other => Err(ApiError::GpioError {
+                failure: GpioFailure::Output,
+                message: format!("Unexpected gpio read output '{}'", other),
+            }),
+        }
+    }
+
+    fn set_mode(&self, bcm: u32, direction: PinDirection) -> crate::error::Result<()> {
+        let mode = match direction {
+            PinDirection::Input => "in",
+            PinDirection::Output => "out",
+        };
+        self.run(&["mode", &bcm.to_string(), mode])?;
+        Ok(())
+    }
+}
+
+/// pigpiod socket commands, from pigpio's command reference
+const PI_CMD_MODES: u32 = 0;
+const PI_CMD_READ: u32 = 3;
+const PI_CMD_WRITE: u32 = 4;
+
+/// How long to wait for pigpiod to accept a connection or answer a command
+const PIGPIOD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Backend talking to a pigpiod daemon over its socket interface, so the server can run
+/// away from the Pi driving the relays. The connection is opened on first use and dropped
+/// on any I/O error, to be reopened by the next call.
+pub struct PigpiodBackend {
+    address: String,
+    connection: Mutex<Option<TcpStream>>,
+}
+
+impl PigpiodBackend {
+    pub fn new(host: Option<&str>, port: Option<u16>) -> Self {
+        Self {
+            address: format!("{}:{}", host.unwrap_or("localhost"), port.unwrap_or(8888)),
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn connect(&self) -> std::io::Result<TcpStream> {
+        let mut last_error = std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses found");
+        for addr in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, PIGPIOD_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(PIGPIOD_TIMEOUT))?;
+                    stream.set_write_timeout(Some(PIGPIOD_TIMEOUT))?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Send one command and return pigpiod's result. Commands share the one connection,
+    /// one at a time, and the wait is handed to `block_in_place` like wiringPi's.
+    fn command(&self, command: u32, p1: u32, p2: u32) -> crate::error::Result<u32> {
+        let result = tokio::task::block_in_place(|| {
+            let mut connection = lock(&self.connection);
+            let stream = match connection.as_mut() {
+                Some(stream) => stream,
+                None => {
+                    let stream = connection.insert(self.connect()?);
+                    tracing::info!("Connected to pigpiod at {}", self.address);
+                    stream
+                }
+            };
+            let exchanged = exchange(stream, command, p1, p2);
+            if exchanged.is_err() {
+                *connection = None;
+            }
+            exchanged
+        })
+        .map_err(|e| ApiError::GpioError {
+            failure: GpioFailure::Connection,
+            message: format!("Can't reach pigpiod at {}: {}", self.address, e),
+        })?;
+        u32::try_from(result).map_err(|_| ApiError::GpioError {
+            failure: GpioFailure::Exit,
+            message: format!("pigpiod command {} on BCM {} failed with pigpio error {}", command, p1, result),
+        })
+    }
+}
+
+/// One request and response on pigpiod's socket: four little-endian words each way, the
+/// command's result in the last word of the response
+fn exchange(stream: &mut TcpStream, command: u32, p1: u32, p2: u32) -> std::io::Result<i32> {
+    let mut request = [0u8; 16];
+    for (chunk, word) in request.chunks_exact_mut(4).zip([command, p1, p2, 0]) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    stream.write_all(&request)?;
+    let mut response = [0u8; 16];
+    stream.read_exact(&mut response)?;
+    Ok(i32::from_le_bytes([response[12], response[13], response[14], response[15]]))
+}
+
+impl GpioBackend for PigpiodBackend {
+    fn name(&self) -> &'static str {
+        "pigpiod"
+    }
+
+    fn write(&self, bcm: u32, level: &PinState) -> crate::error::Result<()> {
+        self.command(PI_CMD_WRITE, bcm, u32::from(*level == PinState::High))?;
+        Ok(())
+    }
+
+    fn read(&self, bcm: u32) -> crate::error::Result<PinState> {
+        match self.command(PI_CMD_READ, bcm, 0)? {
+            0 => Ok(PinState::Low),
+            _ => Ok(PinState::High),
+        }
+    }
+
+    fn set_mode(&self, bcm: u32, direction: PinDirection) -> crate::error::Result<()> {
+        self.command(PI_CMD_MODES, bcm, u32::from(direction == PinDirection::Output))?;
+        Ok(())
+    }
+}
+
+/// MCP23017 registers, in the default IOCON.BANK = 0 layout where each A register is
+/// followed by its B register
+const MCP_IODIRA: u8 = 0x00;
+const MCP_GPIOA: u8 = 0x12;
+const MCP_OLATA: u8 = 0x14;
+
+/// Backend for an MCP23017 I2C expander, through i2c-tools' `i2cget` and `i2cset`.
+/// Pins 0-7 are port A and 8-15 port B.
+pub struct Mcp23017Backend {
+    bus: String,
+    address: String,
+    /// Held across each read-modify-write of a register, which is shared by eight pins
+    registers: Mutex<()>,
+}
+
+impl Mcp23017Backend {
+    pub fn new(config: &ExpanderConfig) -> Self {
+        Self {
+            bus: config.bus.to_string(),
+            address: format!("0x{:02x}", config.address),
+            registers: Mutex::new(()),
+        }
+    }
+
+    /// The register of `pin`'s port among an A/B pair, and the pin's bit in it
+    fn register(base: u8, pin: u32) -> (String, u8) {
+        let register = base + u8::from(pin >= 8);
+        (format!("0x{:02x}", register), 1 << (pin % 8))
+    }
+
+    fn get(&self, register: &str) -> crate::error::Result<u8> {
+        let output = run_command(self.name(), "i2cget", &["-y", &self.bus, &self.address, register])?;
+        u8::from_str_radix(output.trim_start_matches("0x"), 16).map_err(|_| ApiError::GpioError {
+            failure: GpioFailure::Output,
+            message: format!("Unexpected i2cget output '{}'", output),
+        })
+    }
+
+    /// Set or clear `mask` in a register, writing it only if that changes it
+    fn update(&self, base: u8, pin: u32, set: bool) -> crate::error::Result<()> {
+        let (register, mask) = Self::register(base, pin);
+        let _held = lock(&self.registers);
+        let value = self.get(&register)?;
+        let updated = if set { value | mask } else { value & !mask };
+        if updated != value {
+            let updated = format!("0x{:02x}", updated);
+            run_command(self.name(), "i2cset", &["-y", &self.bus, &self.address, &register, &updated])?;
+        }
+        Ok(())
+    }
+}
+
+impl GpioBackend for Mcp23017Backend {
+    fn name(&self) -> &'static str {
+        "mcp23017"
+    }
+
+    fn write(&self, pin: u32, level: &PinState) -> crate::error::Result<()> {
+        self.update(MCP_OLATA, pin, *level == PinState::High)
+    }
+
+    fn read(&self, pin: u32) -> crate::error::Result<PinState> {
+        let (register, mask) = Self::register(MCP_GPIOA, pin);
+        match self.get(&register)? & mask {
+            0 => Ok(PinState::Low),
+            _ => Ok(PinState::High),
+        }
+    }
+
+    fn set_mode(&self, pin: u32, direction: PinDirection) -> crate::error::Result<()> {
+        // IODIR bits are 1 for inputs
+        self.update(MCP_IODIRA, pin, direction == PinDirection::Input)
+    }
+}
+
+/// The backend `[gpio]` selects
+fn backend_for(config: &Config) -> Arc<dyn GpioBackend> {
+    match config.gpio.backend {
+        GpioBackendKind::Simulated => Arc::new(SimulatedBackend::default()),
+        GpioBackendKind::Wiringpi => Arc::new(WiringPiBackend::new(config.gpio.command.as_deref())),
+        GpioBackendKind::Pigpiod => Arc::new(PigpiodBackend::new(config.gpio.host.as_deref(), config.gpio.port)),
+    }
+}
+
+/// The `[gpio]` settings a backend is built from; the backend is rebuilt when they change
+type BackendSettings = (GpioBackendKind, Option<String>, Option<String>, Option<u16>);
+
+fn backend_settings(config: &Config) -> BackendSettings {
+    let gpio = &config.gpio;
+    (gpio.backend, gpio.command.clone(), gpio.host.clone(), gpio.port)
+}
+
+/// Every pin the config wires up, with its direction
+fn configured_pins(config: &Config) -> BTreeMap<u32, PinDirection> {
+    let mut pins = BTreeMap::new();
+    for zone in config.zones() {
+        pins.extend(zone.devices.iter().map(|d| (d.pin, PinDirection::Output)));
+        pins.extend(zone.monitor_pins().map(|pin| (pin, PinDirection::Input)));
+    }
+    for group in &config.groups {
+        pins.extend(group.pins.iter().map(|pin| (*pin, PinDirection::Output)));
+    }
+    pins.extend(config.power.as_ref().map(|p| (p.on_battery_pin, PinDirection::Input)));
+    pins.extend(config.inputs.iter().map(|input| (input.pin, PinDirection::Input)));
+    pins.extend(config.status_led.as_ref().map(|led| (led.pin, PinDirection::Output)));
+    pins
+}
+
+/// How the config wires the pins, replaced as a whole on reload
+struct Wiring {
+    backend: Arc<dyn GpioBackend>,
+    /// The `[gpio]` backend settings `backend` was built from
+    backend_config: BackendSettings,
+    /// Pins the config wires up, reported even before they are first used
+    configured: BTreeMap<u32, PinDirection>,
+    active_low_all: bool,
+    active_low_pins: HashSet<u32>,
+    numbering: PinNumbering,
+    verify: Option<WriteVerifyConfig>,
+    retry: RetryConfig,
+    expanders: Vec<Expander>,
+    /// Sends the codes of devices with `rf`, if there are any
+    rf: Option<Arc<crate::rf::RfBackend>>,
+    /// Sends the keys of devices with `ir`, if there are any
+    ir: Option<Arc<crate::lirc::LircBackend>>,
+}
+
+/// An I2C expander and the backend driving its pins
+struct Expander {
+    config: ExpanderConfig,
+    backend: Arc<dyn GpioBackend>,
+}
+
+impl Wiring {
+    /// Wiring for `config`, keeping the backends of `previous` whose settings haven't changed
+    fn new(config: &Config, previous: Option<&Wiring>) -> Self {
+        let backend = match previous {
+            Some(previous) if previous.backend_config == backend_settings(config) => previous.backend.clone(),
+            _ => backend_for(config),
+        };
+        let expanders = config
+            .gpio
+            .expanders
+            .iter()
+            .map(|config| {
+                let kept = previous
+                    .and_then(|p| p.expanders.iter().find(|e| (e.config.bus, e.config.address) == (config.bus, config.address)));
+                Expander {
+                    config: config.clone(),
+                    backend: match kept {
+                        Some(expander) => expander.backend.clone(),
+                        None => Arc::new(Mcp23017Backend::new(config)),
+                    },
+                }
+            })
+            .collect();
+        let rf = crate::rf::RfBackend::new(config, previous.and_then(|p| p.rf.as_deref())).map(Arc::new);
+        let ir = crate::lirc::LircBackend::new(config, previous.and_then(|p| p.ir.as_deref())).map(Arc::new);
+        Self {
+            backend,
+            expanders,
+            rf,
+            ir,
+            backend_config: backend_settings(config),
+            configured: configured_pins(config),
+            active_low_all: config.gpio.active_low,
+            active_low_pins: config.active_low_pins(),
+            numbering: config.gpio.numbering,
+            verify: config.gpio.verify.clone(),
+            retry: config.gpio.retry.clone(),
+        }
+    }
+}
+
+/// GPIO access shared by every task. Reads go straight to the backend. Writes go through
+/// a [`PinGuard`] holding the locks of just the pins being driven, so a slow operation on
+/// one pin (a pulse, a cycle, a slow backend call) doesn't hold up the others.
 pub struct GpioController {
-    pin_states: HashMap<u32, PinState>,
+    wiring: RwLock<Wiring>,
+    /// When each pin's logical state last changed
+    last_changed: Mutex<HashMap<u32, DateTime<Local>>>,
+    events: broadcast::Sender<StateEvent>,
+    /// The standby of a failover pair leaves the relays to the active node
+    standby: AtomicBool,
+    /// The last backend write failure, cleared by the next successful write
+    last_error: Mutex<Option<(DateTime<Local>, String)>>,
+    timings: Mutex<Timings>,
+    /// Direction each pin was last set to, so the mode is only set when it changes
+    modes: Mutex<HashMap<u32, PinDirection>>,
+    /// One lock per pin, created on first use
+    pin_locks: Mutex<HashMap<u32, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Poisoning only means another thread panicked mid-update of plain data; carry on with it
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
 }
 
 impl GpioController {
-    pub fn new() -> Self {
+    pub fn new(config: &Config, events: broadcast::Sender<StateEvent>) -> Self {
         Self {
-            pin_states: HashMap::new(),
+            wiring: RwLock::new(Wiring::new(config, None)),
+            last_changed: Mutex::new(HashMap::new()),
+            events,
+            standby: AtomicBool::new(config.failover.is_some()),
+            last_error: Mutex::new(None),
+            timings: Mutex::new(Timings::default()),
+            modes: Mutex::new(HashMap::new()),
+            pin_locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn wiring(&self) -> std::sync::RwLockReadGuard<'_, Wiring> {
+        self.wiring.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn backend(&self) -> Arc<dyn GpioBackend> {
+        self.wiring().backend.clone()
+    }
+
+    /// Take the locks of `pins` to drive them on behalf of `source`. Other pins stay free
+    /// for other writers. Locks are taken in pin order so two guards can't deadlock.
+    pub async fn lock(&self, source: ChangeSource, pins: &[u32]) -> PinGuard<'_> {
+        let mut pins = pins.to_vec();
+        pins.sort_unstable();
+        pins.dedup();
+        let mut guard = PinGuard {
+            gpio: self,
+            source,
+            held: HashMap::new(),
+            attempts: HashMap::new(),
+        };
+        for pin in pins {
+            let lock = self.pin_lock(pin);
+            guard.held.insert(pin, lock.lock_owned().await);
+        }
+        guard
+    }
+
+    fn pin_lock(&self, pin: u32) -> Arc<tokio::sync::Mutex<()>> {
+        lock(&self.pin_locks).entry(pin).or_default().clone()
+    }
+
+    /// Stop or resume driving outputs as this node's failover role changes
+    pub fn set_standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::SeqCst);
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::SeqCst)
+    }
+
+    pub fn backend_health(&self) -> BackendHealth {
+        let last_error = lock(&self.last_error).clone();
+        let timings = lock(&self.timings);
+        BackendHealth {
+            backend: self.backend().name(),
+            healthy: last_error.is_none(),
+            standby: self.is_standby(),
+            last_error: last_error.as_ref().map(|(_, error)| error.clone()),
+            last_error_at: last_error.as_ref().map(|(at, _)| at.to_rfc3339()),
+            reads: timings.reads,
+            writes: timings.writes,
+            retries: timings.retries,
+        }
+    }
+
+    /// Make a backend call, trying it again after a doubling backoff while it fails in a
+    /// way `gpio.retry` counts as transient. Like the calls themselves the wait is
+    /// synchronous, so it is handed to `block_in_place`.
+    fn with_retry<T>(
+        &self,
+        what: &str,
+        pin: u32,
+        mut call: impl FnMut() -> crate::error::Result<T>,
+    ) -> crate::error::Result<T> {
+        let policy = self.wiring().retry.clone();
+        let mut backoff = policy.backoff;
+        let mut attempt = 1;
+        loop {
+            match call() {
+                Err(e) if attempt < policy.attempts && policy.retries(&e) => {
+                    tracing::warn!(
+                        "GPIO {} of pin {} failed (attempt {} of {}), retrying in {:?}: {}",
+                        what, pin, attempt, policy.attempts, backoff, e
+                    );
+                    lock(&self.timings).retries += 1;
+                    tokio::task::block_in_place(|| std::thread::sleep(backoff));
+                    backoff = (backoff * 2).min(policy.max_backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The backend driving a pin and the pin's number on it: an expander's own pin number,
+    /// the BCM number of a header pin, or the pin of an RF or IR device as it is. The
+    /// simulated backend stands in for expanders and transmitters too, keyed by pin.
+    fn route(&self, pin: u32) -> crate::error::Result<(Arc<dyn GpioBackend>, u32)> {
+        let wiring = self.wiring();
+        let simulated = wiring.backend_config.0 == GpioBackendKind::Simulated;
+        if let Some(rf) = wiring.rf.as_ref().filter(|rf| rf.drives(pin)) {
+            return Ok(if simulated { (wiring.backend.clone(), pin) } else { (rf.clone(), pin) });
+        }
+        if let Some(ir) = wiring.ir.as_ref().filter(|ir| ir.drives(pin)) {
+            return Ok(if simulated { (wiring.backend.clone(), pin) } else { (ir.clone(), pin) });
+        }
+        match wiring.expanders.iter().find(|e| e.config.contains(pin)) {
+            Some(_) if simulated => Ok((wiring.backend.clone(), pin)),
+            Some(expander) => Ok((expander.backend.clone(), pin - expander.config.base)),
+            None => {
+                let bcm = crate::pinout::to_bcm(wiring.numbering, pin).ok_or(ApiError::InvalidPin)?;
+                Ok((wiring.backend.clone(), bcm))
+            }
+        }
+    }
+
+    /// Read a pin's level from its backend, timing each call
+    fn backend_read(&self, pin: u32) -> crate::error::Result<PinState> {
+        let (backend, address) = self.route(pin)?;
+        self.with_retry("read", pin, || {
+            let started = Instant::now();
+            let result = backend.read(address);
+            let elapsed = started.elapsed();
+            if elapsed >= SLOW_CALL {
+                tracing::warn!("GPIO read of pin {} took {:?} ({})", pin, elapsed, backend.name());
+            }
+            lock(&self.timings).reads.record(elapsed, result.is_err());
+            result
+        })
+    }
+
+    /// Write a pin's level to its backend, timing each call
+    fn backend_write(&self, pin: u32, level: &PinState) -> crate::error::Result<()> {
+        let (backend, address) = self.route(pin)?;
+        tracing::debug!("GPIO write pin {} = {:?} ({} {})", pin, level, backend.name(), address);
+        self.with_retry("write", pin, || {
+            let started = Instant::now();
+            let result = backend.write(address, level);
+            let elapsed = started.elapsed();
+            if elapsed >= SLOW_CALL {
+                tracing::warn!("GPIO write of pin {} took {:?} ({})", pin, elapsed, backend.name());
+            }
+            lock(&self.timings).writes.record(elapsed, result.is_err());
+            result
+        })
+    }
+
+    /// Set a pin's direction unless it is known to be set already. A failed call forgets
+    /// the pin's mode, so the next call sets it again.
+    fn ensure_mode(&self, pin: u32, direction: PinDirection) -> crate::error::Result<()> {
+        if lock(&self.modes).get(&pin) == Some(&direction) {
+            return Ok(());
+        }
+        let (backend, address) = self.route(pin)?;
+        tracing::debug!("GPIO mode pin {} = {:?} ({} {})", pin, direction, backend.name(), address);
+        self.with_retry("mode change", pin, || backend.set_mode(address, direction))?;
+        lock(&self.modes).insert(pin, direction);
+        Ok(())
+    }
+
+    /// Apply new pin polarity, numbering and wiring after a config reload
+    pub fn reconfigure(&self, config: &Config) {
+        let mut wiring = self.wiring.write().unwrap_or_else(|e| e.into_inner());
+        if backend_settings(config) != wiring.backend_config {
+            tracing::info!("Switching GPIO backend to {:?}", config.gpio.backend);
+        }
+        // Pin numbers may now lead somewhere else, so set every mode afresh
+        lock(&self.modes).clear();
+        *wiring = Wiring::new(config, Some(&wiring));
+    }
+
+    /// Translate a pin in the configured numbering scheme to its BCM number
+    pub fn to_bcm(&self, pin: u32) -> crate::error::Result<u32> {
+        crate::pinout::to_bcm(self.wiring().numbering, pin).ok_or(crate::error::ApiError::InvalidPin)
+    }
+
+    /// Whether a pin is wired active-low (logical ON drives it low). A pin sent over RF or
+    /// IR picks its code or key from the level, so a global `gpio.active_low` must not swap it.
+    pub fn is_active_low(&self, pin: u32) -> bool {
+        let wiring = self.wiring();
+        if wiring.rf.as_ref().is_some_and(|rf| rf.drives(pin)) || wiring.ir.as_ref().is_some_and(|ir| ir.drives(pin)) {
+            return false;
+        }
+        wiring.active_low_all || wiring.active_low_pins.contains(&pin)
+    }
+
+    /// Map between logical state and electrical level; the mapping is its own inverse
+    fn apply_polarity(&self, pin: u32, state: PinState) -> PinState {
+        if !self.is_active_low(pin) {
+            return state;
+        }
+        match state {
+            PinState::High => PinState::Low,
+            PinState::Low => PinState::High,
+            PinState::Unknown => PinState::Unknown,
+        }
+    }
+
+    /// Electrical level of a pin as the backend reads it; Unknown if it can't be read
+    fn level(&self, pin: u32) -> PinState {
+        self.backend_read(pin).unwrap_or(PinState::Unknown)
+    }
+
+    /// Drive a pin to a logical state, inverting the written level for active-low pins.
+    /// The caller holds the pin's lock.
+    fn write_pin(&self, pin: u32, state: PinState, source: ChangeSource) -> crate::error::Result<()> {
+        if self.is_standby() {
+            return Err(crate::error::ApiError::StandbyNode);
+        }
+        let level = self.apply_polarity(pin, state.clone());
+        let previous = self.get_pin_state(pin);
+        let written = self
+            .ensure_mode(pin, PinDirection::Output)
+            .and_then(|()| self.backend_write(pin, &level));
+        if let Err(e) = written {
+            lock(&self.modes).remove(&pin);
+            let now = Local::now();
+            *lock(&self.last_error) = Some((now, format!("pin {}: {}", pin, e)));
+            let _ = self.events.send(StateEvent::GpioFailed {
+                pin,
+                error: e.to_string(),
+                source,
+                timestamp: now.to_rfc3339(),
+            });
+            return Err(e);
+        }
+        *lock(&self.last_error) = None;
+
+        // Every control path ends here, so this is where state changes are published
+        if previous != state {
+            let now = Local::now();
+            lock(&self.last_changed).insert(pin, now);
+            let _ = self.events.send(StateEvent::PinChanged {
+                pin,
+                state,
+                source,
+                timestamp: now.to_rfc3339(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Switch an indicator output such as the status LED. Unlike control writes these
+    /// take no pin lock and aren't published on the event bus, so blinking doesn't flood it.
+    pub fn set_indicator(&self, pin: u32, on: bool) -> crate::error::Result<()> {
+        let level = self.apply_polarity(pin, if on { PinState::High } else { PinState::Low });
+        self.ensure_mode(pin, PinDirection::Output)
+            .and_then(|()| self.backend_write(pin, &level))
+            .inspect_err(|_| {
+                lock(&self.modes).remove(&pin);
+            })
+    }
+
+    /// When a pin's logical state last changed, if it has since startup
+    pub fn last_changed(&self, pin: u32) -> Option<DateTime<Local>> {
+        lock(&self.last_changed).get(&pin).copied()
+    }
+
+    /// Read the logical input state of a pin. Pins the config wires as inputs are switched
+    /// to input first; outputs are read back as they are driven.
+    pub async fn read_pin(&self, pin: u32) -> crate::error::Result<PinState> {
+        let input = self.wiring().configured.get(&pin) == Some(&PinDirection::Input);
+        if input {
+            self.ensure_mode(pin, PinDirection::Input)?;
+        }
+        let level = self.backend_read(pin).inspect_err(|_| {
+            lock(&self.modes).remove(&pin);
+        })?;
+        Ok(self.apply_polarity(pin, level))
+    }
+
+    /// Confirm through a monitor input that `pin` reached its expected state.
+    /// Returns `Ok(false)` when the monitor level can't be determined.
+    pub async fn verify_pin(&self, pin: u32, monitor_pin: u32) -> crate::error::Result<bool> {
+        let expected = self.get_pin_state(pin);
+        match self.read_pin(monitor_pin).await? {
+            PinState::Unknown => Ok(false),
+            actual if actual == expected => Ok(true),
+            actual => {
+                tracing::warn!(
+                    "Monitor pin {} reads {:?}, expected {:?} for pin {}",
+                    monitor_pin, actual, expected, pin
+                );
+                Err(crate::error::ApiError::VerificationFailed { pin, monitor_pin })
+            }
+        }
+    }
+
+    /// Get the current logical state of a pin
+    pub fn get_pin_state(&self, pin: u32) -> PinState {
+        self.apply_polarity(pin, self.level(pin))
+    }
+
+    /// Logical state of several pins acting as one device; pins that disagree give Unknown
+    pub fn combined_state(&self, pins: &[u32]) -> PinState {
+        let mut states = pins.iter().map(|pin| self.get_pin_state(*pin));
+        let first = states.next().unwrap_or(PinState::Unknown);
+        if states.all(|state| state == first) {
+            first
+        } else {
+            PinState::Unknown
+        }
+    }
+
+    /// Where an expander pin is, e.g. "relay_hat:A3"
+    fn expander_pin(&self, pin: u32) -> Option<String> {
+        let wiring = self.wiring();
+        let expander = wiring.expanders.iter().find(|e| e.config.contains(pin))?;
+        let offset = pin - expander.config.base;
+        let port = if offset < 8 { 'A' } else { 'B' };
+        Some(format!("{}:{}{}", expander.config.name, port, offset % 8))
+    }
+
+    /// Describe a pin's state together with its wiring configuration
+    pub fn pin_status(&self, pin: u32, direction: PinDirection) -> PinStatus {
+        let level = self.level(pin);
+        PinStatus {
+            pin,
+            state: self.apply_polarity(pin, level.clone()),
+            level,
+            numbering: self.wiring().numbering,
+            bcm: self.to_bcm(pin).ok(),
+            expander: self.expander_pin(pin),
+            active_low: self.is_active_low(pin),
+            direction,
+            backend: self.route(pin).map_or(self.backend().name(), |(backend, _)| backend.name()).to_string(),
+            last_toggled: self.last_changed(pin).map(|at| at.to_rfc3339()),
+        }
+    }
+
+    /// Read every configured pin, plus any other pin written since startup
+    pub fn get_all_pin_states(&self) -> Vec<PinStatus> {
+        let mut pins = self.wiring().configured.clone();
+        for pin in lock(&self.last_changed).keys() {
+            pins.entry(*pin).or_insert(PinDirection::Output);
+        }
+        pins.into_iter()
+            .map(|(pin, direction)| self.pin_status(pin, direction))
+            .collect()
+    }
+}
+
+/// The right to drive a set of pins, from [`GpioController::lock`]. Reads of any pin go
+/// through to the controller.
+pub struct PinGuard<'a> {
+    gpio: &'a GpioController,
+    /// Who the writes through this guard are made for
+    source: ChangeSource,
+    held: HashMap<u32, tokio::sync::OwnedMutexGuard<()>>,
+    /// Under `gpio.verify`, the most writes any one change of a pin took to latch
+    attempts: HashMap<u32, u32>,
+}
+
+impl Deref for PinGuard<'_> {
+    type Target = GpioController;
+
+    fn deref(&self) -> &GpioController {
+        self.gpio
+    }
+}
+
+impl PinGuard<'_> {
+    /// Refuse to write a pin this guard doesn't hold. Pins are only locked up front, in
+    /// order, by [`GpioController::lock`]; taking another one part way through could
+    /// deadlock against a caller that holds it and is waiting for one of ours.
+    fn check_held(&self, pin: u32) -> crate::error::Result<()> {
+        if self.held.contains_key(&pin) {
+            return Ok(());
+        }
+        tracing::error!("GPIO Pin {} written without being locked", pin);
+        Err(ApiError::PinNotHeld(pin))
+    }
+
+    /// Writes the pin's changes through this guard took to latch, if `gpio.verify` is on
+    pub fn attempts(&self, pin: u32) -> Option<u32> {
+        self.attempts.get(&pin).copied()
+    }
+
+    /// Write a pin, then under `gpio.verify` read it back, rewriting it with a doubling
+    /// backoff, capped at `max_backoff`, until it reads as written or the attempts run out
+    async fn write(&mut self, pin: u32, state: PinState) -> crate::error::Result<()> {
+        self.gpio.write_pin(pin, state.clone(), self.source)?;
+        let Some(verify) = self.gpio.wiring().verify.clone() else {
+            return Ok(());
+        };
+        let mut backoff = verify.backoff;
+        let mut attempt = 1;
+        loop {
+            let actual = self.gpio.read_pin(pin).await?;
+            if actual == state {
+                let most = self.attempts.entry(pin).or_default();
+                *most = (*most).max(attempt);
+                return Ok(());
+            }
+            if attempt == verify.attempts {
+                return Err(ApiError::WriteNotLatched {
+                    pin,
+                    attempts: attempt,
+                    expected: state,
+                    actual,
+                });
+            }
+            tracing::warn!("GPIO Pin {} reads {:?} after writing {:?}, rewriting", pin, actual, state);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(verify.max_backoff);
+            attempt += 1;
+            self.gpio.write_pin(pin, state.clone(), self.source)?;
         }
     }
 
     /// Toggle a GPIO pin (simulated for non-Pi systems)
     pub async fn toggle_pin(&mut self, pin: u32) -> crate::error::Result<()> {
-        // On a real Raspberry Pi, this would use rppal:
-        // use rppal::gpio::Gpio;
-        // let gpio = Gpio::new()?;
-        // let mut pin = gpio.get(pin)?.into_output();
-        // pin.toggle();
-
-        // For simulation, just toggle the state
-        let current_state = self.pin_states.get(&pin).cloned().unwrap_or(PinState::Low);
-        let new_state = match current_state {
+        self.check_held(pin)?;
+        let new_state = match self.get_pin_state(pin) {
             PinState::High => PinState::Low,
             PinState::Low => PinState::High,
             PinState::Unknown => PinState::High,
         };
 
-        self.pin_states.insert(pin, new_state.clone());
+        self.write(pin, new_state.clone()).await?;
         tracing::info!("GPIO Pin {} toggled to {:?}", pin, new_state);
         Ok(())
     }
 
-    /// Set a GPIO pin to a specific state
+    /// Set a GPIO pin to a specific logical state
     pub async fn set_pin(&mut self, pin: u32, high: bool) -> crate::error::Result<()> {
+        self.check_held(pin)?;
         let state = if high { PinState::High } else { PinState::Low };
-        self.pin_states.insert(pin, state.clone());
+        self.write(pin, state.clone()).await?;
         tracing::info!("GPIO Pin {} set to {:?}", pin, state);
         Ok(())
     }
 
-    /// Get the current state of a pin
-    pub fn get_pin_state(&self, pin: u32) -> PinState {
-        self.pin_states
-            .get(&pin)
-            .cloned()
-            .unwrap_or(PinState::Unknown)
+    /// Toggle a GPIO pin `cycles` times, waiting `delay` between toggles
+    pub async fn cycle_pin(&mut self, pin: u32, cycles: u32, delay: std::time::Duration) -> crate::error::Result<()> {
+        for cycle in 0..cycles {
+            if cycle > 0 {
+                tokio::time::sleep(delay).await;
+            }
+            self.toggle_pin(pin).await?;
+        }
+        Ok(())
     }
 
-    /// Get all pin states
-    pub fn get_all_pin_states(&self) -> Vec<PinStatus> {
-        self.pin_states
-            .iter()
-            .map(|(pin, state)| PinStatus {
-                pin: *pin,
-                state: state.clone(),
-                last_toggled: Some(chrono::Local::now().to_rfc3339()),
-            })
-            .collect()
+    /// Set several pins together, waiting `stage_delay` between pins when turning on
+    pub async fn set_pins_staged(
+        &mut self,
+        pins: &[u32],
+        high: bool,
+        stage_delay: std::time::Duration,
+    ) -> crate::error::Result<()> {
+        for (index, pin) in pins.iter().enumerate() {
+            if high && index > 0 && !stage_delay.is_zero() {
+                tokio::time::sleep(stage_delay).await;
+            }
+            self.set_pin(*pin, high).await?;
+        }
+        Ok(())
+    }
+
+    /// Pulse a GPIO pin high for `duration`, then drive it low again. The pin is driven low
+    /// even if the pulse fails or is cancelled part way through.
+    pub async fn pulse_pin(&mut self, pin: u32, duration: std::time::Duration) -> crate::error::Result<()> {
+        self.check_held(pin)?;
+        let mut end = PulseEnd {
+            gpio: self.gpio,
+            pin,
+            source: self.source,
+            armed: true,
+        };
+        self.set_pin(pin, true).await?;
+        tokio::time::sleep(duration).await;
+        self.set_pin(pin, false).await?;
+        end.armed = false;
+        tracing::info!("GPIO Pin {} pulsed for {}", pin, humantime::format_duration(duration));
+        Ok(())
+    }
+}
+
+/// Drives a pulsed pin low when dropped before the pulse finished. It lives inside the
+/// pulse, so the pin's lock is still held when it writes.
+struct PulseEnd<'a> {
+    gpio: &'a GpioController,
+    pin: u32,
+    source: ChangeSource,
+    armed: bool,
+}
+
+impl Drop for PulseEnd<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        match self.gpio.write_pin(self.pin, PinState::Low, self.source) {
+            Ok(()) => tracing::warn!("GPIO Pin {} driven low after an unfinished pulse", self.pin),
+            Err(e) => tracing::error!("GPIO Pin {} may be left high after an unfinished pulse: {}", self.pin, e),
+        }
     }
 }
@@ -0,0 +1,189 @@
+﻿use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{Config, DeviceKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    /// A named output in a room's `[pins]`
+    Device,
+    Group,
+    /// An output only reachable through a group
+    Pin,
+    /// A monitor or UPS status input
+    Input,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Relation {
+    /// A group drives the member
+    Contains,
+    /// The device's state is confirmed through the input
+    MonitoredBy,
+    /// The input switches the device off (battery load shedding)
+    Sheds,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub kind: NodeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    pub pin: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub relation: Relation,
+}
+
+/// How the configured devices, groups and inputs depend on each other
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl DeviceGraph {
+    pub fn build(config: &Config) -> Self {
+        let mut graph = Self {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        };
+        let mut outputs: HashMap<u32, String> = HashMap::new();
+
+        for zone in config.zones() {
+            for (name, pin) in zone.named_pins() {
+                let id = format!("{}/{}", zone.name, name);
+                outputs.insert(pin, id.clone());
+                graph.node(id, NodeKind::Device, Some(zone.name), Some(pin));
+            }
+            for device in zone.devices {
+                if let Some(monitor) = device.monitor {
+                    let id = format!("{}/{}.monitor", zone.name, device.name);
+                    graph.node(id.clone(), NodeKind::Input, Some(zone.name), Some(monitor));
+                    graph.edge(format!("{}/{}", zone.name, device.name), id, Relation::MonitoredBy);
+                }
+            }
+        }
+
+        for group in &config.groups {
+            let room = config
+                .zone(group.room.as_deref())
+                .map_or(config.room.name.as_str(), |z| z.name);
+            let id = format!("{}/{}", room, group.name);
+            graph.node(id.clone(), NodeKind::Group, Some(room), None);
+            for pin in &group.pins {
+                let member = match outputs.get(pin) {
+                    Some(device) => device.clone(),
+                    None => {
+                        let pin_id = format!("pin/{}", pin);
+                        if !graph.nodes.iter().any(|n| n.id == pin_id) {
+                            graph.node(pin_id.clone(), NodeKind::Pin, None, Some(*pin));
+                        }
+                        pin_id
+                    }
+                };
+                graph.edge(id.clone(), member, Relation::Contains);
+            }
+        }
+
+        for input in &config.inputs {
+            graph.node(format!("inputs/{}", input.name), NodeKind::Input, Some(input.room(config)), Some(input.pin));
+        }
+
+        if let Some(power) = &config.power {
+            let id = "power/on_battery".to_string();
+            graph.node(id.clone(), NodeKind::Input, None, Some(power.on_battery_pin));
+            if power.shed_fan {
+                for zone in config.zones() {
+                    for fan in zone.of_kind(DeviceKind::Fan) {
+                        graph.edge(id.clone(), format!("{}/{}", zone.name, fan.name), Relation::Sheds);
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    fn node(&mut self, id: String, kind: NodeKind, room: Option<&str>, pin: Option<u32>) {
+        self.nodes.push(Node {
+            id,
+            kind,
+            room: room.map(str::to_string),
+            pin,
+        });
+    }
+
+    fn edge(&mut self, from: String, to: String, relation: Relation) {
+        self.edges.push(Edge { from, to, relation });
+    }
+
+    /// Find contradictions: a pin that is both driven and read, or a dependency cycle
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        let driven: HashMap<u32, &str> = self
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.kind, NodeKind::Device | NodeKind::Pin))
+            .filter_map(|n| n.pin.map(|pin| (pin, n.id.as_str())))
+            .collect();
+        for input in self.nodes.iter().filter(|n| n.kind == NodeKind::Input) {
+            if let Some(output) = input.pin.and_then(|pin| driven.get(&pin)) {
+                return Err(format!(
+                    "{} reads pin {} which {} drives as an output",
+                    input.id,
+                    input.pin.unwrap_or_default(),
+                    output
+                ));
+            }
+        }
+
+        if let Some(cycle) = self.find_cycle() {
+            return Err(format!("dependency cycle {}", cycle.join(" -> ")));
+        }
+        Ok(())
+    }
+
+    fn find_cycle(&self) -> Option<Vec<String>> {
+        let mut adjacency: HashMap<&str, Vec<&str>> = HashMap::new();
+        for edge in &self.edges {
+            adjacency.entry(&edge.from).or_default().push(&edge.to);
+        }
+
+        // Depth-first search, tracking the current path to report the cycle
+        fn visit<'a>(
+            node: &'a str,
+            adjacency: &HashMap<&'a str, Vec<&'a str>>,
+            done: &mut HashSet<&'a str>,
+            path: &mut Vec<&'a str>,
+        ) -> Option<Vec<String>> {
+            if let Some(start) = path.iter().position(|n| *n == node) {
+                let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+                cycle.push(node.to_string());
+                return Some(cycle);
+            }
+            if !done.insert(node) {
+                return None;
+            }
+            path.push(node);
+            for next in adjacency.get(node).into_iter().flatten() {
+                if let Some(cycle) = visit(next, adjacency, done, path) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            None
+        }
+
+        let mut done = HashSet::new();
+        self.nodes
+            .iter()
+            .find_map(|n| visit(&n.id, &adjacency, &mut done, &mut Vec::new()))
+    }
+}
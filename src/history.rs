@@ -0,0 +1,203 @@
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    error::{ApiError, Result},
+    gpio::PinState,
+    state::{AppState, ChangeSource, StateEvent},
+};
+
+/// Sessions kept per pin; older ones are dropped
+const MAX_SESSIONS_PER_PIN: usize = 100;
+
+/// One stretch of a pin being on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub pin: u32,
+    pub start: DateTime<Local>,
+    /// None while the pin is still on
+    pub end: Option<DateTime<Local>>,
+    pub started_by: ChangeSource,
+    pub stopped_by: Option<ChangeSource>,
+    /// Whether the monitor pin confirmed ignition; None if it was never checked
+    #[serde(default)]
+    pub verified: Option<bool>,
+    /// The server stopped while the pin was on, so the real end is unknown
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub interrupted: bool,
+}
+
+impl Session {
+    /// Length of the session, up to `now` if it is still running; None if interrupted
+    pub fn duration_seconds(&self, now: DateTime<Local>) -> Option<i64> {
+        if self.interrupted {
+            return None;
+        }
+        Some((self.end.unwrap_or(now) - self.start).num_seconds())
+    }
+}
+
+/// On/off sessions of every output pin, persisted as JSON so they survive restarts
+pub struct SessionHistory {
+    path: PathBuf,
+    sessions: Vec<Session>,
+}
+
+impl SessionHistory {
+    /// Load the persisted sessions; a missing or unreadable file starts empty. Sessions
+    /// still open were cut short by a restart and are closed as interrupted.
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("history.json");
+        let mut sessions: Vec<Session> = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        for session in sessions.iter_mut().filter(|s| s.end.is_none()) {
+            session.interrupted = true;
+        }
+        Self { path, sessions }
+    }
+
+    /// The most recent sessions of a pin, newest first
+    pub fn recent(&self, pin: u32, limit: usize) -> Vec<Session> {
+        self.sessions
+            .iter()
+            .rev()
+            .filter(|s| s.pin == pin)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// How long a pin was on between `from` and `to`. Sessions still running count up to
+    /// `now`; interrupted ones, whose end is unknown, don't count.
+    pub fn on_seconds(&self, pin: u32, from: DateTime<Local>, to: DateTime<Local>, now: DateTime<Local>) -> i64 {
+        self.sessions
+            .iter()
+            .filter(|s| s.pin == pin && !(s.interrupted && s.end.is_none()))
+            .map(|s| (s.end.unwrap_or(now).min(to) - s.start.max(from)).num_seconds().max(0))
+            .sum()
+    }
+
+    /// Open or close a session for a pin changing to `state`
+    pub fn observe(&mut self, pin: u32, state: &PinState, source: ChangeSource, at: DateTime<Local>) {
+        let open = self.open_session(pin);
+        match (state, open) {
+            (PinState::High, None) => {
+                self.sessions.push(Session {
+                    pin,
+                    start: at,
+                    end: None,
+                    started_by: source,
+                    stopped_by: None,
+                    verified: None,
+                    interrupted: false,
+                });
+                self.prune(pin);
+            }
+            (PinState::Low | PinState::Unknown, Some(session)) => {
+                session.end = Some(at);
+                session.stopped_by = Some(source);
+            }
+            _ => return,
+        }
+        self.save();
+    }
+
+    /// Account for a pin found on at startup. A session the restart interrupted carries on;
+    /// otherwise a new one starts now.
+    pub fn resume(&mut self, pin: u32, at: DateTime<Local>) {
+        let last = self.sessions.iter_mut().rev().find(|s| s.pin == pin);
+        match last {
+            Some(session) if session.interrupted => session.interrupted = false,
+            _ => {
+                self.observe(pin, &PinState::High, ChangeSource::Startup, at);
+                return;
+            }
+        }
+        self.save();
+    }
+
+    /// Record the ignition check of the session running on a pin
+    pub fn verify(&mut self, pin: u32, verified: bool) {
+        if let Some(session) = self.open_session(pin) {
+            session.verified = Some(verified);
+            self.save();
+        }
+    }
+
+    fn open_session(&mut self, pin: u32) -> Option<&mut Session> {
+        self.sessions
+            .iter_mut()
+            .rev()
+            .find(|s| s.pin == pin && s.end.is_none() && !s.interrupted)
+    }
+
+    fn prune(&mut self, pin: u32) {
+        let count = self.sessions.iter().filter(|s| s.pin == pin).count();
+        let mut excess = count.saturating_sub(MAX_SESSIONS_PER_PIN);
+        self.sessions.retain(|s| {
+            if excess > 0 && s.pin == pin {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.write() {
+            tracing::warn!("{}", e);
+        }
+    }
+
+    fn write(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.sessions)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode session history: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
+
+/// Feed every pin change and ignition check on the event bus into the session history
+pub fn spawn_recorder(state: AppState) {
+    // Subscribe before returning so no change made after startup is missed
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(StateEvent::PinChanged { pin, state: pin_state, source, timestamp }) => {
+                    let at = DateTime::parse_from_rfc3339(&timestamp)
+                        .map(|at| at.with_timezone(&Local))
+                        .unwrap_or_else(|_| Local::now());
+                    state.history.write().await.observe(pin, &pin_state, source, at);
+                }
+                Ok(StateEvent::IgnitionChecked { pin, verified, .. }) => {
+                    state.history.write().await.verify(pin, verified);
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Session history missed {} events; sessions may be incomplete", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
@@ -0,0 +1,231 @@
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    config::Config,
+    gpio::PinState,
+    state::{AppState, StateEvent},
+};
+
+/// How often the poller checks which inputs are due
+const TICK: Duration = Duration::from_millis(10);
+
+/// `[[inputs]]`: a contact, flame or motion sensor wired to an input pin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputConfig {
+    pub name: String,
+    pub pin: u32,
+    /// Defaults to the primary room
+    #[serde(default)]
+    pub room: Option<String>,
+    #[serde(default)]
+    pub kind: InputKind,
+    /// The input is active when the pin reads low, e.g. a switch to ground with a pull-up
+    #[serde(default)]
+    pub active_low: bool,
+    #[serde(default = "default_poll_interval", alias = "poll_interval_ms", with = "crate::duration::millis")]
+    pub poll_interval: Duration,
+    /// How long a new level must hold before it counts, to ride out contact bounce
+    #[serde(default = "default_debounce", alias = "debounce_ms", with = "crate::duration::millis")]
+    pub debounce: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputKind {
+    /// Flame sensor or thermocouple relay: active while there is a flame
+    Flame,
+    /// Door or window contact: active while open
+    Door,
+    /// PIR motion sensor: active while it sees motion
+    Motion,
+    #[default]
+    Switch,
+}
+
+fn default_poll_interval() -> Duration {
+    Duration::from_millis(100)
+}
+
+fn default_debounce() -> Duration {
+    Duration::from_millis(50)
+}
+
+impl InputConfig {
+    pub fn validate(&self, config: &Config) -> Result<(), String> {
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("inputs: name '{}' must be letters, digits, '_' and '-'", self.name));
+        }
+        config.zone(self.room.as_deref()).map_err(|e| format!("inputs.{}: {}", self.name, e))?;
+        if self.poll_interval < Duration::from_millis(10) {
+            return Err(format!("inputs.{}: poll_interval must be at least 10ms", self.name));
+        }
+        if self.debounce > Duration::from_secs(10) {
+            return Err(format!("inputs.{}: debounce may be at most 10s", self.name));
+        }
+        Ok(())
+    }
+
+    /// The room the input is in
+    pub fn room<'a>(&'a self, config: &'a Config) -> &'a str {
+        config.zone(self.room.as_deref()).map(|z| z.name).unwrap_or(&config.room.name)
+    }
+
+    /// Whether a pin level means active; None for Unknown
+    fn is_active(&self, level: &PinState) -> Option<bool> {
+        match level {
+            PinState::High => Some(!self.active_low),
+            PinState::Low => Some(self.active_low),
+            PinState::Unknown => None,
+        }
+    }
+}
+
+/// An input's debounced state and how it got there
+#[derive(Debug, Clone, Serialize)]
+pub struct InputStatus {
+    pub name: String,
+    pub room: String,
+    pub pin: u32,
+    pub kind: InputKind,
+    /// Null until the first successful read
+    pub active: Option<bool>,
+    /// When `active` last changed, or was first read
+    pub since: Option<String>,
+    /// Why the last read failed, until one succeeds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Tracked {
+    active: Option<bool>,
+    since: Option<DateTime<Local>>,
+    /// A level that differs from `active`, and since when it has been read
+    pending: Option<(bool, Instant)>,
+    error: Option<String>,
+}
+
+/// The debounced state of every input, keyed by input name
+#[derive(Default)]
+pub struct Inputs {
+    tracked: HashMap<String, Tracked>,
+}
+
+impl Inputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in one read of an input. Returns the new state once a change has held for
+    /// the input's debounce; the first read only sets the starting state.
+    fn record(&mut self, input: &InputConfig, result: Result<bool, String>, now: Instant) -> Option<bool> {
+        let tracked = self.tracked.entry(input.name.clone()).or_default();
+        let active = match result {
+            Ok(active) => active,
+            Err(e) => {
+                // Warn when an input starts failing, not on every poll while it stays down
+                if tracked.error.as_ref() != Some(&e) {
+                    tracing::warn!("Failed to read input {}: {}", input.name, e);
+                }
+                tracked.error = Some(e);
+                return None;
+            }
+        };
+        if tracked.error.take().is_some() {
+            tracing::info!("Input {} is reading again", input.name);
+        }
+
+        match (tracked.active, tracked.pending) {
+            (None, _) => {
+                tracked.active = Some(active);
+                tracked.since = Some(Local::now());
+                return None;
+            }
+            (Some(current), _) if current == active => {
+                tracked.pending = None;
+                return None;
+            }
+            (_, Some((level, at))) if level == active => {
+                if now.duration_since(at) < input.debounce {
+                    return None;
+                }
+            }
+            _ => {
+                tracked.pending = Some((active, now));
+                if !input.debounce.is_zero() {
+                    return None;
+                }
+            }
+        }
+        tracked.active = Some(active);
+        tracked.since = Some(Local::now());
+        tracked.pending = None;
+        Some(active)
+    }
+
+    pub fn status(&self, config: &Config, input: &InputConfig) -> InputStatus {
+        let tracked = self.tracked.get(&input.name);
+        InputStatus {
+            name: input.name.clone(),
+            room: input.room(config).to_string(),
+            pin: input.pin,
+            kind: input.kind,
+            active: tracked.and_then(|t| t.active),
+            since: tracked.and_then(|t| t.since).map(|at| at.to_rfc3339()),
+            error: tracked.and_then(|t| t.error.clone()),
+        }
+    }
+
+    /// Every configured input, in config order
+    pub fn list(&self, config: &Config) -> Vec<InputStatus> {
+        config.inputs.iter().map(|input| self.status(config, input)).collect()
+    }
+
+    /// An input's debounced state, unless it hasn't been read yet
+    pub fn is_active(&self, name: &str) -> Option<bool> {
+        self.tracked.get(name).and_then(|t| t.active)
+    }
+}
+
+/// Poll every configured input on its own interval and publish each debounced change.
+/// Follows config reloads, so inputs can be added without a restart.
+pub fn spawn_poller(state: AppState) {
+    tokio::spawn(async move {
+        let mut due: HashMap<String, Instant> = HashMap::new();
+        let mut tick = tokio::time::interval(TICK);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tick.tick().await;
+            let config = state.config.load_full();
+            for input in &config.inputs {
+                let now = Instant::now();
+                if due.get(&input.name).is_some_and(|at| *at > now) {
+                    continue;
+                }
+                due.insert(input.name.clone(), now + input.poll_interval);
+
+                let result = match state.gpio_controller.read_pin(input.pin).await {
+                    Ok(level) => match input.is_active(&level) {
+                        Some(active) => Ok(active),
+                        None => continue,
+                    },
+                    Err(e) => Err(e.to_string()),
+                };
+                let Some(active) = state.inputs.write().await.record(input, result, now) else {
+                    continue;
+                };
+                tracing::info!("Input {} is {}", input.name, if active { "active" } else { "inactive" });
+                state.publish(StateEvent::InputChanged {
+                    room: input.room(&config).to_string(),
+                    input: input.name.clone(),
+                    pin: input.pin,
+                    active,
+                    timestamp: Local::now().to_rfc3339(),
+                });
+            }
+        }
+    });
+}
@@ -0,0 +1,192 @@
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::{ApiError, Result},
+    gpio::PinState,
+    lockout::LockReason,
+    state::{AppState, ChangeSource, StateEvent},
+    timers,
+};
+
+/// Keep one device running for a while after another turns off, e.g. a fan cooling a
+/// fireplace insert
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterlockRule {
+    /// Defaults to the primary room
+    #[serde(default)]
+    pub room: Option<String>,
+    /// Device whose turning off starts the cooldown
+    pub after: String,
+    /// Device kept on, or started, for the cooldown
+    pub run: String,
+    #[serde(alias = "fan_cooldown_minutes", with = "crate::duration::minutes")]
+    pub cooldown: Duration,
+}
+
+impl InterlockRule {
+    pub fn validate(&self, config: &Config) -> std::result::Result<(), String> {
+        let zone = config.zone(self.room.as_deref()).map_err(|e| format!("interlocks: {}", e))?;
+        let after = zone
+            .device(&self.after)
+            .ok_or_else(|| format!("interlocks: unknown device '{}' in '{}'", self.after, zone.name))?;
+        let run = zone
+            .device(&self.run)
+            .ok_or_else(|| format!("interlocks: unknown device '{}' in '{}'", self.run, zone.name))?;
+        if after.pin == run.pin {
+            return Err(format!("interlocks: '{}' can't keep itself running", self.after));
+        }
+        if self.cooldown.is_zero() {
+            return Err(format!("interlocks: cooldown after '{}' must be longer than zero", self.after));
+        }
+        Ok(())
+    }
+}
+
+/// A device being kept on after its trigger turned off
+#[derive(Debug, Clone, Serialize)]
+pub struct Cooldown {
+    pub room: String,
+    pub device: String,
+    /// The device whose turning off started it
+    pub after: String,
+    pub until: DateTime<Local>,
+    #[serde(skip)]
+    timer: Uuid,
+}
+
+/// Cooldowns in progress, keyed by room and configured device name
+#[derive(Default)]
+pub struct Cooldowns {
+    active: HashMap<(String, String), Cooldown>,
+}
+
+impl Cooldowns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every cooldown still running, soonest to end first
+    pub fn list(&self) -> Vec<Cooldown> {
+        let now = Local::now();
+        let mut active: Vec<Cooldown> = self.active.values().filter(|c| c.until > now).cloned().collect();
+        active.sort_by_key(|c| c.until);
+        active
+    }
+
+    pub fn get(&self, room: &str, device: &str) -> Option<&Cooldown> {
+        self.active
+            .get(&(room.to_string(), device.to_string()))
+            .filter(|c| c.until > Local::now())
+    }
+
+    /// Refuse to turn a device off while it is cooling something down
+    pub fn check(&self, room: &str, device: &str, action: &str) -> Result<()> {
+        if !action.eq_ignore_ascii_case("OFF") {
+            return Ok(());
+        }
+        match self.get(room, device) {
+            Some(cooldown) => Err(ApiError::InterlockActive {
+                device: cooldown.device.clone(),
+                after: cooldown.after.clone(),
+                until: cooldown.until.to_rfc3339(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Apply the configured interlocks to every pin change on the event bus
+pub fn spawn_interlocks(state: AppState) {
+    // Subscribe before returning so no change made after startup is missed
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(StateEvent::PinChanged { pin, state: pin_state, source, .. }) => {
+                    apply(&state, pin, &pin_state, source).await;
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Interlocks missed {} pin changes", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+/// Start a cooldown when a trigger turns off; end it early when the trigger comes back on
+async fn apply(state: &AppState, pin: u32, pin_state: &PinState, source: ChangeSource) {
+    // Stopping and starting the server, or an emergency stop, must not start anything. The
+    // over-temperature cutoff runs the fans itself until it resets.
+    if matches!(
+        source,
+        ChangeSource::Startup | ChangeSource::Shutdown | ChangeSource::EmergencyStop | ChangeSource::Overheat
+    ) {
+        return;
+    }
+    let config = state.config.load_full();
+    for rule in &config.interlocks {
+        let Ok(zone) = config.zone(rule.room.as_deref()) else {
+            continue;
+        };
+        let (Some(after), Some(run)) = (zone.device(&rule.after), zone.device(&rule.run)) else {
+            continue;
+        };
+        if after.pin != pin {
+            continue;
+        }
+        let key = (zone.name.to_string(), run.name.clone());
+
+        match pin_state {
+            PinState::High => {
+                // Back on: the device runs as it's told again
+                let ended = state.cooldowns.write().await.active.remove(&key);
+                if let Some(cooldown) = ended {
+                    state.timers.lock().await.cancel(cooldown.timer);
+                    tracing::info!("{} in {} is back on, ending the cooldown of {}", after.name, zone.name, run.name);
+                }
+            }
+            PinState::Low => {
+                if state.lock.read().await.current().is_some_and(|l| l.reason == LockReason::EmergencyStop) {
+                    continue;
+                }
+                let mut gpio = state.gpio_controller.lock(ChangeSource::Interlock, &[run.pin]).await;
+                if gpio.get_pin_state(run.pin) != PinState::High {
+                    if let Err(e) = gpio.set_pin(run.pin, true).await {
+                        tracing::error!("Interlock failed to start {} in {}: {}", run.name, zone.name, e);
+                        continue;
+                    }
+                }
+                drop(gpio);
+
+                let timer = timers::schedule_off_after(state, zone.name, &run.name, vec![run.pin], rule.cooldown).await;
+                let until = Local::now() + chrono::Duration::from_std(rule.cooldown).unwrap_or_default();
+                tracing::info!(
+                    "{} in {} turned off, running {} until {}",
+                    after.name,
+                    zone.name,
+                    run.name,
+                    until.to_rfc3339()
+                );
+                state.cooldowns.write().await.active.insert(
+                    key,
+                    Cooldown {
+                        room: zone.name.to_string(),
+                        device: run.name.clone(),
+                        after: after.name.clone(),
+                        until,
+                        timer: timer.id,
+                    },
+                );
+            }
+            PinState::Unknown => {}
+        }
+    }
+}
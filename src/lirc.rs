@@ -0,0 +1,141 @@
+﻿use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::error::{ApiError, Result};
+use crate::gpio::{GpioBackend, PinState};
+
+/// `[gpio.lirc]`: how IR codes are sent through LIRC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LircConfig {
+    /// LIRC's `irsend`
+    #[serde(default = "default_command")]
+    pub command: String,
+    /// lircd's socket, when it isn't the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Times each key is sent, for receivers that miss the odd one
+    #[serde(default = "default_count")]
+    pub count: u32,
+}
+
+impl Default for LircConfig {
+    fn default() -> Self {
+        Self {
+            command: default_command(),
+            device: None,
+            count: default_count(),
+        }
+    }
+}
+
+fn default_command() -> String {
+    "irsend".to_string()
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+impl LircConfig {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.command.trim().is_empty() {
+            return Err("gpio.lirc.command may not be empty".to_string());
+        }
+        if !(1..=20).contains(&self.count) {
+            return Err("gpio.lirc.count must be between 1 and 20".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A device's `ir` table: the remote in lircd.conf and its keys for on and off
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IrKeys {
+    pub remote: String,
+    pub on_key: String,
+    pub off_key: String,
+}
+
+impl IrKeys {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if [&self.remote, &self.on_key, &self.off_key].iter().any(|s| s.trim().is_empty()) {
+            return Err("ir needs a remote, an on_key and an off_key".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Backend sending the on and off keys of IR devices through `irsend`, addressed by device
+/// pin. Like RF, an IR device can't be read back, so reads return the last state sent.
+pub struct LircBackend {
+    config: LircConfig,
+    devices: HashMap<u32, IrKeys>,
+    /// The last level sent to each device
+    sent: Mutex<HashMap<u32, PinState>>,
+}
+
+impl LircBackend {
+    /// The backend for `config`'s IR devices, carrying over what `previous` last sent to
+    /// devices whose keys haven't changed. `None` without any IR devices.
+    pub fn new(config: &Config, previous: Option<&LircBackend>) -> Option<Self> {
+        let devices: HashMap<u32, IrKeys> = config
+            .zones()
+            .flat_map(|zone| zone.devices.iter())
+            .filter_map(|device| device.ir.clone().map(|ir| (device.pin, ir)))
+            .collect();
+        if devices.is_empty() {
+            return None;
+        }
+        let sent = previous
+            .map(|previous| {
+                let sent = previous.sent.lock().unwrap_or_else(|e| e.into_inner());
+                sent.iter()
+                    .filter(|(pin, _)| previous.devices.get(pin) == devices.get(pin))
+                    .map(|(pin, state)| (*pin, state.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            config: config.gpio.lirc.clone(),
+            devices,
+            sent: Mutex::new(sent),
+        })
+    }
+
+    /// Whether `pin` is an IR device's
+    pub fn drives(&self, pin: u32) -> bool {
+        self.devices.contains_key(&pin)
+    }
+}
+
+impl GpioBackend for LircBackend {
+    fn name(&self) -> &'static str {
+        "lirc"
+    }
+
+    fn write(&self, pin: u32, level: &PinState) -> Result<()> {
+        let keys = self.devices.get(&pin).ok_or(ApiError::InvalidPin)?;
+        let key = if *level == PinState::High { &keys.on_key } else { &keys.off_key };
+        let mut args = Vec::new();
+        if let Some(device) = &self.config.device {
+            args.push(format!("--device={}", device));
+        }
+        if self.config.count > 1 {
+            args.push(format!("--count={}", self.config.count));
+        }
+        args.extend(["SEND_ONCE".to_string(), keys.remote.clone(), key.clone()]);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        crate::gpio::run_command(self.name(), &self.config.command, &args)?;
+        tracing::debug!("Sent IR key {} of {} for pin {}", key, keys.remote, pin);
+        self.sent.lock().unwrap_or_else(|e| e.into_inner()).insert(pin, level.clone());
+        Ok(())
+    }
+
+    fn read(&self, pin: u32) -> Result<PinState> {
+        let sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(sent.get(&pin).cloned().unwrap_or(PinState::Unknown))
+    }
+}
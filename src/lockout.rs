@@ -0,0 +1,282 @@
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, DeviceConfig, DeviceKind, OutputMode},
+    error::{ApiError, Result},
+    fireplace::Transition,
+    gpio::{PinGuard, PinState},
+    state::{AppState, ChangeSource, StateEvent},
+    timers::Timer,
+};
+
+/// Why control is locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockReason {
+    /// Everything is refused, schedules included
+    EmergencyStop,
+    /// Requests are refused; the owner's schedules still run
+    ChildLock,
+}
+
+/// Control commands are refused until this is released
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lock {
+    pub reason: LockReason,
+    pub since: DateTime<Local>,
+    /// The user who engaged it, when known
+    pub by: Option<String>,
+}
+
+/// The control lock, persisted as JSON so a restart doesn't quietly release it
+pub struct LockStore {
+    path: PathBuf,
+    lock: Option<Lock>,
+}
+
+impl LockStore {
+    /// Load the persisted lock; a missing or unreadable file starts unlocked
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("lock.json");
+        let lock: Option<Lock> = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse {}: {}", path.display(), e);
+                None
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                None
+            }
+        };
+        if let Some(lock) = &lock {
+            tracing::warn!("Control is locked ({:?}) since {}", lock.reason, lock.since.to_rfc3339());
+        }
+        Self { path, lock }
+    }
+
+    pub fn current(&self) -> Option<&Lock> {
+        self.lock.as_ref()
+    }
+
+    /// Lock control. An existing lock is kept unless an emergency stop replaces a child lock.
+    pub fn engage(&mut self, reason: LockReason, by: Option<String>) -> Result<Lock> {
+        if let Some(lock) = &self.lock {
+            if lock.reason == reason || lock.reason == LockReason::EmergencyStop {
+                return Ok(lock.clone());
+            }
+        }
+        let lock = Lock {
+            reason,
+            since: Local::now(),
+            by,
+        };
+        self.lock = Some(lock.clone());
+        self.save()?;
+        Ok(lock)
+    }
+
+    /// Release the lock, returning what it was
+    pub fn release(&mut self) -> Result<Lock> {
+        let lock = self.lock.take().ok_or(ApiError::NotLocked)?;
+        self.save()?;
+        Ok(lock)
+    }
+
+    /// Refuse control from `source` while locked
+    pub fn check(&self, source: ChangeSource) -> Result<()> {
+        let Some(lock) = &self.lock else {
+            return Ok(());
+        };
+        let refused = match lock.reason {
+            LockReason::EmergencyStop => true,
+            LockReason::ChildLock => matches!(
+                source,
+                ChangeSource::Api
+                    | ChangeSource::Legacy
+                    | ChangeSource::Mqtt
+                    | ChangeSource::Telegram
+                    | ChangeSource::Scene
+            ),
+        };
+        if refused {
+            return Err(ApiError::Locked {
+                reason: lock.reason,
+                since: lock.since.to_rfc3339(),
+            });
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.lock)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode lock: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
+
+/// What an emergency stop did
+#[derive(Debug, Serialize)]
+pub struct EmergencyStop {
+    pub lock: Lock,
+    /// Pins not already off that were driven off
+    pub pins_off: Vec<u32>,
+    pub timers_cancelled: Vec<Timer>,
+    pub schedules_disabled: Vec<Uuid>,
+    /// Anything that could not be done, including devices not confirmed off; the rest
+    /// still was
+    pub errors: Vec<String>,
+}
+
+/// Drive one device off and confirm it is off, returning whether it had to be driven. A
+/// relay is driven low whatever it reads and read back. A pulse-mode fireplace is pressed
+/// when the state machine or its monitor pin says it is lit. The monitor pin, when wired,
+/// has the last word.
+async fn stop_device(
+    state: &AppState,
+    config: &Config,
+    gpio: &mut PinGuard<'_>,
+    room: &str,
+    device: &DeviceConfig,
+) -> std::result::Result<bool, String> {
+    let flame = match device.monitor {
+        Some(monitor) => {
+            let level = gpio.read_pin(monitor).await;
+            Some(level.map_err(|e| format!("Failed to read monitor pin {}: {}", monitor, e))?)
+        }
+        None => None,
+    };
+    let driven = match device.mode {
+        OutputMode::Pulse if device.kind != DeviceKind::Fireplace => {
+            return Err("a momentary contact without a tracked state can't be confirmed off".to_string());
+        }
+        OutputMode::Pulse => {
+            let lit = crate::fireplace::is_on(state, gpio, device.pin).await || flame == Some(PinState::High);
+            if lit {
+                gpio.pulse_pin(device.pin, config.safety.pulse_duration)
+                    .await
+                    .map_err(|e| format!("Failed to press it off: {}", e))?;
+                crate::fireplace::transition(state, room, &device.name, Transition::Extinguish).await;
+            }
+            lit
+        }
+        OutputMode::Toggle | OutputMode::Latch => {
+            let was_low = gpio.get_pin_state(device.pin) == PinState::Low;
+            gpio.set_pin(device.pin, false).await.map_err(|e| format!("Failed to drive it off: {}", e))?;
+            match gpio.read_pin(device.pin).await {
+                Ok(PinState::Low) => {}
+                Ok(level) => return Err(format!("Pin {} reads {:?} after driving it off", device.pin, level)),
+                Err(e) => return Err(format!("Failed to read pin {} back: {}", device.pin, e)),
+            }
+            !was_low
+        }
+    };
+    if let Some(monitor) = device.monitor {
+        match gpio.read_pin(monitor).await {
+            Ok(PinState::Low) => {}
+            Ok(level) => return Err(format!("Monitor pin {} still reads {:?}", monitor, level)),
+            Err(e) => return Err(format!("Failed to read monitor pin {}: {}", monitor, e)),
+        }
+    }
+    Ok(driven)
+}
+
+/// Lock control, cancel every timer, disable every schedule and drive every output off.
+/// The lock goes first so nothing queued behind the stop can switch a relay back on.
+pub async fn emergency_stop(state: &AppState, by: Option<String>) -> Result<EmergencyStop> {
+    let lock = state.lock.write().await.engage(LockReason::EmergencyStop, by)?;
+    tracing::error!("Emergency stop by {}", lock.by.as_deref().unwrap_or("anonymous caller"));
+    let mut errors = Vec::new();
+
+    let timers_cancelled = state.timers.lock().await.cancel_all();
+    let schedules_disabled = crate::scheduler::disable_all(state).await.unwrap_or_else(|e| {
+        errors.push(e.to_string());
+        Vec::new()
+    });
+
+    // Taking every output lock waits out any control command still in progress
+    let config = state.config.load_full();
+    let mut gpio = state.gpio_controller.lock(ChangeSource::EmergencyStop, &config.output_pins()).await;
+    let mut pins_off = Vec::new();
+    for zone in config.zones() {
+        for device in zone.devices {
+            match stop_device(state, &config, &mut gpio, zone.name, device).await {
+                Ok(driven) => pins_off.extend(driven.then_some(device.pin)),
+                Err(e) => errors.push(format!("{} in {}: {}", device.name, zone.name, e)),
+            }
+        }
+    }
+    drop(gpio);
+
+    for error in &errors {
+        tracing::error!("Emergency stop: {}", error);
+    }
+    state.publish(StateEvent::EmergencyStopped {
+        by: lock.by.clone(),
+        pins_off: pins_off.clone(),
+        timestamp: lock.since.to_rfc3339(),
+    });
+    Ok(EmergencyStop {
+        lock,
+        pins_off,
+        timers_cancelled,
+        schedules_disabled,
+        errors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locked(reason: LockReason) -> LockStore {
+        LockStore {
+            path: PathBuf::from("unused/lock.json"),
+            lock: Some(Lock {
+                reason,
+                since: Local::now(),
+                by: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn unlocked_allows_everything() {
+        let store = LockStore {
+            path: PathBuf::from("unused/lock.json"),
+            lock: None,
+        };
+        assert!(store.check(ChangeSource::Api).is_ok());
+        assert!(store.check(ChangeSource::Schedule).is_ok());
+    }
+
+    #[test]
+    fn emergency_stop_refuses_every_source() {
+        let store = locked(LockReason::EmergencyStop);
+        for source in [ChangeSource::Api, ChangeSource::Legacy, ChangeSource::Schedule, ChangeSource::Rule] {
+            assert!(matches!(store.check(source), Err(ApiError::Locked { .. })), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn child_lock_refuses_requests_but_not_schedules() {
+        let store = locked(LockReason::ChildLock);
+        for source in [ChangeSource::Api, ChangeSource::Legacy, ChangeSource::Mqtt, ChangeSource::Telegram, ChangeSource::Scene] {
+            assert!(store.check(source).is_err(), "{:?}", source);
+        }
+        assert!(store.check(ChangeSource::Schedule).is_ok());
+        assert!(store.check(ChangeSource::Thermostat).is_ok());
+    }
+}
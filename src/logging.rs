@@ -0,0 +1,295 @@
+﻿use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::config::{default_buffer_lines, LoggingConfig, SyslogConfig, SyslogProtocol};
+
+/// Messages queued for the syslog sender before new ones are dropped
+const SYSLOG_QUEUE_SIZE: usize = 1024;
+
+/// Syslog messages queued but not yet sent
+static SYSLOG_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// How long to wait before retrying a failed TCP syslog connection
+const SYSLOG_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Install the global tracing subscriber: console output, the in-memory ring buffer,
+/// plus any configured shipping. Returns the ring buffer for the admin API.
+pub fn init(config: Option<&LoggingConfig>) -> LogBuffer {
+    let mut errors = Vec::new();
+
+    let buffer = LogBuffer::new(config.map_or_else(default_buffer_lines, |c| c.buffer_lines));
+
+    let syslog = config.and_then(|c| c.syslog.as_ref()).and_then(|c| {
+        SyslogLayer::new(c)
+            .map_err(|e| errors.push(format!("Failed to start syslog output to {}: {}", c.address, e)))
+            .ok()
+    });
+
+    let journald = if config.is_some_and(|c| c.journald) {
+        tracing_journald::layer()
+            .map_err(|e| errors.push(format!("Failed to connect to journald: {}", e)))
+            .ok()
+    } else {
+        None
+    };
+
+    tracing_subscriber::registry()
+        .with(
+            EnvFilter::from_default_env()
+                .add_directive("fireplace_api=debug".parse().unwrap()),
+        )
+        .with(tracing_subscriber::fmt::layer())
+        .with(buffer.clone())
+        .with(syslog)
+        .with(journald)
+        .init();
+
+    for error in errors {
+        tracing::warn!("{}", error);
+    }
+
+    buffer
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+    #[serde(skip)]
+    severity: Level,
+}
+
+/// Ring buffer of the most recent log events, shared with the admin API
+#[derive(Clone)]
+pub struct LogBuffer {
+    entries: Arc<Mutex<VecDeque<LogEntry>>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// The newest `lines` entries at `level` or more severe, oldest first
+    pub fn recent(&self, lines: usize, level: Level) -> Vec<LogEntry> {
+        let entries = self.entries.lock().unwrap();
+        let mut recent: Vec<LogEntry> = entries
+            .iter()
+            .rev()
+            .filter(|e| e.severity <= level)
+            .take(lines)
+            .cloned()
+            .collect();
+        recent.reverse();
+        recent
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBuffer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+
+        let mut message = fields.message;
+        for (name, value) in fields.fields {
+            let _ = write!(message, " {}={}", name, value);
+        }
+
+        let entry = LogEntry {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message,
+            severity: *metadata.level(),
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+/// Tracing layer shipping events to a remote syslog server as RFC 5424 messages
+pub struct SyslogLayer {
+    sender: SyncSender<String>,
+    hostname: String,
+    app_name: String,
+    facility: u8,
+}
+
+impl SyslogLayer {
+    pub fn new(config: &SyslogConfig) -> std::io::Result<Self> {
+        let sender = match config.protocol {
+            SyslogProtocol::Udp => spawn_udp_sender(&config.address)?,
+            SyslogProtocol::Tcp => spawn_tcp_sender(config.address.clone()),
+        };
+
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|h| h.trim().to_string())
+            .unwrap_or_else(|_| "-".to_string());
+
+        Ok(Self {
+            sender,
+            hostname,
+            app_name: config.app_name.clone(),
+            facility: config.facility,
+        })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for SyslogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let severity = match *metadata.level() {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+
+        let mut params = String::new();
+        for (name, value) in &fields.fields {
+            let _ = write!(params, " {}=\"{}\"", name, escape_param(value));
+        }
+
+        // <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID [SD-ELEMENT] MSG
+        let line = format!(
+            "<{}>1 {} {} {} {} - [fields@32473 target=\"{}\"{}] {}",
+            self.facility as u32 * 8 + severity,
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            escape_param(metadata.target()),
+            params,
+            fields.message,
+        );
+
+        // Never block the caller; drop the message if the sender is backed up
+        if self.sender.try_send(line).is_ok() {
+            SYSLOG_PENDING.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Wait up to `timeout` for queued syslog messages to be sent. Returns how many are left.
+pub async fn flush(timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let pending = SYSLOG_PENDING.load(Ordering::SeqCst);
+        if pending == 0 || Instant::now() >= deadline {
+            return pending;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Collects an event's message and its remaining fields
+#[derive(Default)]
+struct EventFields {
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl tracing::field::Visit for EventFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.push((field.name(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields.push((field.name(), format!("{:?}", value)));
+        }
+    }
+}
+
+/// Escape the characters RFC 5424 reserves inside PARAM-VALUE
+fn escape_param(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+fn spawn_udp_sender(address: &str) -> std::io::Result<SyncSender<String>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(address)?;
+
+    let (sender, receiver) = mpsc::sync_channel::<String>(SYSLOG_QUEUE_SIZE);
+    std::thread::spawn(move || {
+        for line in receiver {
+            let _ = socket.send(line.as_bytes());
+            SYSLOG_PENDING.fetch_sub(1, Ordering::SeqCst);
+        }
+    });
+    Ok(sender)
+}
+
+fn spawn_tcp_sender(address: String) -> SyncSender<String> {
+    let (sender, receiver) = mpsc::sync_channel::<String>(SYSLOG_QUEUE_SIZE);
+    std::thread::spawn(move || {
+        let mut stream: Option<TcpStream> = None;
+        let mut last_attempt: Option<Instant> = None;
+
+        for line in receiver {
+            if stream.is_none()
+                && last_attempt.is_none_or(|t| t.elapsed() >= SYSLOG_RECONNECT_INTERVAL)
+            {
+                last_attempt = Some(Instant::now());
+                stream = connect_tcp(&address);
+            }
+
+            // RFC 6587 octet-counting framing
+            if let Some(s) = stream.as_mut() {
+                let frame = format!("{} {}", line.len(), line);
+                if s.write_all(frame.as_bytes()).is_err() {
+                    stream = None;
+                }
+            }
+            SYSLOG_PENDING.fetch_sub(1, Ordering::SeqCst);
+        }
+    });
+    sender
+}
+
+fn connect_tcp(address: &str) -> Option<TcpStream> {
+    let addr = address.to_socket_addrs().ok()?.next()?;
+    TcpStream::connect_timeout(&addr, Duration::from_secs(2)).ok()
+}
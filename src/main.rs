@@ -1,31 +1,79 @@
-﻿mod api;
+﻿mod aliases;
+mod api;
+mod audit;
+mod auth;
+mod canary;
+mod command_log;
+mod commands;
 mod config;
+mod confirmation;
+mod duration;
 mod error;
+mod failover;
+mod fan_control;
+mod fault;
+mod fireplace;
 mod gpio;
+mod graph;
+mod history;
+mod inputs;
+mod interlock;
+mod lirc;
+mod lockout;
+mod logging;
+mod motion;
+mod mqtt;
+mod notifications;
+mod overheat;
+mod pinout;
+mod power;
+mod rate_limit;
+mod rf;
+mod rules;
+mod safety;
+mod scenes;
+mod scheduler;
+mod secrets;
+mod selftest;
+mod sequence;
+mod sensor_history;
+mod sensors;
+mod shutdown;
+mod simulation;
+mod startup;
 mod state;
+mod status_led;
+mod telegram;
+mod thermostat;
+mod timers;
+mod usage;
+mod watcher;
+mod webhooks;
 
+use arc_swap::ArcSwap;
 use axum::{
     Router,
     routing::get,
 };
+use futures::future::BoxFuture;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
-use tracing_subscriber;
+
+const CONFIG_PATH: &str = "config/family_room.toml";
 
 #[tokio::main]
 async fn main() {
+    // Load configuration first so it can direct where logs are shipped
+    let loaded = config::Config::load(CONFIG_PATH);
+
     // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("fireplace_api=debug".parse().unwrap()),
-        )
-        .init();
+    let logs = logging::init(loaded.as_ref().ok().map(|cfg| &cfg.logging));
 
     tracing::info!("Starting Fireplace API Server");
 
-    // Load configuration
-    let config = match config::Config::load("config/family_room.toml") {
+    let config = match loaded {
         Ok(cfg) => {
             tracing::info!("Configuration loaded successfully");
             cfg
@@ -36,14 +84,137 @@ async fn main() {
         }
     };
 
+    // With no config file at all this is a blank install: start in setup mode
+    let setup = if std::path::Path::new(CONFIG_PATH).exists() {
+        None
+    } else {
+        let session = api::setup::SetupSession::new();
+        tracing::warn!("No config at {}, starting in setup mode", CONFIG_PATH);
+        // Only on stdout, so the token never reaches syslog or GET /api/v1/logs
+        println!(
+            "Setup mode: POST the config to /api/v1/setup with header X-Setup-Token: {}",
+            session.token()
+        );
+        Some(session)
+    };
+
     // Create application state
+    let (events, _) = tokio::sync::broadcast::channel(state::EVENT_BUS_CAPACITY);
+    let gpio_controller = gpio::GpioController::new(&config, events.clone());
+    let schedules = scheduler::Scheduler::new(&config.storage.dir);
+    let aliases = aliases::AliasStore::load(&config.storage.dir);
+    let history = history::SessionHistory::load(&config.storage.dir);
+    let command_log = command_log::CommandLog::load(&config.storage.dir);
+    let usage = usage::UsageTracker::load(&config.storage.dir);
+    let lock = lockout::LockStore::load(&config.storage.dir);
+    let thermostat = thermostat::ThermostatStore::load(&config.storage.dir);
+    let sensor_history = sensor_history::SensorHistory::load(&config.storage.dir);
+    let scenes = scenes::SceneStore::load(&config.storage.dir);
+    let rules = rules::RuleStore::load(&config.storage.dir);
+    let failover = failover::FailoverNode::new(config.failover.is_some());
     let state = state::AppState {
-        config: Arc::new(config),
-        gpio_controller: Arc::new(tokio::sync::Mutex::new(
-            gpio::GpioController::new(),
-        )),
+        config: Arc::new(ArcSwap::from_pointee(config)),
+        config_path: Arc::new(CONFIG_PATH.to_string()),
+        gpio_controller: Arc::new(gpio_controller),
+        power: Arc::new(tokio::sync::RwLock::new(power::PowerStatus::new())),
+        faults: Arc::new(tokio::sync::RwLock::new(fault::FaultRegistry::new())),
+        safety_timer: Arc::new(tokio::sync::Mutex::new(safety::SafetyTimer::new())),
+        timers: Arc::new(tokio::sync::Mutex::new(timers::TimerManager::new())),
+        cooldowns: Arc::new(tokio::sync::RwLock::new(interlock::Cooldowns::new())),
+        fireplaces: Arc::new(tokio::sync::RwLock::new(fireplace::Fireplaces::new())),
+        sensors: Arc::new(tokio::sync::RwLock::new(sensors::Sensors::new())),
+        inputs: Arc::new(tokio::sync::RwLock::new(inputs::Inputs::new())),
+        idle_timers: Arc::new(tokio::sync::RwLock::new(motion::IdleTimers::new())),
+        sensor_history: Arc::new(tokio::sync::RwLock::new(sensor_history)),
+        thermostat: Arc::new(tokio::sync::Mutex::new(thermostat)),
+        cutoffs: Arc::new(tokio::sync::RwLock::new(overheat::Cutoffs::new())),
+        scheduler: Arc::new(tokio::sync::Mutex::new(schedules)),
+        scenes: Arc::new(tokio::sync::RwLock::new(scenes)),
+        rules: Arc::new(tokio::sync::RwLock::new(rules)),
+        commands: Arc::new(tokio::sync::Mutex::new(commands::CommandRegistry::new())),
+        confirmations: Arc::new(tokio::sync::Mutex::new(confirmation::ConfirmationStore::new())),
+        aliases: Arc::new(tokio::sync::RwLock::new(aliases)),
+        setup: Arc::new(tokio::sync::RwLock::new(setup)),
+        canary: Arc::new(tokio::sync::Mutex::new(None)),
+        logs,
+        audit: Arc::new(tokio::sync::Mutex::new(audit::AuditLog::new())),
+        command_log: Arc::new(tokio::sync::Mutex::new(command_log)),
+        rate_limiter: Arc::new(tokio::sync::Mutex::new(rate_limit::RateLimiter::new())),
+        usage: Arc::new(tokio::sync::RwLock::new(usage)),
+        history: Arc::new(tokio::sync::RwLock::new(history)),
+        lock: Arc::new(tokio::sync::RwLock::new(lock)),
+        failover: Arc::new(tokio::sync::RwLock::new(failover)),
+        events,
+        started_at: std::time::Instant::now(),
     };
 
+    // Track last change and on-time of every pin, before anything can switch one
+    usage::spawn_tracker(state.clone());
+    history::spawn_recorder(state.clone());
+
+    // Pick up relays left on, and drive devices to their configured safe state
+    startup::reconcile(&state).await;
+
+    // Check the GPIO backend and wiring before anything else drives a pin
+    selftest::run_at_startup(&state).await;
+
+    // Start each fireplace's state machine where its relay was found, then follow it
+    fireplace::init(&state).await;
+    fireplace::spawn_tracker(state.clone());
+
+    // Keep devices such as fans running after the device they cool turns off
+    interlock::spawn_interlocks(state.clone());
+
+    // Read and record the temperature sensors, and hold the thermostat's setpoint with them
+    sensors::spawn_poller(state.clone());
+    sensor_history::spawn_recorder(state.clone());
+    thermostat::spawn_controller(state.clone());
+
+    // Run fans off their firebox sensors, if any [[fan_control]] entries are configured
+    fan_control::spawn_controller(state.clone());
+
+    // Enforce safety.max_temperature_c on every room with a sensor
+    overheat::spawn_monitor(state.clone());
+
+    // Notify the configured webhooks of state changes and safety events
+    webhooks::spawn_dispatcher(state.clone());
+
+    // Push alerts to a phone through ntfy or Pushover, if configured
+    notifications::spawn_notifier(state.clone());
+
+    // Answer status requests and on/off commands from Telegram, if a bot is configured
+    telegram::spawn_bot(state.clone());
+
+    // Mirror device state to the MQTT broker, if one is configured
+    mqtt::spawn_publisher(state.clone());
+
+    // Pair up with the failover peer, if one is configured
+    failover::spawn_heartbeat(state.clone());
+
+    // Watch the UPS status input, if one is configured
+    power::spawn_monitor(state.clone());
+
+    // Poll the [[inputs]] pins and publish their changes
+    inputs::spawn_poller(state.clone());
+
+    // Turn devices off in rooms whose motion sensor has seen no one for a while
+    motion::spawn_monitor(state.clone());
+
+    // Show the fireplace's state on the status LED, if one is configured
+    status_led::spawn_driver(state.clone());
+
+    // Enforce safety.max_runtime on every fireplace
+    safety::spawn_watchdog(state.clone());
+
+    // Run the automation rules when their triggers fire
+    rules::spawn_engine(state.clone());
+
+    // Start the persisted recurring schedules
+    scheduler::load(&state).await;
+
+    // Hot-reload the config file when it changes on disk
+    watcher::spawn_config_watcher(state.clone());
+
     // Build router with both legacy and modern endpoints
     let app = Router::new()
         // Legacy endpoint (backward compatible with Python API)
@@ -52,26 +223,163 @@ async fn main() {
         // Health check
         .route("/health", get(api::handlers::handle_health))
         
+        // Built-in web dashboard
+        .route("/ui", get(api::dashboard::handle_dashboard))
+        
+        // First-boot setup
+        .route("/api/v1/setup", get(api::setup::handle_setup_status).post(api::setup::handle_submit_setup))
+
         // Modern RESTful endpoints
+        .route("/api/v1", get(api::handlers::handle_api_index))
         .route("/api/v1/fireplace/control", axum::routing::post(api::handlers::handle_fireplace_control))
+        .route("/api/v1/fireplace/pulse", axum::routing::post(api::handlers::handle_fireplace_pulse))
+        .route("/api/v1/commands/:id", get(api::handlers::handle_get_command))
         .route("/api/v1/gpio/status", get(api::handlers::handle_gpio_status))
         .route("/api/v1/config", get(api::handlers::handle_get_config))
         .route("/api/v1/config/reload", axum::routing::post(api::handlers::handle_reload_config))
+        .route(
+            "/api/v1/config/canary",
+            get(api::handlers::handle_get_canary)
+                .post(api::handlers::handle_start_canary)
+                .delete(api::handlers::handle_abort_canary),
+        )
+        .route("/api/v1/config/canary/promote", axum::routing::post(api::handlers::handle_promote_canary))
+        .route("/api/v1/system", get(api::handlers::handle_system_status))
+        .route("/api/v1/ws", get(api::ws::handle_ws))
+        .route("/api/v1/devices", get(api::handlers::handle_list_devices))
+        .route("/api/v1/devices/graph", get(api::handlers::handle_device_graph))
+        .route("/api/v1/devices/aliases", get(api::handlers::handle_list_aliases))
+        .route(
+            "/api/v1/devices/:name/aliases",
+            axum::routing::put(api::handlers::handle_set_aliases).delete(api::handlers::handle_delete_aliases),
+        )
+        .route("/api/v1/devices/:name/status", get(api::handlers::handle_device_status))
+        .route("/api/v1/devices/:name/sessions", get(api::handlers::handle_device_sessions))
+        .route("/api/v1/stats", get(api::handlers::handle_stats))
+        .route("/api/v1/devices/:name/watch", get(api::handlers::handle_watch_device))
+        .route("/api/v1/timers", get(api::handlers::handle_list_timers))
+        .route("/api/v1/timers/:id", axum::routing::delete(api::handlers::handle_cancel_timer))
+        .route("/api/v1/schedules", get(api::handlers::handle_list_schedules).post(api::handlers::handle_create_schedule))
+        .route(
+            "/api/v1/schedules/:id",
+            get(api::handlers::handle_get_schedule)
+                .put(api::handlers::handle_update_schedule)
+                .delete(api::handlers::handle_delete_schedule),
+        )
+        .route("/api/v1/scenes", get(api::handlers::handle_list_scenes).post(api::handlers::handle_create_scene))
+        .route(
+            "/api/v1/scenes/:name",
+            get(api::handlers::handle_get_scene)
+                .put(api::handlers::handle_update_scene)
+                .delete(api::handlers::handle_delete_scene),
+        )
+        .route("/api/v1/scenes/:name/activate", axum::routing::post(api::handlers::handle_activate_scene))
+        .route("/api/v1/rules", get(api::handlers::handle_list_rules).post(api::handlers::handle_create_rule))
+        .route(
+            "/api/v1/rules/:name",
+            get(api::handlers::handle_get_rule)
+                .put(api::handlers::handle_update_rule)
+                .delete(api::handlers::handle_delete_rule),
+        )
+        .route("/api/v1/emergency_stop", axum::routing::post(api::handlers::handle_emergency_stop))
+        .route("/api/v1/lock", get(api::handlers::handle_get_lock).post(api::handlers::handle_lock))
+        .route("/api/v1/unlock", axum::routing::post(api::handlers::handle_unlock))
+        .route("/api/v1/safety/timer/reset", axum::routing::post(api::handlers::handle_reset_safety_timer))
+        .route("/api/v1/failover", get(api::handlers::handle_failover_status))
+        .route("/api/v1/failover/heartbeat", axum::routing::post(api::handlers::handle_failover_heartbeat))
+        .route("/api/v1/sensors", get(api::handlers::handle_list_sensors))
+        .route("/api/v1/inputs", get(api::handlers::handle_list_inputs))
+        .route("/api/v1/idle_off/:input", axum::routing::delete(api::handlers::handle_cancel_idle_off))
+        .route("/api/v1/sensors/:id/history", get(api::handlers::handle_sensor_history))
+        .route("/api/v1/thermostat", get(api::handlers::handle_get_thermostat).post(api::handlers::handle_set_thermostat))
+        .route("/api/v1/faults", get(api::handlers::handle_list_faults))
+        .route("/api/v1/faults/reset", axum::routing::post(api::handlers::handle_reset_fault))
+        .route("/api/v1/admin/logs", get(api::handlers::handle_admin_logs))
+        .route("/api/v1/admin/audit", get(api::handlers::handle_admin_audit))
+        .route("/api/v1/history", get(api::handlers::handle_command_history))
+        .route("/api/v1/admin/simulate", axum::routing::post(api::handlers::handle_simulate))
+        .route("/api/v1/deprecations", get(api::handlers::handle_deprecations))
+        .route("/api/v1/selftest", axum::routing::post(api::handlers::handle_selftest))
         
+        // Rate limiting runs inside the command log so refused requests are recorded too
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), api::rate_limit::limit_control_requests))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), api::command_log::record_commands))
+        // Deprecation/Sunset headers need the matched route, so run after routing
+        .route_layer(axum::middleware::from_fn(api::deprecation::add_deprecation_headers))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), api::setup::require_configured))
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), api::auth::authorize))
+        // Wraps every layer above so auth and setup errors are negotiated too
+        .layer(axum::middleware::from_fn(api::problem::negotiate_problem_json))
         .layer(CorsLayer::permissive())
-        .with_state(state);
+        .with_state(state.clone());
+
+    // Serve HTTPS when a certificate is configured. If it can't be loaded, stop rather than
+    // quietly falling back to cleartext.
+    let tls = match state.config.load().server.tls() {
+        Some((cert, key)) => {
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            match axum_server::tls_rustls::RustlsConfig::from_pem_file(cert, key).await {
+                Ok(tls) => Some(tls),
+                Err(e) => {
+                    tracing::error!("Failed to load TLS certificate {} / key {}: {}", cert, key, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
 
     // Start server
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8090")
         .await
         .expect("Failed to bind to port 8090");
 
-    tracing::info!("Server listening on http://0.0.0.0:8090");
+    tracing::info!("Server listening on {}://0.0.0.0:8090", scheme);
     tracing::info!("Legacy endpoint: GET /?cmdType=toggle&cmdAction=ON&v_ACTION=on&m_PIN=37&m_pulsePIN=0&m_monPIN=0&n_CYCLE=0");
     tracing::info!("Modern endpoint: POST /api/v1/fireplace/control");
     tracing::info!("Health check: GET /health");
 
-    axum::serve(listener, app)
-        .await
-        .expect("Server error");
+    // On SIGTERM/SIGINT stop accepting connections and let open ones finish, but don't
+    // wait forever on WebSockets and event streams that never end by themselves
+    let draining = Arc::new(tokio::sync::Notify::new());
+    let shutdown_signal = {
+        let draining = draining.clone();
+        async move {
+            shutdown::signal().await;
+            draining.notify_one();
+        }
+    };
+
+    // The peer address is needed to check requests come through a trusted proxy
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let server: BoxFuture<'static, std::io::Result<()>> = match tls {
+        Some(tls) => {
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    shutdown_signal.await;
+                    handle.graceful_shutdown(None);
+                }
+            });
+            let listener = listener.into_std().expect("Failed to hand over listener");
+            Box::pin(axum_server::from_tcp_rustls(listener, tls).handle(handle).serve(app))
+        }
+        None => Box::pin(axum::serve(listener, app).with_graceful_shutdown(shutdown_signal).into_future()),
+    };
+    let drain_timeout = state.config.load().shutdown.drain_timeout;
+    tokio::select! {
+        result = server => {
+            if let Err(e) = result {
+                tracing::error!("Server error: {}", e);
+            }
+        }
+        _ = async {
+            draining.notified().await;
+            tokio::time::sleep(drain_timeout).await;
+        } => tracing::warn!("Connections still open after {}, closing them", humantime::format_duration(drain_timeout)),
+    }
+
+    shutdown::finish(&state).await;
 }
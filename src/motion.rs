@@ -0,0 +1,179 @@
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::{
+    config::{Config, DeviceKind},
+    state::{AppState, ChangeSource},
+};
+
+/// How often rooms are checked for motion
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `[[idle_off]]`: turn a room's devices off once its motion sensor has seen no one for a while
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdleOffConfig {
+    /// The `[[inputs]]` motion sensor watching the room
+    pub input: String,
+    /// How long without motion before the devices are turned off
+    #[serde(alias = "after_minutes", with = "crate::duration::minutes")]
+    pub after: Duration,
+    /// Devices in the input's room to turn off; defaults to its fireplaces
+    #[serde(default)]
+    pub devices: Vec<String>,
+}
+
+impl IdleOffConfig {
+    pub fn validate(&self, config: &Config) -> Result<(), String> {
+        let input = config
+            .input(&self.input)
+            .ok_or_else(|| format!("idle_off: unknown input '{}'", self.input))?;
+        if self.after.is_zero() {
+            return Err(format!("idle_off.{}: after must be longer than zero", self.input));
+        }
+        let zone = config.zone(Some(input.room(config))).map_err(|e| e.to_string())?;
+        for device in &self.devices {
+            if zone.device(device).is_none() {
+                return Err(format!("idle_off.{}: unknown device '{}' in '{}'", self.input, device, zone.name));
+            }
+        }
+        if self.devices.is_empty() && zone.of_kind(DeviceKind::Fireplace).next().is_none() {
+            return Err(format!("idle_off.{}: '{}' has no fireplace; list its devices", self.input, zone.name));
+        }
+        Ok(())
+    }
+
+    /// The devices turned off, with their pins
+    fn devices(&self, config: &Config) -> Vec<(String, u32)> {
+        let Some(zone) = config.input(&self.input).and_then(|input| config.zone(Some(input.room(config))).ok()) else {
+            return Vec::new();
+        };
+        if self.devices.is_empty() {
+            return zone.of_kind(DeviceKind::Fireplace).map(|d| (d.name.clone(), d.pin)).collect();
+        }
+        self.devices
+            .iter()
+            .filter_map(|name| zone.device(name).map(|d| (d.name.clone(), d.pin)))
+            .collect()
+    }
+}
+
+/// A room counting down to turning its devices off
+#[derive(Debug, Clone, Serialize)]
+pub struct IdleCountdown {
+    pub room: String,
+    pub input: String,
+    pub devices: Vec<String>,
+    pub off_at: String,
+    pub remaining_seconds: i64,
+}
+
+/// Idle countdowns in progress, keyed by input name
+#[derive(Default)]
+pub struct IdleTimers {
+    off_at: HashMap<String, DateTime<Local>>,
+    /// Countdowns cancelled through the API; they don't start again until the room sees
+    /// motion or its devices are turned off
+    cancelled: HashSet<String>,
+}
+
+impl IdleTimers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every countdown in progress, soonest to end first
+    pub fn list(&self, config: &Config) -> Vec<IdleCountdown> {
+        let mut countdowns: Vec<IdleCountdown> = config
+            .idle_off
+            .iter()
+            .filter_map(|entry| self.countdown(config, entry))
+            .collect();
+        countdowns.sort_by_key(|c| c.remaining_seconds);
+        countdowns
+    }
+
+    fn countdown(&self, config: &Config, entry: &IdleOffConfig) -> Option<IdleCountdown> {
+        let off_at = self.off_at.get(&entry.input)?;
+        Some(IdleCountdown {
+            room: config.input(&entry.input)?.room(config).to_string(),
+            input: entry.input.clone(),
+            devices: entry.devices(config).into_iter().map(|(name, _)| name).collect(),
+            off_at: off_at.to_rfc3339(),
+            remaining_seconds: (*off_at - Local::now()).num_seconds().max(0),
+        })
+    }
+
+    /// Stop an input's countdown, leaving the devices on
+    pub fn cancel(&mut self, config: &Config, input: &str) -> Option<IdleCountdown> {
+        let countdown = self.countdown(config, config.idle_off.iter().find(|e| e.input == input)?)?;
+        self.off_at.remove(input);
+        self.cancelled.insert(input.to_string());
+        Some(countdown)
+    }
+}
+
+/// Count down while a room's devices are on and its motion sensor sees no one, and turn
+/// them off when the countdown ends. Motion ends the countdown; it starts over once the
+/// room is quiet again.
+pub fn spawn_monitor(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let config = state.config.load_full();
+            {
+                let mut timers = state.idle_timers.write().await;
+                let inputs: HashSet<&str> = config.idle_off.iter().map(|e| e.input.as_str()).collect();
+                timers.off_at.retain(|input, _| inputs.contains(input.as_str()));
+                timers.cancelled.retain(|input| inputs.contains(input.as_str()));
+            }
+            // The active node turns off the relays it drives
+            if state.gpio_controller.is_standby() {
+                continue;
+            }
+
+            for entry in &config.idle_off {
+                let devices = entry.devices(&config);
+                let mut on = false;
+                for (_, pin) in &devices {
+                    on |= crate::fireplace::is_on(&state, &state.gpio_controller, *pin).await;
+                }
+                let quiet = state.inputs.read().await.is_active(&entry.input) == Some(false);
+
+                let mut timers = state.idle_timers.write().await;
+                if !on || !quiet {
+                    timers.off_at.remove(&entry.input);
+                    timers.cancelled.remove(&entry.input);
+                    continue;
+                }
+                if timers.cancelled.contains(&entry.input) {
+                    continue;
+                }
+                let now = Local::now();
+                let after = chrono::Duration::from_std(entry.after).unwrap_or(chrono::Duration::MAX);
+                let off_at = *timers.off_at.entry(entry.input.clone()).or_insert(now + after);
+                if off_at > now {
+                    continue;
+                }
+                timers.off_at.remove(&entry.input);
+                drop(timers);
+
+                tracing::info!(
+                    "{} has seen no motion for {}, turning its devices off",
+                    entry.input,
+                    humantime::format_duration(entry.after)
+                );
+                let pins: Vec<u32> = devices.iter().map(|(_, pin)| *pin).collect();
+                let mut gpio = state.gpio_controller.lock(ChangeSource::Idle, &pins).await;
+                for (name, pin) in &devices {
+                    if let Err(e) = crate::fireplace::drive_off(&state, &mut gpio, *pin).await {
+                        tracing::error!("Idle auto-off failed for {}: {}", name, e);
+                    }
+                }
+            }
+        }
+    });
+}
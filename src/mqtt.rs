@@ -0,0 +1,368 @@
+﻿use chrono::{DateTime, Local};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{
+    broadcast::error::{RecvError, TryRecvError},
+    Notify,
+};
+
+use crate::{
+    api::{handlers, models::FireplaceControlRequest},
+    commands::Progress,
+    config::DeviceConfig,
+    error::{self, ApiError},
+    gpio::PinState,
+    secrets::Secret,
+    state::{AppState, ChangeSource, StateEvent},
+};
+
+/// Requests queued for the broker before new publishes are dropped
+const QUEUE_CAPACITY: usize = 256;
+
+/// Wait before polling again after the broker connection fails
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Let a command finish (cycles, auto-off timer) before publishing the pins it changed
+const SETTLE_DELAY: Duration = Duration::from_millis(250);
+
+/// `[mqtt]`: publish device state to a broker for Home Assistant, Node-RED and friends
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<Secret>,
+    /// Root of the topic tree, e.g. `fireplace/family_room/fireplace/state`
+    #[serde(default = "default_base_topic")]
+    pub base_topic: String,
+    #[serde(default = "default_retain")]
+    pub retain: bool,
+    /// How often the whole tree is republished so timers and on-time stay current
+    #[serde(default = "default_refresh_interval", alias = "refresh_seconds", with = "crate::duration::seconds")]
+    pub refresh_interval: Duration,
+    /// Accept ON/OFF on `<base_topic>/<room>/<device>/set`. Anyone who can publish to the
+    /// broker can then control the devices, so it's off unless asked for.
+    #[serde(default)]
+    pub commands: bool,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "fireplace_api".to_string()
+}
+
+fn default_base_topic() -> String {
+    "fireplace".to_string()
+}
+
+fn default_retain() -> bool {
+    true
+}
+
+fn default_refresh_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl MqttConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let base = self.base_topic.trim_matches('/');
+        if base.is_empty() || base.contains(['+', '#']) {
+            return Err(format!("mqtt.base_topic: '{}' is not a valid topic prefix", self.base_topic));
+        }
+        if self.refresh_interval.is_zero() {
+            return Err("mqtt.refresh_interval must not be zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Everything published under a device's topic, also sent as one JSON `attributes` payload
+#[derive(Debug, Serialize)]
+struct DeviceAttributes {
+    state: &'static str,
+    pin: u32,
+    last_change: Option<String>,
+    source: Option<ChangeSource>,
+    timer_remaining_seconds: Option<i64>,
+    on_hours_today: f64,
+}
+
+/// A `.../set` payload: `ON`/`OFF`, or JSON such as `{"action": "ON", "duration_minutes": 30}`
+#[derive(Debug, Deserialize)]
+struct SetCommand {
+    action: String,
+    duration_minutes: Option<u32>,
+}
+
+impl SetCommand {
+    fn parse(payload: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(payload).ok()?.trim();
+        if text.starts_with('{') {
+            return serde_json::from_str(text).ok();
+        }
+        Some(Self {
+            action: text.to_string(),
+            duration_minutes: None,
+        })
+    }
+}
+
+/// Connect to the configured broker and keep the device topic tree up to date
+pub fn spawn_publisher(state: AppState) {
+    let Some(mqtt) = state.config.load().mqtt.clone() else {
+        return;
+    };
+    let base = mqtt.base_topic.trim_matches('/').to_string();
+    let availability = format!("{}/status", base);
+
+    let mut options = MqttOptions::new(&mqtt.client_id, &mqtt.host, mqtt.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(&availability, "offline", QoS::AtLeastOnce, true));
+    if let Some(username) = &mqtt.username {
+        let password = mqtt.password.as_ref().map(|p| p.expose()).unwrap_or_default();
+        options.set_credentials(username, password);
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+    tracing::info!("Publishing device state to mqtt://{}:{}/{}", mqtt.host, mqtt.port, base);
+    let publisher = Publisher { client, base, availability, retain: mqtt.retain };
+
+    // Drive the connection; every (re)connect republishes the whole tree
+    let connected = Arc::new(Notify::new());
+    let on_connect = connected.clone();
+    let commands = mqtt.commands.then(|| (publisher.clone(), state.clone()));
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    tracing::info!("Connected to MQTT broker");
+                    on_connect.notify_one();
+                    // Subscriptions don't survive a clean session, so renew them every time
+                    if let Some((publisher, _)) = &commands {
+                        let filter = format!("{}/+/+/set", publisher.base);
+                        if let Err(e) = publisher.client.try_subscribe(&filter, QoS::AtLeastOnce) {
+                            tracing::warn!("Failed to subscribe to {}: {}", filter, e);
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(message))) => {
+                    if let Some((publisher, state)) = &commands {
+                        let (publisher, state) = (publisher.clone(), state.clone());
+                        tokio::spawn(async move { publisher.command(&state, &message.topic, &message.payload).await });
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("MQTT connection error: {}", e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        let period = mqtt.refresh_interval;
+        let mut refresh = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(StateEvent::PinChanged { pin, .. }) => {
+                        tokio::time::sleep(SETTLE_DELAY).await;
+                        let mut pins = vec![pin];
+                        loop {
+                            match events.try_recv() {
+                                Ok(StateEvent::PinChanged { pin, .. }) => pins.push(pin),
+                                Ok(
+                                    StateEvent::IgnitionChecked { .. }
+                                    | StateEvent::FireplaceChanged { .. }
+                                    | StateEvent::GpioFailed { .. }
+                                    | StateEvent::TemperatureCutoff { .. }
+                                    | StateEvent::InputChanged { .. },
+                                ) => {}
+                                // Anything else could remap pins, so start over from the config
+                                Ok(_) | Err(TryRecvError::Lagged(_)) => {
+                                    pins.clear();
+                                    publisher.all(&state).await;
+                                    break;
+                                }
+                                Err(_) => break,
+                            }
+                        }
+                        pins.sort_unstable();
+                        pins.dedup();
+
+                        let config = state.config.load_full();
+                        for (zone, device) in pins.into_iter().filter_map(|pin| config.find_pin(pin)) {
+                            publisher.device(&state, zone.name, device).await;
+                        }
+                    }
+                    Ok(
+                        StateEvent::IgnitionChecked { .. }
+                        | StateEvent::FireplaceChanged { .. }
+                        | StateEvent::GpioFailed { .. }
+                        | StateEvent::TemperatureCutoff { .. }
+                        | StateEvent::EmergencyStopped { .. }
+                        | StateEvent::InputChanged { .. },
+                    ) => {}
+                    // Devices may have been added, removed or renamed
+                    Ok(StateEvent::ConfigReloaded { .. }) => publisher.all(&state).await,
+                    Err(RecvError::Lagged(_)) => publisher.all(&state).await,
+                    Err(RecvError::Closed) => return,
+                },
+                _ = connected.notified() => publisher.all(&state).await,
+                _ = refresh.tick() => publisher.all(&state).await,
+            }
+        }
+    });
+}
+
+#[derive(Clone)]
+struct Publisher {
+    client: AsyncClient,
+    base: String,
+    availability: String,
+    retain: bool,
+}
+
+impl Publisher {
+    async fn all(&self, state: &AppState) {
+        self.send(&self.availability, "online".to_string());
+        let config = state.config.load_full();
+        for zone in config.zones() {
+            for device in zone.devices {
+                self.device(state, zone.name, device).await;
+            }
+        }
+    }
+
+    /// Publish `{base}/{room}/{device}/...`: a leaf topic per attribute, plus `attributes`
+    async fn device(&self, state: &AppState, room: &str, device: &DeviceConfig) {
+        let attributes = attributes(state, room, device).await;
+        let topic = format!("{}/{}/{}", self.base, room, device.name);
+
+        self.send(&format!("{}/state", topic), attributes.state.to_string());
+        self.send(&format!("{}/pin", topic), attributes.pin.to_string());
+        self.send(&format!("{}/last_change", topic), attributes.last_change.clone().unwrap_or_default());
+        self.send(
+            &format!("{}/source", topic),
+            attributes.source.map(|s| format!("{:?}", s).to_lowercase()).unwrap_or_default(),
+        );
+        self.send(
+            &format!("{}/timer_remaining", topic),
+            attributes.timer_remaining_seconds.map(|s| s.to_string()).unwrap_or_default(),
+        );
+        self.send(&format!("{}/on_hours_today", topic), format!("{:.2}", attributes.on_hours_today));
+        if let Ok(json) = serde_json::to_string(&attributes) {
+            self.send(&format!("{}/attributes", topic), json);
+        }
+    }
+
+    /// Carry out a `{base}/{room}/{device}/set` message. The new state arrives on the state
+    /// topics as for any other change; a refused command is reported on `.../error`.
+    async fn command(&self, state: &AppState, topic: &str, payload: &[u8]) {
+        let Some((room, device)) = topic
+            .strip_prefix(&format!("{}/", self.base))
+            .and_then(|rest| rest.strip_suffix("/set"))
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            return;
+        };
+        let error_topic = format!("{}/{}/{}/error", self.base, room, device);
+        let Some(command) = SetCommand::parse(payload) else {
+            tracing::warn!("Ignoring unreadable MQTT command on {}", topic);
+            self.report(&error_topic, ApiError::InvalidAction.to_string());
+            return;
+        };
+        tracing::info!("MQTT command on {}: {}", topic, command.action);
+
+        if let Err(e) = control(state, room, device, command).await {
+            tracing::warn!("MQTT command on {} failed: {}", topic, e);
+            self.report(&error_topic, e.to_string());
+        }
+    }
+
+    /// Errors are events, not state, so they are never retained
+    fn report(&self, topic: &str, message: String) {
+        if let Err(e) = self.client.try_publish(topic, QoS::AtLeastOnce, false, message) {
+            tracing::debug!("Dropped MQTT publish to {}: {}", topic, e);
+        }
+    }
+
+    /// Queue a publish without waiting on the broker; drop it if the queue is full
+    fn send(&self, topic: &str, payload: String) {
+        if let Err(e) = self.client.try_publish(topic, QoS::AtLeastOnce, self.retain, payload) {
+            tracing::debug!("Dropped MQTT publish to {}: {}", topic, e);
+        }
+    }
+}
+
+/// Run a `.../set` command as a control request from MQTT
+async fn control(state: &AppState, room: &str, device: &str, command: SetCommand) -> error::Result<()> {
+    let config = state.config.load_full();
+    let zone = config.zone(Some(room))?;
+    let device = state.aliases.read().await.resolve(zone.name, device).unwrap_or_else(|| device.to_string());
+
+    // There is no way to send a confirming second message, so a fireplace that needs one
+    // can only be turned on through the API
+    if config.safety.require_confirmation
+        && command.action.eq_ignore_ascii_case("ON")
+        && handlers::involves_fireplace(&config, zone, &device)
+    {
+        return Err(ApiError::Forbidden(
+            "Turning a fireplace on needs confirming through the API".to_string(),
+        ));
+    }
+
+    let req = FireplaceControlRequest {
+        action: command.action,
+        device,
+        room: Some(zone.name.to_string()),
+        cycles: None,
+        cycle_delay_ms: None,
+        duration_minutes: command.duration_minutes,
+        run_async: false,
+        confirmation_token: None,
+        source: ChangeSource::Mqtt,
+    };
+    handlers::run_control(state, req, &Progress::none()).await.map(|_| ())
+}
+
+async fn attributes(state: &AppState, room: &str, device: &DeviceConfig) -> DeviceAttributes {
+    let now = Local::now();
+    let pin_state = state.gpio_controller.get_pin_state(device.pin);
+    let usage = state.usage.read().await;
+    let last_change = usage.last_change(device.pin);
+
+    let timer_remaining_seconds = state
+        .timers
+        .lock()
+        .await
+        .list()
+        .into_iter()
+        .find(|timer| timer.room == room && timer.pins.contains(&device.pin))
+        .and_then(|timer| DateTime::parse_from_rfc3339(&timer.fires_at).ok())
+        .map(|fires_at| (fires_at.with_timezone(&Local) - now).num_seconds().max(0));
+
+    DeviceAttributes {
+        state: match pin_state {
+            PinState::High => "ON",
+            PinState::Low => "OFF",
+            PinState::Unknown => "UNKNOWN",
+        },
+        pin: device.pin,
+        last_change: last_change.map(|(at, _)| at.to_rfc3339()),
+        source: last_change.map(|(_, source)| source),
+        timer_remaining_seconds,
+        on_hours_today: usage.on_seconds_today(device.pin, now) as f64 / 3600.0,
+    }
+}
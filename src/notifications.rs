@@ -0,0 +1,296 @@
+﻿use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    fireplace::FireplaceState,
+    secrets::Secret,
+    state::{AppState, StateEvent},
+};
+
+/// How often fireplaces are checked for being left on
+const LEFT_ON_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Repeated failures of the same pin are only reported this often
+const GPIO_FAILURE_QUIET_PERIOD: Duration = Duration::from_secs(15 * 60);
+
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+
+/// `[notifications]`: push messages to a phone through ntfy or Pushover
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub ntfy: Option<NtfyConfig>,
+    #[serde(default)]
+    pub pushover: Option<PushoverConfig>,
+    /// Which events to send; all of them by default
+    #[serde(default = "all_events")]
+    pub events: Vec<NotificationEvent>,
+    /// A fireplace on for this long is reported as left on
+    #[serde(default = "default_left_on_after", alias = "left_on_minutes", with = "crate::duration::minutes")]
+    pub left_on_after: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NtfyConfig {
+    #[serde(default = "default_ntfy_server")]
+    pub server: String,
+    pub topic: String,
+    /// Access token for a protected topic
+    #[serde(default)]
+    pub token: Option<Secret>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushoverConfig {
+    /// Application API token
+    pub token: Secret,
+    /// User or group key to deliver to
+    pub user: Secret,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// A fireplace has been on longer than `left_on_after`
+    LeftOn,
+    /// The GPIO backend failed to drive a pin
+    GpioFailure,
+    EmergencyStop,
+    /// A room passed `safety.max_temperature_c`
+    OverTemperature,
+}
+
+fn all_events() -> Vec<NotificationEvent> {
+    vec![
+        NotificationEvent::LeftOn,
+        NotificationEvent::GpioFailure,
+        NotificationEvent::EmergencyStop,
+        NotificationEvent::OverTemperature,
+    ]
+}
+
+fn default_left_on_after() -> Duration {
+    Duration::from_secs(3 * 60 * 60)
+}
+
+fn default_ntfy_server() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+impl NotificationsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ntfy) = &self.ntfy {
+            if !ntfy.server.starts_with("http://") && !ntfy.server.starts_with("https://") {
+                return Err(format!("notifications.ntfy.server: '{}' is not an http:// or https:// URL", ntfy.server));
+            }
+            if ntfy.topic.trim().is_empty() || ntfy.topic.contains('/') {
+                return Err(format!("notifications.ntfy.topic: '{}' is not a topic name", ntfy.topic));
+            }
+        }
+        if self.left_on_after.is_zero() {
+            return Err("notifications.left_on_after must not be zero".to_string());
+        }
+        Ok(())
+    }
+
+    fn wants(&self, event: NotificationEvent) -> bool {
+        self.events.contains(&event)
+    }
+}
+
+/// One push message
+#[derive(Debug, Clone)]
+struct Notification {
+    title: String,
+    message: String,
+    /// Jump the queue and sound on the phone
+    urgent: bool,
+}
+
+/// Send notifications for events on the bus, and for fireplaces left on
+pub fn spawn_notifier(state: AppState) {
+    // Subscribe before returning so no change made after startup is missed
+    let mut events = state.events.subscribe();
+    let client = reqwest::Client::new();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(LEFT_ON_CHECK_INTERVAL);
+        let mut reported_left_on = HashSet::new();
+        let mut reported_failures: HashMap<u32, Instant> = HashMap::new();
+        loop {
+            let notification = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => on_event(&state, event, &mut reported_failures),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Notifications missed {} events", skipped);
+                        None
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                _ = ticker.tick() => {
+                    for notification in left_on(&state, &mut reported_left_on).await {
+                        send(&client, &state, notification);
+                    }
+                    None
+                }
+            };
+            if let Some(notification) = notification {
+                send(&client, &state, notification);
+            }
+        }
+    });
+}
+
+fn on_event(state: &AppState, event: StateEvent, reported: &mut HashMap<u32, Instant>) -> Option<Notification> {
+    let config = state.config.load();
+    let notifications = config.notifications.as_ref()?;
+    match event {
+        StateEvent::GpioFailed { pin, error, .. } if notifications.wants(NotificationEvent::GpioFailure) => {
+            let now = Instant::now();
+            if reported.get(&pin).is_some_and(|at| now.duration_since(*at) < GPIO_FAILURE_QUIET_PERIOD) {
+                return None;
+            }
+            reported.insert(pin, now);
+            let target = match config.find_pin(pin) {
+                Some((zone, device)) => format!("{} in {} (pin {})", device.name, zone.name, pin),
+                None => format!("pin {}", pin),
+            };
+            Some(Notification {
+                title: "GPIO failure".to_string(),
+                message: format!("Failed to drive {}: {}", target, error),
+                urgent: true,
+            })
+        }
+        StateEvent::EmergencyStopped { by, pins_off, .. } if notifications.wants(NotificationEvent::EmergencyStop) => {
+            Some(Notification {
+                title: "Emergency stop".to_string(),
+                message: format!(
+                    "Emergency stop by {}: {} output(s) turned off. Control is locked until POST /api/v1/unlock",
+                    by.as_deref().unwrap_or("an anonymous caller"),
+                    pins_off.len()
+                ),
+                urgent: true,
+            })
+        }
+        StateEvent::TemperatureCutoff { room, sensor, temperature_c, tripped: true, .. }
+            if notifications.wants(NotificationEvent::OverTemperature) =>
+        {
+            Some(Notification {
+                title: "Over temperature".to_string(),
+                message: format!(
+                    "{} in {} reads {:.1}°C. Fireplaces were turned off and fans on until it cools below {:.1}°C",
+                    sensor,
+                    room,
+                    temperature_c,
+                    config.safety.temperature_reset_c()
+                ),
+                urgent: true,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Fireplaces that have been on longer than `left_on_after`, each reported once per burn
+async fn left_on(state: &AppState, reported: &mut HashSet<(String, String, String)>) -> Vec<Notification> {
+    let config = state.config.load_full();
+    let Some(notifications) = config.notifications.as_ref().filter(|n| n.wants(NotificationEvent::LeftOn)) else {
+        return Vec::new();
+    };
+    let now = Local::now();
+    let on: Vec<_> = state
+        .fireplaces
+        .read()
+        .await
+        .list()
+        .into_iter()
+        .filter(|f| f.state == FireplaceState::On)
+        .collect();
+    // Forget burns that have ended so the next one is reported again
+    reported.retain(|key| on.iter().any(|f| (&f.room, &f.device, &f.since.to_rfc3339()) == (&key.0, &key.1, &key.2)));
+
+    let mut due = Vec::new();
+    for fireplace in on {
+        // Whole minutes read better, unless it hasn't been one yet
+        let on_for = (now - fireplace.since).to_std().unwrap_or_default();
+        let shown = match on_for.as_secs() {
+            secs if secs < 60 => secs,
+            secs => secs / 60 * 60,
+        };
+        let key = (fireplace.room.clone(), fireplace.device.clone(), fireplace.since.to_rfc3339());
+        if on_for < notifications.left_on_after || !reported.insert(key) {
+            continue;
+        }
+        due.push(Notification {
+            title: "Fireplace left on".to_string(),
+            message: format!(
+                "{} in {} has been on for {}",
+                fireplace.device,
+                fireplace.room,
+                humantime::format_duration(Duration::from_secs(shown))
+            ),
+            urgent: false,
+        });
+    }
+    due
+}
+
+/// Send a message of the caller's own, whichever events are configured
+pub fn notify(client: &reqwest::Client, state: &AppState, title: String, message: String) {
+    send(
+        client,
+        state,
+        Notification {
+            title,
+            message,
+            urgent: false,
+        },
+    );
+}
+
+/// Deliver through every configured service without waiting; failures are logged
+fn send(client: &reqwest::Client, state: &AppState, notification: Notification) {
+    let config = state.config.load();
+    let Some(notifications) = config.notifications.clone() else {
+        return;
+    };
+    tracing::info!("Notification: {}: {}", notification.title, notification.message);
+
+    if let Some(telegram) = config.telegram.as_ref().filter(|t| t.alerts) {
+        crate::telegram::alert(client, telegram, &notification.title, &notification.message);
+    }
+
+    if let Some(ntfy) = notifications.ntfy {
+        let url = format!("{}/{}", ntfy.server.trim_end_matches('/'), ntfy.topic);
+        let mut request = client
+            .post(&url)
+            .header("Title", notification.title.clone())
+            .header("Priority", if notification.urgent { "high" } else { "default" })
+            .body(notification.message.clone());
+        if let Some(token) = &ntfy.token {
+            request = request.bearer_auth(token.expose());
+        }
+        tokio::spawn(async move {
+            if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                tracing::warn!("ntfy notification to {} failed: {}", url, e);
+            }
+        });
+    }
+
+    if let Some(pushover) = notifications.pushover {
+        let request = client.post(PUSHOVER_URL).json(&serde_json::json!({
+            "token": pushover.token.expose(),
+            "user": pushover.user.expose(),
+            "title": notification.title,
+            "message": notification.message,
+            "priority": if notification.urgent { 1 } else { 0 },
+        }));
+        tokio::spawn(async move {
+            if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                tracing::warn!("Pushover notification failed: {}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,205 @@
+﻿use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::{
+    config::{Config, DeviceConfig, DeviceKind, Zone},
+    error::{ApiError, Result},
+    gpio::PinState,
+    state::{AppState, ChangeSource, StateEvent},
+};
+
+/// How often sensor readings are compared to `safety.max_temperature_c`
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A room held with its fireplaces off and fans on because a sensor read too hot
+#[derive(Debug, Clone, Serialize)]
+pub struct Cutoff {
+    pub room: String,
+    /// The sensor that tripped it
+    pub sensor: String,
+    pub temperature_c: f64,
+    pub since: DateTime<Local>,
+    /// Fireplaces can be lit again once every sensor in the room reads below this
+    pub reset_below_c: f64,
+    /// Fans the cutoff started, turned off again when it resets
+    #[serde(skip)]
+    fans: Vec<u32>,
+}
+
+/// Tripped cutoffs, keyed by room
+#[derive(Default)]
+pub struct Cutoffs {
+    tripped: HashMap<String, Cutoff>,
+}
+
+impl Cutoffs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every tripped room, in name order
+    pub fn list(&self) -> Vec<Cutoff> {
+        let mut tripped: Vec<Cutoff> = self.tripped.values().cloned().collect();
+        tripped.sort_by(|a, b| a.room.cmp(&b.room));
+        tripped
+    }
+
+    /// Refuse to light a fireplace, or stop a fan, in a room that is too hot
+    pub fn check(&self, room: &str, device: &DeviceConfig, on: bool) -> Result<()> {
+        let Some(cutoff) = self.tripped.get(room) else {
+            return Ok(());
+        };
+        let refused = match device.kind {
+            DeviceKind::Fireplace => on,
+            DeviceKind::Fan => !on,
+            _ => false,
+        };
+        if refused {
+            return Err(ApiError::OverTemperature {
+                room: cutoff.room.clone(),
+                temperature_c: cutoff.temperature_c,
+                reset_below_c: cutoff.reset_below_c,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Trip a room's cutoff when one of its sensors passes `safety.max_temperature_c`, hold
+/// it while tripped, and reset it once the room has cooled
+pub fn spawn_monitor(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let config = state.config.load_full();
+            // The active node drives the relays; a standby only watches
+            if state.gpio_controller.is_standby() {
+                continue;
+            }
+
+            let rooms: Vec<String> = state.cutoffs.read().await.tripped.keys().cloned().collect();
+            let Some(limit) = config.safety.max_temperature_c else {
+                // The limit was removed by a reload
+                for room in rooms {
+                    release(&state, &room, None).await;
+                }
+                continue;
+            };
+            for room in rooms.iter().filter(|room| config.zone(Some(room.as_str())).is_err()) {
+                release(&state, room, None).await;
+            }
+
+            let reset_below = config.safety.temperature_reset_c();
+            for zone in config.zones() {
+                let hottest = hottest(&state, &config, zone.name).await;
+                if rooms.iter().any(|room| room == zone.name) {
+                    // Without a recent reading the room stays tripped
+                    match hottest {
+                        Some((_, temperature)) if temperature < reset_below => {
+                            release(&state, zone.name, Some(temperature)).await
+                        }
+                        _ => hold(&state, zone).await,
+                    }
+                } else if let Some((sensor, temperature)) = hottest.filter(|(_, t)| *t > limit) {
+                    trip(&state, zone, sensor, temperature, reset_below).await;
+                }
+            }
+        }
+    });
+}
+
+/// The highest recent reading in a room, and the sensor it came from
+async fn hottest(state: &AppState, config: &Config, room: &str) -> Option<(String, f64)> {
+    let sensors = state.sensors.read().await;
+    config
+        .sensors
+        .iter()
+        .filter(|sensor| sensor.room(config) == room)
+        // Firebox sensors run hot by design; they only drive their fans
+        .filter(|sensor| !config.fan_control.iter().any(|control| control.sensor == sensor.id))
+        .filter_map(|sensor| sensors.temperature(config, &sensor.id).map(|t| (sensor.id.clone(), t)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+async fn trip(state: &AppState, zone: Zone<'_>, sensor: String, temperature_c: f64, reset_below_c: f64) {
+    tracing::error!(
+        "Sensor {} in {} reads {:.1}°C, over safety.max_temperature_c: turning fireplaces off and fans on",
+        sensor,
+        zone.name,
+        temperature_c
+    );
+    let fans = enforce(state, zone).await;
+    state.cutoffs.write().await.tripped.insert(
+        zone.name.to_string(),
+        Cutoff {
+            room: zone.name.to_string(),
+            sensor: sensor.clone(),
+            temperature_c,
+            since: Local::now(),
+            reset_below_c,
+            fans,
+        },
+    );
+    state.publish(StateEvent::TemperatureCutoff {
+        room: zone.name.to_string(),
+        sensor,
+        temperature_c,
+        tripped: true,
+        timestamp: Local::now().to_rfc3339(),
+    });
+}
+
+/// Put right anything switched back since the cutoff tripped
+async fn hold(state: &AppState, zone: Zone<'_>) {
+    let started = enforce(state, zone).await;
+    if let Some(cutoff) = state.cutoffs.write().await.tripped.get_mut(zone.name) {
+        cutoff.fans.extend(started);
+    }
+}
+
+/// Drive a room's fireplaces off and its fans on, returning the fans it started
+async fn enforce(state: &AppState, zone: Zone<'_>) -> Vec<u32> {
+    let pins: Vec<u32> = zone.devices.iter().map(|d| d.pin).collect();
+    let mut gpio = state.gpio_controller.lock(ChangeSource::Overheat, &pins).await;
+    for fireplace in zone.of_kind(DeviceKind::Fireplace) {
+        if let Err(e) = crate::fireplace::drive_off(state, &mut gpio, fireplace.pin).await {
+            tracing::error!("Over-temperature cutoff failed to turn off {} in {}: {}", fireplace.name, zone.name, e);
+        }
+    }
+    let mut started = Vec::new();
+    for fan in zone.of_kind(DeviceKind::Fan) {
+        if gpio.get_pin_state(fan.pin) != PinState::High {
+            match gpio.set_pin(fan.pin, true).await {
+                Ok(()) => started.push(fan.pin),
+                Err(e) => tracing::error!("Over-temperature cutoff failed to start {} in {}: {}", fan.name, zone.name, e),
+            }
+        }
+    }
+    started
+}
+
+async fn release(state: &AppState, room: &str, temperature_c: Option<f64>) {
+    let Some(cutoff) = state.cutoffs.write().await.tripped.remove(room) else {
+        return;
+    };
+    let mut gpio = state.gpio_controller.lock(ChangeSource::Overheat, &cutoff.fans).await;
+    for pin in &cutoff.fans {
+        if let Err(e) = crate::fireplace::drive_off(state, &mut gpio, *pin).await {
+            tracing::error!("Failed to stop the fan on pin {} after the over-temperature cutoff: {}", pin, e);
+        }
+    }
+    drop(gpio);
+
+    let temperature_c = temperature_c.unwrap_or(cutoff.temperature_c);
+    tracing::warn!("Over-temperature cutoff in {} reset at {:.1}°C", room, temperature_c);
+    state.publish(StateEvent::TemperatureCutoff {
+        room: room.to_string(),
+        sensor: cutoff.sensor,
+        temperature_c,
+        tripped: false,
+        timestamp: Local::now().to_rfc3339(),
+    });
+}
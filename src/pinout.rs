@@ -0,0 +1,65 @@
+﻿use crate::config::PinNumbering;
+
+/// One pin of the Raspberry Pi 40-pin header
+struct HeaderPin {
+    physical: u32,
+    bcm: Option<u32>,
+    wiringpi: Option<u32>,
+}
+
+/// The full 40-pin header (Pi 2 and later); power and ground pins have no GPIO number
+const HEADER: [HeaderPin; 40] = [
+    HeaderPin { physical: 1, bcm: None, wiringpi: None }, // 3V3
+    HeaderPin { physical: 2, bcm: None, wiringpi: None }, // 5V
+    HeaderPin { physical: 3, bcm: Some(2), wiringpi: Some(8) },
+    HeaderPin { physical: 4, bcm: None, wiringpi: None }, // 5V
+    HeaderPin { physical: 5, bcm: Some(3), wiringpi: Some(9) },
+    HeaderPin { physical: 6, bcm: None, wiringpi: None }, // GND
+    HeaderPin { physical: 7, bcm: Some(4), wiringpi: Some(7) },
+    HeaderPin { physical: 8, bcm: Some(14), wiringpi: Some(15) },
+    HeaderPin { physical: 9, bcm: None, wiringpi: None }, // GND
+    HeaderPin { physical: 10, bcm: Some(15), wiringpi: Some(16) },
+    HeaderPin { physical: 11, bcm: Some(17), wiringpi: Some(0) },
+    HeaderPin { physical: 12, bcm: Some(18), wiringpi: Some(1) },
+    HeaderPin { physical: 13, bcm: Some(27), wiringpi: Some(2) },
+    HeaderPin { physical: 14, bcm: None, wiringpi: None }, // GND
+    HeaderPin { physical: 15, bcm: Some(22), wiringpi: Some(3) },
+    HeaderPin { physical: 16, bcm: Some(23), wiringpi: Some(4) },
+    HeaderPin { physical: 17, bcm: None, wiringpi: None }, // 3V3
+    HeaderPin { physical: 18, bcm: Some(24), wiringpi: Some(5) },
+    HeaderPin { physical: 19, bcm: Some(10), wiringpi: Some(12) },
+    HeaderPin { physical: 20, bcm: None, wiringpi: None }, // GND
+    HeaderPin { physical: 21, bcm: Some(9), wiringpi: Some(13) },
+    HeaderPin { physical: 22, bcm: Some(25), wiringpi: Some(6) },
+    HeaderPin { physical: 23, bcm: Some(11), wiringpi: Some(14) },
+    HeaderPin { physical: 24, bcm: Some(8), wiringpi: Some(10) },
+    HeaderPin { physical: 25, bcm: None, wiringpi: None }, // GND
+    HeaderPin { physical: 26, bcm: Some(7), wiringpi: Some(11) },
+    HeaderPin { physical: 27, bcm: Some(0), wiringpi: Some(30) },
+    HeaderPin { physical: 28, bcm: Some(1), wiringpi: Some(31) },
+    HeaderPin { physical: 29, bcm: Some(5), wiringpi: Some(21) },
+    HeaderPin { physical: 30, bcm: None, wiringpi: None }, // GND
+    HeaderPin { physical: 31, bcm: Some(6), wiringpi: Some(22) },
+    HeaderPin { physical: 32, bcm: Some(12), wiringpi: Some(26) },
+    HeaderPin { physical: 33, bcm: Some(13), wiringpi: Some(23) },
+    HeaderPin { physical: 34, bcm: None, wiringpi: None }, // GND
+    HeaderPin { physical: 35, bcm: Some(19), wiringpi: Some(24) },
+    HeaderPin { physical: 36, bcm: Some(16), wiringpi: Some(27) },
+    HeaderPin { physical: 37, bcm: Some(26), wiringpi: Some(25) },
+    HeaderPin { physical: 38, bcm: Some(20), wiringpi: Some(28) },
+    HeaderPin { physical: 39, bcm: None, wiringpi: None }, // GND
+    HeaderPin { physical: 40, bcm: Some(21), wiringpi: Some(29) },
+];
+
+/// Translate a pin number in the given scheme to its BCM GPIO number.
+/// Returns `None` for pins that don't exist or aren't GPIOs (power, ground).
+pub fn to_bcm(numbering: PinNumbering, pin: u32) -> Option<u32> {
+    HEADER
+        .iter()
+        .find(|p| match numbering {
+            PinNumbering::Bcm => p.bcm == Some(pin),
+            PinNumbering::Physical => p.physical == pin,
+            PinNumbering::Wiringpi => p.wiringpi == Some(pin),
+        })
+        .and_then(|p| p.bcm)
+}
@@ -0,0 +1,100 @@
+﻿use serde::Serialize;
+
+use crate::{
+    config::DeviceKind,
+    gpio::PinState,
+    state::{AppState, ChangeSource},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PowerSource {
+    Mains,
+    Battery,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PowerStatus {
+    pub source: PowerSource,
+    pub since: Option<String>,
+    pub load_shed: bool,
+}
+
+impl PowerStatus {
+    pub fn new() -> Self {
+        Self {
+            source: PowerSource::Mains,
+            since: None,
+            load_shed: false,
+        }
+    }
+
+    pub fn on_battery(&self) -> bool {
+        self.source == PowerSource::Battery
+    }
+}
+
+/// Poll the UPS status input and react to mains/battery transitions
+pub fn spawn_monitor(state: AppState) {
+    let Some(power) = state.config.load().power.clone() else {
+        return;
+    };
+
+    tracing::info!("Power monitor watching pin {}", power.on_battery_pin);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(power.poll_interval);
+        loop {
+            interval.tick().await;
+
+            let level = {
+                let gpio = &state.gpio_controller;
+                match gpio.read_pin(power.on_battery_pin).await {
+                    Ok(level) => level,
+                    Err(e) => {
+                        tracing::warn!("Failed to read power status pin: {}", e);
+                        continue;
+                    }
+                }
+            };
+
+            let on_battery = match level {
+                PinState::High => !power.active_low,
+                PinState::Low => power.active_low,
+                PinState::Unknown => continue,
+            };
+
+            let mut status = state.power.write().await;
+            if status.on_battery() == on_battery {
+                continue;
+            }
+
+            status.since = Some(chrono::Local::now().to_rfc3339());
+            if on_battery {
+                status.source = PowerSource::Battery;
+                tracing::warn!("Mains power lost, running on battery");
+
+                if power.shed_fan {
+                    let config = state.config.load_full();
+                    let fans: Vec<u32> =
+                        config.zones().flat_map(|zone| zone.of_kind(DeviceKind::Fan).map(|fan| fan.pin)).collect();
+                    let mut gpio = state.gpio_controller.lock(ChangeSource::Power, &fans).await;
+                    for zone in config.zones() {
+                        for fan in zone.of_kind(DeviceKind::Fan) {
+                            match crate::fireplace::drive_off(&state, &mut gpio, fan.pin).await {
+                                Ok(_) => {
+                                    status.load_shed = true;
+                                    tracing::warn!("Shed {} {} load on pin {} while on battery", zone.name, fan.name, fan.pin);
+                                }
+                                Err(e) => tracing::error!("Failed to shed {} {} load: {}", zone.name, fan.name, e),
+                            }
+                        }
+                    }
+                }
+            } else {
+                status.source = PowerSource::Mains;
+                status.load_shed = false;
+                tracing::info!("Mains power restored");
+            }
+        }
+    });
+}
@@ -0,0 +1,60 @@
+﻿use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Per-client limit on control requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Control requests a client may send within `window`
+    pub max_requests: u32,
+    #[serde(default = "default_window", with = "crate::duration::seconds")]
+    pub window: Duration,
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_requests == 0 {
+            return Err("rate_limit.max_requests must be at least 1".to_string());
+        }
+        if self.window.is_zero() {
+            return Err("rate_limit.window must be longer than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Recent control requests of every client, over a sliding window
+#[derive(Default)]
+pub struct RateLimiter {
+    clients: HashMap<String, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request from `client`, or say how long it has to wait if it is over the limit.
+    /// Refused requests don't count, so a client that backs off gets through again.
+    pub fn check(&mut self, client: &str, limit: &RateLimitConfig, now: Instant) -> Result<(), Duration> {
+        // Forget requests that have left the window, and clients with none left
+        self.clients.retain(|_, hits| {
+            while hits.front().is_some_and(|hit| now.duration_since(*hit) >= limit.window) {
+                hits.pop_front();
+            }
+            !hits.is_empty()
+        });
+
+        let hits = self.clients.entry(client.to_string()).or_default();
+        if hits.len() >= limit.max_requests as usize {
+            let oldest = hits[hits.len() - limit.max_requests as usize];
+            return Err(limit.window - now.duration_since(oldest));
+        }
+        hits.push_back(now);
+        Ok(())
+    }
+}
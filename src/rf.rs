@@ -0,0 +1,170 @@
+﻿use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::error::{ApiError, Result};
+use crate::gpio::{GpioBackend, PinState};
+
+/// Devices sent over RF or IR have no header pin; they are numbered from here unless given a `pin`
+pub const FIRST_RF_PIN: u32 = 1000;
+
+/// `[[gpio.rf_transmitters]]`: a 433MHz transmitter, keyed by a command-line sender such
+/// as 433Utils' `codesend` (rc-switch timing) or rpitx's `sendook`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RfTransmitterConfig {
+    pub name: String,
+    /// Program run for each transmission
+    #[serde(default = "default_command")]
+    pub command: String,
+    /// Its arguments; `{code}`, `{bits}`, `{protocol}` and `{pulse_length}` are filled in
+    #[serde(default = "default_args")]
+    pub args: Vec<String>,
+    /// rc-switch protocol number
+    #[serde(default = "default_protocol")]
+    pub protocol: u32,
+    /// Pulse length in microseconds
+    #[serde(default = "default_pulse_length")]
+    pub pulse_length: u32,
+    /// Times each code is sent, for receivers that miss the odd one
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+}
+
+fn default_command() -> String {
+    "codesend".to_string()
+}
+
+fn default_args() -> Vec<String> {
+    vec!["{code}".to_string(), "{protocol}".to_string(), "{pulse_length}".to_string()]
+}
+
+fn default_protocol() -> u32 {
+    1
+}
+
+fn default_pulse_length() -> u32 {
+    350
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+impl RfTransmitterConfig {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.command.trim().is_empty() {
+            return Err(format!("rf transmitter '{}' has no command", self.name));
+        }
+        if !(1..=20).contains(&self.repeat) {
+            return Err(format!("rf transmitter '{}': repeat must be between 1 and 20", self.name));
+        }
+        Ok(())
+    }
+
+    /// The arguments for sending `code`
+    fn args_for(&self, code: u64, bits: u32) -> Vec<String> {
+        self.args
+            .iter()
+            .map(|arg| {
+                arg.replace("{code}", &code.to_string())
+                    .replace("{bits}", &bits.to_string())
+                    .replace("{protocol}", &self.protocol.to_string())
+                    .replace("{pulse_length}", &self.pulse_length.to_string())
+            })
+            .collect()
+    }
+}
+
+/// A device's `rf` table: the codes its remote sends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RfCodes {
+    /// The `[[gpio.rf_transmitters]]` entry that sends them
+    pub transmitter: String,
+    pub on_code: u64,
+    pub off_code: u64,
+    #[serde(default = "default_bits")]
+    pub bits: u32,
+}
+
+fn default_bits() -> u32 {
+    24
+}
+
+/// Backend sending the on and off codes of RF devices, addressed by device pin. A
+/// transmitter can't be read back, so reads return the last state sent.
+pub struct RfBackend {
+    transmitters: HashMap<String, RfTransmitterConfig>,
+    devices: HashMap<u32, RfCodes>,
+    /// The last level sent to each device
+    sent: Mutex<HashMap<u32, PinState>>,
+    /// Held while transmitting; two senders would garble each other on the air
+    air: Mutex<()>,
+}
+
+impl RfBackend {
+    /// The backend for `config`'s RF devices, carrying over what `previous` last sent to
+    /// devices whose codes haven't changed. `None` without any RF devices.
+    pub fn new(config: &Config, previous: Option<&RfBackend>) -> Option<Self> {
+        let devices: HashMap<u32, RfCodes> = config
+            .zones()
+            .flat_map(|zone| zone.devices.iter())
+            .filter_map(|device| device.rf.clone().map(|rf| (device.pin, rf)))
+            .collect();
+        if devices.is_empty() {
+            return None;
+        }
+        let sent = previous
+            .map(|previous| {
+                let sent = previous.sent.lock().unwrap_or_else(|e| e.into_inner());
+                sent.iter()
+                    .filter(|(pin, _)| previous.devices.get(pin) == devices.get(pin))
+                    .map(|(pin, state)| (*pin, state.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            transmitters: config
+                .gpio
+                .rf_transmitters
+                .iter()
+                .map(|t| (t.name.clone(), t.clone()))
+                .collect(),
+            devices,
+            sent: Mutex::new(sent),
+            air: Mutex::new(()),
+        })
+    }
+
+    /// Whether `pin` is an RF device's
+    pub fn drives(&self, pin: u32) -> bool {
+        self.devices.contains_key(&pin)
+    }
+}
+
+impl GpioBackend for RfBackend {
+    fn name(&self) -> &'static str {
+        "rf"
+    }
+
+    fn write(&self, pin: u32, level: &PinState) -> Result<()> {
+        let codes = self.devices.get(&pin).ok_or(ApiError::InvalidPin)?;
+        let transmitter = self.transmitters.get(&codes.transmitter).ok_or(ApiError::InvalidPin)?;
+        let code = if *level == PinState::High { codes.on_code } else { codes.off_code };
+        let args = transmitter.args_for(code, codes.bits);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        let _air = self.air.lock().unwrap_or_else(|e| e.into_inner());
+        for _ in 0..transmitter.repeat {
+            crate::gpio::run_command(self.name(), &transmitter.command, &args)?;
+        }
+        tracing::debug!("Sent RF code {} for pin {} through {}", code, pin, transmitter.name);
+        self.sent.lock().unwrap_or_else(|e| e.into_inner()).insert(pin, level.clone());
+        Ok(())
+    }
+
+    fn read(&self, pin: u32) -> Result<PinState> {
+        let sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(sent.get(&pin).cloned().unwrap_or(PinState::Unknown))
+    }
+}
@@ -0,0 +1,580 @@
+﻿use chrono::{DateTime, Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    commands::Progress,
+    config::Config,
+    error::{ApiError, Result},
+    gpio::PinState,
+    sequence::{self, Step},
+    state::{AppState, ChangeSource, StateEvent},
+};
+
+/// How often time, temperature and delayed state triggers are checked
+const TICK: Duration = Duration::from_secs(1);
+
+/// An automation: when `trigger` fires and every condition holds, run `actions` in order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Room for triggers, conditions and actions that don't name one; defaults to the primary room
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    pub trigger: Trigger,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Switch {
+    On,
+    Off,
+}
+
+impl Switch {
+    fn matches(self, state: &PinState) -> bool {
+        match self {
+            Switch::On => *state == PinState::High,
+            Switch::Off => *state == PinState::Low,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    /// Fires whenever the cron expression does, in local time
+    Time { cron: String },
+    /// Fires when a fresh reading moves into the range
+    Temperature {
+        sensor: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        above: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        below: Option<f64>,
+    },
+    /// Fires when a device turns on or off, or once it has stayed that way for `for`
+    State {
+        device: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        room: Option<String>,
+        state: Switch,
+        #[serde(default, rename = "for", with = "crate::duration::seconds", skip_serializing_if = "Duration::is_zero")]
+        for_: Duration,
+    },
+    /// Fires when an `[[inputs]]` pin becomes active, or inactive with `active = false`
+    Input {
+        input: String,
+        #[serde(default = "default_active")]
+        active: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// Local time of day, `"HH:MM"`; a window may run past midnight
+    TimeWindow { after: String, before: String },
+    /// A fresh reading is in the range; holds false without one
+    Temperature {
+        sensor: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        above: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        below: Option<f64>,
+    },
+    State {
+        device: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        room: Option<String>,
+        state: Switch,
+    },
+    /// An `[[inputs]]` pin is active, or inactive with `active = false`; holds false before
+    /// its first read
+    Input {
+        input: String,
+        #[serde(default = "default_active")]
+        active: bool,
+    },
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// A sequence step, or a push notification through `[notifications]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Action {
+    Notify {
+        notify: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+    },
+    Step(Step),
+}
+
+fn parse_time(value: &str) -> std::result::Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| format!("invalid time '{}', expected HH:MM", value))
+}
+
+fn validate_range(above: Option<f64>, below: Option<f64>) -> std::result::Result<(), String> {
+    if above.is_none() && below.is_none() {
+        return Err("set above, below or both".to_string());
+    }
+    if !above.into_iter().chain(below).all(f64::is_finite) {
+        return Err("temperatures must be finite numbers".to_string());
+    }
+    Ok(())
+}
+
+/// Above `above` and below `below`, whichever are set
+fn in_range(temperature: f64, above: Option<f64>, below: Option<f64>) -> bool {
+    above.is_none_or(|above| temperature > above) && below.is_none_or(|below| temperature < below)
+}
+
+impl Rule {
+    /// Check the name, trigger, conditions and actions against the config
+    pub fn validate(&self, config: &Config) -> Result<()> {
+        let invalid = |msg: String| ApiError::InvalidRule(format!("Rule '{}': {}", self.name, msg));
+        if self.name.is_empty()
+            || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(ApiError::InvalidRule(format!(
+                "Rule name '{}' must be letters, digits, '_' and '-'",
+                self.name
+            )));
+        }
+        let sensor = |id: &str| match config.sensor(id) {
+            Some(_) => Ok(()),
+            None => Err(format!("unknown sensor '{}'", id)),
+        };
+        let input = |name: &str| match config.input(name) {
+            Some(_) => Ok(()),
+            None => Err(format!("unknown input '{}'", name)),
+        };
+        let device = |device: &str, room: &Option<String>| {
+            let zone = config.zone(room.as_deref().or(self.room.as_deref())).map_err(|e| e.to_string())?;
+            match config.device_pins(zone.name, device) {
+                Some(_) => Ok(()),
+                None => Err(format!("unknown device '{}' in room '{}'", device, zone.name)),
+            }
+        };
+
+        match &self.trigger {
+            Trigger::Time { cron } => crate::scheduler::parse_cron(cron).map(|_| ()).map_err(|e| e.to_string()),
+            Trigger::Temperature { sensor: id, above, below } => sensor(id).and(validate_range(*above, *below)),
+            Trigger::State { device: name, room, .. } => device(name, room),
+            Trigger::Input { input: name, .. } => input(name),
+        }
+        .map_err(|e| invalid(format!("trigger: {}", e)))?;
+
+        for (i, condition) in self.conditions.iter().enumerate() {
+            match condition {
+                Condition::TimeWindow { after, before } => parse_time(after).and(parse_time(before)).map(|_| ()),
+                Condition::Temperature { sensor: id, above, below } => sensor(id).and(validate_range(*above, *below)),
+                Condition::State { device: name, room, .. } => device(name, room),
+                Condition::Input { input: name, .. } => input(name),
+            }
+            .map_err(|e| invalid(format!("condition {}: {}", i + 1, e)))?;
+        }
+
+        if self.actions.is_empty() {
+            return Err(invalid("has no actions".to_string()));
+        }
+        for (i, action) in self.actions.iter().enumerate() {
+            match action {
+                Action::Notify { .. } if config.notifications.is_none() => {
+                    Err("notify needs [notifications] to be configured".to_string())
+                }
+                Action::Notify { notify, .. } if notify.trim().is_empty() => Err("notify has no message".to_string()),
+                Action::Notify { .. } => Ok(()),
+                Action::Step(step) => sequence::validate_step(step, self.room.as_deref(), config),
+            }
+            .map_err(|e| invalid(format!("action {}: {}", i + 1, e)))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleSource {
+    /// A `[[rules]]` entry; read-only through the API
+    Config,
+    /// Created through the API and kept in rules.json
+    Api,
+}
+
+/// What happened the last time a rule fired
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RuleRun {
+    pub last_fired: Option<String>,
+    /// Why the last run stopped early, if it did
+    pub last_error: Option<String>,
+}
+
+/// A rule as listed by GET /api/v1/rules
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleStatus {
+    #[serde(flatten)]
+    pub rule: Rule,
+    pub source: RuleSource,
+    #[serde(flatten)]
+    pub run: RuleRun,
+}
+
+/// Rules created through the API, persisted as JSON, and the last run of every rule.
+/// `[[rules]]` from the config are listed alongside them but can only be changed there.
+pub struct RuleStore {
+    path: PathBuf,
+    rules: Vec<Rule>,
+    /// Keyed by lowercased rule name; not persisted
+    runs: HashMap<String, RuleRun>,
+}
+
+impl RuleStore {
+    /// Load the persisted rules; a missing or unreadable file starts empty
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("rules.json");
+        let rules = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        Self {
+            path,
+            rules,
+            runs: HashMap::new(),
+        }
+    }
+
+    /// Every rule, config rules first, each in name order
+    pub fn list(&self, config: &Config) -> Vec<RuleStatus> {
+        let mut configured: Vec<&Rule> = config.rules.iter().collect();
+        configured.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut created: Vec<&Rule> = self.rules.iter().collect();
+        created.sort_by(|a, b| a.name.cmp(&b.name));
+
+        configured
+            .into_iter()
+            .map(|rule| (rule, RuleSource::Config))
+            .chain(created.into_iter().map(|rule| (rule, RuleSource::Api)))
+            .map(|(rule, source)| self.status(rule, source))
+            .collect()
+    }
+
+    pub fn get(&self, config: &Config, name: &str) -> Option<RuleStatus> {
+        config
+            .rule(name)
+            .map(|rule| self.status(rule, RuleSource::Config))
+            .or_else(|| self.find(name).map(|rule| self.status(rule, RuleSource::Api)))
+    }
+
+    /// Add a rule, or replace the API rule of the same name when `replace` is set
+    pub fn save_rule(&mut self, config: &Config, rule: Rule, replace: bool) -> Result<RuleStatus> {
+        rule.validate(config)?;
+        if config.rule(&rule.name).is_some() {
+            return Err(ApiError::RuleReadOnly(rule.name));
+        }
+        match self.rules.iter().position(|r| r.name.eq_ignore_ascii_case(&rule.name)) {
+            Some(i) if replace => self.rules[i] = rule.clone(),
+            Some(_) => return Err(ApiError::RuleExists(rule.name)),
+            None if replace => return Err(ApiError::RuleNotFound(rule.name)),
+            None => self.rules.push(rule.clone()),
+        }
+        self.save()?;
+        Ok(self.status(&rule, RuleSource::Api))
+    }
+
+    pub fn remove(&mut self, config: &Config, name: &str) -> Result<Rule> {
+        if config.rule(name).is_some() {
+            return Err(ApiError::RuleReadOnly(name.to_string()));
+        }
+        let i = self
+            .rules
+            .iter()
+            .position(|r| r.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| ApiError::RuleNotFound(name.to_string()))?;
+        let removed = self.rules.remove(i);
+        self.runs.remove(&removed.name.to_lowercase());
+        self.save()?;
+        Ok(removed)
+    }
+
+    /// Every enabled rule, config and API alike
+    fn enabled(&self, config: &Config) -> Vec<Rule> {
+        config.rules.iter().chain(self.rules.iter()).filter(|r| r.enabled).cloned().collect()
+    }
+
+    fn status(&self, rule: &Rule, source: RuleSource) -> RuleStatus {
+        RuleStatus {
+            rule: rule.clone(),
+            source,
+            run: self.runs.get(&rule.name.to_lowercase()).cloned().unwrap_or_default(),
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+
+    fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.rules)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode rules: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
+
+/// Trigger bookkeeping, keyed by lowercased rule name
+#[derive(Default)]
+struct Triggers {
+    /// The next fire time of each time trigger, with the expression it was computed from
+    next_run: HashMap<String, (String, DateTime<Local>)>,
+    /// Whether each temperature trigger's sensor was last in range
+    in_range: HashMap<String, bool>,
+    /// State triggers waiting out their `for`
+    pending: HashMap<String, DateTime<Local>>,
+}
+
+/// Watch every enabled rule's trigger and run the rule when it fires
+pub fn spawn_engine(state: AppState) {
+    // Subscribe before returning so no change made after startup is missed
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        let mut triggers = Triggers::default();
+        let mut ticker = tokio::time::interval(TICK);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(StateEvent::PinChanged { pin, .. }) => on_pin_changed(&state, &mut triggers, pin).await,
+                    Ok(StateEvent::InputChanged { input, active, .. }) => on_input_changed(&state, &input, active).await,
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => tracing::warn!("Rules missed {} events", skipped),
+                    Err(RecvError::Closed) => return,
+                },
+                _ = ticker.tick() => on_tick(&state, &mut triggers).await,
+            }
+        }
+    });
+}
+
+/// Fire state triggers on a device reaching their state, or start waiting out their `for`
+async fn on_pin_changed(state: &AppState, triggers: &mut Triggers, pin: u32) {
+    let config = state.config.load_full();
+    let rules = state.rules.read().await.enabled(&config);
+    for rule in rules {
+        let Trigger::State { device, room, state: wanted, for_ } = &rule.trigger else {
+            continue;
+        };
+        let Some(pins) = device_pins(&config, &rule, device, room) else {
+            continue;
+        };
+        if !pins.contains(&pin) {
+            continue;
+        }
+        let key = rule.name.to_lowercase();
+        let reached = wanted.matches(&state.gpio_controller.combined_state(&pins));
+        if !reached {
+            triggers.pending.remove(&key);
+        } else if for_.is_zero() {
+            fire(state, &config, rule).await;
+        } else {
+            // Another pin of a group changing doesn't restart the wait
+            let due = Local::now() + chrono::Duration::from_std(*for_).unwrap_or_default();
+            triggers.pending.entry(key).or_insert(due);
+        }
+    }
+}
+
+/// Fire the input triggers waiting for an input to reach `active`
+async fn on_input_changed(state: &AppState, input: &str, active: bool) {
+    let config = state.config.load_full();
+    let rules = state.rules.read().await.enabled(&config);
+    for rule in rules {
+        if matches!(&rule.trigger, Trigger::Input { input: name, active: wanted } if name == input && *wanted == active) {
+            fire(state, &config, rule).await;
+        }
+    }
+}
+
+async fn on_tick(state: &AppState, triggers: &mut Triggers) {
+    let config = state.config.load_full();
+    let rules = state.rules.read().await.enabled(&config);
+    let now = Local::now();
+
+    // Forget rules that were removed, disabled or given another kind of trigger
+    let keys = |kind: fn(&Trigger) -> bool| -> Vec<String> {
+        rules.iter().filter(|r| kind(&r.trigger)).map(|r| r.name.to_lowercase()).collect()
+    };
+    let timed = keys(|t| matches!(t, Trigger::Time { .. }));
+    let measured = keys(|t| matches!(t, Trigger::Temperature { .. }));
+    let delayed = keys(|t| matches!(t, Trigger::State { .. }));
+    triggers.next_run.retain(|key, _| timed.contains(key));
+    triggers.in_range.retain(|key, _| measured.contains(key));
+    triggers.pending.retain(|key, _| delayed.contains(key));
+
+    for rule in rules {
+        let key = rule.name.to_lowercase();
+        let fires = match &rule.trigger {
+            Trigger::Time { cron } => {
+                let Ok(schedule) = crate::scheduler::parse_cron(cron) else {
+                    continue;
+                };
+                let upcoming = || schedule.upcoming(Local).next();
+                match triggers.next_run.get(&key) {
+                    Some((expr, next)) if expr == cron => {
+                        let due = *next <= now;
+                        if due {
+                            match upcoming() {
+                                Some(next) => triggers.next_run.insert(key, (cron.clone(), next)),
+                                None => triggers.next_run.remove(&key),
+                            };
+                        }
+                        due
+                    }
+                    _ => {
+                        if let Some(next) = upcoming() {
+                            triggers.next_run.insert(key, (cron.clone(), next));
+                        }
+                        false
+                    }
+                }
+            }
+            Trigger::Temperature { sensor, above, below } => {
+                let Some(temperature) = state.sensors.read().await.temperature(&config, sensor) else {
+                    continue;
+                };
+                let now_in = in_range(temperature, *above, *below);
+                let was_in = triggers.in_range.insert(key, now_in).unwrap_or(false);
+                now_in && !was_in
+            }
+            Trigger::State { device, room, state: wanted, .. } => {
+                if triggers.pending.get(&key).is_none_or(|due| *due > now) {
+                    continue;
+                }
+                triggers.pending.remove(&key);
+                // A change out of the state in the meantime has already cleared the wait
+                let Some(pins) = device_pins(&config, &rule, device, room) else {
+                    continue;
+                };
+                let current = state.gpio_controller.combined_state(&pins);
+                wanted.matches(&current)
+            }
+            // Fired from the event bus as the input changes
+            Trigger::Input { .. } => false,
+        };
+        if fires {
+            fire(state, &config, rule).await;
+        }
+    }
+}
+
+fn device_pins(config: &Config, rule: &Rule, device: &str, room: &Option<String>) -> Option<Vec<u32>> {
+    let zone = config.zone(room.as_deref().or(rule.room.as_deref())).ok()?;
+    config.device_pins(zone.name, device)
+}
+
+/// Run a triggered rule's actions in the background if its conditions hold
+async fn fire(state: &AppState, config: &Config, rule: Rule) {
+    // The active node drives the relays; a standby only watches
+    if state.gpio_controller.is_standby() {
+        return;
+    }
+    for condition in &rule.conditions {
+        if !holds(state, config, &rule, condition).await {
+            tracing::debug!("Rule {} triggered, but its conditions don't hold", rule.name);
+            return;
+        }
+    }
+
+    tracing::info!("Rule {} triggered", rule.name);
+    let fired = Local::now();
+    let state = state.clone();
+    tokio::spawn(async move {
+        let outcome = run(&state, &rule).await;
+        if let Err(e) = &outcome {
+            tracing::warn!("Rule {} failed: {}", rule.name, e);
+        }
+        state.rules.write().await.runs.insert(
+            rule.name.to_lowercase(),
+            RuleRun {
+                last_fired: Some(fired.to_rfc3339()),
+                last_error: outcome.err().map(|e| e.to_string()),
+            },
+        );
+    });
+}
+
+async fn holds(state: &AppState, config: &Config, rule: &Rule, condition: &Condition) -> bool {
+    match condition {
+        Condition::TimeWindow { after, before } => {
+            let (Ok(after), Ok(before)) = (parse_time(after), parse_time(before)) else {
+                return false;
+            };
+            let now = Local::now().time();
+            if after <= before {
+                after <= now && now < before
+            } else {
+                now >= after || now < before
+            }
+        }
+        Condition::Temperature { sensor, above, below } => state
+            .sensors
+            .read()
+            .await
+            .temperature(config, sensor)
+            .is_some_and(|t| in_range(t, *above, *below)),
+        Condition::State { device, room, state: wanted } => match device_pins(config, rule, device, room) {
+            Some(pins) => wanted.matches(&state.gpio_controller.combined_state(&pins)),
+            None => false,
+        },
+        Condition::Input { input, active } => state.inputs.read().await.is_active(input) == Some(*active),
+    }
+}
+
+/// Run a rule's actions in order, stopping at the first that fails
+async fn run(state: &AppState, rule: &Rule) -> Result<()> {
+    let client = reqwest::Client::new();
+    for action in &rule.actions {
+        match action {
+            Action::Notify { notify, title } => {
+                let title = title.clone().unwrap_or_else(|| format!("Rule {}", rule.name));
+                crate::notifications::notify(&client, state, title, notify.clone());
+            }
+            Action::Step(step) => {
+                let steps = std::slice::from_ref(step);
+                let room = rule.room.as_deref();
+                sequence::run(state, steps, &[], room, ChangeSource::Rule, &Progress::none()).await?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+﻿use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::{
+    config::DeviceKind,
+    state::{AppState, ChangeSource},
+};
+
+/// How often the watchdog checks fireplace runtimes
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SafetyTimerStatus {
+    pub room: String,
+    pub pin: u32,
+    pub on_since: String,
+    pub remaining_seconds: i64,
+}
+
+/// Tracks how long each fireplace pin has been ON, for the auto-off watchdog
+#[derive(Default)]
+pub struct SafetyTimer {
+    on_since: HashMap<u32, DateTime<Local>>,
+}
+
+impl SafetyTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the current state of a pin, starting the clock when it turns on
+    pub fn observe(&mut self, pin: u32, on: bool) -> DateTime<Local> {
+        if on {
+            *self.on_since.entry(pin).or_insert_with(Local::now)
+        } else {
+            self.on_since.remove(&pin);
+            Local::now()
+        }
+    }
+
+    /// Restart the runtime clock for a pin that is currently on
+    pub fn reset(&mut self, pin: u32) -> bool {
+        match self.on_since.get_mut(&pin) {
+            Some(since) => {
+                *since = Local::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn status(&self, room: &str, pin: u32, max_runtime: Duration) -> Option<SafetyTimerStatus> {
+        let since = self.on_since.get(&pin)?;
+        let deadline = *since + chrono::Duration::from_std(max_runtime).unwrap_or(chrono::Duration::MAX);
+        Some(SafetyTimerStatus {
+            room: room.to_string(),
+            pin,
+            on_since: since.to_rfc3339(),
+            remaining_seconds: (deadline - Local::now()).num_seconds().max(0),
+        })
+    }
+}
+
+/// Turn each room's fireplace off once it has been on for `safety.max_runtime`
+pub fn spawn_watchdog(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let config = state.config.load_full();
+            let Some(limit) = config.safety.max_runtime else {
+                continue;
+            };
+            let max_runtime = chrono::Duration::from_std(limit).unwrap_or(chrono::Duration::MAX);
+
+            let fireplaces: Vec<_> = config
+                .zones()
+                .flat_map(|zone| zone.of_kind(DeviceKind::Fireplace).map(move |device| (zone, device)))
+                .collect();
+            for (zone, fireplace) in fireplaces {
+                let pin = fireplace.pin;
+                if state.gpio_controller.is_standby() {
+                    // The active node enforces the limit on the relays it drives
+                    continue;
+                }
+                let mut gpio = state.gpio_controller.lock(ChangeSource::Safety, &[pin]).await;
+                let on = crate::fireplace::is_on(&state, &gpio, pin).await;
+
+                let since = state.safety_timer.lock().await.observe(pin, on);
+                if !on || Local::now() - since < max_runtime {
+                    continue;
+                }
+
+                tracing::warn!(
+                    "{} in {} has been on for {}, turning it off",
+                    fireplace.name,
+                    zone.name,
+                    humantime::format_duration(limit)
+                );
+                match crate::fireplace::drive_off(&state, &mut gpio, pin).await {
+                    Ok(_) => {
+                        state.safety_timer.lock().await.observe(pin, false);
+                    }
+                    Err(e) => tracing::error!("Safety auto-off failed for pin {}: {}", pin, e),
+                }
+            }
+        }
+    });
+}
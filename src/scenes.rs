@@ -0,0 +1,187 @@
+﻿use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::{
+    commands::Progress,
+    config::Config,
+    error::{ApiError, Result},
+    sequence::{self, Step},
+    state::{AppState, ChangeSource},
+};
+
+/// A named sequence of device actions, run by POST /api/v1/scenes/{name}/activate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    pub name: String,
+    /// Room for steps that don't name one; defaults to the primary room
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    pub steps: Vec<Step>,
+    /// Run if a step fails, e.g. to turn back off what the scene turned on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<Step>,
+}
+
+impl Scene {
+    /// Check the name and every step against the config
+    pub fn validate(&self, config: &Config) -> Result<()> {
+        let invalid = |msg: String| ApiError::InvalidScene(format!("Scene '{}': {}", self.name, msg));
+        if self.name.is_empty()
+            || !self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(ApiError::InvalidScene(format!(
+                "Scene name '{}' must be letters, digits, '_' and '-'",
+                self.name
+            )));
+        }
+        if self.steps.is_empty() {
+            return Err(invalid("has no steps".to_string()));
+        }
+        sequence::validate(&self.steps, self.room.as_deref(), config).map_err(invalid)?;
+        sequence::validate(&self.on_failure, self.room.as_deref(), config)
+            .map_err(|e| invalid(format!("on_failure {}", e)))
+    }
+
+    /// The control steps, with the room each one runs in
+    pub fn controls(&self) -> impl Iterator<Item = (&str, &str, Option<&str>)> {
+        self.steps.iter().filter_map(|step| match step {
+            Step::Control { device, action, room } => {
+                Some((device.as_str(), action.as_str(), room.as_deref().or(self.room.as_deref())))
+            }
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SceneSource {
+    /// A `[[scenes]]` entry; read-only through the API
+    Config,
+    /// Created through the API and kept in scenes.json
+    Api,
+}
+
+/// A scene as listed by GET /api/v1/scenes
+#[derive(Debug, Clone, Serialize)]
+pub struct SceneStatus {
+    #[serde(flatten)]
+    pub scene: Scene,
+    pub source: SceneSource,
+}
+
+/// Scenes created through the API, persisted as JSON. `[[scenes]]` from the config are
+/// listed alongside them but can only be changed in the config file.
+pub struct SceneStore {
+    path: PathBuf,
+    scenes: Vec<Scene>,
+}
+
+impl SceneStore {
+    /// Load the persisted scenes; a missing or unreadable file starts empty
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("scenes.json");
+        let scenes = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        Self { path, scenes }
+    }
+
+    /// Every scene, config scenes first, each in name order
+    pub fn list(&self, config: &Config) -> Vec<SceneStatus> {
+        let mut configured: Vec<&Scene> = config.scenes.iter().collect();
+        configured.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut created: Vec<&Scene> = self.scenes.iter().collect();
+        created.sort_by(|a, b| a.name.cmp(&b.name));
+
+        configured
+            .into_iter()
+            .map(|scene| (scene, SceneSource::Config))
+            .chain(created.into_iter().map(|scene| (scene, SceneSource::Api)))
+            .map(|(scene, source)| SceneStatus {
+                scene: scene.clone(),
+                source,
+            })
+            .collect()
+    }
+
+    pub fn get(&self, config: &Config, name: &str) -> Option<SceneStatus> {
+        let status = |scene: &Scene, source| SceneStatus {
+            scene: scene.clone(),
+            source,
+        };
+        config
+            .scene(name)
+            .map(|scene| status(scene, SceneSource::Config))
+            .or_else(|| self.find(name).map(|scene| status(scene, SceneSource::Api)))
+    }
+
+    /// Add a scene, or replace the API scene of the same name when `replace` is set
+    pub fn save_scene(&mut self, config: &Config, scene: Scene, replace: bool) -> Result<SceneStatus> {
+        scene.validate(config)?;
+        if config.scene(&scene.name).is_some() {
+            return Err(ApiError::SceneReadOnly(scene.name));
+        }
+        match self.scenes.iter().position(|s| s.name.eq_ignore_ascii_case(&scene.name)) {
+            Some(i) if replace => self.scenes[i] = scene.clone(),
+            Some(_) => return Err(ApiError::SceneExists(scene.name)),
+            None if replace => return Err(ApiError::SceneNotFound(scene.name)),
+            None => self.scenes.push(scene.clone()),
+        }
+        self.save()?;
+        Ok(SceneStatus {
+            scene,
+            source: SceneSource::Api,
+        })
+    }
+
+    pub fn remove(&mut self, config: &Config, name: &str) -> Result<Scene> {
+        if config.scene(name).is_some() {
+            return Err(ApiError::SceneReadOnly(name.to_string()));
+        }
+        let i = self
+            .scenes
+            .iter()
+            .position(|s| s.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| ApiError::SceneNotFound(name.to_string()))?;
+        let removed = self.scenes.remove(i);
+        self.save()?;
+        Ok(removed)
+    }
+
+    fn find(&self, name: &str) -> Option<&Scene> {
+        self.scenes.iter().find(|s| s.name.eq_ignore_ascii_case(name))
+    }
+
+    fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.scenes)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode scenes: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
+
+/// Run a scene's steps in order, stopping at the first that fails
+pub async fn run(state: &AppState, scene: &Scene, progress: &Progress) -> Result<()> {
+    tracing::info!("Activating scene {}", scene.name);
+    let room = scene.room.as_deref();
+    sequence::run(state, &scene.steps, &scene.on_failure, room, ChangeSource::Scene, progress)
+        .await
+        .inspect_err(|e| tracing::warn!("Scene {} failed: {}", scene.name, e))
+}
@@ -0,0 +1,369 @@
+﻿use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{
+    api::models::FireplaceControlRequest,
+    commands::Progress,
+    config::{Config, MissedRunPolicy, SchedulerConfig},
+    error::{ApiError, Result},
+    state::{AppState, ChangeSource},
+};
+
+/// A recurring control command, fired on a cron expression
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: Uuid,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// `min hour day month weekday`, optionally with leading seconds, in local time
+    pub cron: String,
+    pub action: String,
+    pub device: String,
+    #[serde(default)]
+    pub room: Option<String>,
+    #[serde(default)]
+    pub duration_minutes: Option<u32>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// What to do about runs missed while the server was down; defaults to scheduler.missed_run_policy
+    #[serde(default)]
+    pub missed_run: Option<MissedRunPolicy>,
+    #[serde(default)]
+    pub state: ScheduleState,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Run history persisted with a schedule so missed runs can be detected after a restart
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleState {
+    pub last_run: Option<String>,
+    /// When the schedule was due to fire next, as last recorded
+    pub next_fire: Option<String>,
+    pub missed_runs: u32,
+    pub last_missed: Option<String>,
+}
+
+impl Schedule {
+    /// Check the cron expression and that the command targets a real device
+    pub fn validate(&self, config: &Config) -> Result<()> {
+        parse_cron(&self.cron)?;
+
+        if !matches!(self.action.to_uppercase().as_str(), "ON" | "OFF") {
+            return Err(ApiError::InvalidAction);
+        }
+        if let Some(minutes) = self.duration_minutes {
+            if minutes == 0 || !self.action.eq_ignore_ascii_case("ON") {
+                return Err(ApiError::InvalidTimerDuration);
+            }
+        }
+
+        let zone = config.zone(self.room.as_deref())?;
+        if config.device_pins(zone.name, &self.device).is_none() {
+            return Err(ApiError::InvalidSchedule(format!(
+                "Unknown device '{}' in room '{}'",
+                self.device, zone.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Next time the schedule fires, if it is enabled
+    pub fn next_run(&self) -> Option<chrono::DateTime<Local>> {
+        if !self.enabled {
+            return None;
+        }
+        parse_cron(&self.cron).ok()?.upcoming(Local).next()
+    }
+}
+
+/// A schedule together with its next firing time
+#[derive(Debug, Serialize)]
+pub struct ScheduleStatus {
+    #[serde(flatten)]
+    pub schedule: Schedule,
+    pub next_run: Option<String>,
+}
+
+impl From<&Schedule> for ScheduleStatus {
+    fn from(schedule: &Schedule) -> Self {
+        Self {
+            schedule: schedule.clone(),
+            next_run: schedule.next_run().map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+/// Parse a cron expression; five-field expressions fire at second 0
+pub fn parse_cron(expr: &str) -> Result<cron::Schedule> {
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {}", expr)
+    } else {
+        expr.to_string()
+    };
+    cron::Schedule::from_str(&expr)
+        .map_err(|e| ApiError::InvalidSchedule(format!("Invalid cron expression: {}", e)))
+}
+
+struct ActiveSchedule {
+    schedule: Schedule,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Recurring schedules, persisted as JSON so they survive restarts
+pub struct Scheduler {
+    path: PathBuf,
+    schedules: HashMap<Uuid, ActiveSchedule>,
+}
+
+impl Scheduler {
+    pub fn new(storage_dir: &str) -> Self {
+        Self {
+            path: PathBuf::from(storage_dir).join("schedules.json"),
+            schedules: HashMap::new(),
+        }
+    }
+
+    /// Every schedule, soonest first; disabled schedules sort last
+    pub fn list(&self) -> Vec<ScheduleStatus> {
+        let mut schedules: Vec<ScheduleStatus> =
+            self.schedules.values().map(|s| (&s.schedule).into()).collect();
+        schedules.sort_by(|a, b| match (&a.next_run, &b.next_run) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+        schedules
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<ScheduleStatus> {
+        self.schedules.get(&id).map(|s| (&s.schedule).into())
+    }
+
+    fn save(&self) -> Result<()> {
+        let schedules: Vec<&Schedule> = self.schedules.values().map(|s| &s.schedule).collect();
+        let json = serde_json::to_string_pretty(&schedules)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode schedules: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+
+    fn insert(&mut self, schedule: Schedule, handle: Option<JoinHandle<()>>) {
+        if let Some(old) = self.schedules.insert(schedule.id, ActiveSchedule { schedule, handle }) {
+            if let Some(handle) = old.handle {
+                handle.abort();
+            }
+        }
+    }
+}
+
+/// Load the persisted schedules and start them
+pub async fn load(state: &AppState) {
+    let path = state.scheduler.lock().await.path.clone();
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            tracing::warn!("Failed to read {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let schedules: Vec<Schedule> = match serde_json::from_str(&content) {
+        Ok(schedules) => schedules,
+        Err(e) => {
+            tracing::warn!("Failed to parse {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let settings = state.config.load().scheduler.clone();
+    let mut scheduler = state.scheduler.lock().await;
+    for mut schedule in schedules {
+        // Keep schedules the current config rejects, but don't run them
+        let handle = match parse_cron(&schedule.cron) {
+            Ok(cron) => {
+                if catch_up(&mut schedule, &cron, &settings) {
+                    let (state, schedule) = (state.clone(), schedule.clone());
+                    tokio::spawn(async move { fire(&state, &schedule).await });
+                }
+                spawn(state, &schedule)
+            }
+            Err(e) => {
+                tracing::warn!("Schedule {} not started: {}", schedule.id, e);
+                None
+            }
+        };
+        scheduler.insert(schedule, handle);
+    }
+    if let Err(e) = scheduler.save() {
+        tracing::warn!("Failed to save schedules: {}", e);
+    }
+    tracing::info!("Loaded {} schedules from {}", scheduler.schedules.len(), path.display());
+}
+
+/// Account for runs that fell due while the server was down. Returns whether the
+/// missed-run policy says to run the schedule once now.
+fn catch_up(schedule: &mut Schedule, cron: &cron::Schedule, settings: &SchedulerConfig) -> bool {
+    let now = Local::now();
+    let due = schedule
+        .state
+        .next_fire
+        .as_deref()
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Local))
+        .filter(|t| *t <= now && schedule.enabled);
+    let Some(first) = due else {
+        return false;
+    };
+
+    // Every occurrence from the recorded next fire up to now was missed
+    let missed: Vec<_> = std::iter::once(first)
+        .chain(cron.after(&first).take_while(|t| *t <= now))
+        .take(10_000)
+        .collect();
+    let latest = *missed.last().expect("at least the recorded fire time");
+
+    let policy = schedule.missed_run.unwrap_or(settings.missed_run_policy);
+    let run = match policy {
+        MissedRunPolicy::Skip => false,
+        MissedRunPolicy::RunIfWithinGrace => {
+            now - latest <= chrono::Duration::from_std(settings.grace_period).unwrap_or(chrono::Duration::MAX)
+        }
+        MissedRunPolicy::AlwaysRunOnce => true,
+    };
+
+    let skipped = missed.len() as u32 - u32::from(run);
+    schedule.state.missed_runs += skipped;
+    if skipped > 0 {
+        schedule.state.last_missed = Some(latest.to_rfc3339());
+    }
+    tracing::warn!(
+        "Schedule {} missed {} run(s) while stopped, last due {}; policy {:?}: {}",
+        schedule.id,
+        missed.len(),
+        latest.to_rfc3339(),
+        policy,
+        if run { "running once now" } else { "skipping" }
+    );
+    run
+}
+
+/// Create or replace a schedule, (re)start it and persist the change
+pub async fn upsert(state: &AppState, mut schedule: Schedule) -> Result<ScheduleStatus> {
+    schedule.validate(&state.config.load_full())?;
+
+    let mut scheduler = state.scheduler.lock().await;
+    // An edited schedule keeps its run history; the next fire time is recomputed
+    if let Some(existing) = scheduler.schedules.get(&schedule.id) {
+        schedule.state = ScheduleState {
+            next_fire: None,
+            ..existing.schedule.state.clone()
+        };
+    }
+    let handle = spawn(state, &schedule);
+    let status = ScheduleStatus::from(&schedule);
+    scheduler.insert(schedule, handle);
+    scheduler.save()?;
+    Ok(status)
+}
+
+/// Stop and delete a schedule
+pub async fn remove(state: &AppState, id: Uuid) -> Result<Schedule> {
+    let mut scheduler = state.scheduler.lock().await;
+    let removed = scheduler.schedules.remove(&id).ok_or(ApiError::ScheduleNotFound)?;
+    if let Some(handle) = removed.handle {
+        handle.abort();
+    }
+    scheduler.save()?;
+    Ok(removed.schedule)
+}
+
+/// Stop and disable every enabled schedule, keeping it so it can be re-enabled later.
+/// Returns the ids of the schedules disabled.
+pub async fn disable_all(state: &AppState) -> Result<Vec<Uuid>> {
+    let mut scheduler = state.scheduler.lock().await;
+    let mut disabled = Vec::new();
+    for active in scheduler.schedules.values_mut().filter(|s| s.schedule.enabled) {
+        active.schedule.enabled = false;
+        active.schedule.state.next_fire = None;
+        if let Some(handle) = active.handle.take() {
+            handle.abort();
+        }
+        disabled.push(active.schedule.id);
+    }
+    scheduler.save()?;
+    Ok(disabled)
+}
+
+/// Run a schedule's command every time its cron expression fires
+fn spawn(state: &AppState, schedule: &Schedule) -> Option<JoinHandle<()>> {
+    if !schedule.enabled {
+        return None;
+    }
+    let cron = parse_cron(&schedule.cron).ok()?;
+    let state = state.clone();
+    let schedule = schedule.clone();
+
+    Some(tokio::spawn(async move {
+        while let Some(next) = cron.upcoming(Local).next() {
+            // Record the due time first so a restart can tell this run was missed
+            record(&state, schedule.id, |s| s.next_fire = Some(next.to_rfc3339())).await;
+            let wait = (next - Local::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+
+            fire(&state, &schedule).await;
+        }
+    }))
+}
+
+/// Run a schedule's command once and record the run
+async fn fire(state: &AppState, schedule: &Schedule) {
+    tracing::info!(
+        "Schedule {} firing: {} {} in {}",
+        schedule.id,
+        schedule.action,
+        schedule.device,
+        schedule.room.as_deref().unwrap_or("primary room")
+    );
+    let req = FireplaceControlRequest {
+        action: schedule.action.clone(),
+        device: schedule.device.clone(),
+        room: schedule.room.clone(),
+        cycles: None,
+        cycle_delay_ms: None,
+        duration_minutes: schedule.duration_minutes,
+        run_async: false,
+        confirmation_token: None,
+        source: ChangeSource::Schedule,
+    };
+    if let Err(e) = crate::api::handlers::run_control(state, req, &Progress::none()).await {
+        tracing::warn!("Schedule {} failed: {}", schedule.id, e);
+    }
+    record(state, schedule.id, |s| s.last_run = Some(Local::now().to_rfc3339())).await;
+}
+
+/// Update a schedule's persisted run state
+async fn record(state: &AppState, id: Uuid, f: impl FnOnce(&mut ScheduleState)) {
+    let mut scheduler = state.scheduler.lock().await;
+    let Some(active) = scheduler.schedules.get_mut(&id) else {
+        return;
+    };
+    f(&mut active.schedule.state);
+    if let Err(e) = scheduler.save() {
+        tracing::warn!("Failed to save schedules: {}", e);
+    }
+}
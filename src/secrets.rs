@@ -0,0 +1,186 @@
+﻿use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::error::{ApiError, Result};
+
+/// Environment variable holding the age identity (`AGE-SECRET-KEY-1...`) for the secrets file
+pub const IDENTITY_ENV: &str = "FIREPLACE_AGE_IDENTITY";
+
+/// Shown in place of any value that came from a secret reference
+pub const REDACTED: &str = "********";
+
+/// `[secrets]`: an age-encrypted TOML file of `name = "value"` pairs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecretsConfig {
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Age identity file, used when FIREPLACE_AGE_IDENTITY is not set
+    #[serde(default)]
+    pub identity_file: Option<String>,
+}
+
+/// A config string that never leaves the process in the clear: it serializes and
+/// debug-prints as `********`. Use it for passwords, tokens and keys in config structs.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+/// Values substituted from secret references; Debug never prints them
+#[derive(Clone, Default)]
+pub struct SecretValues(Vec<String>);
+
+impl std::fmt::Debug for SecretValues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SecretValues({} redacted)", self.0.len())
+    }
+}
+
+impl SecretValues {
+    /// Mask every secret value inside a serialized config fragment
+    pub fn redact(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) if self.0.iter().any(|secret| s.contains(secret.as_str())) => {
+                *s = REDACTED.to_string();
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(|item| self.redact(item)),
+            serde_json::Value::Object(map) => map.values_mut().for_each(|item| self.redact(item)),
+            _ => {}
+        }
+    }
+}
+
+/// Replace `${env:NAME}` and `${secret:NAME}` references in every string of a parsed
+/// config, returning the substituted values so they can be redacted on the way out
+pub fn resolve(raw: &mut toml::Table) -> Result<SecretValues> {
+    let settings: SecretsConfig = match raw.get("secrets") {
+        Some(section) => section
+            .clone()
+            .try_into()
+            .map_err(|e| ApiError::ConfigError(format!("Failed to parse [secrets]: {}", e)))?,
+        None => SecretsConfig::default(),
+    };
+    let file = match &settings.file {
+        Some(path) => Some(load_file(path, &settings)?),
+        None => None,
+    };
+
+    let mut resolved = Vec::new();
+    for (_, value) in raw.iter_mut() {
+        resolve_value(value, file.as_ref(), &mut resolved)?;
+    }
+    Ok(SecretValues(resolved))
+}
+
+fn resolve_value(
+    value: &mut toml::Value,
+    file: Option<&HashMap<String, String>>,
+    resolved: &mut Vec<String>,
+) -> Result<()> {
+    match value {
+        toml::Value::String(s) if s.contains("${") => *s = substitute(s, file, resolved)?,
+        toml::Value::Array(items) => {
+            for item in items {
+                resolve_value(item, file, resolved)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, item) in table.iter_mut() {
+                resolve_value(item, file, resolved)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn substitute(s: &str, file: Option<&HashMap<String, String>>, resolved: &mut Vec<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .map(|i| start + i)
+            .ok_or_else(|| ApiError::ConfigError(format!("Unterminated secret reference in '{}'", s)))?;
+        out.push_str(&rest[..start]);
+
+        let reference = &rest[start + 2..end];
+        let value = match reference.split_once(':') {
+            Some(("env", name)) => std::env::var(name).map_err(|_| {
+                ApiError::ConfigError(format!("Environment variable {} is not set", name))
+            })?,
+            Some(("secret", name)) => file
+                .ok_or_else(|| ApiError::ConfigError(format!("${{secret:{}}} used without [secrets] file", name)))?
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ApiError::ConfigError(format!("Secret {} not found in secrets file", name)))?,
+            _ => {
+                return Err(ApiError::ConfigError(format!(
+                    "Unknown reference '${{{}}}'. Expected ${{env:NAME}} or ${{secret:NAME}}",
+                    reference
+                )))
+            }
+        };
+        out.push_str(&value);
+        if !value.is_empty() {
+            resolved.push(value);
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Decrypt the secrets file with the age identity from the environment or identity_file
+fn load_file(path: &str, settings: &SecretsConfig) -> Result<HashMap<String, String>> {
+    let key = match std::env::var(IDENTITY_ENV) {
+        Ok(key) => key,
+        Err(_) => {
+            let identity_file = settings.identity_file.as_deref().ok_or_else(|| {
+                ApiError::ConfigError(format!(
+                    "Secrets file {} needs {} or secrets.identity_file",
+                    path, IDENTITY_ENV
+                ))
+            })?;
+            let content = std::fs::read_to_string(identity_file)
+                .map_err(|e| ApiError::ConfigError(format!("Failed to read {}: {}", identity_file, e)))?;
+            // age-keygen output carries comment lines before the key
+            content
+                .lines()
+                .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+                .map(str::to_string)
+                .ok_or_else(|| ApiError::ConfigError(format!("No age identity in {}", identity_file)))?
+        }
+    };
+    let identity: age::x25519::Identity = key
+        .trim()
+        .parse()
+        .map_err(|e| ApiError::ConfigError(format!("Invalid age identity: {}", e)))?;
+
+    let ciphertext = std::fs::read(path)
+        .map_err(|e| ApiError::ConfigError(format!("Failed to read {}: {}", path, e)))?;
+    let plaintext = age::decrypt(&identity, &ciphertext)
+        .map_err(|e| ApiError::ConfigError(format!("Failed to decrypt {}: {}", path, e)))?;
+    let plaintext = String::from_utf8(plaintext)
+        .map_err(|_| ApiError::ConfigError(format!("Secrets file {} is not UTF-8", path)))?;
+
+    toml::from_str(&plaintext)
+        .map_err(|e| ApiError::ConfigError(format!("Failed to parse secrets file {}: {}", path, e)))
+}
@@ -0,0 +1,303 @@
+﻿use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, DeviceKind},
+    error::ApiError,
+    gpio::{GpioController, PinGuard, PinState},
+    state::{AppState, ChangeSource},
+};
+
+/// How long the test pin is held at each level before it is read back
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// `[selftest]`: the GPIO self-test run at startup and by POST /api/v1/selftest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTestConfig {
+    #[serde(default = "default_on_startup")]
+    pub on_startup: bool,
+    /// A spare output, wired to nothing that matters, driven high and low and read back
+    #[serde(default)]
+    pub test_pin: Option<u32>,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            on_startup: default_on_startup(),
+            test_pin: None,
+        }
+    }
+}
+
+fn default_on_startup() -> bool {
+    true
+}
+
+impl SelfTestConfig {
+    pub fn validate(&self, config: &Config) -> Result<(), String> {
+        match self.test_pin {
+            Some(pin) => check_test_pin(config, pin).map_err(|e| format!("selftest.test_pin: {}", e)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A test pin must exist and must not be wired to anything the config uses
+fn check_test_pin(config: &Config, pin: u32) -> Result<(), String> {
+    if !config.is_gpio_pin(pin) {
+        return Err(format!("{} is not a GPIO pin in {:?} numbering", pin, config.gpio.numbering));
+    }
+    if let Some(users) = pin_users(config).get(&pin) {
+        return Err(format!("pin {} is used by {}", pin, users[0].0));
+    }
+    if config.groups.iter().any(|group| group.pins.contains(&pin)) {
+        return Err(format!("pin {} is used by a device group", pin));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    /// Suspicious but not broken, e.g. a monitor input with no level to read
+    Warn,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, problems: Vec<String>, warnings: Vec<String>, passed: String) -> Self {
+        let (status, detail) = if !problems.is_empty() {
+            (CheckStatus::Fail, problems.join("; "))
+        } else if !warnings.is_empty() {
+            (CheckStatus::Warn, warnings.join("; "))
+        } else {
+            (CheckStatus::Pass, passed)
+        };
+        Self { name, status, detail }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestReport {
+    /// True unless a check failed; warnings don't fail the self-test
+    pub passed: bool,
+    pub backend: &'static str,
+    pub started_at: String,
+    pub checks: Vec<Check>,
+}
+
+/// Everything in the config wired to each pin, except device groups, which switch
+/// device pins together and so share them by design
+fn pin_users(config: &Config) -> BTreeMap<u32, Vec<(String, bool)>> {
+    let mut users: BTreeMap<u32, Vec<(String, bool)>> = BTreeMap::new();
+    let mut claim = |pin: u32, user: String, output: bool| users.entry(pin).or_default().push((user, output));
+    for zone in config.zones() {
+        for device in zone.devices {
+            claim(device.pin, format!("{}/{}", zone.name, device.name), true);
+            if let Some(monitor) = device.monitor {
+                claim(monitor, format!("{}/{} monitor", zone.name, device.name), false);
+            }
+        }
+    }
+    for sensor in &config.sensors {
+        if let crate::sensors::SensorKind::Dht22 { pin, .. } = sensor.kind {
+            claim(pin, format!("sensor {}", sensor.id), false);
+        }
+    }
+    if let Some(led) = &config.status_led {
+        claim(led.pin, "status_led.pin".to_string(), true);
+    }
+    for input in &config.inputs {
+        claim(input.pin, format!("input {}", input.name), false);
+    }
+    if let Some(power) = &config.power {
+        claim(power.on_battery_pin, "power.on_battery_pin".to_string(), false);
+    }
+    users
+}
+
+/// Run every check against the live config and GPIO backend. `test_pin` overrides
+/// `selftest.test_pin`.
+pub async fn run(state: &AppState, test_pin: Option<u32>) -> crate::error::Result<SelfTestReport> {
+    let config = state.config.load_full();
+    let test_pin = test_pin.or(config.selftest.test_pin);
+    if let Some(pin) = test_pin {
+        check_test_pin(&config, pin).map_err(ApiError::InvalidSelfTest)?;
+    }
+
+    // An emergency stop holds every output off, test pin included
+    let locked = state.lock.read().await.check(ChangeSource::SelfTest).err();
+
+    let started_at = Local::now().to_rfc3339();
+    let gpio = &state.gpio_controller;
+    let users = pin_users(&config);
+    let mut checks = vec![
+        check_backend(gpio, &users).await,
+        check_pins(&config, &users),
+        check_conflicts(&users),
+        check_monitors(gpio, &config).await,
+    ];
+    checks.push(match (test_pin, locked) {
+        (None, _) => Check {
+            name: "test_pin",
+            status: CheckStatus::Skip,
+            detail: "no test pin configured".to_string(),
+        },
+        (Some(_), Some(e)) => Check {
+            name: "test_pin",
+            status: CheckStatus::Skip,
+            detail: e.to_string(),
+        },
+        (Some(pin), None) => exercise_test_pin(&mut gpio.lock(ChangeSource::SelfTest, &[pin]).await, pin).await,
+    });
+
+    Ok(SelfTestReport {
+        passed: checks.iter().all(|check| check.status != CheckStatus::Fail),
+        backend: gpio.backend_health().backend,
+        started_at,
+        checks,
+    })
+}
+
+/// The backend answers a read of every configured pin
+async fn check_backend(gpio: &GpioController, users: &BTreeMap<u32, Vec<(String, bool)>>) -> Check {
+    let mut problems = Vec::new();
+    for pin in users.keys() {
+        match gpio.read_pin(*pin).await {
+            Ok(_) => {}
+            // Every other pin would fail the same way
+            Err(e @ ApiError::BackendUnavailable { .. }) => {
+                problems = vec![e.to_string()];
+                break;
+            }
+            Err(e) => problems.push(format!("reading pin {} failed: {}", pin, e)),
+        }
+    }
+    Check::new("backend", problems, Vec::new(), format!("read {} pins", users.len()))
+}
+
+/// Every pin, group pins included, exists under the configured numbering
+fn check_pins(config: &Config, users: &BTreeMap<u32, Vec<(String, bool)>>) -> Check {
+    let mut pins: Vec<u32> = users.keys().copied().collect();
+    pins.extend(config.groups.iter().flat_map(|group| group.pins.iter().copied()));
+    pins.sort_unstable();
+    pins.dedup();
+
+    let problems = pins
+        .iter()
+        .filter(|pin| !config.is_gpio_pin(**pin))
+        .map(|pin| format!("pin {} is not a GPIO pin in {:?} numbering", pin, config.gpio.numbering))
+        .collect();
+    Check::new("pins", problems, Vec::new(), format!("{} pins valid", pins.len()))
+}
+
+/// No pin is driven by two devices, or read as an input while driven as an output
+fn check_conflicts(users: &BTreeMap<u32, Vec<(String, bool)>>) -> Check {
+    let problems = users
+        .iter()
+        .filter(|(_, users)| users.len() > 1)
+        .map(|(pin, users)| {
+            let names: Vec<&str> = users.iter().map(|(name, _)| name.as_str()).collect();
+            format!("pin {} is shared by {}", pin, names.join(", "))
+        })
+        .collect();
+    Check::new("conflicts", problems, Vec::new(), "no pin is claimed twice".to_string())
+}
+
+/// Each fireplace's monitor input reads a level that agrees with its relay
+async fn check_monitors(gpio: &GpioController, config: &Config) -> Check {
+    let mut problems = Vec::new();
+    let mut warnings = Vec::new();
+    let mut count = 0;
+    for zone in config.zones() {
+        for device in zone.devices.iter().filter(|d| d.kind == DeviceKind::Fireplace) {
+            let Some(monitor) = device.monitor else { continue };
+            count += 1;
+            let name = format!("{}/{} monitor pin {}", zone.name, device.name, monitor);
+            match gpio.read_pin(monitor).await {
+                Err(e) => problems.push(format!("{}: {}", name, e)),
+                Ok(PinState::Unknown) => warnings.push(format!("{} reads no level", name)),
+                Ok(level) => {
+                    let relay = gpio.get_pin_state(device.pin);
+                    if relay != PinState::Unknown && level != relay {
+                        warnings.push(format!("{} reads {:?} while the relay is {:?}", name, level, relay));
+                    }
+                }
+            }
+        }
+    }
+    if count == 0 {
+        return Check {
+            name: "monitors",
+            status: CheckStatus::Skip,
+            detail: "no monitor pins configured".to_string(),
+        };
+    }
+    Check::new("monitors", problems, warnings, format!("{} monitor pins agree with their relays", count))
+}
+
+/// Drive the test pin high then low, reading each level back, and leave it low
+async fn exercise_test_pin(gpio: &mut PinGuard<'_>, pin: u32) -> Check {
+    if gpio.is_standby() {
+        return Check {
+            name: "test_pin",
+            status: CheckStatus::Skip,
+            detail: "the failover standby doesn't drive outputs".to_string(),
+        };
+    }
+    let mut problems = Vec::new();
+    for high in [true, false] {
+        let expected = if high { PinState::High } else { PinState::Low };
+        if let Err(e) = gpio.set_pin(pin, high).await {
+            problems.push(format!("setting pin {} {:?} failed: {}", pin, expected, e));
+            break;
+        }
+        tokio::time::sleep(SETTLE).await;
+        match gpio.read_pin(pin).await {
+            Ok(actual) if actual == expected => {}
+            Ok(actual) => problems.push(format!("pin {} reads {:?} after setting it {:?}", pin, actual, expected)),
+            Err(e) => problems.push(format!("reading pin {} back failed: {}", pin, e)),
+        }
+    }
+    if !problems.is_empty() {
+        let _ = gpio.set_pin(pin, false).await;
+    }
+    Check::new("test_pin", problems, Vec::new(), format!("pin {} switched high and low", pin))
+}
+
+/// Run the self-test once at startup, if `selftest.on_startup`, and log the result
+pub async fn run_at_startup(state: &AppState) {
+    if !state.config.load().selftest.on_startup {
+        return;
+    }
+    let report = match run(state, None).await {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::error!("Self-test could not run: {}", e);
+            return;
+        }
+    };
+    for check in &report.checks {
+        match check.status {
+            CheckStatus::Fail => tracing::error!("Self-test {} failed: {}", check.name, check.detail),
+            CheckStatus::Warn => tracing::warn!("Self-test {}: {}", check.name, check.detail),
+            CheckStatus::Pass | CheckStatus::Skip => tracing::debug!("Self-test {}: {}", check.name, check.detail),
+        }
+    }
+    if report.passed {
+        tracing::info!("Self-test passed ({} backend)", report.backend);
+    }
+}
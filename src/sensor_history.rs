@@ -0,0 +1,353 @@
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{
+    config::{Config, DeviceKind},
+    error::{ApiError, Result},
+    state::AppState,
+};
+
+/// How often the latest reading of each sensor is recorded
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Readings older than `full_resolution` are merged into averages over this long
+const COARSE_BUCKET: Duration = Duration::from_secs(15 * 60);
+
+/// How often old readings are downsampled and expired, in samples
+const COMPACT_EVERY: u32 = 60;
+
+/// Most points one history request may ask for
+pub const MAX_POINTS: u64 = 2000;
+
+/// `[sensor_history]`: how long sensor readings are kept for charts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorHistoryConfig {
+    /// Readings are kept once a minute for this long
+    #[serde(default = "default_full_resolution", with = "crate::duration::minutes")]
+    pub full_resolution: Duration,
+    /// Then as 15-minute averages until they are this old
+    #[serde(default = "default_retention", with = "crate::duration::minutes")]
+    pub retention: Duration,
+}
+
+impl Default for SensorHistoryConfig {
+    fn default() -> Self {
+        Self {
+            full_resolution: default_full_resolution(),
+            retention: default_retention(),
+        }
+    }
+}
+
+fn default_full_resolution() -> Duration {
+    Duration::from_secs(2 * 24 * 60 * 60)
+}
+
+fn default_retention() -> Duration {
+    Duration::from_secs(30 * 24 * 60 * 60)
+}
+
+impl SensorHistoryConfig {
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.full_resolution > self.retention {
+            return Err("sensor_history.full_resolution must not be longer than retention".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// One stored reading, or the average of several once downsampled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sample {
+    sensor: String,
+    at: DateTime<Local>,
+    temperature_c: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    humidity_pct: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pressure_hpa: Option<f64>,
+    /// Readings averaged into this one
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    count: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn is_one(count: &u32) -> bool {
+    *count == 1
+}
+
+/// Averages of the samples falling in one time bucket, weighted by how many readings each stands for
+#[derive(Default)]
+struct Accumulator {
+    count: u32,
+    temperature: f64,
+    min_c: f64,
+    max_c: f64,
+    humidity: (f64, u32),
+    pressure: (f64, u32),
+}
+
+impl Accumulator {
+    fn add(&mut self, sample: &Sample) {
+        let weight = f64::from(sample.count);
+        if self.count == 0 {
+            (self.min_c, self.max_c) = (sample.temperature_c, sample.temperature_c);
+        }
+        self.count += sample.count;
+        self.temperature += sample.temperature_c * weight;
+        self.min_c = self.min_c.min(sample.temperature_c);
+        self.max_c = self.max_c.max(sample.temperature_c);
+        if let Some(humidity) = sample.humidity_pct {
+            self.humidity = (self.humidity.0 + humidity * weight, self.humidity.1 + sample.count);
+        }
+        if let Some(pressure) = sample.pressure_hpa {
+            self.pressure = (self.pressure.0 + pressure * weight, self.pressure.1 + sample.count);
+        }
+    }
+
+    fn mean(total: f64, count: u32) -> Option<f64> {
+        (count > 0).then(|| total / f64::from(count))
+    }
+}
+
+/// One point of a history chart
+#[derive(Debug, Serialize)]
+pub struct HistoryPoint {
+    /// Start of the bucket
+    pub at: DateTime<Local>,
+    /// Mean over the bucket; null where nothing was recorded
+    pub temperature_c: Option<f64>,
+    pub min_c: Option<f64>,
+    pub max_c: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity_pct: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure_hpa: Option<f64>,
+    /// The longest any fireplace in the sensor's room was on during the bucket
+    pub fireplace_on_seconds: i64,
+}
+
+/// Sensor readings, appended once a minute to a JSON Lines file and downsampled as they age
+pub struct SensorHistory {
+    path: PathBuf,
+    samples: Vec<Sample>,
+}
+
+impl SensorHistory {
+    /// Load the history; a missing file starts empty and unreadable lines are skipped
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("sensors.jsonl");
+        let samples = match std::fs::read_to_string(&path) {
+            Ok(content) => content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| match serde_json::from_str(line) {
+                    Ok(sample) => Some(sample),
+                    Err(e) => {
+                        tracing::warn!("Skipping unreadable line in {}: {}", path.display(), e);
+                        None
+                    }
+                })
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+        Self { path, samples }
+    }
+
+    fn append(&mut self, sample: Sample) {
+        if let Err(e) = self.write(&sample) {
+            tracing::warn!("{}", e);
+        }
+        self.samples.push(sample);
+    }
+
+    /// Merge readings past `full_resolution` into 15-minute averages and drop those past
+    /// `retention`, rewriting the file if anything changed
+    fn compact(&mut self, config: &SensorHistoryConfig) {
+        let now = Local::now();
+        let age = |d: Duration| now - chrono::Duration::from_std(d).unwrap_or(chrono::Duration::MAX);
+        let (expire_before, coarse_before) = (age(config.retention), age(config.full_resolution));
+
+        let before = self.samples.len();
+        let mut recent = Vec::new();
+        let mut buckets: BTreeMap<(String, DateTime<Local>), Accumulator> = BTreeMap::new();
+        let mut merged_from = 0;
+        for sample in self.samples.drain(..).filter(|s| s.at >= expire_before) {
+            if sample.at >= coarse_before {
+                recent.push(sample);
+            } else {
+                merged_from += 1;
+                let key = (sample.sensor.clone(), bucket_start(sample.at, COARSE_BUCKET));
+                buckets.entry(key).or_default().add(&sample);
+            }
+        }
+        let coarse: Vec<Sample> = buckets
+            .into_iter()
+            .map(|((sensor, at), acc)| Sample {
+                sensor,
+                at,
+                temperature_c: acc.temperature / f64::from(acc.count),
+                humidity_pct: Accumulator::mean(acc.humidity.0, acc.humidity.1),
+                pressure_hpa: Accumulator::mean(acc.pressure.0, acc.pressure.1),
+                count: acc.count,
+            })
+            .collect();
+
+        let changed = merged_from != coarse.len() || before != merged_from + recent.len();
+        self.samples = coarse;
+        self.samples.extend(recent);
+        if changed {
+            if let Err(e) = self.rewrite() {
+                tracing::warn!("{}", e);
+            }
+        }
+    }
+
+    /// Readings of one sensor from `from` to `to`, averaged into buckets of `resolution`
+    fn query(
+        &self,
+        sensor: &str,
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+        resolution: Duration,
+    ) -> Vec<(DateTime<Local>, Accumulator)> {
+        let step = chrono::Duration::from_std(resolution).unwrap_or(chrono::Duration::MAX);
+        let mut buckets = Vec::new();
+        let mut at = bucket_start(from, resolution);
+        while at < to {
+            buckets.push((at, Accumulator::default()));
+            at += step;
+        }
+        let Some(&(first, _)) = buckets.first() else {
+            return buckets;
+        };
+        for sample in self.samples.iter().filter(|s| s.sensor == sensor && s.at >= first && s.at < to) {
+            let index = ((sample.at - first).num_seconds() / step.num_seconds().max(1)) as usize;
+            if let Some((_, acc)) = buckets.get_mut(index) {
+                acc.add(sample);
+            }
+        }
+        buckets
+    }
+
+    fn write(&self, sample: &Sample) -> Result<()> {
+        let mut line = serde_json::to_string(sample)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode sensor reading: {}", e)))?;
+        line.push('\n');
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+
+    fn rewrite(&self) -> Result<()> {
+        let mut content = String::new();
+        for sample in &self.samples {
+            let line = serde_json::to_string(sample)
+                .map_err(|e| ApiError::StorageError(format!("Failed to encode sensor reading: {}", e)))?;
+            content.push_str(&line);
+            content.push('\n');
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("jsonl.tmp");
+        std::fs::write(&tmp, content)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
+
+/// The start of the `length`-long bucket `at` falls in, counted from the Unix epoch
+fn bucket_start(at: DateTime<Local>, length: Duration) -> DateTime<Local> {
+    let length = length.as_secs().max(1) as i64;
+    let start = at.timestamp() - at.timestamp().rem_euclid(length);
+    DateTime::from_timestamp(start, 0).map_or(at, |t| t.with_timezone(&Local))
+}
+
+/// A sensor's readings over the last `range`, averaged into buckets of `resolution`,
+/// alongside how long the fireplaces in its room were on in each
+pub async fn history(
+    state: &AppState,
+    config: &Config,
+    sensor: &str,
+    range: Duration,
+    resolution: Duration,
+) -> Result<Vec<HistoryPoint>> {
+    let sensor = config.sensor(sensor).ok_or_else(|| ApiError::UnknownSensor(sensor.to_string()))?;
+    let to = Local::now();
+    let from = to - chrono::Duration::from_std(range).unwrap_or(chrono::Duration::MAX);
+    let buckets = state.sensor_history.read().await.query(&sensor.id, from, to, resolution);
+
+    let fireplaces: Vec<u32> = config
+        .zone(Some(sensor.room(config)))
+        .map(|zone| zone.of_kind(DeviceKind::Fireplace).map(|d| d.pin).collect())
+        .unwrap_or_default();
+    let step = chrono::Duration::from_std(resolution).unwrap_or(chrono::Duration::MAX);
+    let sessions = state.history.read().await;
+    Ok(buckets
+        .into_iter()
+        .map(|(at, acc)| HistoryPoint {
+            at,
+            temperature_c: Accumulator::mean(acc.temperature, acc.count),
+            min_c: (acc.count > 0).then_some(acc.min_c),
+            max_c: (acc.count > 0).then_some(acc.max_c),
+            humidity_pct: Accumulator::mean(acc.humidity.0, acc.humidity.1),
+            pressure_hpa: Accumulator::mean(acc.pressure.0, acc.pressure.1),
+            fireplace_on_seconds: fireplaces
+                .iter()
+                .map(|pin| sessions.on_seconds(*pin, at, (at + step).min(to), to))
+                .max()
+                .unwrap_or(0),
+        })
+        .collect())
+}
+
+/// Record each sensor's latest reading once a minute, and downsample old ones every hour
+pub fn spawn_recorder(state: AppState) {
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now() + SAMPLE_INTERVAL;
+        let mut ticker = tokio::time::interval_at(start, SAMPLE_INTERVAL);
+        let mut ticks: u32 = 0;
+        loop {
+            ticker.tick().await;
+            let config = state.config.load_full();
+            let statuses = state.sensors.read().await.list(&config);
+            let mut history = state.sensor_history.write().await;
+            for status in statuses.into_iter().filter(|s| !s.stale) {
+                let Some(reading) = status.reading else {
+                    continue;
+                };
+                history.append(Sample {
+                    sensor: status.id,
+                    at: Local::now(),
+                    temperature_c: reading.temperature_c,
+                    humidity_pct: reading.humidity_pct,
+                    pressure_hpa: reading.pressure_hpa,
+                    count: 1,
+                });
+            }
+            if ticks.is_multiple_of(COMPACT_EVERY) {
+                history.compact(&config.sensor_history);
+            }
+            ticks = ticks.wrapping_add(1);
+        }
+    });
+}
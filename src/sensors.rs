@@ -0,0 +1,322 @@
+﻿use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{config::Config, state::AppState};
+
+/// Where 1-Wire sensors appear once the w1-gpio overlay is enabled
+const W1_DEVICES: &str = "/sys/bus/w1/devices";
+
+/// Where the kernel's industrial I/O drivers (dht11, bmp280) publish their channels
+const IIO_DEVICES: &str = "/sys/bus/iio/devices";
+
+const I2C_DEVICES: &str = "/sys/bus/i2c/devices";
+
+/// A reading older than this many poll intervals is no longer trusted
+const STALE_INTERVALS: u32 = 3;
+
+/// `[[sensors]]`: a temperature (and maybe humidity and pressure) sensor polled on an interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorConfig {
+    pub id: String,
+    /// Defaults to the primary room
+    #[serde(default)]
+    pub room: Option<String>,
+    #[serde(flatten)]
+    pub kind: SensorKind,
+    #[serde(default = "default_interval", with = "crate::duration::seconds")]
+    pub interval: Duration,
+}
+
+/// How a sensor is read, selected by its `type`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SensorKind {
+    /// DS18B20 on the 1-Wire bus, by its ID under /sys/bus/w1/devices, e.g. "28-0000071e4f3b"
+    Ds18b20 { device_id: String },
+    /// DHT22 on a GPIO pin, read through the kernel's dht11 driver
+    Dht22 {
+        pin: u32,
+        /// IIO device to read, e.g. "iio:device0"; found by driver name when there is one DHT
+        #[serde(default)]
+        iio_device: Option<String>,
+    },
+    /// BME280 on the I2C bus, read through the kernel's bmp280 driver
+    Bme280 {
+        #[serde(default = "default_i2c_bus")]
+        bus: u32,
+        #[serde(default = "default_bme280_address")]
+        address: u16,
+    },
+    /// A file holding a temperature in °C, kept up to date by another program
+    File { path: String },
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_i2c_bus() -> u32 {
+    1
+}
+
+fn default_bme280_address() -> u16 {
+    0x76
+}
+
+impl SensorConfig {
+    pub fn validate(&self, config: &Config) -> Result<(), String> {
+        if self.id.trim().is_empty() {
+            return Err("sensors: every sensor needs an id".to_string());
+        }
+        config.zone(self.room.as_deref()).map_err(|e| format!("sensors.{}: {}", self.id, e))?;
+        if self.interval < Duration::from_secs(1) {
+            return Err(format!("sensors.{}: interval must be at least 1s", self.id));
+        }
+        match &self.kind {
+            SensorKind::Ds18b20 { device_id } if device_id.is_empty() || device_id.contains('/') => {
+                Err(format!("sensors.{}: '{}' is not a 1-Wire device ID", self.id, device_id))
+            }
+            SensorKind::File { path } if path.is_empty() => Err(format!("sensors.{}: path is empty", self.id)),
+            SensorKind::Dht22 { iio_device: Some(device), .. } if device.is_empty() || device.contains('/') => {
+                Err(format!("sensors.{}: '{}' is not an IIO device name", self.id, device))
+            }
+            SensorKind::Bme280 { address, .. } if !matches!(address, 0x76 | 0x77) => {
+                Err(format!("sensors.{}: a BME280 is at address 0x76 or 0x77, not {:#04x}", self.id, address))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The room the sensor measures
+    pub fn room<'a>(&'a self, config: &'a Config) -> &'a str {
+        config.zone(self.room.as_deref()).map(|z| z.name).unwrap_or(&config.room.name)
+    }
+}
+
+/// What one read of a sensor returns
+struct Measurement {
+    temperature_c: f64,
+    humidity_pct: Option<f64>,
+    pressure_hpa: Option<f64>,
+}
+
+impl Measurement {
+    fn temperature(temperature_c: f64) -> Self {
+        Self {
+            temperature_c,
+            humidity_pct: None,
+            pressure_hpa: None,
+        }
+    }
+}
+
+impl SensorKind {
+    async fn read(&self) -> Result<Measurement, String> {
+        match self {
+            SensorKind::Ds18b20 { device_id } => {
+                let path = format!("{}/{}/w1_slave", W1_DEVICES, device_id);
+                let content = tokio::fs::read_to_string(&path).await.map_err(|e| format!("{}: {}", path, e))?;
+                parse_w1_slave(&content)
+                    .map(Measurement::temperature)
+                    .ok_or_else(|| format!("{}: no valid reading (CRC check failed?)", path))
+            }
+            SensorKind::File { path } => {
+                let content = tokio::fs::read_to_string(path).await.map_err(|e| format!("{}: {}", path, e))?;
+                content
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|t| t.is_finite())
+                    .map(Measurement::temperature)
+                    .ok_or_else(|| format!("{}: '{}' is not a temperature", path, content.trim()))
+            }
+            SensorKind::Dht22 { iio_device, .. } => {
+                let dir = match iio_device {
+                    Some(device) => format!("{}/{}", IIO_DEVICES, device),
+                    None => find_dht().await?,
+                };
+                Ok(Measurement {
+                    temperature_c: read_channel(&dir, "in_temp_input").await? / 1000.0,
+                    humidity_pct: Some(read_channel(&dir, "in_humidityrelative_input").await? / 1000.0),
+                    pressure_hpa: None,
+                })
+            }
+            SensorKind::Bme280 { bus, address } => {
+                let dir = find_i2c_iio(*bus, *address).await?;
+                Ok(Measurement {
+                    temperature_c: read_channel(&dir, "in_temp_input").await? / 1000.0,
+                    humidity_pct: Some(read_channel(&dir, "in_humidityrelative_input").await? / 1000.0),
+                    // The driver reports kPa
+                    pressure_hpa: Some(read_channel(&dir, "in_pressure_input").await? * 10.0),
+                })
+            }
+        }
+    }
+}
+
+/// One processed IIO channel value
+async fn read_channel(dir: &str, channel: &str) -> Result<f64, String> {
+    let path = format!("{}/{}", dir, channel);
+    let content = tokio::fs::read_to_string(&path).await.map_err(|e| format!("{}: {}", path, e))?;
+    content
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("{}: '{}' is not a number", path, content.trim()))
+}
+
+/// The IIO device of the only DHT sensor the dht11 driver has bound
+async fn find_dht() -> Result<String, String> {
+    let mut entries = tokio::fs::read_dir(IIO_DEVICES)
+        .await
+        .map_err(|e| format!("{}: {} (is dtoverlay=dht11 enabled?)", IIO_DEVICES, e))?;
+    let mut found = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = tokio::fs::read_to_string(entry.path().join("name")).await.unwrap_or_default();
+        if name.trim().contains("dht11") {
+            found.push(entry.path().display().to_string());
+        }
+    }
+    match found.len() {
+        0 => Err("no DHT sensor found (is dtoverlay=dht11 enabled?)".to_string()),
+        1 => Ok(found.remove(0)),
+        _ => Err("several DHT sensors found: set iio_device on each".to_string()),
+    }
+}
+
+/// The IIO device the bmp280 driver created for a BME280 at an I2C address
+async fn find_i2c_iio(bus: u32, address: u16) -> Result<String, String> {
+    let dir = format!("{}/{}-{:04x}", I2C_DEVICES, bus, address);
+    let mut entries = tokio::fs::read_dir(&dir)
+        .await
+        .map_err(|e| format!("{}: {} (is dtoverlay=i2c-sensor,bme280 enabled?)", dir, e))?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if entry.file_name().to_string_lossy().starts_with("iio:device") {
+            return Ok(entry.path().display().to_string());
+        }
+    }
+    Err(format!("{}: no IIO device; is the bmp280 driver loaded?", dir))
+}
+
+/// The kernel's w1_slave file: a line ending in "YES" when the CRC matched, then one
+/// ending in "t=<millidegrees>"
+fn parse_w1_slave(content: &str) -> Option<f64> {
+    let mut lines = content.lines();
+    if !lines.next()?.trim_end().ends_with("YES") {
+        return None;
+    }
+    let (_, millidegrees) = lines.next()?.rsplit_once("t=")?;
+    millidegrees.trim().parse::<i32>().ok().map(|m| f64::from(m) / 1000.0)
+}
+
+/// One successful read of a sensor
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Reading {
+    pub temperature_c: f64,
+    /// Relative humidity, from sensors that measure it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity_pct: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure_hpa: Option<f64>,
+    pub at: DateTime<Local>,
+}
+
+/// A sensor's latest reading and whether it can still be trusted
+#[derive(Debug, Clone, Serialize)]
+pub struct SensorStatus {
+    pub id: String,
+    pub room: String,
+    /// Null until the first successful read
+    pub reading: Option<Reading>,
+    /// The reading is missing or more than three poll intervals old
+    pub stale: bool,
+    /// Why the last read failed, until one succeeds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The latest reading of every sensor, keyed by sensor ID
+#[derive(Default)]
+pub struct Sensors {
+    latest: HashMap<String, (Option<Reading>, Option<String>)>,
+}
+
+impl Sensors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&mut self, sensor: &SensorConfig, result: Result<Measurement, String>) {
+        let entry = self.latest.entry(sensor.id.clone()).or_default();
+        match result {
+            Ok(measurement) => {
+                if entry.1.take().is_some() {
+                    tracing::info!("Sensor {} is reading again", sensor.id);
+                }
+                entry.0 = Some(Reading {
+                    temperature_c: measurement.temperature_c,
+                    humidity_pct: measurement.humidity_pct,
+                    pressure_hpa: measurement.pressure_hpa,
+                    at: Local::now(),
+                });
+            }
+            Err(e) => {
+                // Warn when a sensor starts failing, not on every poll while it stays down
+                if entry.1.as_ref() != Some(&e) {
+                    tracing::warn!("Failed to read sensor {}: {}", sensor.id, e);
+                }
+                entry.1 = Some(e);
+            }
+        }
+    }
+
+    pub fn status(&self, config: &Config, sensor: &SensorConfig) -> SensorStatus {
+        let (reading, error) = self.latest.get(&sensor.id).cloned().unwrap_or_default();
+        let max_age = chrono::Duration::from_std(sensor.interval * STALE_INTERVALS).unwrap_or_default();
+        SensorStatus {
+            id: sensor.id.clone(),
+            room: sensor.room(config).to_string(),
+            reading,
+            stale: reading.is_none_or(|r| Local::now() - r.at > max_age),
+            error,
+        }
+    }
+
+    /// Every configured sensor, in config order
+    pub fn list(&self, config: &Config) -> Vec<SensorStatus> {
+        config.sensors.iter().map(|sensor| self.status(config, sensor)).collect()
+    }
+
+    /// A sensor's temperature, unless it has none recent enough to act on
+    pub fn temperature(&self, config: &Config, id: &str) -> Option<f64> {
+        let sensor = config.sensor(id)?;
+        let status = self.status(config, sensor);
+        match (status.stale, status.reading) {
+            (false, Some(reading)) => Some(reading.temperature_c),
+            _ => None,
+        }
+    }
+}
+
+/// Read every configured sensor on its own interval. Follows config reloads, so sensors
+/// can be added without a restart.
+pub fn spawn_poller(state: AppState) {
+    tokio::spawn(async move {
+        let mut due: HashMap<String, Instant> = HashMap::new();
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tick.tick().await;
+            let config = state.config.load_full();
+            for sensor in &config.sensors {
+                let now = Instant::now();
+                if due.get(&sensor.id).is_some_and(|at| *at > now) {
+                    continue;
+                }
+                due.insert(sensor.id.clone(), now + sensor.interval);
+                let result = sensor.kind.read().await;
+                state.sensors.write().await.record(sensor, result);
+            }
+        }
+    });
+}
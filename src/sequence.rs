@@ -0,0 +1,249 @@
+﻿use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+use crate::{
+    api::models::FireplaceControlRequest,
+    commands::Progress,
+    config::{Config, DeviceKind},
+    error::{ApiError, Result},
+    gpio::{PinGuard, PinState},
+    state::{AppState, ChangeSource},
+};
+
+/// How often `assert_pin` re-reads a pin while waiting for it
+const ASSERT_POLL: Duration = Duration::from_millis(100);
+
+/// Longest an `assert_pin` may wait for its level
+const MAX_ASSERT_WITHIN: Duration = Duration::from_secs(60);
+
+/// One step of a sequence. Steps are told apart by their fields:
+///
+/// - `{device, action, room}` runs a control request, with all of its checks
+/// - `{wait}` pauses before the next step
+/// - `{set_pin, high}` drives an output pin directly
+/// - `{pulse, duration}` drives an output pin high for `duration`, then low
+/// - `{assert_pin, high, within}` fails the sequence unless the pin reads `high` within
+///   `within` (default: right away)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Step {
+    Control {
+        /// A device or group
+        device: String,
+        /// ON or OFF
+        action: String,
+        /// Defaults to the sequence's room
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        room: Option<String>,
+    },
+    Wait {
+        #[serde(with = "crate::duration::seconds")]
+        wait: Duration,
+    },
+    SetPin {
+        set_pin: u32,
+        high: bool,
+    },
+    Pulse {
+        pulse: u32,
+        #[serde(with = "crate::duration::millis")]
+        duration: Duration,
+    },
+    AssertPin {
+        assert_pin: u32,
+        high: bool,
+        #[serde(default, with = "crate::duration::millis", skip_serializing_if = "Duration::is_zero")]
+        within: Duration,
+    },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = |high: bool| if high { "high" } else { "low" };
+        match self {
+            Step::Control { device, action, .. } => write!(f, "{} {}", action.to_uppercase(), device),
+            Step::Wait { wait } => write!(f, "waiting {}", humantime::format_duration(*wait)),
+            Step::SetPin { set_pin, high } => write!(f, "setting pin {} {}", set_pin, level(*high)),
+            Step::Pulse { pulse, duration } => {
+                write!(f, "pulsing pin {} for {}", pulse, humantime::format_duration(*duration))
+            }
+            Step::AssertPin { assert_pin, high, .. } => {
+                write!(f, "checking pin {} reads {}", assert_pin, level(*high))
+            }
+        }
+    }
+}
+
+/// Check every step against the config. `room` is where steps that don't name one run.
+/// Messages name the step, starting from 1.
+pub fn validate(steps: &[Step], room: Option<&str>, config: &Config) -> std::result::Result<(), String> {
+    for (i, step) in steps.iter().enumerate() {
+        validate_step(step, room, config).map_err(|e| format!("step {}: {}", i + 1, e))?;
+    }
+    Ok(())
+}
+
+pub fn validate_step(step: &Step, room: Option<&str>, config: &Config) -> std::result::Result<(), String> {
+    let output = |pin: u32| {
+        config
+            .find_pin(pin)
+            .map(|(_, device)| device)
+            .ok_or_else(|| format!("pin {} is not a configured output", pin))
+    };
+    match step {
+        Step::Control { device, action, room: step_room } => {
+            if !matches!(action.to_uppercase().as_str(), "ON" | "OFF") {
+                return Err("action must be ON or OFF".to_string());
+            }
+            let zone = config.zone(step_room.as_deref().or(room)).map_err(|e| e.to_string())?;
+            if config.device_pins(zone.name, device).is_none() {
+                return Err(format!("unknown device '{}' in room '{}'", device, zone.name));
+            }
+        }
+        Step::Wait { wait } if wait.is_zero() => return Err("waits for no time".to_string()),
+        Step::Wait { .. } => {}
+        Step::SetPin { set_pin, .. } => {
+            // A fireplace has to go through ignition, with its checks and verification
+            if output(*set_pin)?.kind == DeviceKind::Fireplace {
+                return Err(format!("pin {} is a fireplace; switch it with a device step", set_pin));
+            }
+        }
+        Step::Pulse { pulse, duration } => {
+            if output(*pulse)?.kind == DeviceKind::Fireplace {
+                return Err(format!("pin {} is a fireplace; switch it with a device step", pulse));
+            }
+            let max = config.safety.max_pulse_duration;
+            if duration.is_zero() || *duration > max {
+                return Err(format!("pulse duration must be between 1ms and {}", humantime::format_duration(max)));
+            }
+        }
+        Step::AssertPin { assert_pin, within, .. } => {
+            if *within > MAX_ASSERT_WITHIN {
+                return Err(format!("within must be at most {}", humantime::format_duration(MAX_ASSERT_WITHIN)));
+            }
+            let monitored = config.zones().any(|zone| zone.devices.iter().any(|d| d.monitor == Some(*assert_pin)));
+            if config.find_pin(*assert_pin).is_none() && !monitored {
+                return Err(format!("pin {} is not a configured output or monitor pin", assert_pin));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run steps in order, stopping at the first that fails. The `on_failure` steps then run,
+/// best effort, to put things back, and the failing step's error is returned.
+pub async fn run(
+    state: &AppState,
+    steps: &[Step],
+    on_failure: &[Step],
+    room: Option<&str>,
+    source: ChangeSource,
+    progress: &Progress,
+) -> Result<()> {
+    for (i, step) in steps.iter().enumerate() {
+        progress.step(format!("Step {}: {}", i + 1, step)).await;
+        let Err(e) = execute(state, step, room, source, progress).await else {
+            continue;
+        };
+        progress.step(format!("Step {} failed: {}", i + 1, e)).await;
+        if !on_failure.is_empty() {
+            progress.step("Running the on_failure steps").await;
+            for step in on_failure {
+                if let Err(e) = execute(state, step, room, source, progress).await {
+                    tracing::warn!("on_failure step '{}' failed: {}", step, e);
+                }
+            }
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+async fn execute(
+    state: &AppState,
+    step: &Step,
+    room: Option<&str>,
+    source: ChangeSource,
+    progress: &Progress,
+) -> Result<()> {
+    match step {
+        Step::Control { device, action, room: step_room } => {
+            let req = FireplaceControlRequest {
+                action: action.clone(),
+                device: device.clone(),
+                room: step_room.clone().or_else(|| room.map(str::to_string)),
+                cycles: None,
+                cycle_delay_ms: None,
+                duration_minutes: None,
+                run_async: false,
+                confirmation_token: None,
+                source,
+            };
+            crate::api::handlers::run_control(state, req, progress).await.map(|_| ())
+        }
+        Step::Wait { wait } => {
+            tokio::time::sleep(*wait).await;
+            Ok(())
+        }
+        Step::SetPin { set_pin: pin, .. } | Step::Pulse { pulse: pin, .. } => {
+            let on = !matches!(step, Step::SetPin { high: false, .. });
+            state.lock.read().await.check(source)?;
+            let config = state.config.load_full();
+            if let Some((zone, device)) = config.find_pin(*pin) {
+                state.cutoffs.read().await.check(zone.name, device, on)?;
+            }
+            let mut gpio = state.gpio_controller.lock(source, std::slice::from_ref(pin)).await;
+            run_on(&mut gpio, std::slice::from_ref(step)).await
+        }
+        Step::AssertPin { assert_pin, high, within } => {
+            // Poll without holding the pin, so other requests aren't held up
+            let deadline = tokio::time::Instant::now() + *within;
+            loop {
+                let actual = state.gpio_controller.read_pin(*assert_pin).await?;
+                match check_level(*assert_pin, *high, actual, deadline) {
+                    Some(result) => return result,
+                    None => tokio::time::sleep(ASSERT_POLL).await,
+                }
+            }
+        }
+    }
+}
+
+/// `Some` once an `assert_pin` has passed, or failed for good
+fn check_level(pin: u32, high: bool, actual: PinState, deadline: tokio::time::Instant) -> Option<Result<()>> {
+    let expected = if high { PinState::High } else { PinState::Low };
+    if actual == expected {
+        return Some(Ok(()));
+    }
+    if tokio::time::Instant::now() >= deadline {
+        return Some(Err(ApiError::PinAssertionFailed { pin, expected, actual }));
+    }
+    None
+}
+
+/// Run pin-level steps on a controller the caller already holds, stopping at the first
+/// that fails. Control steps need the whole server and are refused here.
+pub async fn run_on(gpio: &mut PinGuard<'_>, steps: &[Step]) -> Result<()> {
+    for step in steps {
+        match step {
+            Step::Control { .. } => {
+                return Err(ApiError::InvalidSequence(format!("'{}' can't run on the controller alone", step)));
+            }
+            Step::Wait { wait } => tokio::time::sleep(*wait).await,
+            Step::SetPin { set_pin, high } => gpio.set_pin(*set_pin, *high).await?,
+            Step::Pulse { pulse, duration } => gpio.pulse_pin(*pulse, *duration).await?,
+            Step::AssertPin { assert_pin, high, within } => {
+                let deadline = tokio::time::Instant::now() + *within;
+                loop {
+                    let actual = gpio.read_pin(*assert_pin).await?;
+                    match check_level(*assert_pin, *high, actual, deadline) {
+                        Some(result) => break result?,
+                        None => tokio::time::sleep(ASSERT_POLL).await,
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
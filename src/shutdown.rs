@@ -0,0 +1,85 @@
+﻿use chrono::Local;
+use std::time::Duration;
+
+use crate::{
+    config::SafeState,
+    gpio::PinState,
+    state::{AppState, ChangeSource},
+};
+
+/// How long queued syslog messages get to go out before the process exits
+const LOG_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Resolve on the first SIGINT (Ctrl-C) or SIGTERM
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = interrupt => tracing::info!("SIGINT received, shutting down"),
+        _ = terminate => tracing::info!("SIGTERM received, shutting down"),
+    }
+}
+
+/// Leave the hardware and logs in a clean state once the server has stopped serving.
+/// With `shutdown.apply_safe_states` devices with `safe_state = "off"` are driven off;
+/// otherwise every relay is left exactly as it is.
+pub async fn finish(state: &AppState) {
+    let config = state.config.load_full();
+
+    // Taking every output lock waits out any control command still in progress
+    let mut gpio = state.gpio_controller.lock(ChangeSource::Shutdown, &config.output_pins()).await;
+    if !config.shutdown.apply_safe_states {
+        tracing::info!("Leaving relays as they are");
+    } else if gpio.is_standby() {
+        tracing::info!("Standby node, leaving relays to the active node");
+    } else {
+        for zone in config.zones() {
+            for device in zone.devices.iter().filter(|d| d.safe_state == SafeState::Off) {
+                match crate::fireplace::drive_off(state, &mut gpio, device.pin).await {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        tracing::warn!("Drove {} in {} to its safe state (off)", device.name, zone.name);
+                        // Record the session end now; the recorder may not get to the event
+                        state
+                            .history
+                            .write()
+                            .await
+                            .observe(device.pin, &PinState::Low, ChangeSource::Shutdown, Local::now());
+                    }
+                    Err(e) => tracing::error!("Failed to drive {} in {} off: {}", device.name, zone.name, e),
+                }
+            }
+        }
+    }
+    drop(gpio);
+
+    // Nothing shows the fireplace's state any more, so don't leave the LED claiming one
+    if let Some(led) = &config.status_led {
+        let _ = state.gpio_controller.set_indicator(led.pin, false);
+    }
+
+    // Bank the on-time of anything left running so burn hours survive the restart
+    state.usage.write().await.checkpoint(Local::now());
+
+    tracing::info!("Shutdown complete");
+    let unsent = crate::logging::flush(LOG_FLUSH_TIMEOUT).await;
+    if unsent > 0 {
+        eprintln!("{} syslog messages were not sent before exit", unsent);
+    }
+}
@@ -0,0 +1,360 @@
+﻿use chrono::{DateTime, Duration, Local};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, DeviceKind, OutputMode},
+    error::{ApiError, Result},
+    gpio::PinState,
+    scheduler::{parse_cron, Schedule},
+};
+
+/// Longest window one simulation may cover
+pub const MAX_SIMULATION_HOURS: u32 = 168;
+
+/// Occurrences simulated per schedule, so an every-second cron can't run away
+const MAX_FIRES_PER_SCHEDULE: usize = 10_000;
+
+/// A change of the UPS status input at a point in simulated time
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PowerSample {
+    pub at: String,
+    pub on_battery: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionSource {
+    Schedule,
+    Timer,
+    Safety,
+    Power,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PinChange {
+    pub pin: u32,
+    pub state: PinState,
+}
+
+/// One thing the engines did (or tried to do) in simulated time
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulatedAction {
+    pub at: String,
+    pub source: ActionSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Uuid>,
+    pub description: String,
+    pub changes: Vec<PinChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Where a pin ended up and how long it was on
+#[derive(Debug, Clone, Serialize)]
+pub struct PinSummary {
+    pub pin: u32,
+    pub room: Option<String>,
+    pub device: Option<String>,
+    pub state: PinState,
+    pub on_minutes: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimulationReport {
+    pub start: String,
+    pub end: String,
+    pub actions: Vec<SimulatedAction>,
+    pub pins: Vec<PinSummary>,
+}
+
+enum Event {
+    Fire(usize),
+    AutoOff { room: String, device: String, pins: Vec<u32>, token: u64 },
+    SafetyOff { pin: u32, since: DateTime<Local> },
+    Power(bool),
+}
+
+/// Schedules, timers, the safety watchdog and battery load shedding, replayed against
+/// simulated pin states in simulated time
+struct Simulation<'a> {
+    config: &'a Config,
+    schedules: &'a [Schedule],
+    now: DateTime<Local>,
+    queue: BTreeMap<(DateTime<Local>, u64), Event>,
+    seq: u64,
+    pins: BTreeMap<u32, bool>,
+    on_since: HashMap<u32, DateTime<Local>>,
+    on_time: HashMap<u32, Duration>,
+    timers: HashMap<(String, String), u64>,
+    on_battery: bool,
+    actions: Vec<SimulatedAction>,
+}
+
+/// Replay `schedules` against `config` from `start` for `hours`, without touching hardware.
+/// Pins start low unless listed in `initial_high`.
+pub fn run(
+    config: &Config,
+    schedules: &[Schedule],
+    start: DateTime<Local>,
+    hours: u32,
+    initial_high: &[u32],
+    power_trace: &[PowerSample],
+) -> Result<SimulationReport> {
+    if hours == 0 || hours > MAX_SIMULATION_HOURS {
+        return Err(ApiError::InvalidSimulation(format!(
+            "Invalid hours. Expected 1-{}",
+            MAX_SIMULATION_HOURS
+        )));
+    }
+    let end = start + Duration::hours(hours as i64);
+
+    let mut sim = Simulation {
+        config,
+        schedules,
+        now: start,
+        queue: BTreeMap::new(),
+        seq: 0,
+        pins: BTreeMap::new(),
+        on_since: HashMap::new(),
+        on_time: HashMap::new(),
+        timers: HashMap::new(),
+        on_battery: false,
+        actions: Vec::new(),
+    };
+
+    for zone in config.zones() {
+        for (_, pin) in zone.named_pins() {
+            sim.pins.insert(pin, false);
+        }
+    }
+    for group in &config.groups {
+        for pin in &group.pins {
+            sim.pins.insert(*pin, false);
+        }
+    }
+    for pin in initial_high {
+        sim.set(*pin, true);
+    }
+
+    for (index, schedule) in schedules.iter().enumerate().filter(|(_, s)| s.enabled) {
+        // Persisted schedules the current config rejects never start, so never fire here
+        let Ok(cron) = parse_cron(&schedule.cron) else {
+            continue;
+        };
+        for at in cron.after(&start).take_while(|t| *t <= end).take(MAX_FIRES_PER_SCHEDULE) {
+            sim.push(at, Event::Fire(index));
+        }
+    }
+    for sample in power_trace {
+        let at = parse_time(&sample.at)?;
+        sim.push(at, Event::Power(sample.on_battery));
+    }
+
+    while let Some(((at, _), event)) = sim.queue.pop_first() {
+        if at > end {
+            break;
+        }
+        sim.now = at;
+        sim.handle(event);
+    }
+
+    sim.now = end;
+    let pins = sim.summary();
+    Ok(SimulationReport {
+        start: start.to_rfc3339(),
+        end: end.to_rfc3339(),
+        actions: sim.actions,
+        pins,
+    })
+}
+
+pub fn parse_time(value: &str) -> Result<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Local))
+        .map_err(|e| ApiError::InvalidSimulation(format!("Invalid time '{}': {}", value, e)))
+}
+
+impl Simulation<'_> {
+    fn push(&mut self, at: DateTime<Local>, event: Event) {
+        self.seq += 1;
+        self.queue.insert((at, self.seq), event);
+    }
+
+    /// Drive a pin, returning the change if its state actually changed
+    fn set(&mut self, pin: u32, high: bool) -> Option<PinChange> {
+        let was_high = self.pins.insert(pin, high).unwrap_or(false);
+        if was_high == high {
+            return None;
+        }
+
+        if high {
+            self.on_since.insert(pin, self.now);
+            // The watchdog only looks after fireplaces
+            let fireplace = self
+                .config
+                .find_pin(pin)
+                .is_some_and(|(_, device)| device.kind == DeviceKind::Fireplace);
+            if let (true, Some(limit)) = (fireplace, self.config.safety.max_runtime) {
+                let since = self.now;
+                let limit = Duration::from_std(limit).unwrap_or(Duration::MAX);
+                self.push(since + limit, Event::SafetyOff { pin, since });
+            }
+        } else if let Some(since) = self.on_since.remove(&pin) {
+            *self.on_time.entry(pin).or_insert_with(Duration::zero) += self.now - since;
+        }
+
+        Some(PinChange {
+            pin,
+            state: if high { PinState::High } else { PinState::Low },
+        })
+    }
+
+    fn log(&mut self, source: ActionSource, schedule: Option<Uuid>, description: String, changes: Vec<PinChange>, error: Option<String>) {
+        self.actions.push(SimulatedAction {
+            at: self.now.to_rfc3339(),
+            source,
+            schedule,
+            description,
+            changes,
+            error,
+        });
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Fire(index) => self.fire(index),
+            Event::AutoOff { room, device, pins, token } => {
+                let key = (room, device);
+                if self.timers.get(&key) != Some(&token) {
+                    return;
+                }
+                self.timers.remove(&key);
+                let changes = pins.iter().filter_map(|pin| self.set(*pin, false)).collect();
+                self.log(ActionSource::Timer, None, format!("Auto-off timer for {} in {}", key.1, key.0), changes, None);
+            }
+            Event::SafetyOff { pin, since } => {
+                if self.on_since.get(&pin) != Some(&since) {
+                    return;
+                }
+                let limit = self.config.safety.max_runtime.unwrap_or_default();
+                let changes = self.set(pin, false).into_iter().collect();
+                self.log(
+                    ActionSource::Safety,
+                    None,
+                    format!("Fireplace on pin {} on for {}, safety auto-off", pin, humantime::format_duration(limit)),
+                    changes,
+                    None,
+                );
+            }
+            Event::Power(on_battery) => {
+                if on_battery == self.on_battery {
+                    return;
+                }
+                self.on_battery = on_battery;
+                if !on_battery {
+                    self.log(ActionSource::Power, None, "Mains power restored".to_string(), Vec::new(), None);
+                    return;
+                }
+
+                let shed = self.config.power.as_ref().is_some_and(|p| p.shed_fan);
+                let fans: Vec<u32> = self
+                    .config
+                    .zones()
+                    .flat_map(|zone| zone.of_kind(DeviceKind::Fan))
+                    .map(|fan| fan.pin)
+                    .collect();
+                let changes = if shed {
+                    fans.into_iter().filter_map(|pin| self.set(pin, false)).collect()
+                } else {
+                    Vec::new()
+                };
+                let description = if shed {
+                    "Mains power lost, shedding fan load"
+                } else {
+                    "Mains power lost"
+                };
+                self.log(ActionSource::Power, None, description.to_string(), changes, None);
+            }
+        }
+    }
+
+    /// Apply a schedule's command the way the control endpoint would
+    fn fire(&mut self, index: usize) {
+        let schedule = &self.schedules[index];
+        let (id, action, device) = (schedule.id, schedule.action.to_uppercase(), schedule.device.clone());
+        let duration_minutes = schedule.duration_minutes;
+        let label = schedule.name.clone().unwrap_or_else(|| id.to_string());
+
+        let zone = match self.config.zone(schedule.room.as_deref()) {
+            Ok(zone) => zone,
+            Err(e) => {
+                let description = format!("Schedule '{}' {} {}", label, action, device);
+                self.log(ActionSource::Schedule, Some(id), description, Vec::new(), Some(e.to_string()));
+                return;
+            }
+        };
+        let room = zone.name.to_string();
+        let description = format!("Schedule '{}' {} {} in {}", label, action, device, room);
+
+        let changes = if let Some(group) = self.config.group(&room, &device) {
+            let pins = group.pins.clone();
+            pins.into_iter().filter_map(|pin| self.set(pin, action == "ON")).collect()
+        } else {
+            let Some(target) = zone.device(&device) else {
+                let error = ApiError::UnknownDevice(device.clone()).to_string();
+                self.log(ActionSource::Schedule, Some(id), description, Vec::new(), Some(error));
+                return;
+            };
+            // Drive the relay as the control endpoint would; a momentary press leaves no state
+            let pin = target.pin;
+            match target.mode {
+                OutputMode::Toggle => {
+                    let high = !self.pins.get(&pin).copied().unwrap_or(false);
+                    self.set(pin, high).into_iter().collect()
+                }
+                OutputMode::Latch => self.set(pin, action == "ON").into_iter().collect(),
+                OutputMode::Pulse => Vec::new(),
+            }
+        };
+
+        // A new command supersedes any pending auto-off for the device
+        let key = (room.clone(), device.to_lowercase());
+        match duration_minutes {
+            Some(minutes) => {
+                self.seq += 1;
+                let token = self.seq;
+                self.timers.insert(key, token);
+                let pins = self.config.device_pins(&room, &device).unwrap_or_default();
+                let at = self.now + Duration::minutes(minutes as i64);
+                self.push(at, Event::AutoOff { room, device: device.to_lowercase(), pins, token });
+            }
+            None => {
+                self.timers.remove(&key);
+            }
+        }
+
+        self.log(ActionSource::Schedule, Some(id), description, changes, None);
+    }
+
+    fn summary(&mut self) -> Vec<PinSummary> {
+        let pins: Vec<(u32, bool)> = self.pins.iter().map(|(pin, high)| (*pin, *high)).collect();
+        pins.into_iter()
+            .map(|(pin, high)| {
+                let mut on_time = self.on_time.get(&pin).copied().unwrap_or_else(Duration::zero);
+                if let Some(since) = self.on_since.get(&pin) {
+                    on_time += self.now - *since;
+                }
+                let named = self.config.find_pin(pin);
+                PinSummary {
+                    pin,
+                    room: named.as_ref().map(|(zone, _)| zone.name.to_string()),
+                    device: named.map(|(_, device)| device.name.clone()),
+                    state: if high { PinState::High } else { PinState::Low },
+                    on_minutes: on_time.num_minutes(),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,40 @@
+﻿use chrono::Local;
+
+use crate::{
+    config::SafeState,
+    gpio::PinState,
+    state::{AppState, ChangeSource},
+};
+
+/// Reconcile with the relays as the server finds them. Devices already on are fed into
+/// the usage tracker and session history, then devices with `safe_state = "off"` are
+/// driven off. Runs once at startup, before anything else can switch a pin.
+pub async fn reconcile(state: &AppState) {
+    let config = state.config.load_full();
+    let now = Local::now();
+    let mut gpio = state.gpio_controller.lock(ChangeSource::Startup, &config.output_pins()).await;
+
+    for zone in config.zones() {
+        for device in zone.devices {
+            let found = gpio.get_pin_state(device.pin);
+            tracing::info!("{} in {} (pin {}) is {:?} at startup", device.name, zone.name, device.pin, found);
+            if found == PinState::High {
+                state.usage.write().await.observe(device.pin, &found, ChangeSource::Startup, now);
+                state.history.write().await.resume(device.pin, now);
+            }
+
+            if device.safe_state != SafeState::Off || found == PinState::Low {
+                continue;
+            }
+            if gpio.is_standby() {
+                // The active node of a failover pair owns the relays
+                tracing::info!("Standby node, leaving {} in {} as it is", device.name, zone.name);
+                continue;
+            }
+            match gpio.set_pin(device.pin, false).await {
+                Ok(()) => tracing::warn!("Drove {} in {} to its safe state (off)", device.name, zone.name),
+                Err(e) => tracing::error!("Failed to drive {} in {} off at startup: {}", device.name, zone.name, e),
+            }
+        }
+    }
+}
@@ -1,8 +1,201 @@
-﻿use std::sync::Arc;
-use tokio::sync::Mutex;
+﻿use arc_swap::ArcSwap;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex, RwLock};
+
+use crate::{config::Config, error::ApiError, gpio::PinState};
+
+/// Events buffered per subscriber before a slow one starts missing them
+pub const EVENT_BUS_CAPACITY: usize = 256;
+
+/// What drove a pin change
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeSource {
+    /// The modern REST API, directly or as an asynchronous command
+    #[default]
+    Api,
+    /// The Python-compatible legacy endpoint
+    Legacy,
+    Schedule,
+    /// A command on an MQTT `.../set` topic
+    Mqtt,
+    /// A command sent to the Telegram bot
+    Telegram,
+    /// A "turn off after N minutes" timer
+    Timer,
+    /// The max-runtime watchdog
+    Safety,
+    /// Load shedding on battery power
+    Power,
+    /// Outputs restored by a standby taking over from its failover peer
+    Failover,
+    /// Relays found on, or driven to their safe state, when the server started
+    Startup,
+    /// Relays driven to their safe state as the server stopped
+    Shutdown,
+    /// Every output driven off by POST /api/v1/emergency_stop
+    EmergencyStop,
+    /// A device kept running by an `[[interlocks]]` rule
+    Interlock,
+    /// The thermostat holding its setpoint
+    Thermostat,
+    /// The high-temperature cutoff, `safety.max_temperature_c`
+    Overheat,
+    /// A fan run off its firebox sensor by a `[[fan_control]]` entry
+    FanControl,
+    /// A step of a scene activated through POST /api/v1/scenes/{name}/activate
+    Scene,
+    /// An action of an automation rule
+    Rule,
+    /// Devices turned off by an `[[idle_off]]` motion sensor seeing no one
+    Idle,
+    /// The test pin exercised by a GPIO self-test
+    SelfTest,
+}
+
+/// A state change published on the event bus
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StateEvent {
+    /// A pin's logical state changed, whatever path drove it
+    PinChanged {
+        pin: u32,
+        state: PinState,
+        source: ChangeSource,
+        timestamp: String,
+    },
+    /// A fireplace's monitor pin confirmed, or failed to confirm, that it lit
+    IgnitionChecked {
+        pin: u32,
+        verified: bool,
+        timestamp: String,
+    },
+    /// A fireplace moved through its ignition cycle
+    FireplaceChanged {
+        room: String,
+        device: String,
+        state: crate::fireplace::FireplaceState,
+        timestamp: String,
+    },
+    /// An `[[inputs]]` pin became active or inactive, after debouncing
+    InputChanged {
+        room: String,
+        input: String,
+        pin: u32,
+        active: bool,
+        timestamp: String,
+    },
+    /// Writing a pin failed in the GPIO backend
+    GpioFailed {
+        pin: u32,
+        error: String,
+        source: ChangeSource,
+        timestamp: String,
+    },
+    /// A sensor passed `safety.max_temperature_c`, or the room cooled and the cutoff reset
+    TemperatureCutoff {
+        room: String,
+        sensor: String,
+        temperature_c: f64,
+        tripped: bool,
+        timestamp: String,
+    },
+    /// POST /api/v1/emergency_stop locked control and drove every output off
+    EmergencyStopped {
+        by: Option<String>,
+        pins_off: Vec<u32>,
+        timestamp: String,
+    },
+    /// The config was reloaded; pin mappings may have changed
+    ConfigReloaded {
+        changed: Vec<String>,
+        config_generation: u64,
+        timestamp: String,
+    },
+}
 
 #[derive(Clone)]
 pub struct AppState {
-    pub config: Arc<crate::config::Config>,
-    pub gpio_controller: Arc<Mutex<crate::gpio::GpioController>>,
+    pub config: Arc<ArcSwap<Config>>,
+    pub config_path: Arc<String>,
+    /// Pin reads, and per-pin locks for writers
+    pub gpio_controller: Arc<crate::gpio::GpioController>,
+    pub power: Arc<RwLock<crate::power::PowerStatus>>,
+    pub faults: Arc<RwLock<crate::fault::FaultRegistry>>,
+    pub safety_timer: Arc<Mutex<crate::safety::SafetyTimer>>,
+    pub timers: Arc<Mutex<crate::timers::TimerManager>>,
+    /// Devices kept on by an interlock after their trigger turned off
+    pub cooldowns: Arc<RwLock<crate::interlock::Cooldowns>>,
+    pub fireplaces: Arc<RwLock<crate::fireplace::Fireplaces>>,
+    /// Latest reading of every `[[sensors]]` entry
+    pub sensors: Arc<RwLock<crate::sensors::Sensors>>,
+    /// Debounced state of every `[[inputs]]` entry
+    pub inputs: Arc<RwLock<crate::inputs::Inputs>>,
+    /// Rooms counting down to turning off for lack of motion
+    pub idle_timers: Arc<RwLock<crate::motion::IdleTimers>>,
+    /// Sensor readings kept for charts
+    pub sensor_history: Arc<RwLock<crate::sensor_history::SensorHistory>>,
+    pub thermostat: Arc<Mutex<crate::thermostat::ThermostatStore>>,
+    /// Rooms held off by the high-temperature cutoff
+    pub cutoffs: Arc<RwLock<crate::overheat::Cutoffs>>,
+    pub scheduler: Arc<Mutex<crate::scheduler::Scheduler>>,
+    /// Scenes created through the API
+    pub scenes: Arc<RwLock<crate::scenes::SceneStore>>,
+    /// Rules created through the API, and when each rule last ran
+    pub rules: Arc<RwLock<crate::rules::RuleStore>>,
+    pub commands: Arc<Mutex<crate::commands::CommandRegistry>>,
+    pub confirmations: Arc<Mutex<crate::confirmation::ConfirmationStore>>,
+    pub aliases: Arc<RwLock<crate::aliases::AliasStore>>,
+    /// Present while the server is waiting for its first config
+    pub setup: Arc<RwLock<Option<crate::api::setup::SetupSession>>>,
+    /// A pushed config shadowing the live one until promoted
+    pub canary: Arc<Mutex<Option<crate::canary::Canary>>>,
+    pub logs: crate::logging::LogBuffer,
+    pub audit: Arc<Mutex<crate::audit::AuditLog>>,
+    pub command_log: Arc<Mutex<crate::command_log::CommandLog>>,
+    pub rate_limiter: Arc<Mutex<crate::rate_limit::RateLimiter>>,
+    pub usage: Arc<RwLock<crate::usage::UsageTracker>>,
+    pub history: Arc<RwLock<crate::history::SessionHistory>>,
+    /// Set by an emergency stop or POST /api/v1/lock; control is refused until it is released
+    pub lock: Arc<RwLock<crate::lockout::LockStore>>,
+    pub failover: Arc<RwLock<crate::failover::FailoverNode>>,
+    /// Every state change, for live consumers (WebSocket, SSE, ...)
+    pub events: broadcast::Sender<StateEvent>,
+    /// When the process started, for uptime
+    pub started_at: std::time::Instant,
+}
+
+impl AppState {
+    /// Re-read and validate the config file, then atomically swap it in under the next
+    /// generation. Returns the dotted paths of every setting that changed.
+    pub async fn reload_config(&self) -> crate::error::Result<Vec<String>> {
+        let mut new_config = Config::load(&self.config_path).map_err(|e| match e {
+            ApiError::ConfigError(msg) => ApiError::InvalidConfig(msg),
+            other => other,
+        })?;
+
+        let old_config = self.config.load_full();
+        let changed = old_config.diff(&new_config);
+        new_config.generation = old_config.generation + 1;
+        let generation = new_config.generation;
+
+        // Keep the GPIO layer's pin polarity and numbering in step with the new config
+        self.gpio_controller.reconfigure(&new_config);
+        self.config.store(Arc::new(new_config));
+
+        tracing::info!("Configuration reloaded from {} ({} changes)", self.config_path, changed.len());
+        self.publish(StateEvent::ConfigReloaded {
+            changed: changed.clone(),
+            config_generation: generation,
+            timestamp: Local::now().to_rfc3339(),
+        });
+        Ok(changed)
+    }
+
+    /// Publish an event; having no subscribers is not an error
+    pub fn publish(&self, event: StateEvent) {
+        let _ = self.events.send(event);
+    }
 }
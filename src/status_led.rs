@@ -0,0 +1,179 @@
+﻿use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    config::{Config, DeviceConfig, DeviceKind},
+    fireplace::FireplaceState,
+    lockout::LockReason,
+    state::{AppState, StateEvent},
+};
+
+/// How often the pattern is worked out afresh, for changes that aren't published as
+/// events such as a fault being reset
+const RESYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `[status_led]`: an LED showing a fireplace's state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusLedConfig {
+    pub pin: u32,
+    /// Defaults to the primary room
+    #[serde(default)]
+    pub room: Option<String>,
+    /// The fireplace shown; defaults to the room's first
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Time on, and then off, while igniting or cooling
+    #[serde(default = "default_blink", alias = "blink_ms", with = "crate::duration::millis")]
+    pub blink: Duration,
+    /// Time on, and then off, after an error
+    #[serde(default = "default_fast_blink", alias = "fast_blink_ms", with = "crate::duration::millis")]
+    pub fast_blink: Duration,
+}
+
+fn default_blink() -> Duration {
+    Duration::from_millis(500)
+}
+
+fn default_fast_blink() -> Duration {
+    Duration::from_millis(100)
+}
+
+impl StatusLedConfig {
+    pub fn validate(&self, config: &Config) -> Result<(), String> {
+        let zone = config.zone(self.room.as_deref()).map_err(|e| format!("status_led: {}", e))?;
+        match (&self.device, self.fireplace(config)) {
+            (Some(name), None) => return Err(format!("status_led: unknown device '{}' in '{}'", name, zone.name)),
+            (None, None) => return Err(format!("status_led: '{}' has no fireplace", zone.name)),
+            (_, Some((_, device))) if device.kind != DeviceKind::Fireplace => {
+                return Err(format!("status_led: '{}' is not a fireplace", device.name));
+            }
+            _ => {}
+        }
+        if self.blink < Duration::from_millis(20) || self.fast_blink < Duration::from_millis(20) {
+            return Err("status_led: blink and fast_blink must be at least 20ms".to_string());
+        }
+        Ok(())
+    }
+
+    /// The room of the fireplace shown, and the fireplace
+    fn fireplace<'a>(&self, config: &'a Config) -> Option<(&'a str, &'a DeviceConfig)> {
+        let zone = config.zone(self.room.as_deref()).ok()?;
+        let device = match &self.device {
+            Some(name) => zone.device(name)?,
+            None => zone.of_kind(DeviceKind::Fireplace).next()?,
+        };
+        Some((zone.name, device))
+    }
+}
+
+/// What the LED shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    Off,
+    /// The fireplace is on
+    Solid,
+    /// Igniting or cooling down
+    Blink,
+    /// A latched fault, a GPIO failure, the over-temperature cutoff or an emergency stop
+    FastBlink,
+}
+
+/// Work out the pattern for the fireplace's current state
+async fn pattern(state: &AppState, config: &Config, led: &StatusLedConfig, gpio_failed: bool) -> Pattern {
+    let Some((room, device)) = led.fireplace(config) else {
+        return Pattern::Off;
+    };
+    let faulted = state.faults.read().await.get(room, &device.name).is_some();
+    let too_hot = state.cutoffs.read().await.list().iter().any(|c| c.room == room);
+    let stopped = state.lock.read().await.current().is_some_and(|l| l.reason == LockReason::EmergencyStop);
+    if faulted || too_hot || stopped || gpio_failed {
+        return Pattern::FastBlink;
+    }
+    match state.fireplaces.read().await.status(room, &device.name).state {
+        FireplaceState::Off => Pattern::Off,
+        FireplaceState::On => Pattern::Solid,
+        FireplaceState::Igniting | FireplaceState::Cooling => Pattern::Blink,
+    }
+}
+
+/// Keep the `[status_led]` in step with its fireplace, following the event bus. Follows
+/// config reloads; an LED moved to another pin, or removed, is switched off.
+pub fn spawn_driver(state: AppState) {
+    // Subscribe before returning so no change made after startup is missed
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        let mut resync = tokio::time::interval(RESYNC_INTERVAL);
+        let mut blink = tokio::time::interval(default_blink());
+        // The LED's pin and whether it is lit, once it has been written
+        let mut lit: Option<(u32, bool)> = None;
+        let mut pattern_now = Pattern::Off;
+        // A write to the fireplace failed and nothing has switched it since
+        let mut gpio_failed = false;
+        // The LED can't be driven; warned about once until it can again
+        let mut failing = false;
+        loop {
+            let config = state.config.load_full();
+            let fireplace_pin = config.status_led.as_ref().and_then(|led| led.fireplace(&config)).map(|(_, d)| d.pin);
+
+            let blinked = tokio::select! {
+                event = events.recv() => {
+                    match event {
+                        Ok(StateEvent::GpioFailed { pin, .. }) if Some(pin) == fireplace_pin => gpio_failed = true,
+                        Ok(StateEvent::PinChanged { pin, .. }) if Some(pin) == fireplace_pin => gpio_failed = false,
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(skipped)) => tracing::warn!("Status LED missed {} events", skipped),
+                        Err(RecvError::Closed) => return,
+                    }
+                    false
+                }
+                _ = resync.tick() => false,
+                _ = blink.tick() => true,
+            };
+
+            let Some(led) = &config.status_led else {
+                if let Some((pin, true)) = lit.take() {
+                    let _ = state.gpio_controller.set_indicator(pin, false);
+                }
+                continue;
+            };
+            if lit.is_some_and(|(pin, _)| pin != led.pin) {
+                if let Some((pin, true)) = lit.take() {
+                    let _ = state.gpio_controller.set_indicator(pin, false);
+                }
+            }
+
+            let next = pattern(&state, &config, led, gpio_failed).await;
+            if next != pattern_now {
+                let period = if next == Pattern::FastBlink { led.fast_blink } else { led.blink };
+                blink = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                pattern_now = next;
+            }
+            let on = match pattern_now {
+                Pattern::Off => false,
+                Pattern::Solid => true,
+                // Toggle on each blink tick, and hold in between
+                Pattern::Blink | Pattern::FastBlink => match lit {
+                    Some((_, on)) if blinked => !on,
+                    Some((_, on)) => on,
+                    None => true,
+                },
+            };
+            if lit != Some((led.pin, on)) {
+                match state.gpio_controller.set_indicator(led.pin, on) {
+                    Ok(()) => {
+                        lit = Some((led.pin, on));
+                        failing = false;
+                    }
+                    Err(e) => {
+                        if !failing {
+                            tracing::warn!("Failed to drive the status LED on pin {}: {}", led.pin, e);
+                        }
+                        lit = None;
+                        failing = true;
+                    }
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,240 @@
+﻿use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+    api::{handlers, models::FireplaceControlRequest},
+    commands::Progress,
+    error::{self, ApiError},
+    fireplace::FireplaceState,
+    gpio::PinState,
+    secrets::Secret,
+    state::{AppState, ChangeSource},
+};
+
+/// How long each getUpdates call waits for a message before returning empty
+const POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wait before polling again after the Bot API can't be reached
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// `[telegram]`: a bot that reports status, sends alerts and takes on/off commands
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelegramConfig {
+    /// Bot token from @BotFather
+    pub token: Secret,
+    /// Chats the bot answers and alerts; messages from anywhere else are ignored
+    pub allowed_chats: Vec<i64>,
+    /// Also deliver `[notifications]` alerts to the allowed chats
+    #[serde(default = "default_alerts")]
+    pub alerts: bool,
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_alerts() -> bool {
+    true
+}
+
+fn default_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+impl TelegramConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.allowed_chats.is_empty() {
+            return Err("telegram.allowed_chats must list at least one chat ID".to_string());
+        }
+        if !self.api_url.starts_with("http://") && !self.api_url.starts_with("https://") {
+            return Err(format!("telegram.api_url: '{}' is not an http:// or https:// URL", self.api_url));
+        }
+        Ok(())
+    }
+
+    fn method_url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.api_url.trim_end_matches('/'), self.token.expose(), method)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Updates {
+    result: Vec<Update>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+/// Long-poll the Bot API for commands from the allowed chats
+pub fn spawn_bot(state: AppState) {
+    let Some(telegram) = state.config.load().telegram.clone() else {
+        return;
+    };
+    tracing::info!("Telegram bot answering {} chat(s)", telegram.allowed_chats.len());
+    let client = reqwest::Client::builder()
+        .timeout(POLL_TIMEOUT + Duration::from_secs(10))
+        .build()
+        .unwrap_or_default();
+
+    tokio::spawn(async move {
+        let mut offset = 0;
+        loop {
+            let request = client.get(telegram.method_url("getUpdates")).query(&[
+                ("offset", offset.to_string()),
+                ("timeout", POLL_TIMEOUT.as_secs().to_string()),
+                ("allowed_updates", r#"["message"]"#.to_string()),
+            ]);
+            let updates = match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(response) => response.json::<Updates>().await,
+                Err(e) => Err(e),
+            };
+            let updates = match updates {
+                Ok(updates) => updates.result,
+                Err(e) => {
+                    // The token is part of the URL, so keep it out of the log
+                    tracing::warn!("Telegram getUpdates failed: {}", e.without_url());
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+
+            for update in updates {
+                offset = offset.max(update.update_id + 1);
+                let Some(Message { chat, text: Some(text) }) = update.message else {
+                    continue;
+                };
+                if !telegram.allowed_chats.contains(&chat.id) {
+                    tracing::warn!("Ignoring Telegram message from chat {}", chat.id);
+                    continue;
+                }
+                // Replies are awaited so they arrive in the order the messages were sent
+                let reply = answer(&state, &text).await;
+                post(&client, &telegram, chat.id, reply).await;
+            }
+        }
+    });
+}
+
+/// Send an alert to every allowed chat without waiting
+pub fn alert(client: &reqwest::Client, telegram: &TelegramConfig, title: &str, message: &str) {
+    for chat in telegram.allowed_chats.clone() {
+        let (client, telegram) = (client.clone(), telegram.clone());
+        let text = format!("{}\n{}", title, message);
+        tokio::spawn(async move { post(&client, &telegram, chat, text).await });
+    }
+}
+
+async fn post(client: &reqwest::Client, telegram: &TelegramConfig, chat: i64, text: String) {
+    let request = client
+        .post(telegram.method_url("sendMessage"))
+        .json(&serde_json::json!({ "chat_id": chat, "text": text }));
+    if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+        tracing::warn!("Telegram message to chat {} failed: {}", chat, e.without_url());
+    }
+}
+
+/// The reply to one message: `/status`, `/help`, or `/<device> on|off`
+async fn answer(state: &AppState, text: &str) -> String {
+    let mut words = text.split_whitespace();
+    let Some(command) = words.next().and_then(|c| c.strip_prefix('/')) else {
+        return help();
+    };
+    // In group chats commands arrive as /command@botname
+    let command = command.split('@').next().unwrap_or_default();
+    let args: Vec<&str> = words.collect();
+
+    match (command, args.as_slice()) {
+        ("start" | "help", _) => help(),
+        ("status", _) => status(state).await,
+        (device, [action, rest @ ..]) if matches!(action.to_uppercase().as_str(), "ON" | "OFF") => {
+            let token = rest.first().copied();
+            match control(state, device, &action.to_uppercase(), token).await {
+                Ok(reply) => reply,
+                Err(e) => format!("Refused: {}", e),
+            }
+        }
+        _ => format!("Unknown command /{}. Send /help", command),
+    }
+}
+
+fn help() -> String {
+    "/status - every device's state\n\
+     /<device> on|off - switch a device in the primary room, e.g. /fireplace on"
+        .to_string()
+}
+
+async fn status(state: &AppState) -> String {
+    let config = state.config.load_full();
+    let gpio = &state.gpio_controller;
+    let fireplaces = state.fireplaces.read().await;
+    let mut lines = Vec::new();
+    for zone in config.zones() {
+        lines.push(format!("{}:", zone.name));
+        for device in zone.devices {
+            let pin_state = match gpio.get_pin_state(device.pin) {
+                PinState::High => "ON",
+                PinState::Low => "OFF",
+                PinState::Unknown => "UNKNOWN",
+            };
+            let fireplace = fireplaces.status(zone.name, &device.name).state;
+            let detail = match device.kind {
+                crate::config::DeviceKind::Fireplace if fireplace != FireplaceState::Off => {
+                    format!(" ({:?})", fireplace).to_lowercase()
+                }
+                _ => String::new(),
+            };
+            lines.push(format!("  {}: {}{}", device.name, pin_state, detail));
+        }
+    }
+    if let Some(lock) = state.lock.read().await.current() {
+        lines.push(format!("Control is locked ({:?}) since {}", lock.reason, lock.since.to_rfc3339()));
+    }
+    lines.join("\n")
+}
+
+/// Run `/<device> on|off` through the same checks as a REST control request
+async fn control(state: &AppState, device: &str, action: &str, token: Option<&str>) -> error::Result<String> {
+    state.lock.read().await.check(ChangeSource::Telegram)?;
+    let config = state.config.load_full();
+    let zone = config.zone(None)?;
+    let device = state.aliases.read().await.resolve(zone.name, device).unwrap_or_else(|| device.to_string());
+    if config.device_pins(zone.name, &device).is_none() {
+        return Err(ApiError::UnknownDevice(device));
+    }
+
+    if let Some(pending) = handlers::check_confirmation(state, &config, zone, &device, action, token, "token").await? {
+        return Ok(format!(
+            "Confirm by sending /{} on {} before {}",
+            device, pending.confirmation_token, pending.expires_at
+        ));
+    }
+
+    let req = FireplaceControlRequest {
+        action: action.to_string(),
+        device: device.clone(),
+        room: Some(zone.name.to_string()),
+        cycles: None,
+        cycle_delay_ms: None,
+        duration_minutes: None,
+        run_async: false,
+        confirmation_token: None,
+        source: ChangeSource::Telegram,
+    };
+    let response = handlers::run_control(state, req, &Progress::none()).await?;
+    Ok(match response.verified {
+        Some(false) => format!("{} is {} (not confirmed by its monitor pin)", device, response.action),
+        _ => format!("{} is {}", device, response.action),
+    })
+}
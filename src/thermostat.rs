@@ -0,0 +1,329 @@
+﻿use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{
+    api::{handlers, models::FireplaceControlRequest},
+    commands::Progress,
+    config::{Config, DeviceKind},
+    error::{ApiError, Result},
+    fireplace::FireplaceState,
+    state::{AppState, ChangeSource},
+};
+
+/// How often the thermostat compares the room to its setpoint
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// `[thermostat]`: hold a room at a setpoint by switching its fireplace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThermostatConfig {
+    /// The `[[sensors]]` entry measuring the room; the fireplace is in the sensor's room
+    pub sensor: String,
+    #[serde(default = "default_device")]
+    pub device: String,
+    /// The fireplace lights at setpoint - hysteresis and goes out at setpoint + hysteresis
+    #[serde(default = "default_hysteresis")]
+    pub hysteresis_c: f64,
+    /// Used until a setpoint is set through the API
+    #[serde(default = "default_setpoint")]
+    pub setpoint_c: f64,
+    #[serde(default = "default_min_setpoint")]
+    pub min_setpoint_c: f64,
+    #[serde(default = "default_max_setpoint")]
+    pub max_setpoint_c: f64,
+}
+
+fn default_device() -> String {
+    "fireplace".to_string()
+}
+
+fn default_hysteresis() -> f64 {
+    0.5
+}
+
+fn default_setpoint() -> f64 {
+    20.0
+}
+
+fn default_min_setpoint() -> f64 {
+    5.0
+}
+
+fn default_max_setpoint() -> f64 {
+    30.0
+}
+
+impl ThermostatConfig {
+    pub fn validate(&self, config: &Config) -> std::result::Result<(), String> {
+        let sensor = config
+            .sensor(&self.sensor)
+            .ok_or_else(|| format!("thermostat.sensor: unknown sensor '{}'", self.sensor))?;
+        let room = sensor.room(config);
+        let device = config
+            .zone(Some(room))
+            .ok()
+            .and_then(|zone| zone.device(&self.device))
+            .ok_or_else(|| format!("thermostat.device: unknown device '{}' in '{}'", self.device, room))?;
+        if device.kind != DeviceKind::Fireplace {
+            return Err(format!("thermostat.device: '{}' is not a fireplace", device.name));
+        }
+        let values = [self.hysteresis_c, self.setpoint_c, self.min_setpoint_c, self.max_setpoint_c];
+        if !values.iter().all(|v| v.is_finite()) {
+            return Err("thermostat: temperatures must be finite numbers".to_string());
+        }
+        if self.hysteresis_c <= 0.0 {
+            return Err("thermostat.hysteresis_c must be greater than zero".to_string());
+        }
+        if self.min_setpoint_c >= self.max_setpoint_c {
+            return Err("thermostat.min_setpoint_c must be below max_setpoint_c".to_string());
+        }
+        if !(self.min_setpoint_c..=self.max_setpoint_c).contains(&self.setpoint_c) {
+            return Err(format!(
+                "thermostat.setpoint_c must be between {} and {}",
+                self.min_setpoint_c, self.max_setpoint_c
+            ));
+        }
+        Ok(())
+    }
+
+    /// The room and configured name of the fireplace the thermostat switches
+    fn target(&self, config: &Config) -> Option<(String, String)> {
+        let room = config.sensor(&self.sensor)?.room(config);
+        let device = config.zone(Some(room)).ok()?.device(&self.device)?;
+        Some((room.to_string(), device.name.clone()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThermostatMode {
+    /// The thermostat leaves the fireplace alone
+    #[default]
+    Off,
+    /// The fireplace is switched to hold the setpoint
+    Heat,
+}
+
+/// What is changed through the API and kept across restarts
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Settings {
+    mode: ThermostatMode,
+    /// Null until set through the API; `thermostat.setpoint_c` applies until then
+    setpoint_c: Option<f64>,
+}
+
+/// The thermostat as reported by GET /api/v1/thermostat
+#[derive(Debug, Serialize)]
+pub struct ThermostatStatus {
+    pub mode: ThermostatMode,
+    pub setpoint_c: f64,
+    pub hysteresis_c: f64,
+    pub sensor: String,
+    pub room: String,
+    pub device: String,
+    /// Null while the sensor has no recent reading
+    pub temperature_c: Option<f64>,
+    /// The fireplace is igniting or lit
+    pub heating: bool,
+    /// Why the thermostat can't currently do its job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Thermostat mode and setpoint, persisted as JSON, and what the controller last did
+pub struct ThermostatStore {
+    path: PathBuf,
+    settings: Settings,
+    /// The thermostat lit the fireplace and may put it out again
+    lit: bool,
+    error: Option<String>,
+}
+
+impl ThermostatStore {
+    /// Load the persisted settings; a missing or unreadable file starts with the thermostat off
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("thermostat.json");
+        let settings = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse {}: {}", path.display(), e);
+                Settings::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Settings::default(),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                Settings::default()
+            }
+        };
+        Self {
+            path,
+            settings,
+            lit: false,
+            error: None,
+        }
+    }
+
+    fn setpoint(&self, config: &ThermostatConfig) -> f64 {
+        self.settings
+            .setpoint_c
+            .unwrap_or(config.setpoint_c)
+            .clamp(config.min_setpoint_c, config.max_setpoint_c)
+    }
+
+    /// Record why the controller couldn't act, warning only when the reason changes
+    fn report(&mut self, error: Option<String>) {
+        if let Some(e) = &error {
+            if self.error.as_ref() != Some(e) {
+                tracing::warn!("Thermostat: {}", e);
+            }
+        }
+        self.error = error;
+    }
+
+    fn save(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.settings)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode thermostat settings: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
+
+/// The thermostat's settings and what it currently sees
+pub async fn status(state: &AppState) -> Result<ThermostatStatus> {
+    let thermostat = state.thermostat.lock().await;
+    describe(state, &thermostat).await
+}
+
+/// Change the mode and/or setpoint, then act on them straight away
+pub async fn update(state: &AppState, mode: Option<ThermostatMode>, setpoint_c: Option<f64>) -> Result<ThermostatStatus> {
+    let config = state.config.load_full();
+    let settings = config.thermostat.as_ref().ok_or(ApiError::ThermostatNotConfigured)?;
+    if let Some(setpoint) = setpoint_c {
+        if !(settings.min_setpoint_c..=settings.max_setpoint_c).contains(&setpoint) {
+            return Err(ApiError::InvalidSetpoint {
+                min: settings.min_setpoint_c,
+                max: settings.max_setpoint_c,
+            });
+        }
+    }
+
+    let mut thermostat = state.thermostat.lock().await;
+    if let Some(mode) = mode {
+        thermostat.settings.mode = mode;
+    }
+    if setpoint_c.is_some() {
+        thermostat.settings.setpoint_c = setpoint_c;
+    }
+    thermostat.save()?;
+    tracing::info!(
+        "Thermostat set to {:?} at {:.1}°C",
+        thermostat.settings.mode,
+        thermostat.setpoint(settings)
+    );
+
+    evaluate(state, &mut thermostat).await;
+    describe(state, &thermostat).await
+}
+
+async fn describe(state: &AppState, thermostat: &ThermostatStore) -> Result<ThermostatStatus> {
+    let config = state.config.load_full();
+    let settings = config.thermostat.as_ref().ok_or(ApiError::ThermostatNotConfigured)?;
+    let (room, device) = settings.target(&config).ok_or(ApiError::ThermostatNotConfigured)?;
+    Ok(ThermostatStatus {
+        mode: thermostat.settings.mode,
+        setpoint_c: thermostat.setpoint(settings),
+        hysteresis_c: settings.hysteresis_c,
+        sensor: settings.sensor.clone(),
+        temperature_c: state.sensors.read().await.temperature(&config, &settings.sensor),
+        heating: is_lit(state, &room, &device).await,
+        room,
+        device,
+        error: thermostat.error.clone(),
+    })
+}
+
+async fn is_lit(state: &AppState, room: &str, device: &str) -> bool {
+    matches!(
+        state.fireplaces.read().await.status(room, device).state,
+        FireplaceState::Igniting | FireplaceState::On
+    )
+}
+
+/// Light or put out the fireplace once the room leaves the band around the setpoint
+async fn evaluate(state: &AppState, thermostat: &mut ThermostatStore) {
+    let config = state.config.load_full();
+    let Some(settings) = &config.thermostat else {
+        return;
+    };
+    let Some((room, device)) = settings.target(&config) else {
+        return;
+    };
+    // The active node runs the thermostat
+    if state.gpio_controller.is_standby() {
+        return;
+    }
+
+    let lit = is_lit(state, &room, &device).await;
+    thermostat.lit &= lit;
+    let temperature = state.sensors.read().await.temperature(&config, &settings.sensor);
+    let setpoint = thermostat.setpoint(settings);
+    let action = match (thermostat.settings.mode, temperature) {
+        (ThermostatMode::Heat, Some(t)) if !lit && t <= setpoint - settings.hysteresis_c => Some("ON"),
+        (ThermostatMode::Heat, Some(t)) if lit && t >= setpoint + settings.hysteresis_c => Some("OFF"),
+        (ThermostatMode::Heat, Some(_)) => None,
+        // Switched off, or blind without a recent reading: put out a fire the thermostat lit
+        _ if thermostat.lit => Some("OFF"),
+        _ => None,
+    };
+    let mut error = match (thermostat.settings.mode, temperature) {
+        (ThermostatMode::Heat, None) => Some(format!("sensor '{}' has no recent reading", settings.sensor)),
+        _ => None,
+    };
+
+    if let Some(action) = action {
+        let req = FireplaceControlRequest {
+            action: action.to_string(),
+            device: device.clone(),
+            room: Some(room.clone()),
+            cycles: None,
+            cycle_delay_ms: None,
+            duration_minutes: None,
+            run_async: false,
+            confirmation_token: None,
+            source: ChangeSource::Thermostat,
+        };
+        match handlers::run_control(state, req, &Progress::none()).await {
+            Ok(_) => {
+                thermostat.lit = action == "ON";
+                match temperature {
+                    Some(t) => tracing::info!(
+                        "Thermostat turned {}/{} {} at {:.1}°C (setpoint {:.1}°C)",
+                        room, device, action, t, setpoint
+                    ),
+                    None => tracing::info!("Thermostat turned {}/{} {}", room, device, action),
+                }
+            }
+            Err(e) => error = Some(format!("couldn't turn {}/{} {}: {}", room, device, action, e)),
+        }
+    }
+    thermostat.report(error);
+}
+
+/// Hold the setpoint while the thermostat is in heat mode
+pub fn spawn_controller(state: AppState) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            tick.tick().await;
+            let mut thermostat = state.thermostat.lock().await;
+            evaluate(&state, &mut thermostat).await;
+        }
+    });
+}
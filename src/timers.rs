@@ -0,0 +1,119 @@
+﻿use chrono::Local;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::state::{AppState, ChangeSource};
+
+/// A pending automatic OFF for a device
+#[derive(Debug, Clone, Serialize)]
+pub struct Timer {
+    pub id: Uuid,
+    pub room: String,
+    pub device: String,
+    pub pins: Vec<u32>,
+    pub created_at: String,
+    pub fires_at: String,
+}
+
+struct ActiveTimer {
+    timer: Timer,
+    handle: JoinHandle<()>,
+}
+
+/// Pending "turn off after N minutes" timers
+#[derive(Default)]
+pub struct TimerManager {
+    timers: HashMap<Uuid, ActiveTimer>,
+}
+
+impl TimerManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every pending timer, soonest first
+    pub fn list(&self) -> Vec<Timer> {
+        let mut timers: Vec<Timer> = self.timers.values().map(|t| t.timer.clone()).collect();
+        timers.sort_by(|a, b| a.fires_at.cmp(&b.fires_at));
+        timers
+    }
+
+    pub fn cancel(&mut self, id: Uuid) -> Option<Timer> {
+        let active = self.timers.remove(&id)?;
+        active.handle.abort();
+        tracing::info!("Timer {} for {}/{} cancelled", id, active.timer.room, active.timer.device);
+        Some(active.timer)
+    }
+
+    /// Cancel every pending timer, returning them
+    pub fn cancel_all(&mut self) -> Vec<Timer> {
+        let timers = self.list();
+        for timer in &timers {
+            self.cancel(timer.id);
+        }
+        timers
+    }
+
+    /// Cancel any pending timer for a device, e.g. when it is switched manually
+    pub fn cancel_device(&mut self, room: &str, device: &str) {
+        let ids: Vec<Uuid> = self
+            .timers
+            .values()
+            .filter(|t| t.timer.room == room && t.timer.device.eq_ignore_ascii_case(device))
+            .map(|t| t.timer.id)
+            .collect();
+        for id in ids {
+            self.cancel(id);
+        }
+    }
+}
+
+/// Turn a device's pins off after `minutes`, replacing any timer already pending for it
+pub async fn schedule_off(state: &AppState, room: &str, device: &str, pins: Vec<u32>, minutes: u32) -> Timer {
+    schedule_off_after(state, room, device, pins, Duration::from_secs(minutes as u64 * 60)).await
+}
+
+/// Turn a device's pins off after `delay`, replacing any timer already pending for it
+pub async fn schedule_off_after(state: &AppState, room: &str, device: &str, pins: Vec<u32>, delay: Duration) -> Timer {
+    let now = Local::now();
+    let timer = Timer {
+        id: Uuid::new_v4(),
+        room: room.to_string(),
+        device: device.to_string(),
+        pins,
+        created_at: now.to_rfc3339(),
+        fires_at: (now + chrono::Duration::from_std(delay).unwrap_or_default()).to_rfc3339(),
+    };
+
+    let task_state = state.clone();
+    let task_timer = timer.clone();
+    let handle = tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+
+        // Deregister first so the timer can no longer be cancelled mid-switch
+        task_state.timers.lock().await.timers.remove(&task_timer.id);
+
+        let mut gpio = task_state.gpio_controller.lock(ChangeSource::Timer, &task_timer.pins).await;
+        for pin in &task_timer.pins {
+            if let Err(e) = crate::fireplace::drive_off(&task_state, &mut gpio, *pin).await {
+                tracing::error!("Timer {} failed to turn off pin {}: {}", task_timer.id, pin, e);
+            }
+        }
+        tracing::info!("Timer {} turned {}/{} off", task_timer.id, task_timer.room, task_timer.device);
+    });
+
+    let mut timers = state.timers.lock().await;
+    timers.cancel_device(room, device);
+    timers.timers.insert(
+        timer.id,
+        ActiveTimer {
+            timer: timer.clone(),
+            handle,
+        },
+    );
+    tracing::info!("Timer {} will turn {}/{} off at {}", timer.id, room, device, timer.fires_at);
+    timer
+}
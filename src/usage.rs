@@ -0,0 +1,199 @@
+﻿use chrono::{DateTime, Datelike, Days, Local, NaiveDate};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    error::{ApiError, Result},
+    gpio::PinState,
+    state::{AppState, ChangeSource, StateEvent},
+};
+
+/// Last change and running state of one output pin since the server started
+#[derive(Debug, Clone)]
+struct PinUsage {
+    last_change: DateTime<Local>,
+    source: ChangeSource,
+    on_since: Option<DateTime<Local>>,
+    /// On-time of completed runs since startup
+    on_seconds: i64,
+}
+
+/// Tracks when each pin last changed, what changed it and how long it has been on.
+/// On-time per local day is persisted as JSON so totals survive restarts.
+pub struct UsageTracker {
+    path: PathBuf,
+    pins: HashMap<u32, PinUsage>,
+    /// Seconds on per pin per day, completed runs only
+    daily: BTreeMap<u32, BTreeMap<NaiveDate, i64>>,
+}
+
+impl UsageTracker {
+    /// Load the persisted daily totals; a missing or unreadable file starts empty
+    pub fn load(storage_dir: &str) -> Self {
+        let path = PathBuf::from(storage_dir).join("usage.json");
+        let daily = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                tracing::warn!("Failed to read {}: {}", path.display(), e);
+                BTreeMap::new()
+            }
+        };
+        Self { path, pins: HashMap::new(), daily }
+    }
+
+    /// Record a pin changing to `state` at `at`
+    pub fn observe(&mut self, pin: u32, state: &PinState, source: ChangeSource, at: DateTime<Local>) {
+        let usage = self.pins.entry(pin).or_insert_with(|| PinUsage {
+            last_change: at,
+            source,
+            on_since: None,
+            on_seconds: 0,
+        });
+        usage.last_change = at;
+        usage.source = source;
+
+        match (state, usage.on_since) {
+            (PinState::High, None) => usage.on_since = Some(at),
+            (PinState::Low | PinState::Unknown, Some(since)) => {
+                usage.on_since = None;
+                usage.on_seconds += (at - since).num_seconds();
+                self.credit(pin, since, at);
+                self.save();
+            }
+            _ => {}
+        }
+    }
+
+    /// Bank the on-time of runs still in progress, e.g. before shutting down, so it
+    /// isn't lost if the pins are never seen going off
+    pub fn checkpoint(&mut self, now: DateTime<Local>) {
+        let running: Vec<(u32, DateTime<Local>)> = self
+            .pins
+            .iter_mut()
+            .filter_map(|(pin, usage)| {
+                let since = usage.on_since.replace(now)?;
+                usage.on_seconds += (now - since).num_seconds();
+                Some((*pin, since))
+            })
+            .collect();
+        for (pin, since) in &running {
+            self.credit(*pin, *since, now);
+        }
+        if !running.is_empty() {
+            self.save();
+        }
+    }
+
+    /// When the pin last changed and what drove it; None if it never has
+    pub fn last_change(&self, pin: u32) -> Option<(DateTime<Local>, ChangeSource)> {
+        self.pins.get(&pin).map(|usage| (usage.last_change, usage.source))
+    }
+
+    /// Total seconds the pin has been on since startup, including a run still in progress
+    pub fn on_seconds(&self, pin: u32, now: DateTime<Local>) -> i64 {
+        self.pins.get(&pin).map_or(0, |usage| {
+            usage.on_seconds + usage.on_since.map_or(0, |since| (now - since).num_seconds())
+        })
+    }
+
+    /// Seconds the pin has been on since local midnight
+    pub fn on_seconds_today(&self, pin: u32, now: DateTime<Local>) -> i64 {
+        self.on_seconds_since(pin, now.date_naive(), now)
+    }
+
+    /// Seconds the pin has been on from the start of `from` until `now`, across restarts
+    pub fn on_seconds_since(&self, pin: u32, from: NaiveDate, now: DateTime<Local>) -> i64 {
+        let completed: i64 = self
+            .daily
+            .get(&pin)
+            .map_or(0, |days| days.range(from..).map(|(_, seconds)| seconds).sum());
+        let running = self
+            .pins
+            .get(&pin)
+            .and_then(|usage| usage.on_since)
+            .map_or(0, |since| (now - since.max(start_of_day(from, now))).num_seconds().max(0));
+        completed + running
+    }
+
+    /// Spread a completed run over the days it covered
+    fn credit(&mut self, pin: u32, from: DateTime<Local>, to: DateTime<Local>) {
+        let days = self.daily.entry(pin).or_default();
+        let mut start = from;
+        while start < to {
+            let midnight = start.date_naive().succ_opt().map_or(to, |next| start_of_day(next, to));
+            let end = if midnight > start { midnight.min(to) } else { to };
+            *days.entry(start.date_naive()).or_insert(0) += (end - start).num_seconds();
+            start = end;
+        }
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.write() {
+            tracing::warn!("{}", e);
+        }
+    }
+
+    fn write(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.daily)
+            .map_err(|e| ApiError::StorageError(format!("Failed to encode usage totals: {}", e)))?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ApiError::StorageError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        }
+
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ApiError::StorageError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+}
+
+/// Local midnight starting `day`; `fallback` if that time doesn't exist
+fn start_of_day(day: NaiveDate, fallback: DateTime<Local>) -> DateTime<Local> {
+    day.and_hms_opt(0, 0, 0)
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .unwrap_or(fallback)
+}
+
+/// The Monday starting the week of `day`
+pub fn start_of_week(day: NaiveDate) -> NaiveDate {
+    day - Days::new(day.weekday().num_days_from_monday() as u64)
+}
+
+/// The latest `(month, day)` on or before `today`, i.e. when the current season began
+pub fn start_of_season(today: NaiveDate, (month, day): (u32, u32)) -> NaiveDate {
+    let this_year = NaiveDate::from_ymd_opt(today.year(), month, day);
+    match this_year {
+        Some(start) if start <= today => start,
+        _ => NaiveDate::from_ymd_opt(today.year() - 1, month, day).unwrap_or(today),
+    }
+}
+
+/// Feed every pin change on the event bus into the usage tracker
+pub fn spawn_tracker(state: AppState) {
+    // Subscribe before returning so no change made after startup is missed
+    let mut events = state.events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(StateEvent::PinChanged { pin, state: pin_state, source, timestamp }) => {
+                    let at = DateTime::parse_from_rfc3339(&timestamp)
+                        .map(|at| at.with_timezone(&Local))
+                        .unwrap_or_else(|_| Local::now());
+                    state.usage.write().await.observe(pin, &pin_state, source, at);
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Usage tracker missed {} pin changes; on-time may be off", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
@@ -0,0 +1,70 @@
+﻿use notify::{RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::state::AppState;
+
+/// Editors often save in several steps; wait this long for writes to settle
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watch the active config file and hot-swap the config whenever it changes
+pub fn spawn_config_watcher(state: AppState) {
+    let path = PathBuf::from(state.config_path.as_str());
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            let _ = tx.send(event);
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!("Config file watching unavailable: {}", e);
+            return;
+        }
+    };
+
+    // Watch the directory rather than the file so atomic rename-on-save is seen
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+        tracing::warn!("Failed to watch {}: {}", dir.display(), e);
+        return;
+    }
+
+    tracing::info!("Watching {} for changes", path.display());
+
+    tokio::spawn(async move {
+        // Keep the watcher alive for as long as the task runs
+        let _watcher = watcher;
+
+        while let Some(event) = rx.recv().await {
+            if !is_config_change(&event, &path) {
+                continue;
+            }
+
+            tokio::time::sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+
+            match state.reload_config().await {
+                Ok(changed) if changed.is_empty() => {
+                    tracing::debug!("Config file touched without changes");
+                }
+                Ok(changed) => {
+                    tracing::info!(event = "config_reloaded", changed = ?changed, "Config file changed, reloaded");
+                }
+                Err(e) => {
+                    tracing::warn!("Ignoring invalid config file change: {}", e);
+                }
+            }
+        }
+    });
+}
+
+fn is_config_change(event: &notify::Event, path: &Path) -> bool {
+    (event.kind.is_modify() || event.kind.is_create())
+        && event.paths.iter().any(|p| p.file_name() == path.file_name())
+}
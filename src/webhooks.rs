@@ -0,0 +1,235 @@
+﻿use chrono::Local;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    fireplace::FireplaceState,
+    gpio::PinState,
+    secrets::Secret,
+    state::{AppState, ChangeSource, StateEvent},
+};
+
+/// Header carrying the hex HMAC-SHA256 of the body, as `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "X-Fireplace-Signature";
+
+/// `[[webhooks]]`: POST a JSON payload to a URL when something happens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Which events to send; all of them when empty
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    /// Signs every body so the receiver can check it came from this server
+    #[serde(default)]
+    pub secret: Option<Secret>,
+    #[serde(default = "default_timeout", alias = "timeout_seconds", with = "crate::duration::seconds")]
+    pub timeout: Duration,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(format!("webhooks: '{}' is not an http:// or https:// URL", self.url));
+        }
+        if self.timeout.is_zero() {
+            return Err(format!("webhooks: timeout for '{}' must not be zero", self.url));
+        }
+        Ok(())
+    }
+
+    fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A device turned on or off, whatever drove it
+    DeviceChanged,
+    /// A fireplace moved through its ignition cycle
+    FireplaceChanged,
+    /// The safety watchdog, load shedding or an emergency stop switched something off, an
+    /// ignition was not confirmed, the GPIO backend failed to drive a pin, or a room passed
+    /// `safety.max_temperature_c`
+    Safety,
+}
+
+/// Body of every webhook POST
+#[derive(Debug, Clone, Serialize)]
+struct Payload {
+    event: WebhookEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    room: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pin: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<PinState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fireplace_state: Option<FireplaceState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<ChangeSource>,
+    /// What tripped a safety event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sensor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature_c: Option<f64>,
+    timestamp: String,
+}
+
+/// Deliver the configured webhooks for every event on the bus
+pub fn spawn_dispatcher(state: AppState) {
+    // Subscribe before returning so no change made after startup is missed
+    let mut events = state.events.subscribe();
+    let client = reqwest::Client::new();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let webhooks = state.config.load().webhooks.clone();
+                    if webhooks.is_empty() {
+                        continue;
+                    }
+                    for payload in payloads(&state, event) {
+                        for webhook in webhooks.iter().filter(|w| w.wants(payload.event)) {
+                            tokio::spawn(deliver(client.clone(), webhook.clone(), payload.clone()));
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Webhooks missed {} events", skipped);
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+/// What an event on the bus means to webhook receivers
+fn payloads(state: &AppState, event: StateEvent) -> Vec<Payload> {
+    let config = state.config.load();
+    let blank = |event: WebhookEvent, timestamp: String| Payload {
+        event,
+        room: None,
+        device: None,
+        pin: None,
+        state: None,
+        fireplace_state: None,
+        source: None,
+        reason: None,
+        sensor: None,
+        temperature_c: None,
+        timestamp,
+    };
+
+    match event {
+        StateEvent::PinChanged { pin, state: pin_state, source, timestamp } => {
+            // Pins no device is configured on don't concern anyone
+            let Some((zone, device)) = config.find_pin(pin) else {
+                return Vec::new();
+            };
+            let changed = Payload {
+                room: Some(zone.name.to_string()),
+                device: Some(device.name.clone()),
+                pin: Some(pin),
+                state: Some(pin_state),
+                source: Some(source),
+                ..blank(WebhookEvent::DeviceChanged, timestamp)
+            };
+            let reason = match source {
+                ChangeSource::Safety => Some("max_runtime"),
+                ChangeSource::Power => Some("load_shed"),
+                ChangeSource::EmergencyStop => Some("emergency_stop"),
+                _ => None,
+            };
+            let safety = reason.map(|reason| Payload {
+                event: WebhookEvent::Safety,
+                reason: Some(reason),
+                ..changed.clone()
+            });
+            std::iter::once(changed).chain(safety).collect()
+        }
+        StateEvent::IgnitionChecked { pin, verified: false, timestamp } => {
+            let owner = config.find_pin(pin);
+            vec![Payload {
+                room: owner.map(|(zone, _)| zone.name.to_string()),
+                device: owner.map(|(_, device)| device.name.clone()),
+                pin: Some(pin),
+                reason: Some("ignition_not_confirmed"),
+                ..blank(WebhookEvent::Safety, timestamp)
+            }]
+        }
+        StateEvent::GpioFailed { pin, source, timestamp, .. } => {
+            let owner = config.find_pin(pin);
+            vec![Payload {
+                room: owner.map(|(zone, _)| zone.name.to_string()),
+                device: owner.map(|(_, device)| device.name.clone()),
+                pin: Some(pin),
+                source: Some(source),
+                reason: Some("gpio_failure"),
+                ..blank(WebhookEvent::Safety, timestamp)
+            }]
+        }
+        StateEvent::FireplaceChanged { room, device, state, timestamp } => vec![Payload {
+            room: Some(room),
+            device: Some(device),
+            fireplace_state: Some(state),
+            ..blank(WebhookEvent::FireplaceChanged, timestamp)
+        }],
+        StateEvent::TemperatureCutoff { room, sensor, temperature_c, tripped: true, timestamp } => vec![Payload {
+            room: Some(room),
+            sensor: Some(sensor),
+            temperature_c: Some(temperature_c),
+            reason: Some("over_temperature"),
+            ..blank(WebhookEvent::Safety, timestamp)
+        }],
+        StateEvent::TemperatureCutoff { tripped: false, .. } => Vec::new(),
+        // Its pins going off are reported one by one as safety events
+        StateEvent::EmergencyStopped { .. } => Vec::new(),
+        StateEvent::IgnitionChecked { .. } | StateEvent::ConfigReloaded { .. } | StateEvent::InputChanged { .. } => {
+            Vec::new()
+        }
+    }
+}
+
+/// POST one payload; failures are logged, not retried
+async fn deliver(client: reqwest::Client, webhook: WebhookConfig, payload: Payload) {
+    let Ok(body) = serde_json::to_vec(&payload) else {
+        return;
+    };
+    let mut request = client
+        .post(&webhook.url)
+        .timeout(webhook.timeout)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    if let Some(secret) = &webhook.secret {
+        request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(secret.expose(), &body)));
+    }
+
+    let started = Local::now();
+    match request.body(body).send().await.and_then(|r| r.error_for_status()) {
+        Ok(_) => tracing::debug!(
+            "Webhook {:?} delivered to {} in {}ms",
+            payload.event,
+            webhook.url,
+            (Local::now() - started).num_milliseconds()
+        ),
+        Err(e) => tracing::warn!("Webhook {:?} to {} failed: {}", payload.event, webhook.url, e),
+    }
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key length");
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}